@@ -0,0 +1,432 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Headless frontend for batch analysis.
+//!
+//! Loads an ELF, PE, Mach-O, DOS .com, Intel HEX, S-record or raw file, disassembles all reachable functions and writes the result into
+//! a Panopticon session file. A summary of the recovered functions is printed to stdout as JSON.
+//! With `-x` the whole project is printed as JSON or as an objdump-like listing instead.
+//!
+//! ```text
+//! panop [-o OUT.panop] [-f auto|elf|pe|macho|com|ihex|srec|raw] [-t TARGET] [-b BASE] [-e ENTRY] [-a ANNOTATIONS [-m keep|replace|append]] [-x json|listing|annotations] FILE
+//! ```
+
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+
+extern crate panopticon;
+extern crate graph_algos;
+extern crate uuid;
+extern crate rustc_serialize;
+
+use std::env;
+use std::path::{Path,PathBuf};
+use std::process;
+use std::fmt::Debug;
+use std::io::Write;
+
+use panopticon::{
+    Project,
+    CallTarget,
+    ControlFlowTarget,
+    DisassemblyEvent,
+    Rvalue,
+    Result,
+    Target,
+    Architecture,
+    elf,
+    pe,
+    macho,
+    dos,
+    firmware,
+    export,
+    Annotations,
+    Merge,
+};
+use panopticon::amd64;
+use panopticon::avr;
+use panopticon::mos;
+
+use graph_algos::{
+    GraphTrait,
+    VertexListGraphTrait,
+    EdgeListGraphTrait,
+    MutableGraphTrait,
+};
+use rustc_serialize::json;
+use uuid::Uuid;
+
+const USAGE: &'static str = "Usage: panop [-o OUT.panop] [-f auto|elf|pe|macho|com|ihex|srec|raw] [-t TARGET] [-b BASE] [-e ENTRY] [-a ANNOTATIONS [-m keep|replace|append]] [-x json|listing|annotations] FILE
+
+Options:
+    -o OUT      Write the session to OUT instead of FILE.panop
+    -f FORMAT   File format: auto, elf, pe, macho, com (DOS), ihex, srec or
+                raw. Guessed from the file extension, defaults to auto which
+                detects the format from the file contents
+    -t TARGET   Override the CPU: amd64, ia32, ia16, mos6502, atmega103,
+                atmega8, atmega88 or atmega16. Raw files default to amd64,
+                Intel HEX and S-record files need a target. Selects the
+                slice of universal Mach-O files
+    -b BASE     Load address of raw files
+    -e ENTRY    Additional function entry point
+    -a FILE     Import function names, entry points and comments from an
+                annotation database
+    -m RULE     Resolve conflicts with imported annotations: keep (default),
+                replace or append
+    -x EXPORT   Print the whole project instead of the summary. Either json,
+                listing (objdump-like text) or annotations";
+
+/// Command line arguments
+struct Arguments {
+    input: PathBuf,
+    output: PathBuf,
+    format: String,
+    target: Option<String>,
+    base: u64,
+    entry: Option<u64>,
+    export: Option<String>,
+    annotations: Option<PathBuf>,
+    merge: Merge,
+}
+
+/// Summary of a single call graph node
+#[derive(RustcEncodable)]
+struct FunctionSummary {
+    uuid: String,
+    name: String,
+    kind: String,
+    entry: Option<u64>,
+    basic_blocks: usize,
+    jumps: usize,
+    unresolved_jumps: Vec<String>,
+    failed: Vec<u64>,
+}
+
+/// Machine-readable result of a batch run
+#[derive(RustcEncodable)]
+struct Summary {
+    name: String,
+    session: String,
+    functions: Vec<FunctionSummary>,
+    calls: usize,
+}
+
+fn parse_number(s: &str) -> Result<u64> {
+    let ret = if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..],16)
+    } else {
+        u64::from_str_radix(s,10)
+    };
+
+    ret.map_err(|_| format!("'{}' is not a number",s).into())
+}
+
+fn parse_arguments() -> Result<Arguments> {
+    let mut args = env::args().skip(1);
+    let mut input = None;
+    let mut output = None;
+    let mut format = None;
+    let mut target = None;
+    let mut base = 0;
+    let mut entry = None;
+    let mut export = None;
+    let mut annotations = None;
+    let mut merge = Merge::Keep;
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "-o" | "-f" | "-t" | "-b" | "-e" | "-x" | "-a" | "-m" => {
+                let v = try!(args.next().ok_or(format!("option {} needs an argument",a)));
+
+                match a.as_str() {
+                    "-o" => output = Some(PathBuf::from(v)),
+                    "-f" => format = Some(v),
+                    "-t" => target = Some(v),
+                    "-b" => base = try!(parse_number(&v)),
+                    "-e" => entry = Some(try!(parse_number(&v))),
+                    "-x" if v == "json" || v == "listing" || v == "annotations" => export = Some(v),
+                    "-x" => return Err(format!("unknown export format '{}'",v).into()),
+                    "-a" => annotations = Some(PathBuf::from(v)),
+                    "-m" => merge = match v.as_str() {
+                        "keep" => Merge::Keep,
+                        "replace" => Merge::Replace,
+                        "append" => Merge::Append,
+                        _ => return Err(format!("unknown merge rule '{}'",v).into()),
+                    },
+                    _ => unreachable!(),
+                }
+            },
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if input.is_none() => input = Some(PathBuf::from(a)),
+            _ => return Err(format!("unexpected argument '{}'\n\n{}",a,USAGE).into()),
+        }
+    }
+
+    let input = try!(input.ok_or(USAGE));
+    let output = output.unwrap_or_else(|| input.with_extension("panop"));
+    let format = format.unwrap_or_else(|| {
+        match input.extension().and_then(|x| x.to_str()) {
+            Some("com") | Some("COM") => "com".to_string(),
+            Some("hex") | Some("ihex") => "ihex".to_string(),
+            Some("srec") | Some("s19") | Some("s28") | Some("s37") | Some("mot") => "srec".to_string(),
+            _ => "auto".to_string(),
+        }
+    });
+
+    Ok(Arguments{
+        input: input,
+        output: output,
+        format: format,
+        target: target,
+        base: base,
+        entry: entry,
+        export: export,
+        annotations: annotations,
+        merge: merge,
+    })
+}
+
+/// Disassembles all `CallTarget::Todo` nodes of the first program until no new functions are
+/// found.
+fn disassemble<A: Architecture + Debug>(proj: &mut Project, cfg: A::Configuration) -> Result<()> where A::Configuration: Debug {
+    let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+    let prog = try!(proj.code.first_mut().ok_or("Project has no program"));
+
+    let approx = try!(prog.disassemble_all::<A,_>(root,cfg,|ev| match ev {
+        DisassemblyEvent::Started(uu) => info!("disassemble function {}",uu),
+        DisassemblyEvent::Finished(uu) => debug!("finished function {}",uu),
+        DisassemblyEvent::Discovered(uu) => debug!("found function {}",uu),
+    }));
+
+    debug!("summarized {} functions",approx.summaries.len());
+    Ok(())
+}
+
+fn summarize(proj: &Project, session: &Path) -> Summary {
+    let mut functions = Vec::new();
+    let mut calls = 0;
+
+    for prog in proj.code.iter() {
+        calls += prog.call_graph.num_edges();
+
+        for vx in prog.call_graph.vertices() {
+            let s = match prog.call_graph.vertex_label(vx) {
+                Some(&CallTarget::Concrete(ref f)) => {
+                    let mut unresolved = Vec::new();
+                    let mut failed = Vec::new();
+                    let mut bbs = 0;
+
+                    for v in f.cflow_graph.vertices() {
+                        match f.cflow_graph.vertex_label(v) {
+                            Some(&ControlFlowTarget::Resolved(_)) => bbs += 1,
+                            Some(&ControlFlowTarget::Unresolved(Rvalue::Constant{ .. })) => {},
+                            Some(&ControlFlowTarget::Unresolved(ref rv)) => unresolved.push(format!("{}",rv)),
+                            Some(&ControlFlowTarget::Failed(pos,_)) => failed.push(pos),
+                            None => {},
+                        }
+                    }
+
+                    let entry = f.entry_point.and_then(|e| match f.cflow_graph.vertex_label(e) {
+                        Some(&ControlFlowTarget::Resolved(ref bb)) => Some(bb.area.start),
+                        _ => None,
+                    });
+
+                    FunctionSummary{
+                        uuid: f.uuid.to_string(),
+                        name: f.name.clone(),
+                        kind: "function".to_string(),
+                        entry: entry,
+                        basic_blocks: bbs,
+                        jumps: f.cflow_graph.num_edges(),
+                        unresolved_jumps: unresolved,
+                        failed: failed,
+                    }
+                },
+                Some(&CallTarget::Symbolic(ref name,uuid)) => FunctionSummary{
+                    uuid: uuid.to_string(),
+                    name: name.clone(),
+                    kind: "symbolic".to_string(),
+                    entry: None,
+                    basic_blocks: 0,
+                    jumps: 0,
+                    unresolved_jumps: vec![],
+                    failed: vec![],
+                },
+                Some(&CallTarget::Todo(ref rv,ref name,uuid)) => FunctionSummary{
+                    uuid: uuid.to_string(),
+                    name: name.clone().unwrap_or(format!("{}",rv)),
+                    kind: "todo".to_string(),
+                    entry: if let &Rvalue::Constant{ value,.. } = rv { Some(value) } else { None },
+                    basic_blocks: 0,
+                    jumps: 0,
+                    unresolved_jumps: vec![format!("{}",rv)],
+                    failed: vec![],
+                },
+                None => continue,
+            };
+
+            functions.push(s);
+        }
+    }
+
+    functions.sort_by(|a,b| a.entry.cmp(&b.entry));
+
+    Summary{
+        name: proj.name.clone(),
+        session: session.to_string_lossy().to_string(),
+        functions: functions,
+        calls: calls,
+    }
+}
+
+fn run(args: Arguments) -> Result<()> {
+    let target = args.target.clone();
+    let (mut proj,target) = match args.format.as_str() {
+        "auto" => {
+            let (proj,tgt) = try!(Project::load(&args.input));
+            let tgt = match tgt {
+                Some(Target::Amd64(amd64::Mode::Long)) => "amd64",
+                Some(Target::Amd64(amd64::Mode::Protected)) => "ia32",
+                Some(Target::Amd64(amd64::Mode::Real)) => "ia16",
+                Some(Target::Avr(_)) => "atmega88",
+                Some(Target::Mos(_)) => "mos6502",
+                None => "none",
+            };
+
+            (proj,target.unwrap_or(tgt.to_string()))
+        },
+        "elf" => {
+            let (proj,machine) = try!(elf::load(&args.input));
+            let tgt = match machine {
+                elf::Machine::Amd64 => "amd64",
+                elf::Machine::Ia32 => "ia32",
+                elf::Machine::Avr => "atmega88",
+                _ => "none",
+            };
+
+            (proj,target.unwrap_or(tgt.to_string()))
+        },
+        "macho" => {
+            let slice = match target.as_ref().map(|x| x.as_str()) {
+                Some("amd64") => Some(macho::Machine::Amd64),
+                Some("ia32") => Some(macho::Machine::Ia32),
+                _ => None,
+            };
+            let (proj,machine) = try!(macho::load(&args.input,slice));
+            let tgt = match machine {
+                macho::Machine::Amd64 => "amd64",
+                macho::Machine::Ia32 => "ia32",
+                _ => "none",
+            };
+
+            (proj,target.unwrap_or(tgt.to_string()))
+        },
+        "pe" => {
+            let (proj,machine) = try!(pe::load(&args.input));
+            let tgt = match machine {
+                pe::Machine::Amd64 => "amd64",
+                pe::Machine::Ia32 => "ia32",
+            };
+
+            (proj,target.unwrap_or(tgt.to_string()))
+        },
+        "com" => (try!(dos::load_com(&args.input)),target.unwrap_or("ia16".to_string())),
+        "raw" => (try!(Project::raw(&args.input,args.base)),target.unwrap_or("amd64".to_string())),
+        "ihex" => (try!(firmware::ihex(&args.input)),try!(target.ok_or("Intel HEX files need a target (-t)"))),
+        "srec" => (try!(firmware::srec(&args.input)),try!(target.ok_or("S-record files need a target (-t)"))),
+        f => return Err(format!("Unknown file format '{}'",f).into()),
+    };
+
+    if let Some(entry) = args.entry {
+        if let Some(prog) = proj.code.first_mut() {
+            prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(entry),Some("Entry point".to_string()),Uuid::new_v4()));
+        }
+    }
+
+    let use_vectors = args.entry.is_none() && (args.format == "raw" || args.format == "ihex" || args.format == "srec");
+    let annotations = match args.annotations {
+        Some(ref p) => Some(try!(Annotations::read(p))),
+        None => None,
+    };
+
+    // named entry points are added before disassembly, names of functions found while
+    // disassembling are applied afterwards
+    if let Some(ref ann) = annotations {
+        let report = try!(ann.apply(&mut proj,args.merge));
+
+        info!("{} new entry points from {}",report.entry_points,args.annotations.as_ref().unwrap().display());
+    }
+
+    match target.as_str() {
+        "amd64" => try!(disassemble::<amd64::Amd64>(&mut proj,amd64::Mode::Long)),
+        "ia32" => try!(disassemble::<amd64::Amd64>(&mut proj,amd64::Mode::Protected)),
+        "ia16" => try!(disassemble::<amd64::Amd64>(&mut proj,amd64::Mode::Real)),
+        "mos6502" => {
+            let cfg = mos::Variant::mos6502();
+            if use_vectors { try!(proj.prepare::<mos::Mos>(&cfg)); }
+            try!(disassemble::<mos::Mos>(&mut proj,cfg))
+        },
+        "atmega103" | "atmega8" | "atmega88" | "atmega16" => {
+            let cfg = match target.as_str() {
+                "atmega103" => avr::Mcu::atmega103(),
+                "atmega8" => avr::Mcu::atmega8(),
+                "atmega88" => avr::Mcu::atmega88(),
+                "atmega16" => avr::Mcu::atmega16(),
+                _ => unreachable!(),
+            };
+            if use_vectors { try!(proj.prepare::<avr::Avr>(&cfg)); }
+            try!(disassemble::<avr::Avr>(&mut proj,cfg))
+        },
+        "none" => warn!("No disassembler for this file, only mapping it"),
+        t => return Err(format!("No such target '{}'",t).into()),
+    }
+
+    if let Some(ref ann) = annotations {
+        let report = try!(ann.apply(&mut proj,args.merge));
+
+        for c in report.name_conflicts.iter().chain(report.comment_conflicts.iter()) {
+            warn!("conflict at {}:{:#x}: '{}' vs. imported '{}'",c.region,c.address,c.existing,c.imported);
+        }
+
+        info!("renamed {} functions, set {} comments",report.renamed,report.comments);
+    }
+
+    try!(proj.snapshot(&args.output));
+
+    let out = match args.export.as_ref().map(|x| x.as_str()) {
+        Some("json") => try!(export::to_json(&proj)),
+        Some("listing") => export::listing(&proj),
+        Some("annotations") => Annotations::from_project(&proj).to_string(),
+        _ => try!(json::encode(&summarize(&proj,&args.output))),
+    };
+
+    println!("{}",out);
+    Ok(())
+}
+
+fn main() {
+    env_logger::init().unwrap();
+
+    let ret = parse_arguments().and_then(run);
+
+    if let Err(e) = ret {
+        let _ = writeln!(&mut std::io::stderr(),"{}",e);
+        process::exit(1);
+    }
+}
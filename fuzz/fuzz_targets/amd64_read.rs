@@ -0,0 +1,42 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate panopticon;
+
+use panopticon::amd64;
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+
+    // first byte selects the CPU mode, the rest is the instruction
+    let mode = match data[0] % 3 {
+        0 => amd64::Mode::Real,
+        1 => amd64::Mode::Protected,
+        _ => amd64::Mode::Long,
+    };
+
+    if let Ok((len,mne,_)) = amd64::read(mode,&data[1..],0x1000) {
+        assert_eq!(mne.area.start,0x1000);
+        assert_eq!(mne.area.len(),len);
+        assert!(len as usize <= data.len() - 1);
+    }
+});
@@ -0,0 +1,32 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate panopticon;
+
+use panopticon::Region;
+use panopticon::avr::{syntax,Mcu};
+
+fuzz_target!(|data: &[u8]| {
+    let reg = Region::wrap("flash".to_string(),data.to_vec());
+    let disass = syntax::disassembler();
+    let mut i = reg.iter().seek(0);
+
+    let _ = disass.next_match(&mut i,0,Mcu::atmega88());
+});
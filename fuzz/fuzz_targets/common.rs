@@ -0,0 +1,35 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Helpers shared by the fuzz targets.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use tempdir::TempDir;
+
+/// Writes `data` into a fresh temporary file and calls `f` with its path. The loaders only
+/// accept paths.
+pub fn with_file<F: FnOnce(&Path)>(data: &[u8], f: F) {
+    let dir = TempDir::new("panopticon-fuzz").unwrap();
+    let p = dir.path().join("input");
+
+    File::create(&p).and_then(|mut fd| fd.write_all(data)).unwrap();
+    f(&p);
+}
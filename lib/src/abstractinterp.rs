@@ -26,6 +26,10 @@
 //! abstract sign domain. For example multiplying two positive values yields a positive value.
 //! Adding a positive and a negative sign yields an abstract value representing both signs (called
 //! join).
+//!
+//! This module implements the `Kset` domain. Interval and strided interval domains are in
+//! [`interval`](../interval/index.html). The abstract memory used to track values written to
+//! memory is in [`memory`](../memory/index.html).
 
 use std::hash::Hash;
 use std::fmt::Debug;
@@ -39,6 +43,7 @@ use graph_algos::{
     IncidenceGraphTrait,
     VertexListGraphTrait,
     BidirectionalGraphTrait,
+    EdgeListGraphTrait,
 };
 use rustc_serialize::{Encodable,Decodable};
 use graph_algos::dominator::{
@@ -61,6 +66,11 @@ use {
     Result,
     flag_operations,
 };
+use memory::{
+    AbstractStore,
+    locations,
+    stack_offsets,
+};
 
 /// Linear constraint.
 pub enum Constraint {
@@ -83,6 +93,13 @@ pub struct ProgramPoint {
     position: usize,
 }
 
+impl ProgramPoint {
+    /// The `position`th RREIL instruction of the basic block starting at `address`.
+    pub fn new(address: u64, position: usize) -> ProgramPoint {
+        ProgramPoint{ address: address, position: position }
+    }
+}
+
 /// Abstract Domain. Models both under- and over-approximation.
 pub trait Avalue: Clone + PartialEq + Eq + Hash + Debug + Encodable + Decodable {
     /// Alpha function. Returns domain element that approximates the concrete value the best
@@ -104,27 +121,56 @@ pub trait Avalue: Clone + PartialEq + Eq + Hash + Debug + Encodable + Decodable
     fn initial() -> Self;
     /// Mimics the Select operation.
     fn extract(&self,size: usize,offset: usize) -> Self;
+    /// Returns the concrete values approximated by `self` if there are only a few. Used to
+    /// resolve memory addresses.
+    fn concretize(&self) -> Option<Vec<u64>> { None }
 }
 
 /// Does an abstract interpretation of `func` using the abstract domain `A`. The function uses a
 /// fixed point iteration and the widening strategy outlined in
 /// Bourdoncle: "Efficient chaotic iteration strategies with widenings".
+///
+/// Values written to memory at constant addresses are tracked, see
+/// [`memory`](../memory/index.html). Use `approximate_with` to track stack slots too.
 pub fn approximate<A: Avalue>(func: &Function) -> Result<HashMap<Lvalue,A>> {
+    approximate_with(func,&HashMap::new(),&HashMap::new(),None)
+}
+
+/// Same as `approximate` but with known values for some variables. Variables that are read
+/// before they are written are set to their value in `entry` by the `__init` mnemonic of the SSA
+/// form. SSA variables in `fixed` aren't computed by executing their definition, their value is
+/// taken from `fixed` instead. If `stack_pointer` is given, memory cells addressed relative to its
+/// value at function entry are tracked as stack slots.
+pub fn approximate_with<A: Avalue>(func: &Function, entry: &HashMap<Cow<'static,str>,A>,
+                                   fixed: &HashMap<(Cow<'static,str>,usize),A>,
+                                   stack_pointer: Option<&Lvalue>) -> Result<HashMap<Lvalue,A>> {
     if func.entry_point.is_none() {
-        return Err("function has no entry point".into());
+        return Err(::result::Error::analysis("function has no entry point"));
+    }
+
+    struct Context<'a,A: Avalue + 'a> {
+        graph: &'a ControlFlowGraph,
+        entry_point: ControlFlowRef,
+        constr: HashMap<Lvalue,A>,
+        sizes: HashMap<Cow<'static,str>,usize>,
+        entry: &'a HashMap<Cow<'static,str>,A>,
+        fixed: &'a HashMap<(Cow<'static,str>,usize),A>,
+        offsets: HashMap<(Cow<'static,str>,usize),i64>,
+    }
+    struct State<A: Avalue> {
+        values: HashMap<(Cow<'static,str>,usize),A>,
+        // memory state at the end of each basic block
+        memory: HashMap<ControlFlowRef,AbstractStore<A>>,
     }
 
     let wto = weak_topo_order(func.entry_point.unwrap(),&func.cflow_graph);
-    let edge_ops = flag_operations(func);
-    fn stabilize<A: Avalue>(h: &Vec<Box<HierarchicalOrdering<ControlFlowRef>>>, graph: &ControlFlowGraph,
-                            constr: &HashMap<Lvalue,A>, sizes: &HashMap<Cow<'static,str>,usize>,
-                            ret: &mut HashMap<(Cow<'static,str>,usize),A>) -> Result<()> {
+    fn stabilize<A: Avalue>(h: &Vec<Box<HierarchicalOrdering<ControlFlowRef>>>, ctx: &Context<A>, st: &mut State<A>) -> Result<()> {
         let mut stable = true;
         let mut iter_cnt = 0;
         let head = if let Some(h) = h.first() {
             match &**h {
                 &HierarchicalOrdering::Element(ref vx) => vx.clone(),
-                &HierarchicalOrdering::Component(ref vec) => return stabilize(vec,graph,constr,sizes,ret),
+                &HierarchicalOrdering::Component(ref vec) => return stabilize(vec,ctx,st),
             }
         } else {
             return Ok(())
@@ -134,18 +180,18 @@ pub fn approximate<A: Avalue>(func: &Function) -> Result<HashMap<Lvalue,A>> {
             for x in h.iter() {
                 match &**x {
                     &HierarchicalOrdering::Element(ref vx) =>
-                        stable &= !try!(execute(*vx,iter_cnt >= 2 && *vx == head,graph,constr,sizes,ret)),
+                        stable &= !try!(execute(*vx,iter_cnt >= 2 && *vx == head,ctx,st)),
                     &HierarchicalOrdering::Component(ref vec) => {
-                        try!(stabilize(&*vec,graph,constr,sizes,ret));
+                        try!(stabilize(&*vec,ctx,st));
                         stable = true;
                     },
                 }
             }
 
             if stable {
-                for (lv,a) in constr.iter() {
+                for (lv,a) in ctx.constr.iter() {
                     if let &Lvalue::Variable{ ref name, subscript: Some(ref subscript),.. } = lv {
-                    if let Some(ref mut x) = ret.get_mut(&(name.clone(),*subscript)) {
+                    if let Some(ref mut x) = st.values.get_mut(&(name.clone(),*subscript)) {
                         let n = x.narrow(&a);
                         **x = n;
                     }
@@ -160,63 +206,109 @@ pub fn approximate<A: Avalue>(func: &Function) -> Result<HashMap<Lvalue,A>> {
             iter_cnt += 1;
         }
     }
-    fn execute<A: Avalue>(t: ControlFlowRef, do_widen: bool, graph: &ControlFlowGraph,
-                          _: &HashMap<Lvalue,A>, sizes: &HashMap<Cow<'static,str>,usize>,
-                          ret: &mut HashMap<(Cow<'static,str>,usize),A>) -> Result<bool> {
-        if let Some(&ControlFlowTarget::Resolved(ref bb)) = graph.vertex_label(t) {
+    fn execute<A: Avalue>(t: ControlFlowRef, do_widen: bool, ctx: &Context<A>, st: &mut State<A>) -> Result<bool> {
+        if let Some(&ControlFlowTarget::Resolved(ref bb)) = ctx.graph.vertex_label(t) {
             let mut change = false;
             let mut pos = 0usize;
-            bb.execute(|i| {
-                if let Statement{ ref op, assignee: Lvalue::Variable{ ref name, subscript: Some(ref subscript),.. } } = *i {
-                    let pp = ProgramPoint{ address: bb.area.start, position: pos };
-                    let new = A::execute(&pp,&lift(op,&|x| res::<A>(x,sizes,&ret)));
-                    let assignee = (name.clone(),*subscript);
-                    let cur = ret.get(&assignee).cloned();
-
-                    if cur.is_none() {
-                        change = true;
-                        ret.insert(assignee,new);
-                    } else {
-                        if do_widen {
-                            let c = cur.unwrap();
-                            let w = c.widen(&new);
+            let mut mem = if t == ctx.entry_point {
+                AbstractStore::new()
+            } else {
+                let mut preds = ctx.graph.in_edges(t).filter_map(|e| st.memory.get(&ctx.graph.source(e)));
+                let first = preds.next().cloned().unwrap_or(AbstractStore::new());
+
+                preds.fold(first,|acc,m| acc.combine(m))
+            };
 
-                            if w != c {
+            for (mne,i) in bb.mnemonics.iter().flat_map(|m| m.instructions.iter().map(move |i| (m,i))) {
+                match *i {
+                    Statement{ op: Operation::Store(ref bank,ref addr), ref assignee } => {
+                        let a = res::<A>(addr,ctx,&st.values);
+                        let locs = locations(addr,&a,&ctx.offsets);
+                        let val = res::<A>(&assignee.clone().into(),ctx,&st.values);
+
+                        mem.store(bank,&locs,val,assignee.size().unwrap_or(0));
+                    },
+                    Statement{ op: Operation::Call(_),.. } => mem.call(),
+                    Statement{ ref op, assignee: Lvalue::Variable{ ref name, subscript: Some(ref subscript), size } } => {
+                        let pp = ProgramPoint{ address: bb.area.start, position: pos };
+                        let assignee = (name.clone(),*subscript);
+                        let new = match (ctx.fixed.get(&assignee),ctx.entry.get(name),op) {
+                            (Some(f),_,_) => f.clone(),
+                            (None,Some(e),_) if mne.opcode == "__init" => e.clone(),
+                            (None,_,&Operation::Load(ref bank,ref addr)) => {
+                                let a = res::<A>(addr,ctx,&st.values);
+                                let locs = locations(addr,&a,&ctx.offsets);
+
+                                mem.load(bank,&locs,size).unwrap_or(A::abstract_value(&Rvalue::Undefined))
+                            },
+                            _ => A::execute(&pp,&lift(op,&|x| res::<A>(x,ctx,&st.values))),
+                        };
+                        let cur = st.values.get(&assignee).cloned();
+
+                        if cur.is_none() {
+                            change = true;
+                            st.values.insert(assignee,new);
+                        } else {
+                            if do_widen {
+                                let c = cur.unwrap();
+                                let w = c.widen(&new);
+
+                                if w != c {
+                                    change = true;
+                                    st.values.insert(assignee,w);
+                                }
+                            } else if new.more_exact(&cur.clone().unwrap()) {
                                 change = true;
-                                ret.insert(assignee,w);
+                                st.values.insert(assignee,new);
                             }
-                        } else if new.more_exact(&cur.clone().unwrap()) {
-                            change = true;
-                            ret.insert(assignee,new);
                         }
-                    }
+                    },
+                    _ => {},
                 }
 
                 pos += 1;
-            });
+            }
+
+            let mem = match st.memory.get(&t) {
+                Some(cur) if do_widen => cur.widen(&mem),
+                _ => mem,
+            };
+
+            if st.memory.get(&t) != Some(&mem) {
+                change = true;
+                st.memory.insert(t,mem);
+            }
 
             Ok(change)
         } else {
             Ok(false)
         }
     }
-    fn res<A: Avalue>(v: &Rvalue, sizes: &HashMap<Cow<'static,str>,usize>, env: &HashMap<(Cow<'static,str>,usize),A>) -> A {
-        if let &Rvalue::Variable{ ref name, subscript: Some(ref subscript), ref size, ref offset } = v {
-            let nam = (name.clone(),*subscript);
-            let t = env.get(&nam).unwrap_or(&A::initial()).clone();
+    fn res<A: Avalue>(v: &Rvalue, ctx: &Context<A>, env: &HashMap<(Cow<'static,str>,usize),A>) -> A {
+        match v {
+            &Rvalue::Variable{ ref name, subscript: Some(ref subscript), ref size, ref offset } => {
+                let nam = (name.clone(),*subscript);
+                let t = env.get(&nam).unwrap_or(&A::initial()).clone();
+
+                if *offset > 0 || *size != *ctx.sizes.get(&nam.0).unwrap_or(&0) {
+                    t.extract(*size,*offset)
+                } else {
+                    t
+                }
+            },
+            &Rvalue::Variable{ ref name, subscript: None, ref size, ref offset } if ctx.entry.contains_key(name) => {
+                let t = ctx.entry[name].clone();
 
-            if *offset > 0 || *size != *sizes.get(&nam.0).unwrap_or(&0) {
-                t.extract(*size,*offset)
-            } else {
-                t
-            }
-        } else {
-            A::abstract_value(v)
+                if *offset > 0 || *size != *ctx.sizes.get(name).unwrap_or(size) {
+                    t.extract(*size,*offset)
+                } else {
+                    t
+                }
+            },
+            _ => A::abstract_value(v),
         }
     };
-    let mut ret = HashMap::<(Cow<'static,str>,usize),A>::new();
     let mut sizes = HashMap::<Cow<'static,str>,usize>::new();
-    let mut constr = HashMap::<Lvalue,A>::new();
 
     for vx in func.cflow_graph.vertices() {
         if let Some(&ControlFlowTarget::Resolved(ref bb)) = func.cflow_graph.vertex_label(vx) {
@@ -230,6 +322,48 @@ pub fn approximate<A: Avalue>(func: &Function) -> Result<HashMap<Lvalue,A>> {
         }
     }
 
+    let ctx = Context{
+        graph: &func.cflow_graph,
+        entry_point: func.entry_point.unwrap(),
+        constr: constraints::<A>(func),
+        sizes: sizes,
+        entry: entry,
+        fixed: fixed,
+        offsets: stack_pointer.map(|sp| stack_offsets(func,sp)).unwrap_or(HashMap::new()),
+    };
+    let mut st = State{
+        values: HashMap::new(),
+        memory: HashMap::new(),
+    };
+
+    match wto {
+        HierarchicalOrdering::Component(ref v) => {
+            try!(stabilize(v,&ctx,&mut st));
+        },
+        HierarchicalOrdering::Element(ref v) => {
+            try!(execute(*v,false,&ctx,&mut st));
+        },
+    }
+
+    Ok(HashMap::from_iter(st.values.iter().filter_map(|(&(ref name,ref subscript),val)| {
+        if let Some(sz) = ctx.sizes.get(name) {
+            Some((Lvalue::Variable{
+                name: name.clone(),
+                subscript: Some(*subscript),
+                size: *sz,
+            },val.clone()))
+        } else {
+            None
+        }
+    })))
+}
+
+/// Returns the abstract values implied by the branch conditions of `func` for each variable
+/// compared against a constant.
+pub fn constraints<A: Avalue>(func: &Function) -> HashMap<Lvalue,A> {
+    let edge_ops = flag_operations(func);
+    let mut constr = HashMap::<Lvalue,A>::new();
+
     for vx in func.cflow_graph.vertices() {
         for e in func.cflow_graph.in_edges(vx) {
             if let Some(&Guard::Predicate{ .. }) = func.cflow_graph.edge_label(e) {
@@ -270,26 +404,7 @@ pub fn approximate<A: Avalue>(func: &Function) -> Result<HashMap<Lvalue,A>> {
         }
     }
 
-    match wto {
-        HierarchicalOrdering::Component(ref v) => {
-            try!(stabilize(v,&func.cflow_graph,&constr,&sizes,&mut ret));
-        },
-        HierarchicalOrdering::Element(ref v) => {
-            try!(execute(*v,false,&func.cflow_graph,&constr,&sizes,&mut ret));
-        },
-    }
-
-    Ok(HashMap::from_iter(ret.iter().filter_map(|(&(ref name,ref subscript),val)| {
-        if let Some(sz) = sizes.get(name) {
-            Some((Lvalue::Variable{
-                name: name.clone(),
-                subscript: Some(*subscript),
-                size: *sz,
-            },val.clone()))
-        } else {
-            None
-        }
-    })))
+    constr
 }
 
 /// Given a function and an abstract interpretation result this functions returns that variable
@@ -587,6 +702,13 @@ impl Avalue for Kset {
                 }).collect::<Vec<_>>()),
         }
     }
+    fn concretize(&self) -> Option<Vec<u64>> {
+        match self {
+            &Kset::Join => None,
+            &Kset::Meet => Some(vec![]),
+            &Kset::Set(ref v) => Some(v.iter().map(|&(v,_)| v).collect()),
+        }
+    }
 }
 
 /// Mihaila et.al. Widening Point inferring cofibered domain. This domain is parameterized with a
@@ -674,6 +796,9 @@ impl<A: Avalue> Avalue for Widening<A> {
             point: self.point.clone(),
         }
     }
+    fn concretize(&self) -> Option<Vec<u64>> {
+        self.value.concretize()
+    }
 }
 
 #[cfg(test)]
@@ -693,6 +818,7 @@ mod tests {
         MutableGraphTrait,
     };
     use std::borrow::Cow;
+    use std::collections::HashMap;
 
     #[derive(Debug,Clone,PartialEq,Eq,Hash,RustcDecodable,RustcEncodable)]
     enum Sign {
@@ -1080,6 +1206,90 @@ mod tests {
         assert_eq!(res[&(Cow::Borrowed("x"),32)],Kset::Join);
     }
 
+    /*
+     * bb0:
+     *   SP = SP - 8
+     *   a = 1
+     *   t = SP + 4
+     *   store [t], a
+     *   store [0x100], a
+     *   a = 2
+     *   flag = ?
+     * bb1 (if flag):
+     *   store [0x100], a
+     * bb2:
+     *   x = load [t]
+     *   y = load [0x100]
+     *   z = load [SP]
+     */
+    #[test]
+    fn memory() {
+        let sp = Lvalue::Variable{ name: Cow::Borrowed("SP"), size: 32, subscript: None };
+        let a = Lvalue::Variable{ name: Cow::Borrowed("a"), size: 32, subscript: None };
+        let t = Lvalue::Variable{ name: Cow::Borrowed("t"), size: 32, subscript: None };
+        let x = Lvalue::Variable{ name: Cow::Borrowed("x"), size: 32, subscript: None };
+        let y = Lvalue::Variable{ name: Cow::Borrowed("y"), size: 32, subscript: None };
+        let z = Lvalue::Variable{ name: Cow::Borrowed("z"), size: 32, subscript: None };
+        let flag = Lvalue::Variable{ name: Cow::Borrowed("flag"), size: 1, subscript: None };
+        let ram = Cow::Borrowed("ram");
+        let bb0 = BasicBlock::from_vec(vec![
+            Mnemonic::new(0..1,"test".to_string(),"".to_string(),vec![].iter(),vec![
+                Statement{ op: Operation::Subtract(sp.clone().into(),Rvalue::new_u32(8)), assignee: sp.clone() },
+                Statement{ op: Operation::Move(Rvalue::new_u32(1)), assignee: a.clone() },
+                Statement{ op: Operation::Add(sp.clone().into(),Rvalue::new_u32(4)), assignee: t.clone() },
+                Statement{ op: Operation::Store(ram.clone(),t.clone().into()), assignee: a.clone() },
+                Statement{ op: Operation::Store(ram.clone(),Rvalue::new_u32(0x100)), assignee: a.clone() },
+                Statement{ op: Operation::Move(Rvalue::new_u32(2)), assignee: a.clone() },
+                Statement{ op: Operation::Move(Rvalue::Undefined), assignee: flag.clone() }].iter()).ok().unwrap()]);
+        let bb1 = BasicBlock::from_vec(vec![
+            Mnemonic::new(1..2,"test".to_string(),"".to_string(),vec![].iter(),vec![
+                Statement{ op: Operation::Store(ram.clone(),Rvalue::new_u32(0x100)), assignee: a.clone() }].iter()).ok().unwrap()]);
+        let bb2 = BasicBlock::from_vec(vec![
+            Mnemonic::new(2..3,"test".to_string(),"".to_string(),vec![].iter(),vec![
+                Statement{ op: Operation::Load(ram.clone(),t.clone().into()), assignee: x.clone() },
+                Statement{ op: Operation::Load(ram.clone(),Rvalue::new_u32(0x100)), assignee: y.clone() },
+                Statement{ op: Operation::Load(ram.clone(),sp.clone().into()), assignee: z.clone() }].iter()).ok().unwrap()]);
+        let mut cfg = ControlFlowGraph::new();
+        let v0 = cfg.add_vertex(ControlFlowTarget::Resolved(bb0));
+        let v1 = cfg.add_vertex(ControlFlowTarget::Resolved(bb1));
+        let v2 = cfg.add_vertex(ControlFlowTarget::Resolved(bb2));
+        let g = Guard::from_flag(&flag.into()).ok().unwrap();
+
+        cfg.add_edge(g.clone(),v0,v1);
+        cfg.add_edge(g.negation(),v0,v2);
+        cfg.add_edge(Guard::always(),v1,v2);
+
+        let mut func = Function::new("func".to_string(),"ram".to_string());
+
+        func.cflow_graph = cfg;
+        func.entry_point = Some(v0);
+
+        ssa_convertion(&mut func);
+
+        let vals = approximate_with::<Kset>(&func,&HashMap::new(),&HashMap::new(),Some(&sp)).ok().unwrap();
+        let get = |n: &str| vals.iter().filter(|&(lv,_)| match lv {
+            &Lvalue::Variable{ ref name,.. } => name == n,
+            _ => false,
+        }).map(|(_,v)| v.clone()).next();
+
+        // strong update of a stack slot
+        assert_eq!(get("x"),Some(Kset::Set(vec![(1,32)])));
+        // global written on both paths
+        assert_eq!(get("y"),Some(Kset::Set(vec![(1,32),(2,32)])));
+        // never written
+        assert_eq!(get("z"),Some(Kset::Join));
+
+        // without the stack pointer only globals are known
+        let vals = approximate::<Kset>(&func).ok().unwrap();
+        let get = |n: &str| vals.iter().filter(|&(lv,_)| match lv {
+            &Lvalue::Variable{ ref name,.. } => name == n,
+            _ => false,
+        }).map(|(_,v)| v.clone()).next();
+
+        assert_eq!(get("x"),Some(Kset::Join));
+        assert_eq!(get("y"),Some(Kset::Set(vec![(1,32),(2,32)])));
+    }
+
     #[test]
     fn bit_extract() {
         let p_var = Lvalue::Variable{ name: Cow::Borrowed("p"), size: 22, subscript: None };
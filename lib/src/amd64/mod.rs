@@ -43,6 +43,7 @@ use {
     Region,
     Mnemonic,
     Statement,
+    Operation,
     Guard,
 };
 
@@ -123,7 +124,10 @@ impl Architecture for Amd64 {
 
         info!("disass @ {:#x}: {:?}",p,buf);
 
-        let ret = ::amd64::read(*cfg,&buf,p).and_then(|(len,mne,mut jmp)| {
+        let ret = ::amd64::read(*cfg,&buf,p).map_err(|e| match e {
+            e@::result::Error::Decode{ .. } => e,
+            e => ::result::Error::decode(p,format!("{}",e)),
+        }).and_then(|(len,mne,mut jmp)| {
             Ok(Match::<Amd64> {
                 tokens: buf[0..len as usize].to_vec(),
                 mnemonics: vec![mne],
@@ -136,6 +140,10 @@ impl Architecture for Amd64 {
 
         ret
     }
+
+    fn stack_pointer(cfg: &Self::Configuration) -> Option<Lvalue> {
+        Some(semantic::stack_pointer(*cfg))
+    }
 }
 
 #[derive(PartialEq,Clone,Copy,Debug)]
@@ -2243,6 +2251,8 @@ pub fn read(mode: Mode, buf: &[u8], addr: u64) -> Result<(u64,Mnemonic,Vec<(Rval
                 let ip = addr + i as u64 + 1;
                 let mut stmts = vec![];
                 let mut ops = vec![];
+                let mut fmts = vec![];
+                let mut addrs = vec![];
 
                 for op in opc.operands().iter() {
                    let maybe_op = read_operand(op,&mut tail,mode,prefix.seg_override,prefix.vvvv,rex,
@@ -2250,9 +2260,11 @@ pub fn read(mode: Mode, buf: &[u8], addr: u64) -> Result<(u64,Mnemonic,Vec<(Rval
                                   and_then(|x| to_rreil(x));
 
                    match maybe_op {
-                       Ok((rv,mut st)) => {
+                       Ok((rv,fmt,addr,mut st)) => {
                            stmts.append(&mut st);
                            ops.push(rv);
+                           fmts.push(fmt);
+                           addrs.push(addr);
                        },
                        Err(e) => {
                            error!("error while decoding operands of '{}': {:?}",s,e);
@@ -2266,10 +2278,13 @@ pub fn read(mode: Mode, buf: &[u8], addr: u64) -> Result<(u64,Mnemonic,Vec<(Rval
                 //if prefix.repne { print!("repnz "); }
 
                 debug!("call {} with {:?}",s,ops);
-                let res = opc.call(&ops.get(0).cloned(),
-                                   &ops.get(1).cloned(),
-                                   &ops.get(2).cloned(),
-                                   &ops.get(3).cloned());
+                let res = match semantic::stack_operation(s,mode,prefix.operand_size,&ops) {
+                    Some(res) => res,
+                    None => opc.call(&ops.get(0).cloned(),
+                                     &ops.get(1).cloned(),
+                                     &ops.get(2).cloned(),
+                                     &ops.get(3).cloned()),
+                };
                 let (mut op_stmts,jmp_spec) = match res {
                     Ok(o) => o,
                     Err(e) => {
@@ -2277,17 +2292,31 @@ pub fn read(mode: Mode, buf: &[u8], addr: u64) -> Result<(u64,Mnemonic,Vec<(Rval
                         return Err(e);
                     }
                 };
+
+                // write memory operands modified by the instruction back
+                for (val,addr) in ops.iter().zip(addrs.iter()) {
+                    if let (&Rvalue::Variable{ ref name, size,.. },&Some(ref addr)) = (val,addr) {
+                        let written = op_stmts.iter().any(|st| match st {
+                            &Statement{ op: Operation::Store(..),.. } => false,
+                            &Statement{ assignee: Lvalue::Variable{ name: ref n,.. },.. } => n == name,
+                            _ => false,
+                        });
+
+                        if written {
+                            let val = Lvalue::Variable{ name: name.clone(), size: size, subscript: None };
+
+                            op_stmts.append(&mut try!(rreil!{
+                                store/ram (val), (addr);
+                            }));
+                        }
+                    }
+                }
                 stmts.append(&mut op_stmts);
 
+                // memory operands are displayed as pointers to their address
                 let len = tail.fd.position() + i as u64 + 1;
-                let mne = try!(match ops.len() {
-                    0 => Mnemonic::new((addr..addr+len),format!("{}",s),"".to_string(),ops.iter(),stmts.iter()),
-                    1 => Mnemonic::new((addr..addr+len),format!("{}",s),"{u}".to_string(),ops.iter(),stmts.iter()),
-                    2 => Mnemonic::new((addr..addr+len),format!("{}",s),"{u}, {u}".to_string(),ops.iter(),stmts.iter()),
-                    3 => Mnemonic::new((addr..addr+len),format!("{}",s),"{u}, {u}, {u}".to_string(),ops.iter(),stmts.iter()),
-                    4 => Mnemonic::new((addr..addr+len),format!("{}",s),"{u}, {u}, {u}, {u}".to_string(),ops.iter(),stmts.iter()),
-                    _ => unreachable!(),
-                });
+                let disp_ops = ops.iter().zip(addrs.iter()).map(|(v,a)| a.clone().unwrap_or(v.clone())).collect::<Vec<_>>();
+                let mne = try!(Mnemonic::new((addr..addr+len),format!("{}",s),fmts.join(", "),disp_ops.iter(),stmts.iter()));
                 let next = match jmp_spec {
                     JumpSpec::DeadEnd => vec![],
                     JumpSpec::FallThru => vec![(Rvalue::Constant{ value: addr + len, size: 64 },Guard::always())],
@@ -2308,59 +2337,85 @@ pub fn read(mode: Mode, buf: &[u8], addr: u64) -> Result<(u64,Mnemonic,Vec<(Rval
     }
 }
 
-fn to_rreil(op: Operand) -> Result<(Rvalue,Vec<Statement>)> {
+/// Lifts `op` to RREIL. Returns the value of the operand, the format string used to display it,
+/// the address of memory operands and the statements computing both. Memory operands are loaded
+/// from their address and displayed as pointers to it.
+fn to_rreil(op: Operand) -> Result<(Rvalue,String,Option<Rvalue>,Vec<Statement>)> {
     match op {
-        Operand::Register(ref name) => Ok((Rvalue::Variable{ name: format!("{}",name).into(), size: name.width(), offset: 0, subscript: None },vec![])),
-        Operand::Immediate(ref value,ref size) => Ok((Rvalue::Constant{ value: *value, size: *size },vec![])),
-        Operand::Indirect(ref seg,ref base,ref index,ref scale,ref disp,ref width) => {
+        Operand::Register(ref name) => Ok((Rvalue::Variable{ name: format!("{}",name).into(), size: name.width(), offset: 0, subscript: None },"{u}".to_string(),None,vec![])),
+        Operand::Immediate(ref value,ref size) => Ok((Rvalue::Constant{ value: *value, size: *size },"{u}".to_string(),None,vec![])),
+        Operand::Indirect(_,ref base,ref index,ref scale,ref disp,ref width) => {
+            // "DWORD PTR ss:[EBP-0x4]" is split into the prefix "DWORD PTR ss:" and the address
+            // "EBP-0x4", which names the variable holding the address.
+            let text = format!("{}",op);
+            let lb = text.find('[').unwrap_or(0);
+            let name = text[lb + 1..text.len() - 1].to_string();
+            let fmt = format!("{}[{{p:ram}}]",&text[..lb]);
+            let w = if *base != Register::None {
+                base.width()
+            } else if *scale > 0 && *index != Register::None {
+                index.width()
+            } else {
+                disp.1
+            };
             let mut stmts = vec![];
-            let mut ret = Rvalue::Undefined;
+            let mut terms = vec![];
 
             if *base != Register::None {
-                ret = Rvalue::Variable{ name: format!("{}",base).into(), size: base.width(), offset: 0, subscript: None };
+                terms.push(Rvalue::Variable{ name: format!("{}",base).into(), size: base.width(), offset: 0, subscript: None });
             }
 
             if *scale > 0 && *index != Register::None {
                 let s = *scale;
-                let w = index.width();
-                let rw = ret.size().unwrap_or(w);
-                let i = Lvalue::Variable{ name: format!("{}",index).into(), size: w, subscript: None };
-                if *base != Register::None {
-                    stmts = try!(rreil!{
-                        mul t:w, [s]:w, (i);
-                        zext/rw t1:rw, t:w;
-                        add (Lvalue::from_rvalue(ret.clone()).unwrap()), (ret), t1:rw;
-                    });
+                let iw = index.width();
+                let i = Rvalue::Variable{ name: format!("{}",index).into(), size: iw, offset: 0, subscript: None };
+
+                if s == 1 && iw == w {
+                    terms.push(i);
                 } else {
                     stmts = try!(rreil!{
-                        mul t:w, (i), [s]:w;
+                        mul t:iw, (i), [s]:iw;
                     });
-                    ret = Rvalue::Variable{ name: "t".into(), size: w, offset: 0, subscript: None };
+
+                    if iw != w {
+                        stmts.append(&mut try!(rreil!{
+                            zext/w t1:w, t:iw;
+                        }));
+                        terms.push(rreil_rvalue!{ t1:w });
+                    } else {
+                        terms.push(rreil_rvalue!{ t:w });
+                    }
                 }
             }
 
-            if disp.0 > 0 {
-                if ret != Rvalue::Undefined {
-                    let d = Rvalue::Constant{ value: disp.0, size: disp.1 };
-                    let w = ret.size().unwrap_or(disp.1);
+            if disp.0 != 0 || terms.is_empty() {
+                let d = if w < 64 { disp.0 % (1u64 << w) } else { disp.0 };
+                terms.push(Rvalue::Constant{ value: d, size: w });
+            }
+
+            let addr = if terms.len() == 1 {
+                terms[0].clone()
+            } else {
+                let a = Lvalue::Variable{ name: name.into(), size: w, subscript: None };
+                let mut acc = terms[0].clone();
+
+                for t in terms[1..].iter() {
                     stmts.append(&mut try!(rreil!{
-                        zext/w d:w, (d);
-                        add (Lvalue::from_rvalue(ret.clone()).unwrap()), (ret), d:w;
+                        add (a), (acc), (t);
                     }));
-                } else {
-                    ret = Rvalue::Constant{ value: disp.0, size: disp.1 };
+                    acc = a.clone().into();
                 }
-            }
 
-            let tgt = Lvalue::Variable{ name: format!("{}",op).into(), size: *width, subscript: None };
+                acc
+            };
+            let val = Lvalue::Variable{ name: text.clone().into(), size: *width, subscript: None };
 
             stmts.append(&mut try!(rreil!{
-                load/ram (tgt), (ret);
+                load/ram (val), (addr);
             }));
-            ret = tgt.into();
 
-            Ok((ret,stmts))
+            Ok((val.into(),fmt,Some(addr),stmts))
         },
-        Operand::Optional => Ok((Rvalue::Undefined,vec![]))
+        Operand::Optional => Ok((Rvalue::Undefined,"{u}".to_string(),None,vec![]))
     }
 }
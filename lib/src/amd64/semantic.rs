@@ -242,7 +242,7 @@ fn sign_extend(a: &Rvalue, b: &Rvalue) -> Result<(Rvalue,Rvalue,usize,Vec<Statem
     Ok((ext_a,ext_b,sz,stmts))
 }
 
-fn write_reg(_reg: &Rvalue, _: &Rvalue, sz: usize) -> Result<Vec<Statement>> {
+fn write_reg(_reg: &Rvalue, val: &Rvalue, sz: usize) -> Result<Vec<Statement>> {
     if let Some(ref reg) = Lvalue::from_rvalue(_reg.clone()) {
         if sz < 64 {
             if let &Lvalue::Variable{ ref name,.. } = reg {
@@ -253,13 +253,13 @@ fn write_reg(_reg: &Rvalue, _: &Rvalue, sz: usize) -> Result<Vec<Statement>> {
                    name == "R12" || name == "R13" || name == "R14" ||
                    name == "R15" {
                     return rreil!{
-                        zext/64 reg:64, res:sz;
+                        zext/64 (reg), (val);
                     };
                 }
             }
         }
         rreil!{
-            mov reg:sz, res:sz;
+            mov (reg), (val);
         }
     } else {
         unreachable!()
@@ -655,20 +655,20 @@ pub fn sbb(_: Rvalue, _: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> {
     set_arithm_flags(&res,&res_half.clone().into(),&a.clone().into(),cg);*/
 }
 
-pub fn sub(_: Rvalue, _: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> {
-    return Ok((vec![],JumpSpec::FallThru));
- /*   let aw = bitwidth(&_a);
-    let bw = if let Rvalue::Constant(_) = b { aw } else { bitwidth(&b) };
-    let res = new_temp(aw);
-    let res_half = new_temp(8);
-    let a = Lvalue::from_rvalue(&_a).unwrap();
-    let b_ext = if aw == bw { b.clone() } else { sign_ext(&b,bw,aw,cg) };
+pub fn sub(_a: Rvalue, _b: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> {
+    let (a,b,sz,mut stmts) = try!(sign_extend(&_a,&_b));
+    let res = rreil_lvalue!{ res:sz };
 
-    cg.sub_i(&res,&a,&b_ext);
-    cg.mod_i(&res_half,&res.clone().into(),&Rvalue::Constant(0x100));
+    stmts.append(&mut try!(rreil!{
+        sub res:sz, (a), (b);
+        cmpltu CF:1, (a), (b);
+        cmplts SF:1, res:sz, [0]:sz;
+        cmpeq ZF:1, res:sz, [0]:sz;
+    }));
+    stmts.append(&mut try!(set_parity_flag(&res)));
+    stmts.append(&mut try!(write_reg(&_a,&res.clone().into(),sz)));
 
-    cg.assign(&a,&res.clone().into());
-    set_arithm_flags(&res,&res_half.clone().into(),&a.clone().into(),cg);*/
+    Ok((stmts,JumpSpec::FallThru))
 }
 
 pub fn xor(_: Rvalue, _: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> {
@@ -905,7 +905,15 @@ pub fn loopne(_: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> {
     Ok((vec![],JumpSpec::FallThru))
 }
 
-pub fn mov(_: Rvalue, _: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> { Ok((vec![],JumpSpec::FallThru)) }
+pub fn mov(_a: Rvalue, b: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> {
+    let a = match Lvalue::from_rvalue(_a) {
+        Some(a) => a,
+        None => return Err("mov to a constant".into()),
+    };
+
+    Ok((try!(assign(&a,&b)),JumpSpec::FallThru))
+}
+
 pub fn movbe(_: Rvalue, _: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> { Ok((vec![],JumpSpec::FallThru)) }
 
 pub fn movsb() -> Result<(Vec<Statement>,JumpSpec)> {
@@ -1029,6 +1037,167 @@ pub fn retnf(_: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> {
     Ok((vec![],JumpSpec::DeadEnd))
 }
 
+/// Stack pointer register in `mode`.
+pub fn stack_pointer(mode: Mode) -> Lvalue {
+    let reg = match mode {
+        Mode::Real => Register::SP,
+        Mode::Protected => Register::ESP,
+        Mode::Long => Register::RSP,
+    };
+
+    Lvalue::Variable{ name: format!("{}",reg).into(), size: reg.width(), subscript: None }
+}
+
+fn frame_pointer(mode: Mode) -> Lvalue {
+    let reg = match mode {
+        Mode::Real => Register::BP,
+        Mode::Protected => Register::EBP,
+        Mode::Long => Register::RBP,
+    };
+
+    Lvalue::Variable{ name: format!("{}",reg).into(), size: reg.width(), subscript: None }
+}
+
+fn is_stack_pointer(a: &Rvalue, sp: &Lvalue) -> bool {
+    match (a,sp) {
+        (&Rvalue::Variable{ name: ref a,.. },&Lvalue::Variable{ name: ref b,.. }) => a == b,
+        _ => false,
+    }
+}
+
+/// dst := src, zero extends src if it's smaller than dst.
+fn assign(dst: &Lvalue, src: &Rvalue) -> Result<Vec<Statement>> {
+    let sz = dst.size().unwrap_or(0);
+
+    if src.size().map(|s| s < sz).unwrap_or(false) {
+        rreil!{
+            zext/sz (dst), (src);
+        }
+    } else {
+        rreil!{
+            mov (dst), (src);
+        }
+    }
+}
+
+fn push_value(sp: &Lvalue, opsz: usize, a: &Rvalue) -> Result<Vec<Statement>> {
+    let spsz = sp.size().unwrap_or(0);
+    let bytes = opsz / 8;
+    let mut stmts = vec![];
+    // registers are stored as-is, so spills of their entry value can be recognized
+    let val = match Lvalue::from_rvalue(a.clone()) {
+        Some(ref lv) if a.size() == Some(opsz) && !is_stack_pointer(a,sp) => lv.clone(),
+        _ => {
+            let tmp = rreil_lvalue!{ val:opsz };
+
+            stmts = try!(assign(&tmp,a));
+            tmp
+        }
+    };
+
+    stmts.append(&mut try!(rreil!{
+        sub (sp), (sp), [bytes]:spsz;
+        store/ram (val), (sp);
+    }));
+    Ok(stmts)
+}
+
+fn pop_value(sp: &Lvalue, opsz: usize, a: &Rvalue) -> Result<Vec<Statement>> {
+    let spsz = sp.size().unwrap_or(0);
+    let bytes = opsz / 8;
+    let dst = match Lvalue::from_rvalue(a.clone()) {
+        Some(lv) => lv,
+        None => return Err("pop to a constant".into()),
+    };
+    let mut stmts = if a.size() == Some(opsz) {
+        try!(rreil!{
+            load/ram (dst), (sp);
+        })
+    } else {
+        let tmp = rreil_lvalue!{ val:opsz };
+        let mut stmts = try!(rreil!{
+            load/ram (tmp), (sp);
+        });
+
+        stmts.append(&mut try!(assign(&dst,&tmp.into())));
+        stmts
+    };
+
+    // pop rSP overwrites the incremented stack pointer
+    if !is_stack_pointer(a,sp) {
+        stmts.append(&mut try!(rreil!{
+            add (sp), (sp), [bytes]:spsz;
+        }));
+    }
+
+    Ok(stmts)
+}
+
+/// Semantics of the mnemonics that move the stack pointer. The width of the stack pointer
+/// depends on `mode` instead of the operands, so `read` uses this function instead of the ones in
+/// the opcode tables. Returns None for all other mnemonics.
+///
+/// `call` and `ret` leave the stack pointer alone. The return address is at offset 0 of the
+/// callee's frame and stack arguments start above it. A function returning with `ret` has a
+/// stack delta of 0, one returning with `ret imm16` releases its arguments and has a delta of
+/// `imm16`.
+pub fn stack_operation(mnemonic: &str, mode: Mode, opsz: usize, ops: &[Rvalue]) -> Option<Result<(Vec<Statement>,JumpSpec)>> {
+    let sp = stack_pointer(mode);
+    let bp = frame_pointer(mode);
+    let spsz = sp.size().unwrap_or(0);
+    let bytes = opsz / 8;
+    let constant = |rv: &Rvalue| -> Result<u64> {
+        match rv {
+            &Rvalue::Constant{ value,.. } => Ok(value),
+            _ => Err("Expected an immediate operand".into()),
+        }
+    };
+
+    match (mnemonic,ops.get(0),ops.get(1)) {
+        ("push",Some(a),_) => Some(push_value(&sp,opsz,a).map(|s| (s,JumpSpec::FallThru))),
+        ("pop",Some(a),_) => Some(pop_value(&sp,opsz,a).map(|s| (s,JumpSpec::FallThru))),
+        ("pushfw",_,_) => {
+            let stmts = rreil!{
+                sub (sp), (sp), [bytes]:spsz;
+            };
+
+            Some(stmts.map(|s| (s,JumpSpec::FallThru)))
+        },
+        ("popfw",_,_) => {
+            let stmts = rreil!{
+                add (sp), (sp), [bytes]:spsz;
+            };
+
+            Some(stmts.map(|s| (s,JumpSpec::FallThru)))
+        },
+        ("enter",Some(sz),_) => Some(constant(sz).and_then(|sz| {
+            let mut stmts = try!(push_value(&sp,spsz,&bp.clone().into()));
+
+            stmts.append(&mut try!(rreil!{
+                mov (bp), (sp);
+                sub (sp), (sp), [sz]:spsz;
+            }));
+            Ok((stmts,JumpSpec::FallThru))
+        })),
+        ("leave",_,_) => {
+            let stmts = rreil!{
+                mov (sp), (bp);
+            };
+
+            Some(stmts.and_then(|mut stmts| {
+                stmts.append(&mut try!(pop_value(&sp,spsz,&bp.clone().into())));
+                Ok((stmts,JumpSpec::FallThru))
+            }))
+        },
+        ("retn",Some(sz),_) => Some(constant(sz).and_then(|sz| {
+            rreil!{
+                add (sp), (sp), [sz]:spsz;
+            }
+        }).map(|s| (s,JumpSpec::DeadEnd))),
+        _ => None,
+    }
+}
+
 pub fn ror(_: Rvalue, _: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> { Ok((vec![],JumpSpec::FallThru)) }
 pub fn rol(_: Rvalue, _: Rvalue) -> Result<(Vec<Statement>,JumpSpec)> { Ok((vec![],JumpSpec::FallThru)) }
 pub fn sahf() -> Result<(Vec<Statement>,JumpSpec)> { Ok((vec![],JumpSpec::FallThru)) }
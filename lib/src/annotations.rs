@@ -0,0 +1,560 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Portable database of function names, function boundaries and comments.
+//!
+//! Annotations are keyed by region name and address instead of UUIDs. This allows sharing them
+//! between projects, for example to re-apply them after analyzing a new build of the same
+//! binary.
+//!
+//! The file format is line based text. The first line is `# panopticon annotations 1`. Other
+//! lines starting with `#` and empty lines are ignored. All other lines are tab separated
+//! records, one of
+//!
+//! ```text
+//! function <region> <start> <end> <name>
+//! comment  <region> <offset> <text>
+//! ```
+//!
+//! Addresses are hexadecimal with a `0x` prefix. `<end>` is the first address after the last
+//! basic block of the function, or `-` if it's unknown. Tabs, newlines and backslashes inside
+//! names and comments are escaped as `\t`, `\n` and `\\`.
+
+use std::path::Path;
+use std::fs::File;
+use std::io::{Read,Write};
+use std::fmt;
+
+use graph_algos::{GraphTrait,VertexListGraphTrait,MutableGraphTrait};
+use uuid::Uuid;
+
+use project::Project;
+use program::CallTarget;
+use history::Edit;
+use function::{Function,ControlFlowTarget};
+use il::Rvalue;
+use result::Result;
+
+/// First line of every annotation file.
+pub const ANNOTATIONS_HEADER: &'static str = "# panopticon annotations 1";
+
+/// Name and extent of a function.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct FunctionAnnotation {
+    /// Region the function is located in
+    pub region: String,
+    /// Entry point
+    pub start: u64,
+    /// First address after the last basic block, if known
+    pub end: Option<u64>,
+    /// Function name
+    pub name: String,
+}
+
+/// Comment at an address.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct CommentAnnotation {
+    /// Region the comment is attached to
+    pub region: String,
+    /// Offset inside the region
+    pub offset: u64,
+    /// Comment text
+    pub text: String,
+}
+
+/// Set of annotations, sorted by region and address.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Annotations {
+    /// Function names and boundaries
+    pub functions: Vec<FunctionAnnotation>,
+    /// Comments
+    pub comments: Vec<CommentAnnotation>,
+}
+
+/// How conflicts between existing and imported annotations are resolved.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Merge {
+    /// Keep the existing name or comment.
+    Keep,
+    /// Replace the existing name or comment with the imported one.
+    Replace,
+    /// Keep the existing name. Append the imported comment to the existing one.
+    Append,
+}
+
+/// Name or comment that differed between the project and the imported annotations.
+#[derive(Clone,Debug,PartialEq,Eq,RustcEncodable)]
+pub struct Conflict {
+    /// Region name
+    pub region: String,
+    /// Address of the function or comment
+    pub address: u64,
+    /// Value in the project before the import
+    pub existing: String,
+    /// Imported value
+    pub imported: String,
+}
+
+/// Result of `Annotations::apply`.
+#[derive(Clone,Debug,PartialEq,Eq,Default,RustcEncodable)]
+pub struct Report {
+    /// Number of renamed functions
+    pub renamed: usize,
+    /// Number of new or changed comments
+    pub comments: usize,
+    /// Number of new function entry points. These are disassembled with the next pass.
+    pub entry_points: usize,
+    /// Conflicting function names
+    pub name_conflicts: Vec<Conflict>,
+    /// Conflicting comments
+    pub comment_conflicts: Vec<Conflict>,
+}
+
+/// True if `name` was generated by the disassembler (`func_` followed by a number). These names
+/// never conflict with imported ones.
+pub fn is_default_name(name: &str) -> bool {
+    name.starts_with("func_") && name.len() > 5 && name[5..].chars().all(|c| c.is_digit(16))
+}
+
+fn escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '\\' => ret.push_str("\\\\"),
+            '\t' => ret.push_str("\\t"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            c => ret.push(c),
+        }
+    }
+
+    ret
+}
+
+fn unescape(s: &str, line: usize) -> Result<String> {
+    let mut ret = String::with_capacity(s.len());
+    let mut i = s.chars();
+
+    while let Some(c) = i.next() {
+        if c == '\\' {
+            match i.next() {
+                Some('\\') => ret.push('\\'),
+                Some('t') => ret.push('\t'),
+                Some('n') => ret.push('\n'),
+                Some('r') => ret.push('\r'),
+                _ => return Err(format!("Invalid escape sequence in line {}",line).into()),
+            }
+        } else {
+            ret.push(c);
+        }
+    }
+
+    Ok(ret)
+}
+
+fn parse_address(s: &str, line: usize) -> Result<u64> {
+    if s.starts_with("0x") {
+        u64::from_str_radix(&s[2..],16).map_err(|_| format!("Invalid address '{}' in line {}",s,line).into())
+    } else {
+        Err(format!("Address '{}' in line {} is missing the 0x prefix",s,line).into())
+    }
+}
+
+/// Returns the entry point and the end of the last basic block of `f`.
+fn boundaries(f: &Function) -> Option<(u64,Option<u64>)> {
+    let start = f.entry_point.and_then(|e| match f.cflow_graph.vertex_label(e) {
+        Some(&ControlFlowTarget::Resolved(ref bb)) => Some(bb.area.start),
+        _ => None,
+    });
+    let end = f.cflow_graph.vertices().filter_map(|vx| match f.cflow_graph.vertex_label(vx) {
+        Some(&ControlFlowTarget::Resolved(ref bb)) => Some(bb.area.end),
+        _ => None,
+    }).max();
+
+    start.map(|s| (s,end))
+}
+
+impl Annotations {
+    /// Returns an empty set.
+    pub fn new() -> Annotations {
+        Annotations{ functions: vec![], comments: vec![] }
+    }
+
+    /// Collects the names and boundaries of all disassembled functions and all comments of
+    /// `proj`.
+    pub fn from_project(proj: &Project) -> Annotations {
+        let mut ret = Annotations::new();
+
+        for prog in proj.code.iter() {
+            for vx in prog.call_graph.vertices() {
+                if let Some(&CallTarget::Concrete(ref f)) = prog.call_graph.vertex_label(vx) {
+                    if let Some((start,end)) = boundaries(f) {
+                        ret.functions.push(FunctionAnnotation{
+                            region: f.region.clone(),
+                            start: start,
+                            end: end,
+                            name: f.name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        for (&(ref reg,off),txt) in proj.comments.iter() {
+            ret.comments.push(CommentAnnotation{ region: reg.clone(), offset: off, text: txt.clone() });
+        }
+
+        ret.sort();
+        ret
+    }
+
+    fn sort(&mut self) {
+        self.functions.sort_by(|a,b| (&a.region,a.start,&a.name).cmp(&(&b.region,b.start,&b.name)));
+        self.comments.sort_by(|a,b| (&a.region,a.offset).cmp(&(&b.region,b.offset)));
+    }
+
+    /// Parses the text representation of an annotation database.
+    pub fn parse(s: &str) -> Result<Annotations> {
+        let mut ret = Annotations::new();
+        let mut lines = s.lines().enumerate();
+
+        match lines.next() {
+            Some((_,l)) if l.trim_right() == ANNOTATIONS_HEADER => {},
+            _ => return Err("Not an annotation file".into()),
+        }
+
+        for (idx,l) in lines {
+            let line = idx + 1;
+            let l = l.trim_right_matches('\r');
+
+            if l.is_empty() || l.starts_with('#') {
+                continue;
+            }
+
+            let fields = l.split('\t').collect::<Vec<_>>();
+
+            match (fields[0],fields.len()) {
+                ("function",5) => ret.functions.push(FunctionAnnotation{
+                    region: try!(unescape(fields[1],line)),
+                    start: try!(parse_address(fields[2],line)),
+                    end: if fields[3] == "-" { None } else { Some(try!(parse_address(fields[3],line))) },
+                    name: try!(unescape(fields[4],line)),
+                }),
+                ("comment",4) => ret.comments.push(CommentAnnotation{
+                    region: try!(unescape(fields[1],line)),
+                    offset: try!(parse_address(fields[2],line)),
+                    text: try!(unescape(fields[3],line)),
+                }),
+                ("function",_) | ("comment",_) => return Err(format!("Wrong number of fields in line {}",line).into()),
+                (t,_) => return Err(format!("Unknown record '{}' in line {}",t,line).into()),
+            }
+        }
+
+        ret.sort();
+        Ok(ret)
+    }
+
+    /// Reads an annotation database from the file at `p`.
+    pub fn read(p: &Path) -> Result<Annotations> {
+        let mut fd = try!(File::open(p));
+        let mut s = String::new();
+
+        try!(fd.read_to_string(&mut s));
+        Annotations::parse(&s)
+    }
+
+    /// Writes the annotations into the file at `p`.
+    pub fn write(&self, p: &Path) -> Result<()> {
+        let mut fd = try!(File::create(p));
+
+        try!(fd.write_all(self.to_string().as_bytes()));
+        Ok(())
+    }
+
+    /// Applies the annotations to `proj`. Functions are matched by region and entry point. If no
+    /// function starts at an annotated address a named `CallTarget::Todo` is added to the first
+    /// program, so the next disassembly pass creates it. Existing names and comments that differ
+    /// from the imported ones are resolved using `rule` and listed in the returned `Report`.
+    /// Names generated by the disassembler are always replaced. Renames and comments are
+    /// recorded in the project's `History` and can be undone.
+    ///
+    /// Annotations for regions missing from `proj` are applied to its root region instead. This
+    /// allows importing annotations taken from a file with a different name.
+    pub fn apply(&self, proj: &mut Project, rule: Merge) -> Result<Report> {
+        let mut report = Report::default();
+        let root = try!(proj.data.dependencies.vertex_label(proj.data.root).ok_or("Project has no root region")).name().clone();
+        let regions = proj.data.dependencies.vertices()
+            .filter_map(|vx| proj.data.dependencies.vertex_label(vx).map(|r| r.name().clone()))
+            .collect::<Vec<_>>();
+        let region = |r: &String| if regions.contains(r) { r.clone() } else { root.clone() };
+        let mut renames = vec![];
+
+        for ann in self.functions.iter() {
+            let reg = region(&ann.region);
+            let mut found = false;
+
+            for prog in proj.code.iter() {
+                let vxs = prog.call_graph.vertices().collect::<Vec<_>>();
+
+                for vx in vxs {
+                    if let Some(&CallTarget::Concrete(ref f)) = prog.call_graph.vertex_label(vx) {
+                        if f.region != reg || boundaries(f).map(|x| x.0) != Some(ann.start) {
+                            continue;
+                        }
+
+                        found = true;
+
+                        if f.name == ann.name || is_default_name(&ann.name) {
+                            continue;
+                        }
+
+                        if !is_default_name(&f.name) {
+                            report.name_conflicts.push(Conflict{
+                                region: reg.clone(),
+                                address: ann.start,
+                                existing: f.name.clone(),
+                                imported: ann.name.clone(),
+                            });
+
+                            if rule != Merge::Replace {
+                                continue;
+                            }
+                        }
+
+                        renames.push((f.uuid,ann.name.clone()));
+                    }
+                }
+            }
+
+            if !found && reg == root {
+                let prog = try!(proj.code.first_mut().ok_or("Project has no program"));
+                let name = if is_default_name(&ann.name) { None } else { Some(ann.name.clone()) };
+                let todo = prog.call_graph.vertices().find(|&vx| match prog.call_graph.vertex_label(vx) {
+                    Some(&CallTarget::Todo(Rvalue::Constant{ value,.. },_,_)) => value == ann.start,
+                    _ => false,
+                });
+
+                match todo {
+                    Some(vx) => {
+                        if let Some(&mut CallTarget::Todo(_,ref mut n,_)) = prog.call_graph.vertex_label_mut(vx) {
+                            if n.is_none() || rule == Merge::Replace {
+                                *n = name.or(n.clone());
+                            }
+                        }
+                    },
+                    None => {
+                        prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(ann.start),name,Uuid::new_v4()));
+                        report.entry_points += 1;
+                    }
+                }
+            }
+        }
+
+        for (uu,name) in renames {
+            try!(proj.edit(Edit::Rename{ function: uu, name: name }));
+            report.renamed += 1;
+        }
+
+        for ann in self.comments.iter() {
+            let key = (region(&ann.region),ann.offset);
+            let new = match proj.comments.get(&key) {
+                None => Some(ann.text.clone()),
+                Some(old) if *old == ann.text || (rule == Merge::Append && old.contains(&ann.text)) => None,
+                Some(old) => {
+                    report.comment_conflicts.push(Conflict{
+                        region: key.0.clone(),
+                        address: key.1,
+                        existing: old.clone(),
+                        imported: ann.text.clone(),
+                    });
+
+                    match rule {
+                        Merge::Keep => None,
+                        Merge::Replace => Some(ann.text.clone()),
+                        Merge::Append => Some(format!("{}\n{}",old,ann.text)),
+                    }
+                }
+            };
+
+            if let Some(txt) = new {
+                try!(proj.edit(Edit::Comment{ region: key.0, offset: key.1, comment: Some(txt) }));
+                report.comments += 1;
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+impl fmt::Display for Annotations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f,"{}",ANNOTATIONS_HEADER));
+
+        for func in self.functions.iter() {
+            let end = func.end.map(|x| format!("{:#x}",x)).unwrap_or("-".to_string());
+
+            try!(writeln!(f,"function\t{}\t{:#x}\t{}\t{}",escape(&func.region),func.start,end,escape(&func.name)));
+        }
+
+        for cmnt in self.comments.iter() {
+            try!(writeln!(f,"comment\t{}\t{:#x}\t{}",escape(&cmnt.region),cmnt.offset,escape(&cmnt.text)));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_algos::{GraphTrait,MutableGraphTrait,VertexListGraphTrait};
+    use {
+        Project,
+        Program,
+        CallTarget,
+        Function,
+        ControlFlowTarget,
+        BasicBlock,
+        Mnemonic,
+        Region,
+    };
+
+    fn project(name: &str) -> Project {
+        let mut proj = Project::new("test".to_string(),Region::undefined("base".to_string(),0x100));
+        let mut prog = Program::new("prog0");
+        let mut func = Function::new(name.to_string(),"base".to_string());
+        let bb = BasicBlock::from_vec(vec![Mnemonic::new(0x10..0x14,"nop".to_string(),"".to_string(),vec![].iter(),vec![].iter()).unwrap()]);
+        let vx = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(bb));
+
+        func.entry_point = Some(vx);
+        prog.call_graph.add_vertex(CallTarget::Concrete(func));
+        proj.code.push(prog);
+        proj
+    }
+
+    fn names(proj: &Project) -> Vec<String> {
+        let prog = &proj.code[0];
+
+        prog.call_graph.vertices().filter_map(|vx| match prog.call_graph.vertex_label(vx) {
+            Some(&CallTarget::Concrete(ref f)) => Some(f.name.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn default_names() {
+        assert!(is_default_name("func_1a2b"));
+        assert!(is_default_name("func_16"));
+        assert!(!is_default_name("func_"));
+        assert!(!is_default_name("func_main"));
+        assert!(!is_default_name("main"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut proj = project("main");
+
+        proj.comments.insert(("base".to_string(),0x12),"tab\there\nnew line \\".to_string());
+
+        let ann = Annotations::from_project(&proj);
+        let s = ann.to_string();
+
+        assert_eq!(ann.functions,vec![FunctionAnnotation{ region: "base".to_string(), start: 0x10, end: Some(0x14), name: "main".to_string() }]);
+        assert!(s.starts_with(ANNOTATIONS_HEADER));
+        assert!(s.contains("function\tbase\t0x10\t0x14\tmain\n"));
+        assert_eq!(s.lines().count(),3);
+        assert_eq!(Annotations::parse(&s).unwrap(),ann);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Annotations::parse("function\tbase\t0x10\t-\tmain\n").is_err());
+        assert!(Annotations::parse("# panopticon annotations 1\nfunction\tbase\t0x10\tmain\n").is_err());
+        assert!(Annotations::parse("# panopticon annotations 1\nfunction\tbase\t16\t-\tmain\n").is_err());
+        assert!(Annotations::parse("# panopticon annotations 1\nlabel\tbase\t0x10\tmain\n").is_err());
+        assert!(Annotations::parse("# panopticon annotations 1\ncomment\tbase\t0x10\ta\\qb\n").is_err());
+
+        let ann = Annotations::parse("# panopticon annotations 1\n\n# note\nfunction\tbase\t0x10\t-\tmain\n").unwrap();
+
+        assert_eq!(ann.functions[0].end,None);
+    }
+
+    #[test]
+    fn merge_names() {
+        let ann = Annotations::from_project(&project("init"));
+
+        let mut proj = project("func_10");
+        let rep = ann.apply(&mut proj,Merge::Keep).unwrap();
+
+        assert_eq!(names(&proj),vec!["init".to_string()]);
+        assert_eq!(rep.renamed,1);
+        assert!(rep.name_conflicts.is_empty());
+
+        let mut proj = project("main");
+        let rep = ann.apply(&mut proj,Merge::Keep).unwrap();
+
+        assert_eq!(names(&proj),vec!["main".to_string()]);
+        assert_eq!(rep.name_conflicts.len(),1);
+
+        let rep = ann.apply(&mut proj,Merge::Replace).unwrap();
+
+        assert_eq!(names(&proj),vec!["init".to_string()]);
+        assert_eq!(rep.renamed,1);
+    }
+
+    #[test]
+    fn merge_comments() {
+        let mut ann = Annotations::new();
+
+        ann.comments.push(CommentAnnotation{ region: "base".to_string(), offset: 1, text: "theirs".to_string() });
+        ann.comments.push(CommentAnnotation{ region: "other".to_string(), offset: 2, text: "moved".to_string() });
+
+        for &(rule,res) in [(Merge::Keep,"ours"),(Merge::Replace,"theirs"),(Merge::Append,"ours\ntheirs")].iter() {
+            let mut proj = project("main");
+
+            proj.comments.insert(("base".to_string(),1),"ours".to_string());
+
+            let rep = ann.apply(&mut proj,rule).unwrap();
+
+            assert_eq!(proj.comments.get(&("base".to_string(),1)).map(|x| x.as_str()),Some(res));
+            assert_eq!(proj.comments.get(&("base".to_string(),2)).map(|x| x.as_str()),Some("moved"));
+            assert_eq!(rep.comment_conflicts.len(),1);
+
+            // applying twice changes nothing
+            let rep = ann.apply(&mut proj,rule).unwrap();
+
+            assert_eq!(proj.comments.get(&("base".to_string(),1)).map(|x| x.as_str()),Some(res));
+            assert_eq!(rep.comments,0);
+        }
+    }
+
+    #[test]
+    fn new_entry_points() {
+        let mut ann = Annotations::new();
+        let mut proj = project("main");
+
+        ann.functions.push(FunctionAnnotation{ region: "base".to_string(), start: 0x40, end: None, name: "helper".to_string() });
+
+        let rep = ann.apply(&mut proj,Merge::Keep).unwrap();
+
+        assert_eq!(rep.entry_points,1);
+        assert_eq!(proj.code[0].next_todo().map(|x| (x.0,x.1)),Some((0x40,Some("helper".to_string()))));
+        assert_eq!(ann.apply(&mut proj,Merge::Keep).unwrap().entry_points,0);
+    }
+}
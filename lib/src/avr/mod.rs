@@ -61,7 +61,7 @@ impl Architecture for Avr {
             info!("    res: {:?}",st);
             Ok(st.into())
         } else {
-            Err("Unrecognized instruction".into())
+            Err(::result::Error::decode(addr,"Unrecognized instruction"))
         }
    }
 }
@@ -91,8 +91,16 @@ pub fn liveness_sets(func: &Function) ->  (HashMap<ControlFlowRef,HashSet<Cow<'s
                             }
                         }
 
-                        if let &Lvalue::Variable{ ref name,.. } = assignee {
-                            vk.insert(name.clone());
+                        // the assignee of a store is the value written to memory
+                        match (op,assignee) {
+                            (&Operation::Store(..),&Lvalue::Variable{ ref name,.. }) =>
+                                if !vk.contains(name) {
+                                    uev.insert(name);
+                                },
+                            (_,&Lvalue::Variable{ ref name,.. }) => {
+                                vk.insert(name.clone());
+                            },
+                            _ => {},
                         }
                     }
                 }
@@ -303,7 +311,8 @@ pub fn phi_functions(func: &mut Function) {
 
 /// Sets the SSA subscripts of all variables in `func`. Follows the algorithm outlined
 /// Cooper, Torczon: "Engineering a Compiler". The function expects that Phi functions to be
-/// already inserted.
+/// already inserted. The assignee of a `Store` operation is the value written to memory. It is
+/// read, not written and gets the subscript of its reaching definition.
 pub fn rename_variables(func: &mut Function) {
     let (globals,_) = global_names(func);
     let mut cfg = &mut func.cflow_graph;
@@ -346,8 +355,12 @@ pub fn rename_variables(func: &mut Function) {
                                 }
                             }
 
-                            if let &mut Lvalue::Variable{ ref name, ref mut subscript,.. } = assignee {
-                                *subscript = Some(new_name(name,counter,stack));
+                            match (op,assignee) {
+                                (&mut Operation::Store(..),&mut Lvalue::Variable{ ref name, ref mut subscript,.. }) =>
+                                    *subscript = stack.get(name).and_then(|x| x.last()).cloned(),
+                                (_,&mut Lvalue::Variable{ ref name, ref mut subscript,.. }) =>
+                                    *subscript = Some(new_name(name,counter,stack)),
+                                _ => {},
                             }
                         }
                     }
@@ -393,6 +406,7 @@ pub fn rename_variables(func: &mut Function) {
 
         if let Some(&mut ControlFlowTarget::Resolved(ref mut bb)) = cfg.vertex_label_mut(b) {
             bb.execute(|i| match i {
+                &Statement{ op: Operation::Store(..),.. } => {},
                 &Statement{ assignee: Lvalue::Variable{ ref name,.. },.. } => {
                     stack.get_mut(name).map(|x| x.pop());
                 },
@@ -410,6 +424,52 @@ pub fn ssa_convertion(func: &mut Function) {
     rename_variables(func);
 }
 
+/// Removes all Phi functions, the `__init` mnemonic and SSA subscripts from `func`, undoing
+/// `ssa_convertion`. Also removes the `__call` mnemonics inserted by
+/// `interproc::approximate_program`.
+pub fn ssa_deconstruction(func: &mut Function) {
+    fn clear(v: &mut Rvalue) {
+        if let &mut Rvalue::Variable{ ref mut subscript,.. } = v {
+            *subscript = None;
+        }
+    }
+
+    let vxs = func.cflow_graph.vertices().collect::<Vec<_>>();
+    let es = func.cflow_graph.edges().collect::<Vec<_>>();
+
+    for vx in vxs {
+        match func.cflow_graph.vertex_label_mut(vx) {
+            Some(&mut ControlFlowTarget::Resolved(ref mut bb)) => {
+                bb.mnemonics.retain(|mne| mne.opcode != "__phi" && mne.opcode != "__init" && mne.opcode != "__call");
+
+                for mne in bb.mnemonics.iter_mut() {
+                    for o in mne.operands.iter_mut() {
+                        clear(o);
+                    }
+
+                    for i in mne.instructions.iter_mut() {
+                        for o in i.op.operands_mut() {
+                            clear(o);
+                        }
+
+                        if let Lvalue::Variable{ ref mut subscript,.. } = i.assignee {
+                            *subscript = None;
+                        }
+                    }
+                }
+            },
+            Some(&mut ControlFlowTarget::Unresolved(ref mut v)) => clear(v),
+            _ => {},
+        }
+    }
+
+    for e in es {
+        if let Some(&mut Guard::Predicate{ ref mut flag,.. }) = func.cflow_graph.edge_label_mut(e) {
+            clear(flag);
+        }
+    }
+}
+
 /// Computes for every control flow guard the dependend RREIL operation via reverse data flow
 /// analysis.
 pub fn flag_operations(func: &Function) -> HashMap<ControlFlowEdge,Operation<Rvalue>> {
@@ -453,6 +513,7 @@ mod tests {
     use graph_algos::{
         GraphTrait,
         VertexListGraphTrait,
+        EdgeListGraphTrait,
         MutableGraphTrait,
     };
     use {
@@ -874,4 +935,101 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn deconstruction() {
+        let i = Lvalue::Variable{ name: Cow::Borrowed("i"), size: 32, subscript: None };
+        let f = Lvalue::Variable{ name: Cow::Borrowed("f"), size: 1, subscript: None };
+        let mne0 = Mnemonic::new(0..1,"b0".to_string(),"".to_string(),vec![].iter(),vec![
+                                 Statement{ op: Operation::Move(Rvalue::new_u32(0)), assignee: i.clone() }].iter()).ok().unwrap();
+        let mne10 = Mnemonic::new(1..2,"b1.0".to_string(),"".to_string(),vec![].iter(),vec![
+                                  Statement{ op: Operation::Add(i.clone().into(),Rvalue::new_u32(1)), assignee: i.clone() }].iter()).ok().unwrap();
+        let mne11 = Mnemonic::new(2..3,"b1.1".to_string(),"".to_string(),vec![].iter(),vec![
+                                  Statement{ op: Operation::LessUnsigned(i.clone().into(),Rvalue::new_u32(10)), assignee: f.clone() }].iter()).ok().unwrap();
+        let mne2 = Mnemonic::new(3..4,"b2".to_string(),"".to_string(),vec![].iter(),vec![].iter()).ok().unwrap();
+        let mut cfg = ControlFlowGraph::new();
+        let v0 = cfg.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne0])));
+        let v1 = cfg.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne10,mne11])));
+        let v2 = cfg.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne2])));
+        let g = Guard::from_flag(&f.clone().into()).ok().unwrap();
+
+        cfg.add_edge(Guard::always(),v0,v1);
+        cfg.add_edge(g.clone(),v1,v1);
+        cfg.add_edge(g.negation(),v1,v2);
+
+        let mut func = Function::new("test".to_string(),"ram".to_string());
+        let phis = |func: &Function| {
+            func.cflow_graph.vertices().fold(0,|acc,v| match func.cflow_graph.vertex_label(v) {
+                Some(&ControlFlowTarget::Resolved(ref bb)) => acc + bb.mnemonics.iter().filter(|m| m.opcode == "__phi").count(),
+                _ => acc,
+            })
+        };
+
+        func.cflow_graph = cfg;
+        func.entry_point = Some(v0);
+
+        ssa_convertion(&mut func);
+        let num_phis = phis(&func);
+        assert!(num_phis > 0);
+
+        ssa_deconstruction(&mut func);
+        assert_eq!(phis(&func),0);
+
+        let num_mnes = func.cflow_graph.vertices().fold(0,|acc,v| match func.cflow_graph.vertex_label(v) {
+            Some(&ControlFlowTarget::Resolved(ref bb)) => acc + bb.mnemonics.len(),
+            _ => acc,
+        });
+        assert_eq!(num_mnes,4);
+
+        for v in func.cflow_graph.vertices() {
+            if let Some(&ControlFlowTarget::Resolved(ref bb)) = func.cflow_graph.vertex_label(v) {
+                bb.execute(|i| {
+                    if let Lvalue::Variable{ subscript,.. } = i.assignee {
+                        assert!(subscript.is_none());
+                    }
+
+                    for op in i.op.operands() {
+                        if let &Rvalue::Variable{ subscript,.. } = op {
+                            assert!(subscript.is_none());
+                        }
+                    }
+                });
+            }
+        }
+
+        for e in func.cflow_graph.edges() {
+            if let Some(&Guard::Predicate{ flag: Rvalue::Variable{ subscript,.. },.. }) = func.cflow_graph.edge_label(e) {
+                assert!(subscript.is_none());
+            }
+        }
+
+        // converting again yields the same number of Phi functions
+        ssa_convertion(&mut func);
+        assert_eq!(phis(&func),num_phis);
+    }
+
+    #[test]
+    fn store_assignee() {
+        let a = Lvalue::Variable{ name: Cow::Borrowed("a"), size: 32, subscript: None };
+        let mne0 = Mnemonic::new(0..1,"b0".to_string(),"".to_string(),vec![].iter(),vec![
+                                 Statement{ op: Operation::Move(Rvalue::new_u32(1)), assignee: a.clone() },
+                                 Statement{ op: Operation::Store(Cow::Borrowed("ram"),Rvalue::new_u32(0x100)), assignee: a.clone() },
+                                 Statement{ op: Operation::Add(a.clone().into(),Rvalue::new_u32(1)), assignee: a.clone() }].iter()).ok().unwrap();
+        let mut func = Function::new("test".to_string(),"ram".to_string());
+        let v0 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne0])));
+
+        func.entry_point = Some(v0);
+        ssa_convertion(&mut func);
+
+        if let Some(&ControlFlowTarget::Resolved(ref bb)) = func.cflow_graph.vertex_label(v0) {
+            let stmts = bb.mnemonics.iter().filter(|m| m.opcode == "b0").flat_map(|m| m.instructions.iter()).collect::<Vec<_>>();
+            let sub = |lv: &Lvalue| if let &Lvalue::Variable{ subscript,.. } = lv { subscript } else { None };
+
+            assert_eq!(sub(&stmts[1].assignee),sub(&stmts[0].assignee));
+            assert!(sub(&stmts[2].assignee) != sub(&stmts[0].assignee));
+            assert_eq!(stmts[2].op.operands()[0],&Rvalue::from(stmts[0].assignee.clone()));
+        } else {
+            unreachable!()
+        }
+    }
 }
@@ -106,6 +106,7 @@ use graph_algos::adjacency_list::{
 
 use {
     Rvalue,
+    Lvalue,
     Mnemonic,
     Guard,
     Region,
@@ -144,6 +145,13 @@ pub trait Architecture: Clone
 
     /// Start to disassemble a single Opcode inside a given region at a given address.
     fn decode(&Region,u64,&Self::Configuration) -> Result<Match<Self>>;
+
+    /// Register used as stack pointer in the RREIL code of the given configuration. Used to
+    /// recover stack frames. None if the architecture has no stack pointer the analysis can
+    /// follow.
+    fn stack_pointer(&Self::Configuration) -> Option<Lvalue> {
+        None
+    }
 }
 
 /// Result of a single disassembly operation.
@@ -0,0 +1,101 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Loader for DOS .com files.
+//!
+//! A .com file is a flat memory image without any header. DOS copies it into a single 64KB
+//! segment right after the 256 byte Program Segment Prefix and starts executing at offset
+//! 0x100 in real mode.
+
+use std::path::Path;
+
+use graph_algos::MutableGraphTrait;
+use uuid::Uuid;
+
+use project::Project;
+use program::{Program,CallTarget};
+use region::Region;
+use mnemonic::Bound;
+use layer::{Layer,OpaqueLayer};
+use il::Rvalue;
+use result::{Result,Error};
+
+/// Offset of the first instruction inside the segment.
+pub const COM_ENTRY: u64 = 0x100;
+/// Size of the segment the file is loaded into.
+pub const COM_SEGMENT_SIZE: u64 = 0x1_0000;
+
+/// Loads a DOS .com file from disk and creates a project from it.
+///
+/// The file is mapped at `COM_ENTRY` into a 64KB `Region` named "ram". The entry point is added
+/// to the call graph as `CallTarget::Todo`. The code is meant to be disassembled with
+/// `amd64::Mode::Real`.
+pub fn load_com(p: &Path) -> Result<Project> {
+    let name = p.file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or("(encoding error)".to_string());
+    let b = try!(OpaqueLayer::open(p));
+    let len = b.iter().len();
+
+    if len == 0 {
+        return Err(Error::format("Empty .com file"));
+    }
+
+    if len > COM_SEGMENT_SIZE - COM_ENTRY {
+        return Err(Error::format(format!("File too large for a .com file: {} bytes",len)));
+    }
+
+    let mut reg = Region::undefined("ram".to_string(),COM_SEGMENT_SIZE);
+
+    if !reg.cover(Bound::new(COM_ENTRY,COM_ENTRY + len),Layer::Opaque(b)) {
+        return Err(Error::format("Failed to map .com file"));
+    }
+
+    let mut prog = Program::new("prog0");
+    let mut proj = Project::new(name.clone(),reg);
+
+    prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(COM_ENTRY),Some(name),Uuid::new_v4()));
+    proj.comments.insert(("ram".to_string(),COM_ENTRY),"Entry point".to_string());
+    proj.code.push(prog);
+
+    Ok(proj)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use graph_algos::{GraphTrait,VertexListGraphTrait};
+    use {CallTarget,Rvalue};
+
+    #[test]
+    fn load() {
+        let proj = load_com(Path::new("tests/data/dos.com")).unwrap();
+        let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+        let prog = &proj.code[0];
+
+        assert_eq!(root.size(),COM_SEGMENT_SIZE);
+        assert_eq!(root.iter().seek(COM_ENTRY).next(),Some(Some(0xb9)));
+        assert_eq!(root.iter().seek(0xff).next(),Some(None));
+        assert_eq!(prog.call_graph.num_vertices(),1);
+        assert!(prog.call_graph.vertices().all(|vx| match prog.call_graph.vertex_label(vx) {
+            Some(&CallTarget::Todo(Rvalue::Constant{ value: COM_ENTRY,.. },_,_)) => true,
+            _ => false,
+        }));
+    }
+}
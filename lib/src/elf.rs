@@ -21,25 +21,29 @@
 use std::io::{Seek,SeekFrom,Read};
 use std::fs::File;
 use std::path::Path;
+use std::collections::{BTreeMap,HashSet};
 
 use graph_algos::MutableGraphTrait;
 use uuid::Uuid;
 use goblin;
-use goblin::elf::{program_header,Binary};
+use goblin::elf::Binary;
+use byteorder::{ByteOrder,ReadBytesExt,LittleEndian,BigEndian};
 
 use {
     Program,
     CallTarget,
     Project,
     Layer,
+    OpaqueLayer,
     Region,
     Bound,
     Rvalue,
     Result,
+    Error,
 };
 
 /// CPU the ELF file is intended for.
-#[derive(Clone,Copy,Debug)]
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
 pub enum Machine {
     /// 8-bit AVR
     Avr,
@@ -47,10 +51,51 @@ pub enum Machine {
     Amd64,
     /// Intel x86
     Ia32,
+    /// 32-bit ARM
+    Arm,
+    /// 64-bit ARM
+    Aarch64,
+    /// MIPS
+    Mips,
+    /// 32-bit PowerPC
+    PowerPc,
+    /// 64-bit PowerPC
+    PowerPc64,
+    /// SPARC
+    Sparc,
+    /// Any other value of `e_machine`
+    Unknown(u16),
+}
+
+impl Machine {
+    /// Returns the CPU for the `e_machine` field of the ELF header.
+    pub fn from_e_machine(m: u16) -> Machine {
+        match m {
+            2 => Machine::Sparc,
+            3 => Machine::Ia32,
+            8 => Machine::Mips,
+            20 => Machine::PowerPc,
+            21 => Machine::PowerPc64,
+            40 => Machine::Arm,
+            62 => Machine::Amd64,
+            83 => Machine::Avr,
+            183 => Machine::Aarch64,
+            m => Machine::Unknown(m),
+        }
+    }
+}
+
+/// Byte order of the ELF file.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Endianess {
+    /// Least significant byte first
+    Little,
+    /// Most significant byte first
+    Big,
 }
 
 /// Initial ELF identifier section
-#[derive(Debug)]
+#[derive(Clone,Debug)]
 pub struct Ident {
     /// ELF magic number. Must be `ELF\177`
     pub magic: [u8; 4],
@@ -68,6 +113,11 @@ pub struct Ident {
     pub pad: [u8; 7],
 }
 
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+
 const EI_CLASS: usize = 4;
 const EI_DATA: usize = 5;
 const EI_VERSION: usize = 6;
@@ -80,20 +130,28 @@ impl Ident {
     pub fn read<R: Read>(strm: &mut R) -> Result<Ident> {
         let mut e_ident = [0u8; 16];
 
-        if let Err(_) = strm.read(&mut e_ident) {
-            return Err("Failed to read ident".into());
+        if let Err(_) = strm.read_exact(&mut e_ident) {
+            return Err(Error::format_at(0,"File too small for an ELF identifier"));
         }
 
         if e_ident[0..4] != [0x7f, 0x45, 0x4c, 0x46] {
-            return Err("Invalid magic number".into());
+            return Err(Error::format_at(0,"Invalid magic number"));
         }
 
         if e_ident[EI_PAD..16].iter().any(|&x| x != 0) {
-            return Err("Invalid padding".into());
+            return Err(Error::format_at(EI_PAD as u64,"Invalid padding"));
         }
 
         if e_ident[EI_VERSION] != 1 {
-            return Err("Invalid ELF version".into());
+            return Err(Error::format_at(EI_VERSION as u64,"Invalid ELF version"));
+        }
+
+        if e_ident[EI_CLASS] != ELFCLASS32 && e_ident[EI_CLASS] != ELFCLASS64 {
+            return Err(Error::format_at(EI_CLASS as u64,"Invalid ELF class"));
+        }
+
+        if e_ident[EI_DATA] != ELFDATA2LSB && e_ident[EI_DATA] != ELFDATA2MSB {
+            return Err(Error::format_at(EI_DATA as u64,"Invalid ELF data encoding"));
         }
 
         Ok(Ident{
@@ -114,57 +172,412 @@ impl Ident {
             ],
         })
     }
+
+    /// Reads the ELF identifier section of the file at `p`.
+    pub fn open(p: &Path) -> Result<Ident> {
+        let mut fd = try!(File::open(p));
+        Ident::read(&mut fd)
+    }
+
+    /// Byte order of all multi-byte values in the file.
+    pub fn endianess(&self) -> Endianess {
+        if self.data == ELFDATA2MSB {
+            Endianess::Big
+        } else {
+            Endianess::Little
+        }
+    }
+
+    /// True if this is an ELF64 file.
+    pub fn is_64bit(&self) -> bool {
+        self.class == ELFCLASS64
+    }
+}
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_DYNSYM: u32 = 11;
+const SHT_NOBITS: u32 = 8;
+
+/// Fields of the ELF header needed to map the file into memory.
+struct Header {
+    machine: u16,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
 }
 
-macro_rules! load_impl {
-    ($elf:expr, $fd:expr, $interp:expr, $entry:expr, $reg:expr) => {{
+/// A `PT_LOAD` program header.
+struct Segment {
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+/// Reads the ELF header following the identifier section.
+fn read_header<E: ByteOrder, R: Read>(strm: &mut R, is_64: bool) -> Result<Header> {
+    let _ty = try!(strm.read_u16::<E>());
+    let machine = try!(strm.read_u16::<E>());
+    let _version = try!(strm.read_u32::<E>());
+    let (entry,phoff,shoff) = if is_64 {
+        let entry = try!(strm.read_u64::<E>());
+        let phoff = try!(strm.read_u64::<E>());
+        let shoff = try!(strm.read_u64::<E>());
+        (entry,phoff,shoff)
+    } else {
+        let entry = try!(strm.read_u32::<E>()) as u64;
+        let phoff = try!(strm.read_u32::<E>()) as u64;
+        let shoff = try!(strm.read_u32::<E>()) as u64;
+        (entry,phoff,shoff)
+    };
+    let _flags = try!(strm.read_u32::<E>());
+    let _ehsize = try!(strm.read_u16::<E>());
+    let phentsize = try!(strm.read_u16::<E>());
+    let phnum = try!(strm.read_u16::<E>());
+    let shentsize = try!(strm.read_u16::<E>());
+    let shnum = try!(strm.read_u16::<E>());
+    let shstrndx = try!(strm.read_u16::<E>());
+
+    Ok(Header{
+        machine: machine,
+        entry: entry,
+        phoff: phoff,
+        shoff: shoff,
+        phentsize: phentsize,
+        phnum: phnum,
+        shentsize: shentsize,
+        shnum: shnum,
+        shstrndx: shstrndx,
+    })
+}
+
+/// Fails unless the table of `num` entries of `entsize` bytes at `off` is inside a file of
+/// `file_len` bytes and each entry is at least `min_entsize` bytes large.
+fn check_table(what: &str, off: u64, entsize: u16, num: u16, min_entsize: u64, file_len: u64) -> Result<()> {
+    if num == 0 {
+        return Ok(());
+    }
+
+    if (entsize as u64) < min_entsize {
+        return Err(Error::format(format!("{} entry size {} is too small",what,entsize)));
+    }
+
+    match off.checked_add(entsize as u64 * num as u64) {
+        Some(end) if end <= file_len => Ok(()),
+        _ => Err(Error::format_at(off,format!("{} is outside of the file",what))),
+    }
+}
+
+/// Reads all `PT_LOAD` program headers.
+fn read_segments<E: ByteOrder, R: Read + Seek>(strm: &mut R, hdr: &Header, is_64: bool, file_len: u64) -> Result<Vec<Segment>> {
+    let mut ret = Vec::new();
+
+    try!(check_table("Program header table",hdr.phoff,hdr.phentsize,hdr.phnum,if is_64 { 56 } else { 32 },file_len));
+
+    for i in 0..hdr.phnum {
+        try!(strm.seek(SeekFrom::Start(hdr.phoff + (i as u64) * (hdr.phentsize as u64))));
+
+        let ty = try!(strm.read_u32::<E>());
+        let seg = if is_64 {
+            let _flags = try!(strm.read_u32::<E>());
+            let offset = try!(strm.read_u64::<E>());
+            let vaddr = try!(strm.read_u64::<E>());
+            let _paddr = try!(strm.read_u64::<E>());
+            let filesz = try!(strm.read_u64::<E>());
+
+            Segment{ offset: offset, vaddr: vaddr, filesz: filesz }
+        } else {
+            let offset = try!(strm.read_u32::<E>()) as u64;
+            let vaddr = try!(strm.read_u32::<E>()) as u64;
+            let _paddr = try!(strm.read_u32::<E>());
+            let filesz = try!(strm.read_u32::<E>()) as u64;
+
+            Segment{ offset: offset, vaddr: vaddr, filesz: filesz }
+        };
+
+        if ty != PT_LOAD || seg.filesz == 0 {
+            continue;
+        }
+
+        match seg.offset.checked_add(seg.filesz) {
+            Some(end) if end <= file_len => {},
+            _ => return Err(Error::format_at(seg.offset,format!("Segment of {} bytes is outside of the file",seg.filesz))),
+        }
+
+        if seg.vaddr.checked_add(seg.filesz).is_none() {
+            return Err(Error::format(format!("Segment at {:#x} is outside of the address space",seg.vaddr)));
+        }
+
+        let bound = Bound::new(seg.vaddr,seg.vaddr + seg.filesz);
+
+        if let Some(other) = ret.iter().find(|s: &&Segment| Bound::new(s.vaddr,s.vaddr + s.filesz).overlaps(&bound)) {
+            return Err(Error::format(format!("Segment at {:#x} overlaps segment at {:#x}",seg.vaddr,other.vaddr)));
+        }
+
+        ret.push(seg);
+    }
+
+    Ok(ret)
+}
+
+/// A section header.
+struct Section {
+    name: u32,
+    ty: u32,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+/// Checks that all section headers and the contents of the sections are inside the file and
+/// that all section and symbol names point into their string tables. The symbol table parser
+/// allocates buffers based on these sizes and indexes string tables without bounds checks.
+fn check_sections<E: ByteOrder, R: Read + Seek>(strm: &mut R, hdr: &Header, is_64: bool, file_len: u64) -> Result<()> {
+    try!(check_table("Section header table",hdr.shoff,hdr.shentsize,hdr.shnum,if is_64 { 64 } else { 40 },file_len));
+
+    let mut sections = Vec::with_capacity(hdr.shnum as usize);
+
+    for i in 0..hdr.shnum {
+        try!(strm.seek(SeekFrom::Start(hdr.shoff + (i as u64) * (hdr.shentsize as u64))));
+
+        let name = try!(strm.read_u32::<E>());
+        let ty = try!(strm.read_u32::<E>());
+        let (offset,size) = if is_64 {
+            let _flags = try!(strm.read_u64::<E>());
+            let _addr = try!(strm.read_u64::<E>());
+            let offset = try!(strm.read_u64::<E>());
+            let size = try!(strm.read_u64::<E>());
+
+            (offset,size)
+        } else {
+            let _flags = try!(strm.read_u32::<E>());
+            let _addr = try!(strm.read_u32::<E>());
+            let offset = try!(strm.read_u32::<E>()) as u64;
+            let size = try!(strm.read_u32::<E>()) as u64;
+
+            (offset,size)
+        };
+        let link = try!(strm.read_u32::<E>());
+
+        if ty != SHT_NOBITS {
+            match offset.checked_add(size) {
+                Some(end) if end <= file_len => {},
+                _ => return Err(Error::format_at(offset,format!("Section {} is outside of the file",i))),
+            }
+        }
+
+        sections.push(Section{ name: name, ty: ty, offset: offset, size: size, link: link });
+    }
+
+    if sections.is_empty() {
+        return Ok(());
+    }
+
+    let shstrtab = match sections.get(hdr.shstrndx as usize) {
+        Some(sec) if sec.ty != SHT_NOBITS => sec.size,
+        _ => return Err(Error::format(format!("Invalid section name table index {}",hdr.shstrndx))),
+    };
+
+    for (i,sec) in sections.iter().enumerate() {
+        if sec.name as u64 >= shstrtab {
+            return Err(Error::format(format!("Name of section {} is outside of the section name table",i)));
+        }
+    }
+
+    // st_name is the first field of both Elf32_Sym and Elf64_Sym
+    let symsize = if is_64 { 24 } else { 16 };
+
+    for (i,sec) in sections.iter().enumerate().filter(|&(_,s)| s.ty == SHT_SYMTAB || s.ty == SHT_DYNSYM) {
+        let strtab = match sections.get(sec.link as usize) {
+            Some(s) if s.ty != SHT_NOBITS => s.size,
+            _ => return Err(Error::format(format!("Symbol table {} links to invalid string table {}",i,sec.link))),
+        };
+
+        for j in 0..sec.size / symsize {
+            try!(strm.seek(SeekFrom::Start(sec.offset + j * symsize)));
+
+            if try!(strm.read_u32::<E>()) as u64 >= strtab {
+                return Err(Error::format_at(sec.offset + j * symsize,format!("Name of symbol {} in section {} is outside of the string table",j,i)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+/// Symbols found in the ELF file.
+struct Symbols {
+    /// Address and name of all defined functions.
+    functions: BTreeMap<u64,String>,
+    /// Names of all functions imported from shared objects.
+    imports: Vec<String>,
+    /// Symbol names of the PLT relocations in order of their PLT entries.
+    plt_relocations: Vec<String>,
+    /// Address of the `.plt` section.
+    plt: Option<u64>,
+    /// Address of the `.plt.sec` section of binaries with indirect branch tracking.
+    plt_sec: Option<u64>,
+}
+
+impl Symbols {
+    fn empty() -> Symbols {
+        Symbols{
+            functions: BTreeMap::new(),
+            imports: vec![],
+            plt_relocations: vec![],
+            plt: None,
+            plt_sec: None,
+        }
+    }
+}
+
+macro_rules! symbols_impl {
+    ($elf:expr, $r_sym_shift:expr) => {{
         info!("Soname: {:?} with interpreter: {:?}", $elf.soname, $elf.interpreter);
 
-        for ph in $elf.program_headers {
-            if ph.p_type == program_header::PT_LOAD {
-                let mut buf = vec![0u8; ph.p_filesz as usize];
+        let mut functions = BTreeMap::<u64,String>::new();
+        let mut imports = Vec::<String>::new();
+        let mut plt_relocations = Vec::<String>::new();
+
+        for sym in $elf.syms.iter() {
+            if sym.st_info & 0xf == STT_FUNC && sym.st_shndx as u16 != SHN_UNDEF && sym.st_value != 0 {
+                let name = &$elf.strtab[sym.st_name as usize];
+
+                if !name.is_empty() {
+                    functions.entry(sym.st_value as u64).or_insert(name.to_string());
+                }
+            }
+        }
 
-                debug!("Load ELF {} bytes segment to {:#x}",ph.p_filesz,ph.p_vaddr);
+        for sym in $elf.dynsyms.iter() {
+            let name = &$elf.dynstrtab[sym.st_name as usize];
+            let ty = sym.st_info & 0xf;
 
-                if $fd.seek(SeekFrom::Start(ph.p_offset as u64)).ok() == Some(ph.p_offset as u64) {
-                    try!($fd.read_exact(&mut buf));
-                    $reg.cover(Bound::new(ph.p_vaddr as u64, (ph.p_vaddr + ph.p_filesz) as u64), Layer::wrap(buf));
-                } else {
-                    return Err("Failed to read segment".into())
+            if name.is_empty() {
+                continue;
+            } else if sym.st_shndx as u16 == SHN_UNDEF {
+                if ty == STT_FUNC || ty == STT_NOTYPE {
+                    imports.push(name.to_string());
                 }
+            } else if ty == STT_FUNC && sym.st_value != 0 {
+                functions.entry(sym.st_value as u64).or_insert(name.to_string());
             }
         }
 
-        ($elf.entry,$elf.interpreter)
+        for rel in $elf.pltrela.iter() {
+            let idx = ((rel.r_info as u64) >> $r_sym_shift) as usize;
+            let name = $elf.dynsyms.get(idx).map(|s| $elf.dynstrtab[s.st_name as usize].to_string());
+
+            plt_relocations.push(name.unwrap_or(String::new()));
+        }
+
+        let section = |name: &str| {
+            $elf.section_headers.iter().find(|sh| &$elf.shdr_strtab[sh.sh_name as usize] == name).map(|sh| sh.sh_addr as u64)
+        };
+        let plt = section(".plt");
+        let plt_sec = section(".plt.sec");
+
+        debug!("{} functions, {} imports and {} PLT relocations",functions.len(),imports.len(),plt_relocations.len());
+
+        Symbols{
+            functions: functions,
+            imports: imports,
+            plt_relocations: plt_relocations,
+            plt: plt,
+            plt_sec: plt_sec,
+        }
     }}
 }
 
+/// Size of a PLT entry of IA32 and AMD64 binaries. The entry size in the section header is
+/// unreliable, i386 linkers commonly set it to 4.
+const PLT_ENTRY_SIZE: u64 = 16;
+
+/// Addresses of the PLT stubs of IA32 and AMD64 binaries and the name of the function they jump
+/// to. `relocs` are the symbol names of the PLT relocations in order. Binaries with indirect
+/// branch tracking call the entries of `.plt.sec`, otherwise calls go to `.plt`, where the first
+/// entry (PLT0) is the resolver stub and the entries for each symbol follow.
+fn plt_stubs(plt: Option<u64>, plt_sec: Option<u64>, relocs: &[String]) -> Vec<(u64,String)> {
+    let first = match (plt_sec,plt) {
+        (Some(sec),_) => sec,
+        (None,Some(plt)) => plt + PLT_ENTRY_SIZE,
+        (None,None) => return vec![],
+    };
+
+    relocs.iter().enumerate().filter(|&(_,sym)| !sym.is_empty()).map(|(idx,sym)| {
+        (first + PLT_ENTRY_SIZE * idx as u64,sym.clone())
+    }).collect()
+}
+
 /// Load an ELF file from disk and creates a `Project` from it. Returns the `Project` instance and
 /// the CPU its intended for.
+///
+/// All `PT_LOAD` segments are mapped regardless of the CPU. All function symbols are added to the
+/// call graph as `CallTarget::Todo` and imported functions as `CallTarget::Symbolic`. Calls to PLT
+/// entries of IA32 and AMD64 binaries are linked to the imported symbol. Symbol tables of
+/// big-endian files are ignored.
 pub fn load(p: &Path) -> Result<(Project,Machine)> {
-    let mut fd = File::open(p).ok().unwrap();
-
-    // consider endianess
-    let ((entry,interp),machine,reg) = match goblin::elf::from_fd(&mut fd) {
-        Ok(Binary::Elf64(elf)) => match elf.header.e_machine {
-            62 => {
-                let mut reg = Region::undefined("RAM".to_string(), 0xFFFF_FFFF_FFFF_FFFF);
-                (load_impl!(elf, fd, interp, entry, reg),Machine::Amd64,reg)
-            }
-            _ => return Err("Unsupported class/data combination".into()),
-        },
-        Ok(Binary::Elf32(elf)) => match elf.header.e_machine {
-            3 => {
-                let mut reg = Region::undefined("RAM".to_string(), 0x1_0000_0000);
-                (load_impl!(elf, fd, interp, entry, reg),Machine::Ia32,reg)
-            }
-            83 => {
-                let mut reg = Region::undefined("Flash".to_string(), 0x2_0000);
-                (load_impl!(elf, fd, interp, entry, reg),Machine::Avr,reg)
+    let mut fd = try!(File::open(p));
+    let file_len = try!(fd.metadata()).len();
+    let ident = try!(Ident::read(&mut fd));
+    let is_64 = ident.is_64bit();
+    let hdr = try!(match ident.endianess() {
+        Endianess::Little => read_header::<LittleEndian,_>(&mut fd,is_64),
+        Endianess::Big => read_header::<BigEndian,_>(&mut fd,is_64),
+    });
+    let segs = try!(match ident.endianess() {
+        Endianess::Little => read_segments::<LittleEndian,_>(&mut fd,&hdr,is_64,file_len),
+        Endianess::Big => read_segments::<BigEndian,_>(&mut fd,&hdr,is_64,file_len),
+    });
+    let machine = Machine::from_e_machine(hdr.machine);
+    let entry = hdr.entry;
+
+    debug!("{:?} ELF file for {:?}",ident.endianess(),machine);
+
+    let mut reg = match machine {
+        Machine::Avr => Region::undefined("Flash".to_string(), 0x2_0000),
+        _ if is_64 => Region::undefined("RAM".to_string(), 0xFFFF_FFFF_FFFF_FFFF),
+        _ => Region::undefined("RAM".to_string(), 0x1_0000_0000),
+    };
+
+    for seg in segs {
+        debug!("Load ELF {} bytes segment to {:#x}",seg.filesz,seg.vaddr);
+
+        let l = try!(OpaqueLayer::open_range(p,seg.offset,seg.filesz));
+
+        // AVR data memory is mirrored at 0x800000 and doesn't fit into the flash region
+        if !reg.cover(Bound::new(seg.vaddr, seg.vaddr + seg.filesz), Layer::Opaque(l)) {
+            warn!("ignoring segment at {:#x} outside of the address space",seg.vaddr);
+        }
+    }
+
+    let syms = if ident.endianess() == Endianess::Little {
+        if let Err(e) = check_sections::<LittleEndian,_>(&mut fd,&hdr,is_64,file_len) {
+            warn!("ignoring symbol tables: {}",e);
+            Symbols::empty()
+        } else {
+            try!(fd.seek(SeekFrom::Start(0)));
+
+            match goblin::elf::from_fd(&mut fd) {
+                Ok(Binary::Elf64(elf)) => symbols_impl!(elf, 32),
+                Ok(Binary::Elf32(elf)) => symbols_impl!(elf, 8),
+                _ => {
+                    debug!("failed to read symbol tables");
+                    Symbols::empty()
+                }
             }
-            _ => return Err("Unsupported class/data combination".into()),
-        },
-        _ => return Err("Unsupported class/data combination".into()),
+        }
+    } else {
+        Symbols::empty()
     };
 
     let name = p.file_name()
@@ -172,11 +585,167 @@ pub fn load(p: &Path) -> Result<(Project,Machine)> {
         .unwrap_or("(encoding error)".to_string());
 
     let mut prog = Program::new("prog0");
+    let reg_name = reg.name().clone();
     let mut proj = Project::new(name.clone(),reg);
+    let entry_name = syms.functions.get(&entry).cloned().unwrap_or(name);
+
+    prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(entry),Some(entry_name),Uuid::new_v4()));
+    proj.comments.insert((reg_name,entry),"main".to_string());
+
+    for (addr,func) in syms.functions.iter() {
+        if *addr != entry {
+            prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(*addr),Some(func.clone()),Uuid::new_v4()));
+        }
+    }
+
+    let mut imports = HashSet::<String>::new();
+
+    for sym in syms.imports.iter() {
+        if imports.insert(sym.clone()) {
+            prog.call_graph.add_vertex(CallTarget::Symbolic(sym.clone(),Uuid::new_v4()));
+        }
+    }
+
+    if machine == Machine::Amd64 || machine == Machine::Ia32 {
+        for (stub,sym) in plt_stubs(syms.plt,syms.plt_sec,&syms.plt_relocations) {
+            debug!("PLT entry for {} at {:#x}",sym,stub);
+
+            if imports.insert(sym.clone()) {
+                prog.call_graph.add_vertex(CallTarget::Symbolic(sym.clone(),Uuid::new_v4()));
+            }
+
+            prog.imports.insert(stub,sym);
+        }
+    }
 
-    prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(entry as u64),Some(name),Uuid::new_v4()));
-    proj.comments.insert(("base".to_string(),entry as u64),"main".to_string());
     proj.code.push(prog);
 
     Ok((proj,machine))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+    use std::fs::File;
+    use std::io::Write;
+    use byteorder::{BigEndian,WriteBytesExt};
+    use graph_algos::{GraphTrait,VertexListGraphTrait};
+    use {CallTarget,Rvalue};
+
+    /// Minimal big-endian MIPS ELF32 executable with a single 4 byte `PT_LOAD` segment at
+    /// 0x400000.
+    fn mips_be() -> Vec<u8> {
+        let mut buf = vec![0x7f,b'E',b'L',b'F',1,2,1,0,0,0,0,0,0,0,0,0];
+
+        buf.write_u16::<BigEndian>(2).unwrap(); // e_type
+        buf.write_u16::<BigEndian>(8).unwrap(); // e_machine
+        buf.write_u32::<BigEndian>(1).unwrap(); // e_version
+        buf.write_u32::<BigEndian>(0x400000).unwrap(); // e_entry
+        buf.write_u32::<BigEndian>(52).unwrap(); // e_phoff
+        buf.write_u32::<BigEndian>(0).unwrap(); // e_shoff
+        buf.write_u32::<BigEndian>(0).unwrap(); // e_flags
+        buf.write_u16::<BigEndian>(52).unwrap(); // e_ehsize
+        buf.write_u16::<BigEndian>(32).unwrap(); // e_phentsize
+        buf.write_u16::<BigEndian>(1).unwrap(); // e_phnum
+        buf.write_u16::<BigEndian>(40).unwrap(); // e_shentsize
+        buf.write_u16::<BigEndian>(0).unwrap(); // e_shnum
+        buf.write_u16::<BigEndian>(0).unwrap(); // e_shstrndx
+
+        buf.write_u32::<BigEndian>(PT_LOAD).unwrap(); // p_type
+        buf.write_u32::<BigEndian>(84).unwrap(); // p_offset
+        buf.write_u32::<BigEndian>(0x400000).unwrap(); // p_vaddr
+        buf.write_u32::<BigEndian>(0x400000).unwrap(); // p_paddr
+        buf.write_u32::<BigEndian>(4).unwrap(); // p_filesz
+        buf.write_u32::<BigEndian>(4).unwrap(); // p_memsz
+        buf.write_u32::<BigEndian>(5).unwrap(); // p_flags
+        buf.write_u32::<BigEndian>(0x1000).unwrap(); // p_align
+
+        buf.extend_from_slice(&[0x03,0xe0,0x00,0x08]);
+        buf
+    }
+
+    #[test]
+    fn load_big_endian() {
+        let tmpdir = TempDir::new("test-panop-elf").unwrap();
+        let p = tmpdir.path().join("mips");
+
+        File::create(&p).unwrap().write_all(&mips_be()).unwrap();
+
+        let ident = Ident::open(&p).unwrap();
+        assert_eq!(ident.endianess(), Endianess::Big);
+        assert!(!ident.is_64bit());
+
+        let (proj,machine) = load(&p).unwrap();
+        assert_eq!(machine, Machine::Mips);
+
+        let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+        let bytes = root.iter().seek(0x400000).take(4).map(|x| x.unwrap_or(0)).collect::<Vec<u8>>();
+        assert_eq!(bytes, vec![0x03,0xe0,0x00,0x08]);
+        assert!(root.iter().seek(0x400004).next().unwrap().is_none());
+        assert_eq!(root.size(), 0x1_0000_0000);
+
+        let prog = &proj.code[0];
+        assert!(prog.call_graph.vertices().any(|vx| match prog.call_graph.vertex_label(vx) {
+            Some(&CallTarget::Todo(Rvalue::Constant{ value: 0x400000,.. },_,_)) => true,
+            _ => false,
+        }));
+        assert!(proj.comments.contains_key(&("RAM".to_string(),0x400000)));
+    }
+
+    #[test]
+    fn machine_from_e_machine() {
+        assert_eq!(Machine::from_e_machine(40), Machine::Arm);
+        assert_eq!(Machine::from_e_machine(183), Machine::Aarch64);
+        assert_eq!(Machine::from_e_machine(8), Machine::Mips);
+        assert_eq!(Machine::from_e_machine(0xbeef), Machine::Unknown(0xbeef));
+    }
+
+    #[test]
+    fn plt_stub_addresses() {
+        let relocs = vec!["puts".to_string(),"".to_string(),"exit".to_string()];
+
+        assert_eq!(plt_stubs(Some(0x1020),None,&relocs),vec![(0x1030,"puts".to_string()),(0x1050,"exit".to_string())]);
+        assert_eq!(plt_stubs(Some(0x1020),Some(0x1080),&relocs),vec![(0x1080,"puts".to_string()),(0x10a0,"exit".to_string())]);
+        assert!(plt_stubs(None,None,&relocs).is_empty());
+    }
+
+    #[test]
+    fn symbol_names_outside_of_string_table() {
+        use std::io::Cursor;
+        use byteorder::LittleEndian;
+
+        // null section, section name table, string table and a symbol table with one entry
+        let sections = [(0,0,0,0,0),(0,3,4,1,0),(0,3,0,4,0),(0,SHT_SYMTAB,5,16,2)];
+        let mk = |st_name: u32| {
+            let mut buf = vec![0u8,b'a',b'b',b'c',0];
+
+            buf.write_u32::<LittleEndian>(st_name).unwrap();
+            buf.extend_from_slice(&[0u8; 12]);
+
+            for &(name,ty,off,size,link) in sections.iter() {
+                for &x in [name,ty,0,0,off,size,link,0,0,0].iter() {
+                    buf.write_u32::<LittleEndian>(x).unwrap();
+                }
+            }
+            buf
+        };
+        let hdr = Header{
+            machine: 3,
+            entry: 0,
+            phoff: 0,
+            shoff: 21,
+            phentsize: 32,
+            phnum: 0,
+            shentsize: 40,
+            shnum: 4,
+            shstrndx: 1,
+        };
+
+        let good = mk(1);
+        assert!(check_sections::<LittleEndian,_>(&mut Cursor::new(&good),&hdr,false,good.len() as u64).is_ok());
+
+        let bad = mk(4);
+        assert!(check_sections::<LittleEndian,_>(&mut Cursor::new(&bad),&hdr,false,bad.len() as u64).is_err());
+    }
+}
@@ -0,0 +1,581 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Export of analysis results for other tools.
+//!
+//! Two formats are supported. `to_json` encodes the whole project as JSON. The structure is
+//! described by the `...Export` types in this module and does not change with the internal
+//! representation. Incompatible changes increase `EXPORT_VERSION`. `listing` prints all functions
+//! as plain text, similar to the output of `objdump -d`.
+//!
+//! Addresses are always unsigned integers, UUIDs are strings in hyphenated form. Mnemonics are
+//! exported with the operands formatted according to their format string, RREIL statements are
+//! printed like `Statement`'s `Display` implementation.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use graph_algos::{
+    GraphTrait,
+    VertexListGraphTrait,
+    EdgeListGraphTrait,
+};
+use rustc_serialize::json;
+
+use project::Project;
+use program::{Program,CallTarget};
+use function::{Function,ControlFlowTarget};
+use mnemonic::{Mnemonic,MnemonicFormatToken};
+use region::Region;
+use il::Rvalue;
+use result::Result;
+
+/// Version of the JSON structure.
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Exported `Project`.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct ProjectExport {
+    /// Always `EXPORT_VERSION`
+    pub version: u32,
+    /// Project name
+    pub name: String,
+    /// Memory regions
+    pub regions: Vec<RegionExport>,
+    /// Programs
+    pub programs: Vec<ProgramExport>,
+    /// Comments, sorted by region and offset
+    pub comments: Vec<CommentExport>,
+}
+
+/// Exported `Region`.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct RegionExport {
+    /// Region name
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// Single comment.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct CommentExport {
+    /// Region name
+    pub region: String,
+    /// Offset inside the region
+    pub offset: u64,
+    /// Comment text
+    pub text: String,
+}
+
+/// Exported `Program`.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct ProgramExport {
+    /// Program UUID
+    pub uuid: String,
+    /// Program name
+    pub name: String,
+    /// Disassembled functions, sorted by entry point
+    pub functions: Vec<FunctionExport>,
+    /// Call targets that are not disassembled (yet)
+    pub references: Vec<ReferenceExport>,
+    /// Call graph edges
+    pub calls: Vec<CallExport>,
+    /// Import stubs
+    pub imports: Vec<ImportExport>,
+}
+
+/// Symbolic or not yet disassembled call target.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct ReferenceExport {
+    /// UUID of the call graph node
+    pub uuid: String,
+    /// Either "symbolic" for imported functions or "todo" for unknown code
+    pub kind: String,
+    /// Symbol name, if known
+    pub name: Option<String>,
+    /// Address, if known
+    pub address: Option<u64>,
+}
+
+/// Edge in the call graph.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct CallExport {
+    /// UUID of the calling function
+    pub from: String,
+    /// UUID of the called function or reference
+    pub to: String,
+}
+
+/// Import stub.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct ImportExport {
+    /// Address of the stub
+    pub address: u64,
+    /// Imported symbol
+    pub name: String,
+}
+
+/// Exported `Function`.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct FunctionExport {
+    /// Function UUID
+    pub uuid: String,
+    /// Function name
+    pub name: String,
+    /// Region the function is located in
+    pub region: String,
+    /// Address of the entry basic block
+    pub entry: Option<u64>,
+    /// Basic blocks, sorted by address
+    pub basic_blocks: Vec<BasicBlockExport>,
+    /// Control flow edges
+    pub jumps: Vec<JumpExport>,
+    /// Indirect jumps that could not be resolved
+    pub unresolved: Vec<String>,
+    /// Addresses where disassembly failed, with the reason
+    pub failed: Vec<(u64,String)>,
+}
+
+/// Exported `BasicBlock`.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct BasicBlockExport {
+    /// First address
+    pub start: u64,
+    /// First address after the basic block
+    pub end: u64,
+    /// Mnemonics in execution order
+    pub mnemonics: Vec<MnemonicExport>,
+}
+
+/// Exported `Mnemonic`.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct MnemonicExport {
+    /// First address
+    pub start: u64,
+    /// First address after the mnemonic
+    pub end: u64,
+    /// Opcode
+    pub opcode: String,
+    /// Formatted operands, in order
+    pub operands: Vec<String>,
+    /// Opcode and operands as they would be printed by a disassembler
+    pub text: String,
+    /// RREIL statements implementing the mnemonic
+    pub statements: Vec<String>,
+}
+
+/// Edge in a function's control flow graph.
+#[derive(RustcEncodable,RustcDecodable,Debug)]
+pub struct JumpExport {
+    /// Start of the source basic block
+    pub from: u64,
+    /// Start of the target basic block. None if the target is unresolved.
+    pub to: Option<u64>,
+    /// Target value if `to` is None
+    pub target: Option<String>,
+    /// Condition under which the jump is taken
+    pub guard: String,
+}
+
+/// Returns the region named `name`.
+fn find_region<'a>(proj: &'a Project, name: &str) -> Option<&'a Region> {
+    proj.data.dependencies.vertices()
+        .filter_map(|vx| proj.data.dependencies.vertex_label(vx))
+        .find(|r| r.name() == name)
+}
+
+/// Returns the address of the entry basic block of `f`.
+fn entry_address(f: &Function) -> Option<u64> {
+    f.entry_point.and_then(|e| match f.cflow_graph.vertex_label(e) {
+        Some(&ControlFlowTarget::Resolved(ref bb)) => Some(bb.area.start),
+        _ => None,
+    })
+}
+
+/// Returns the names of all functions and import stubs in `prog`, keyed by address.
+fn symbols(prog: &Program) -> HashMap<u64,String> {
+    let mut ret = prog.imports.clone();
+
+    for vx in prog.call_graph.vertices() {
+        if let Some(&CallTarget::Concrete(ref f)) = prog.call_graph.vertex_label(vx) {
+            if let Some(a) = entry_address(f) {
+                ret.insert(a,f.name.clone());
+            }
+        }
+    }
+
+    ret
+}
+
+/// Returns all concrete functions of `prog` sorted by entry point.
+fn functions(prog: &Program) -> Vec<&Function> {
+    let mut ret = prog.call_graph.vertices().filter_map(|vx| match prog.call_graph.vertex_label(vx) {
+        Some(&CallTarget::Concrete(ref f)) => Some(f),
+        _ => None,
+    }).collect::<Vec<_>>();
+
+    ret.sort_by(|a,b| (entry_address(a),&a.name).cmp(&(entry_address(b),&b.name)));
+    ret
+}
+
+/// Formats a single operand of a mnemonic.
+fn format_operand(tok: &MnemonicFormatToken, op: Option<&Rvalue>, syms: &HashMap<u64,String>) -> String {
+    match (tok,op) {
+        (&MnemonicFormatToken::Variable{ has_sign },Some(&Rvalue::Constant{ value, size })) => {
+            let val = if size > 0 && size < 64 { value % (1u64 << size) } else { value };
+            let neg = has_sign && size > 0 && size <= 64 && val & (1u64 << (size - 1)) != 0;
+
+            if neg {
+                let abs = if size < 64 { (1u64 << size) - val } else { val.wrapping_neg() };
+                format!("-0x{:x}",abs)
+            } else {
+                format!("0x{:x}",val)
+            }
+        },
+        (&MnemonicFormatToken::Pointer{ is_code,.. },Some(&Rvalue::Constant{ value, size })) => {
+            let val = if size > 0 && size < 64 { value % (1u64 << size) } else { value };
+
+            match syms.get(&val) {
+                Some(name) if is_code => format!("0x{:x} <{}>",val,name),
+                _ => format!("0x{:x}",val),
+            }
+        },
+        (_,Some(&Rvalue::Variable{ ref name,.. })) => name.to_string(),
+        _ => "?".to_string(),
+    }
+}
+
+/// Formats the operands of `mne` according to its format string. Returns the operands and the
+/// complete operand string, including literals.
+fn format_operands(mne: &Mnemonic, syms: &HashMap<u64,String>) -> (Vec<String>,String) {
+    let mut ops = mne.operands.iter();
+    let mut list = vec![];
+    let mut text = String::new();
+
+    for tok in mne.format_string.iter() {
+        match tok {
+            &MnemonicFormatToken::Literal(c) => text.push(c),
+            _ => {
+                let s = format_operand(tok,ops.next(),syms);
+
+                text.push_str(&s);
+                list.push(s);
+            }
+        }
+    }
+
+    (list,text)
+}
+
+fn export_function(f: &Function, syms: &HashMap<u64,String>) -> FunctionExport {
+    let mut bbs = vec![];
+    let mut unresolved = vec![];
+    let mut failed = vec![];
+    let mut jumps = vec![];
+
+    for vx in f.cflow_graph.vertices() {
+        match f.cflow_graph.vertex_label(vx) {
+            Some(&ControlFlowTarget::Resolved(ref bb)) => bbs.push(BasicBlockExport{
+                start: bb.area.start,
+                end: bb.area.end,
+                mnemonics: bb.mnemonics.iter().map(|mne| {
+                    let (operands,ops) = format_operands(mne,syms);
+
+                    MnemonicExport{
+                        start: mne.area.start,
+                        end: mne.area.end,
+                        opcode: mne.opcode.clone(),
+                        operands: operands,
+                        text: if ops.is_empty() { mne.opcode.clone() } else { format!("{} {}",mne.opcode,ops) },
+                        statements: mne.instructions.iter().map(|i| format!("{}",i)).collect(),
+                    }
+                }).collect(),
+            }),
+            Some(&ControlFlowTarget::Unresolved(Rvalue::Constant{ .. })) => {},
+            Some(&ControlFlowTarget::Unresolved(ref rv)) => unresolved.push(format!("{}",rv)),
+            Some(&ControlFlowTarget::Failed(pos,ref msg)) => failed.push((pos,msg.to_string())),
+            None => {},
+        }
+    }
+
+    for e in f.cflow_graph.edges() {
+        let from = match f.cflow_graph.vertex_label(f.cflow_graph.source(e)) {
+            Some(&ControlFlowTarget::Resolved(ref bb)) => bb.area.start,
+            _ => continue,
+        };
+        let (to,target) = match f.cflow_graph.vertex_label(f.cflow_graph.target(e)) {
+            Some(&ControlFlowTarget::Resolved(ref bb)) => (Some(bb.area.start),None),
+            Some(&ControlFlowTarget::Unresolved(ref rv)) => (None,Some(format!("{}",rv))),
+            _ => (None,None),
+        };
+
+        jumps.push(JumpExport{
+            from: from,
+            to: to,
+            target: target,
+            guard: f.cflow_graph.edge_label(e).map(|g| format!("{}",g)).unwrap_or("?".to_string()),
+        });
+    }
+
+    bbs.sort_by(|a,b| a.start.cmp(&b.start));
+    jumps.sort_by(|a,b| (a.from,a.to).cmp(&(b.from,b.to)));
+    failed.sort();
+
+    FunctionExport{
+        uuid: f.uuid.to_string(),
+        name: f.name.clone(),
+        region: f.region.clone(),
+        entry: entry_address(f),
+        basic_blocks: bbs,
+        jumps: jumps,
+        unresolved: unresolved,
+        failed: failed,
+    }
+}
+
+fn export_program(prog: &Program) -> ProgramExport {
+    let syms = symbols(prog);
+    let mut refs = vec![];
+    let mut calls = vec![];
+    let mut imports = prog.imports.iter().map(|(&a,n)| ImportExport{ address: a, name: n.clone() }).collect::<Vec<_>>();
+
+    for vx in prog.call_graph.vertices() {
+        match prog.call_graph.vertex_label(vx) {
+            Some(&CallTarget::Symbolic(ref name,uuid)) => refs.push(ReferenceExport{
+                uuid: uuid.to_string(),
+                kind: "symbolic".to_string(),
+                name: Some(name.clone()),
+                address: None,
+            }),
+            Some(&CallTarget::Todo(ref rv,ref name,uuid)) => refs.push(ReferenceExport{
+                uuid: uuid.to_string(),
+                kind: "todo".to_string(),
+                name: name.clone(),
+                address: if let &Rvalue::Constant{ value,.. } = rv { Some(value) } else { None },
+            }),
+            _ => {},
+        }
+    }
+
+    for e in prog.call_graph.edges() {
+        let from = prog.call_graph.vertex_label(prog.call_graph.source(e)).map(|x| x.uuid());
+        let to = prog.call_graph.vertex_label(prog.call_graph.target(e)).map(|x| x.uuid());
+
+        if let (Some(from),Some(to)) = (from,to) {
+            calls.push(CallExport{ from: from.to_string(), to: to.to_string() });
+        }
+    }
+
+    refs.sort_by(|a,b| (&a.kind,a.address,&a.name).cmp(&(&b.kind,b.address,&b.name)));
+    calls.sort_by(|a,b| (&a.from,&a.to).cmp(&(&b.from,&b.to)));
+    imports.sort_by(|a,b| a.address.cmp(&b.address));
+
+    ProgramExport{
+        uuid: prog.uuid.to_string(),
+        name: prog.name.clone(),
+        functions: functions(prog).into_iter().map(|f| export_function(f,&syms)).collect(),
+        references: refs,
+        calls: calls,
+        imports: imports,
+    }
+}
+
+/// Converts `proj` into the structure written by `to_json`.
+pub fn export(proj: &Project) -> ProjectExport {
+    let mut regions = proj.data.dependencies.vertices()
+        .filter_map(|vx| proj.data.dependencies.vertex_label(vx))
+        .map(|r| RegionExport{ name: r.name().clone(), size: r.size() })
+        .collect::<Vec<_>>();
+    let mut comments = proj.comments.iter().map(|(&(ref reg,off),txt)| CommentExport{
+        region: reg.clone(),
+        offset: off,
+        text: txt.clone(),
+    }).collect::<Vec<_>>();
+
+    regions.sort_by(|a,b| a.name.cmp(&b.name));
+    comments.sort_by(|a,b| (&a.region,a.offset).cmp(&(&b.region,b.offset)));
+
+    ProjectExport{
+        version: EXPORT_VERSION,
+        name: proj.name.clone(),
+        regions: regions,
+        programs: proj.code.iter().map(export_program).collect(),
+        comments: comments,
+    }
+}
+
+/// Returns `proj` encoded as pretty printed JSON.
+pub fn to_json(proj: &Project) -> Result<String> {
+    let exp = export(proj);
+
+    // make sure encoding errors are reported instead of written into the string
+    try!(json::encode(&exp));
+    Ok(format!("{}",json::as_pretty_json(&exp)))
+}
+
+/// Returns an objdump-like listing of all functions in `proj`.
+pub fn listing(proj: &Project) -> String {
+    let mut ret = String::new();
+
+    let _ = writeln!(ret,"{}:     file format panopticon",proj.name);
+
+    for prog in proj.code.iter() {
+        let syms = symbols(prog);
+
+        let _ = writeln!(ret,"\n\nDisassembly of program {}:",prog.name);
+
+        for f in functions(prog) {
+            let func = export_function(f,&syms);
+            let reg = find_region(proj,&f.region);
+
+            let _ = writeln!(ret,"\n{:016x} <{}>:",func.entry.unwrap_or(0),func.name);
+
+            for bb in func.basic_blocks.iter() {
+                for mne in bb.mnemonics.iter() {
+                    let bytes = reg.map(|r| {
+                        r.iter().seek(mne.start).take((mne.end - mne.start) as usize).map(|b| match b {
+                            Some(b) => format!("{:02x} ",b),
+                            None => "?? ".to_string(),
+                        }).collect::<String>()
+                    }).unwrap_or(String::new());
+
+                    let _ = write!(ret,"{:>8x}:\t{:<21}\t{}",mne.start,bytes,mne.text);
+
+                    match proj.comments.get(&(f.region.clone(),mne.start)) {
+                        Some(c) => { let _ = writeln!(ret,"\t; {}",c); },
+                        None => { let _ = writeln!(ret,""); },
+                    }
+                }
+            }
+
+            for rv in func.unresolved.iter() {
+                let _ = writeln!(ret,"\t; unresolved jump to {}",rv);
+            }
+
+            for &(pos,ref msg) in func.failed.iter() {
+                let _ = writeln!(ret,"{:>8x}:\t; disassembly failed: {}",pos,msg);
+            }
+        }
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use graph_algos::MutableGraphTrait;
+    use rustc_serialize::json::Json;
+    use {
+        Project,
+        Program,
+        CallTarget,
+        Function,
+        ControlFlowTarget,
+        BasicBlock,
+        Mnemonic,
+        Region,
+        Guard,
+        Rvalue,
+        Lvalue,
+        Statement,
+        Operation,
+    };
+
+    fn project() -> Project {
+        let reg = Region::wrap("base".to_string(),vec![0x01,0x02,0x03,0x04,0x05,0x06]);
+        let mut proj = Project::new("test".to_string(),reg);
+        let mut prog = Program::new("prog0");
+        let mut func = Function::new("main".to_string(),"base".to_string());
+        let r0 = Lvalue::Variable{ name: Cow::Borrowed("r0"), subscript: None, size: 8 };
+        let mov = Statement{ assignee: r0.clone(), op: Operation::Move(Rvalue::new_u8(0xff)) };
+        let mne1 = Mnemonic::new(0..2,"ldi".to_string(),"{u}, {s}".to_string(),
+                                 vec![Rvalue::from(r0.clone()),Rvalue::new_u8(0xff)].iter(),vec![mov].iter()).unwrap();
+        let mne2 = Mnemonic::new(2..4,"call".to_string(),"{c:base}".to_string(),vec![Rvalue::new_u64(0x10)].iter(),vec![].iter()).unwrap();
+        let mne3 = Mnemonic::new(4..6,"ret".to_string(),"".to_string(),vec![].iter(),vec![].iter()).unwrap();
+        let bb1 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne1,mne2])));
+        let bb2 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne3])));
+
+        func.cflow_graph.add_edge(Guard::True,bb1,bb2);
+        func.entry_point = Some(bb1);
+
+        let main = prog.call_graph.add_vertex(CallTarget::Concrete(func));
+        let puts = prog.call_graph.add_vertex(CallTarget::Symbolic("puts".to_string(),::uuid::Uuid::new_v4()));
+
+        prog.call_graph.add_edge((),main,puts);
+        prog.imports.insert(0x10,"puts".to_string());
+        proj.code.push(prog);
+        proj.comments.insert(("base".to_string(),4),"done".to_string());
+
+        proj
+    }
+
+    #[test]
+    fn export_structure() {
+        let exp = export(&project());
+        let prog = &exp.programs[0];
+        let func = &prog.functions[0];
+
+        assert_eq!(exp.version,EXPORT_VERSION);
+        assert_eq!(exp.regions.len(),1);
+        assert_eq!(exp.regions[0].size,6);
+        assert_eq!(func.entry,Some(0));
+        assert_eq!(func.basic_blocks.len(),2);
+        assert_eq!(func.basic_blocks[0].mnemonics[0].operands,vec!["r0".to_string(),"-0x1".to_string()]);
+        assert_eq!(func.basic_blocks[0].mnemonics[0].text,"ldi r0, -0x1");
+        assert_eq!(func.basic_blocks[0].mnemonics[0].statements.len(),1);
+        assert_eq!(func.basic_blocks[0].mnemonics[1].text,"call 0x10 <puts>");
+        assert_eq!(func.basic_blocks[1].mnemonics[0].text,"ret");
+        assert_eq!(func.jumps.len(),1);
+        assert_eq!(func.jumps[0].to,Some(4));
+        assert_eq!(prog.references.len(),1);
+        assert_eq!(prog.calls.len(),1);
+        assert_eq!(prog.imports.len(),1);
+        assert_eq!(exp.comments[0].text,"done");
+    }
+
+    #[test]
+    fn export_json() {
+        let s = to_json(&project()).unwrap();
+        let j = Json::from_str(&s).unwrap();
+
+        assert_eq!(j.find("name").and_then(|x| x.as_string()),Some("test"));
+        assert_eq!(j.find_path(&["programs"]).and_then(|x| x.as_array()).map(|x| x.len()),Some(1));
+    }
+
+    #[test]
+    fn export_listing() {
+        let s = listing(&project());
+
+        assert!(s.contains("0000000000000000 <main>:"));
+        assert!(s.contains("       0:\t01 02 "));
+        assert!(s.contains("call 0x10 <puts>"));
+        assert!(s.contains("ret\t; done"));
+    }
+
+    #[test]
+    fn listing_undefined_bytes() {
+        let mut proj = project();
+        let reg = Region::undefined("base".to_string(),6);
+
+        proj.data = ::region::World::new(reg);
+        assert!(listing(&proj).contains("?? ?? "));
+    }
+}
@@ -0,0 +1,334 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Loaders for Intel HEX and Motorola S-record firmware images.
+//!
+//! Both formats are line based text files. Each line carries a checksummed record with a load
+//! address and a few bytes of data. The loaders map each contiguous run of data into an
+//! undefined `Region` using a `Layer`. Addresses not mentioned in the file stay undefined.
+//!
+//! The resulting `Project` has a single, empty `Program`. If the file contains a start address
+//! it is added as `CallTarget::Todo`. Other entry points are usually added with
+//! `Project::prepare`.
+
+use std::path::Path;
+use std::fs::File;
+use std::io::Read;
+
+use graph_algos::MutableGraphTrait;
+use uuid::Uuid;
+
+use project::Project;
+use program::{Program,CallTarget};
+use region::Region;
+use mnemonic::Bound;
+use layer::Layer;
+use il::Rvalue;
+use result::{Result,Error};
+
+/// Memory image read from a firmware file.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Image {
+    /// Contiguous, non-overlapping runs of data sorted by address
+    pub chunks: Vec<(u64,Vec<u8>)>,
+    /// Start address, if the file has one
+    pub entry: Option<u64>,
+}
+
+impl Image {
+    fn new(mut records: Vec<(u64,Vec<u8>)>, entry: Option<u64>) -> Result<Image> {
+        let mut chunks: Vec<(u64,Vec<u8>)> = vec![];
+
+        records.sort_by(|a,b| a.0.cmp(&b.0));
+
+        for (addr,data) in records.into_iter() {
+            if data.is_empty() {
+                continue;
+            }
+
+            if let Some(&mut (start,ref mut prev)) = chunks.last_mut() {
+                let end = start + prev.len() as u64;
+
+                if end > addr {
+                    return Err(Error::format(format!("Overlapping records at {:#x}",addr)));
+                } else if end == addr {
+                    prev.extend_from_slice(&data);
+                    continue;
+                }
+            }
+
+            chunks.push((addr,data));
+        }
+
+        Ok(Image{ chunks: chunks, entry: entry })
+    }
+
+    /// Address one past the last defined byte.
+    pub fn end(&self) -> u64 {
+        self.chunks.last().map(|&(a,ref d)| a + d.len() as u64).unwrap_or(0)
+    }
+
+    /// Maps the image into an undefined `Region` named `name` that spans address 0 up to the
+    /// last defined byte.
+    pub fn to_region(&self, name: String) -> Result<Region> {
+        if self.chunks.is_empty() {
+            return Err(Error::format("Image has no data records"));
+        }
+
+        let mut reg = Region::undefined(name,self.end());
+
+        for &(addr,ref data) in self.chunks.iter() {
+            if !reg.cover(Bound::new(addr,addr + data.len() as u64),Layer::wrap(data.clone())) {
+                return Err(Error::format(format!("Failed to map {} bytes at {:#x}",data.len(),addr)));
+            }
+        }
+
+        Ok(reg)
+    }
+}
+
+/// Decodes a string of hexadecimal digits.
+fn decode_hex(s: &[u8], line: usize) -> Result<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return Err(Error::format(format!("Odd number of hex digits in line {}",line)));
+    }
+
+    let mut ret = Vec::with_capacity(s.len() / 2);
+
+    for p in s.chunks(2) {
+        let hi = try!((p[0] as char).to_digit(16).ok_or_else(|| Error::format(format!("Invalid hex digit in line {}",line))));
+        let lo = try!((p[1] as char).to_digit(16).ok_or_else(|| Error::format(format!("Invalid hex digit in line {}",line))));
+
+        ret.push((hi * 16 + lo) as u8);
+    }
+
+    Ok(ret)
+}
+
+fn be_value(b: &[u8]) -> u64 {
+    b.iter().fold(0,|acc,&x| (acc << 8) | x as u64)
+}
+
+/// Parses an Intel HEX file. Supports data (00), end of file (01), extended segment address
+/// (02), start segment address (03), extended linear address (04) and start linear address (05)
+/// records.
+pub fn parse_ihex(s: &str) -> Result<Image> {
+    let mut records = vec![];
+    let mut base = 0u64;
+    let mut entry = None;
+
+    for (idx,l) in s.lines().enumerate() {
+        let line = idx + 1;
+        let l = l.trim();
+
+        if l.is_empty() {
+            continue;
+        }
+
+        if !l.starts_with(':') {
+            return Err(Error::format(format!("Line {} does not start with ':'",line)));
+        }
+
+        let rec = try!(decode_hex(&l.as_bytes()[1..],line));
+
+        if rec.len() < 5 || rec.len() != rec[0] as usize + 5 {
+            return Err(Error::format(format!("Invalid record length in line {}",line)));
+        }
+
+        if rec.iter().fold(0u8,|acc,&x| acc.wrapping_add(x)) != 0 {
+            return Err(Error::format(format!("Checksum mismatch in line {}",line)));
+        }
+
+        let addr = be_value(&rec[1..3]);
+        let data = &rec[4..rec.len() - 1];
+
+        match (rec[3],data.len()) {
+            (0x00,_) => records.push((base + addr,data.to_vec())),
+            (0x01,_) => break,
+            (0x02,2) => base = be_value(data) << 4,
+            (0x03,4) => entry = Some((be_value(&data[0..2]) << 4) + be_value(&data[2..4])),
+            (0x04,2) => base = be_value(data) << 16,
+            (0x05,4) => entry = Some(be_value(data)),
+            (t,_) => return Err(Error::format(format!("Invalid record type {:#x} in line {}",t,line))),
+        }
+    }
+
+    Image::new(records,entry)
+}
+
+/// Parses a Motorola S-record file. Supports S19, S28 and S37 files. Header (S0) and record
+/// count (S5, S6) records are ignored.
+pub fn parse_srec(s: &str) -> Result<Image> {
+    let mut records = vec![];
+    let mut entry = None;
+
+    for (idx,l) in s.lines().enumerate() {
+        let line = idx + 1;
+        let l = l.trim();
+
+        if l.is_empty() {
+            continue;
+        }
+
+        if l.len() < 2 || !l.starts_with('S') {
+            return Err(Error::format(format!("Line {} does not start with 'S'",line)));
+        }
+
+        let ty = l.as_bytes()[1];
+        let rec = try!(decode_hex(&l.as_bytes()[2..],line));
+
+        if rec.len() < 2 || rec.len() != rec[0] as usize + 1 {
+            return Err(Error::format(format!("Invalid record length in line {}",line)));
+        }
+
+        if rec.iter().fold(0u8,|acc,&x| acc.wrapping_add(x)) != 0xff {
+            return Err(Error::format(format!("Checksum mismatch in line {}",line)));
+        }
+
+        let addr_len = match ty {
+            b'0' | b'1' | b'5' | b'9' => 2,
+            b'2' | b'6' | b'8' => 3,
+            b'3' | b'7' => 4,
+            _ => return Err(Error::format(format!("Invalid record type S{} in line {}",ty as char,line))),
+        };
+
+        if rec.len() < addr_len + 2 {
+            return Err(Error::format(format!("Record too short in line {}",line)));
+        }
+
+        let addr = be_value(&rec[1..1 + addr_len]);
+        let data = &rec[1 + addr_len..rec.len() - 1];
+
+        match ty {
+            b'1' | b'2' | b'3' => records.push((addr,data.to_vec())),
+            b'7' | b'8' | b'9' => entry = Some(addr),
+            _ => {}
+        }
+    }
+
+    Image::new(records,entry)
+}
+
+fn load(p: &Path, parse: fn(&str) -> Result<Image>) -> Result<Project> {
+    let name = p.file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or("(encoding error)".to_string());
+    let mut fd = try!(File::open(p));
+    let mut s = String::new();
+
+    try!(fd.read_to_string(&mut s));
+
+    let img = try!(parse(&s));
+    let reg = try!(img.to_region(name.clone()));
+    let mut prog = Program::new("prog0");
+    let mut proj = Project::new(name.clone(),reg);
+
+    if let Some(entry) = img.entry {
+        prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(entry),Some("Entry point".to_string()),Uuid::new_v4()));
+        proj.comments.insert((name,entry),"Start address".to_string());
+    }
+
+    proj.code.push(prog);
+    Ok(proj)
+}
+
+/// Loads an Intel HEX file from disk and creates a project from it.
+pub fn ihex(p: &Path) -> Result<Project> {
+    load(p,parse_ihex)
+}
+
+/// Loads a Motorola S-record file from disk and creates a project from it.
+pub fn srec(p: &Path) -> Result<Project> {
+    load(p,parse_srec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ihex_data() {
+        let img = parse_ihex(":0300300002337A1E\n:0400000000010203F6\n:00000001FF\n").unwrap();
+
+        assert_eq!(img.chunks,vec![(0,vec![0,1,2,3]),(0x30,vec![0x02,0x33,0x7a])]);
+        assert_eq!(img.entry,None);
+        assert_eq!(img.end(),0x33);
+    }
+
+    #[test]
+    fn ihex_extended() {
+        let img = parse_ihex(":020000021000EC\n:02000000AABB99\n:020000040001F9\n:02001000CCDD45\n:0400000500000100F6\n:00000001FF\n").unwrap();
+
+        assert_eq!(img.chunks,vec![(0x10000,vec![0xaa,0xbb]),(0x10010,vec![0xcc,0xdd])]);
+        assert_eq!(img.entry,Some(0x100));
+
+        let img = parse_ihex(":0400000310000020C9\n:00000001FF\n").unwrap();
+
+        assert_eq!(img.entry,Some(0x10020));
+    }
+
+    #[test]
+    fn ihex_checksum() {
+        assert!(parse_ihex(":0400000000010203F7\n").is_err());
+        assert!(parse_ihex("0400000000010203F6\n").is_err());
+        assert!(parse_ihex(":04000000000102F6\n").is_err());
+    }
+
+    #[test]
+    fn srec_data() {
+        let img = parse_srec("S00600004844521B\nS1070000010203FFF3\nS1050010AABB85\nS9030000FC\n").unwrap();
+
+        assert_eq!(img.chunks,vec![(0,vec![1,2,3,0xff]),(0x10,vec![0xaa,0xbb])]);
+        assert_eq!(img.entry,Some(0));
+    }
+
+    #[test]
+    fn srec_checksum() {
+        assert!(parse_srec("S1070000010203FFF4\n").is_err());
+        assert!(parse_srec("S4070000010203FFF3\n").is_err());
+    }
+
+    #[test]
+    fn srec_address_sizes() {
+        let img = parse_srec("S2060100000102F5\nS3061234567809DC\nS70512345678E6\n").unwrap();
+
+        assert_eq!(img.chunks,vec![(0x10000,vec![1,2]),(0x12345678,vec![9])]);
+        assert_eq!(img.entry,Some(0x12345678));
+        assert_eq!(parse_srec("S804010000FA\n").unwrap().entry,Some(0x10000));
+    }
+
+    #[test]
+    fn gaps_are_undefined() {
+        let img = parse_srec("S1050000AABB95\nS1050010CCDD41\n").unwrap();
+        let reg = img.to_region("flash".to_string()).unwrap();
+        let cells = reg.iter().collect::<Vec<_>>();
+
+        assert_eq!(cells.len(),0x12);
+        assert_eq!(cells[0],Some(0xaa));
+        assert_eq!(cells[1],Some(0xbb));
+        assert_eq!(cells[2],None);
+        assert_eq!(cells[0x0f],None);
+        assert_eq!(cells[0x10],Some(0xcc));
+        assert_eq!(cells[0x11],Some(0xdd));
+    }
+
+    #[test]
+    fn overlap() {
+        assert!(parse_srec("S1050000AABB95\nS1050001CCDD50\n").is_err());
+    }
+}
@@ -0,0 +1,407 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Stack frame recovery.
+//!
+//! Pushing, popping and allocating stack space is plain RREIL arithmetic on the stack pointer.
+//! `recover_frame` follows the stack pointer through a function in SSA form and computes its
+//! offset from the value at function entry (the stack delta) at every mnemonic. Memory accesses
+//! with an address at a known offset are accesses to stack slots. Slots below the entry stack
+//! pointer are local variables, slots at or above it are arguments (or the return address).
+//! Slots that are written the entry value of a register are register spills.
+//!
+//! Returns with a non-zero or unknown stack delta are recorded as unbalanced. Either the
+//! function cleans up its arguments (like `stdcall` functions) or the analysis lost track of the
+//! stack pointer.
+
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::{HashMap,HashSet,BTreeMap};
+
+use graph_algos::{
+    GraphTrait,
+    VertexListGraphTrait,
+    IncidenceGraphTrait,
+};
+use graph_algos::dominator::immediate_dominator;
+
+use {
+    Function,
+    ControlFlowTarget,
+    BasicBlock,
+    Statement,
+    Operation,
+    Rvalue,
+    Lvalue,
+};
+use memory::stack_offsets;
+
+/// What a stack slot is used for.
+#[derive(RustcDecodable,RustcEncodable,Clone,Debug,PartialEq,Eq)]
+pub enum SlotKind {
+    /// Local variable
+    Local,
+    /// Saved entry value of the named register
+    Spill(String),
+    /// Slot in the caller's frame, like stack arguments or the return address
+    Argument,
+}
+
+/// Single variable in a stack frame.
+#[derive(RustcDecodable,RustcEncodable,Clone,Debug,PartialEq,Eq)]
+pub struct StackSlot {
+    /// Offset from the stack pointer value at function entry
+    pub offset: i64,
+    /// Size of the largest access in bits
+    pub size: usize,
+    /// Display name, `var_<n>` for locals and spills, `arg_<n>` for arguments
+    pub name: String,
+    /// Usage of the slot
+    pub kind: SlotKind,
+}
+
+impl StackSlot {
+    /// New slot at `offset`, named after its offset.
+    pub fn new(offset: i64, size: usize, kind: SlotKind) -> StackSlot {
+        let name = match kind {
+            SlotKind::Argument => format!("arg_{:x}",offset),
+            _ => format!("var_{:x}",-offset),
+        };
+
+        StackSlot{
+            offset: offset,
+            size: size,
+            name: name,
+            kind: kind,
+        }
+    }
+}
+
+/// Recovered stack frame layout of a function.
+#[derive(RustcDecodable,RustcEncodable,Clone,Debug,PartialEq)]
+pub struct Frame {
+    /// Bytes allocated below the entry stack pointer
+    pub size: u64,
+    /// Stack slots, sorted by offset
+    pub slots: Vec<StackSlot>,
+    /// Stack delta at the start of each mnemonic. Mnemonics with an unknown delta are missing.
+    pub deltas: BTreeMap<u64,i64>,
+    /// Address of the last mnemonic and the stack delta of each return that doesn't restore the
+    /// stack pointer. None if the delta is unknown.
+    pub unbalanced: Vec<(u64,Option<i64>)>,
+    /// Address of the mnemonic and the name of the variable holding the address of a load or
+    /// store to the offset of the accessed stack slot.
+    pub accesses: HashMap<(u64,String),i64>,
+}
+
+impl Frame {
+    /// Empty frame.
+    pub fn new() -> Frame {
+        Frame{
+            size: 0,
+            slots: vec![],
+            deltas: BTreeMap::new(),
+            unbalanced: vec![],
+            accesses: HashMap::new(),
+        }
+    }
+
+    /// Stack slot accessed by the mnemonic at `address` when loading from or storing to the
+    /// address in the variable `name`.
+    pub fn slot(&self, address: u64, name: &str) -> Option<&StackSlot> {
+        self.accesses.get(&(address,name.to_string())).and_then(|&o| {
+            self.slots.iter().find(|s| s.offset == o)
+        })
+    }
+
+    /// True if all returns restore the stack pointer.
+    pub fn is_balanced(&self) -> bool {
+        self.unbalanced.is_empty()
+    }
+}
+
+/// Last SSA subscript of `name` assigned in `bb`.
+fn last_definition(bb: &BasicBlock, name: &Cow<'static,str>) -> Option<usize> {
+    let mut ret = None;
+
+    bb.execute(|i| {
+        match i {
+            &Statement{ op: Operation::Store(..),.. } => {},
+            &Statement{ assignee: Lvalue::Variable{ name: ref n, subscript: Some(s),.. },.. } if n == name => ret = Some(s),
+            _ => {},
+        }
+    });
+
+    ret
+}
+
+/// Recovers the stack frame of `func` using `stack_pointer` as stack pointer register. `func`
+/// must be in SSA form.
+pub fn recover_frame(func: &Function, stack_pointer: &Lvalue) -> Frame {
+    let mut frame = Frame::new();
+    let sp = match stack_pointer {
+        &Lvalue::Variable{ ref name,.. } => name.clone(),
+        _ => return frame,
+    };
+    let entry = match func.entry_point {
+        Some(vx) => vx,
+        None => return frame,
+    };
+    let cfg = &func.cflow_graph;
+    let idom = immediate_dominator(entry,cfg);
+    let offsets = stack_offsets(func,stack_pointer);
+    let mut init = HashSet::<(Cow<'static,str>,usize)>::new();
+    let mut slots = BTreeMap::<i64,StackSlot>::new();
+
+    for vx in cfg.vertices() {
+        if let Some(&ControlFlowTarget::Resolved(ref bb)) = cfg.vertex_label(vx) {
+            for mne in bb.mnemonics.iter().filter(|m| m.opcode == "__init") {
+                for stmt in mne.instructions.iter() {
+                    if let Lvalue::Variable{ ref name, subscript: Some(s),.. } = stmt.assignee {
+                        init.insert((name.clone(),s));
+                    }
+                }
+            }
+        }
+    }
+
+    let delta = |s: Option<usize>| -> Option<i64> {
+        match s {
+            Some(s) => offsets.get(&(sp.clone(),s)).cloned(),
+            None => Some(0),
+        }
+    };
+
+    for vx in cfg.vertices() {
+        let bb = match cfg.vertex_label(vx) {
+            Some(&ControlFlowTarget::Resolved(ref bb)) => bb,
+            _ => continue,
+        };
+
+        // stack pointer at the start of the block is the last one defined in a dominator
+        let mut cur = None;
+        let mut dom = vx;
+
+        while let Some(&next) = idom.get(&dom) {
+            if next == dom {
+                break;
+            }
+
+            dom = next;
+            cur = match cfg.vertex_label(dom) {
+                Some(&ControlFlowTarget::Resolved(ref bb)) => last_definition(bb,&sp),
+                _ => None,
+            };
+
+            if cur.is_some() {
+                break;
+            }
+        }
+
+        let mut last = None;
+
+        for mne in bb.mnemonics.iter() {
+            if !mne.opcode.starts_with("__") {
+                let d = delta(cur);
+
+                if let Some(d) = d {
+                    frame.deltas.insert(mne.area.start,d);
+                }
+                last = Some((mne.area.start,d));
+            }
+
+            for stmt in mne.instructions.iter() {
+                let addr = match &stmt.op {
+                    &Operation::Load(_,ref a) | &Operation::Store(_,ref a) => a.clone(),
+                    _ => Rvalue::Undefined,
+                };
+
+                match stmt {
+                    &Statement{ op: Operation::Store(..),.. } => {},
+                    &Statement{ assignee: Lvalue::Variable{ ref name, subscript: Some(s),.. },.. } if *name == sp => cur = Some(s),
+                    _ => {},
+                }
+
+                let (ptr,off) = match &addr {
+                    &Rvalue::Variable{ ref name, subscript: Some(s), offset: 0,.. } => match offsets.get(&(name.clone(),s)) {
+                        Some(&o) => (name.clone(),o),
+                        None => continue,
+                    },
+                    &Rvalue::Variable{ ref name, subscript: None, offset: 0,.. } if *name == sp => (name.clone(),0),
+                    _ => continue,
+                };
+                let (size,spill) = match (&stmt.op,&stmt.assignee) {
+                    (&Operation::Store(..),&Lvalue::Variable{ ref name, size, subscript: Some(s) }) if *name != sp && init.contains(&(name.clone(),s)) =>
+                        (size,Some(name.to_string())),
+                    (_,&Lvalue::Variable{ size,.. }) => (size,None),
+                    _ => continue,
+                };
+
+                let kind = match spill {
+                    _ if off >= 0 => SlotKind::Argument,
+                    Some(reg) => SlotKind::Spill(reg),
+                    None => SlotKind::Local,
+                };
+                let slot = slots.entry(off).or_insert(StackSlot::new(off,size,kind.clone()));
+
+                slot.size = cmp::max(slot.size,size);
+                if slot.kind == SlotKind::Local {
+                    slot.kind = kind;
+                }
+
+                frame.accesses.insert((mne.area.start,ptr.to_string()),off);
+            }
+        }
+
+        if cfg.out_degree(vx) == 0 {
+            if let Some((addr,d)) = last {
+                if d != Some(0) {
+                    frame.unbalanced.push((addr,d));
+                }
+            }
+        }
+    }
+
+    let lowest = frame.deltas.values().cloned().chain(slots.keys().cloned()).min().unwrap_or(0);
+
+    frame.size = cmp::max(0,-lowest) as u64;
+    frame.slots = slots.into_iter().map(|(_,s)| s).collect();
+    frame.unbalanced.sort();
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use graph_algos::MutableGraphTrait;
+    use {
+        Function,
+        ControlFlowTarget,
+        BasicBlock,
+        Mnemonic,
+        Statement,
+        Operation,
+        Rvalue,
+        Guard,
+        ssa_convertion,
+    };
+    use test_util::var;
+
+    fn mne(addr: u64, stmts: Vec<Statement>) -> Mnemonic {
+        Mnemonic::new(addr..addr + 1,"test".to_string(),"".to_string(),vec![].iter(),stmts.iter()).unwrap()
+    }
+
+    /*
+     * bb0:
+     *   SP = SP - 4; store FP, SP    (push FP)
+     *   FP = SP
+     *   SP = SP - 8
+     *   t = FP - 8; load [FP-8], t
+     *   f = ?
+     * bb1 (if f):
+     *   u = SP + 16; store [SP+16], u
+     * bb2:
+     *   SP = FP
+     *   load FP, SP; SP = SP + 4     (pop FP)
+     *   ret
+     */
+    #[test]
+    fn push_and_pop() {
+        let mne0 = mne(0,vec![
+            Statement{ op: Operation::Subtract(var("SP",32).into(),Rvalue::new_u32(4)), assignee: var("SP",32) },
+            Statement{ op: Operation::Store(Cow::Borrowed("ram"),var("SP",32).into()), assignee: var("FP",32) },
+        ]);
+        let mne1 = mne(1,vec![
+            Statement{ op: Operation::Move(var("SP",32).into()), assignee: var("FP",32) },
+        ]);
+        let mne2 = mne(2,vec![
+            Statement{ op: Operation::Subtract(var("SP",32).into(),Rvalue::new_u32(8)), assignee: var("SP",32) },
+        ]);
+        let mne3 = mne(3,vec![
+            Statement{ op: Operation::Subtract(var("FP",32).into(),Rvalue::new_u32(8)), assignee: var("t",32) },
+            Statement{ op: Operation::Load(Cow::Borrowed("ram"),var("t",32).into()), assignee: var("[FP-8]",32) },
+            Statement{ op: Operation::Move(Rvalue::Undefined), assignee: var("f",1) },
+        ]);
+        let mne4 = mne(4,vec![
+            Statement{ op: Operation::Add(var("SP",32).into(),Rvalue::new_u32(16)), assignee: var("u",32) },
+            Statement{ op: Operation::Store(Cow::Borrowed("ram"),var("u",32).into()), assignee: var("[SP+16]",16) },
+        ]);
+        let mne5 = mne(5,vec![
+            Statement{ op: Operation::Move(var("FP",32).into()), assignee: var("SP",32) },
+        ]);
+        let mne6 = mne(6,vec![
+            Statement{ op: Operation::Load(Cow::Borrowed("ram"),var("SP",32).into()), assignee: var("FP",32) },
+            Statement{ op: Operation::Add(var("SP",32).into(),Rvalue::new_u32(4)), assignee: var("SP",32) },
+        ]);
+        let mne7 = mne(7,vec![]);
+        let mut func = Function::new("test".to_string(),"ram".to_string());
+        let vx0 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne0,mne1,mne2,mne3])));
+        let vx1 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne4])));
+        let vx2 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne5,mne6,mne7])));
+        let g = Guard::from_flag(&var("f",1).into()).unwrap();
+
+        func.cflow_graph.add_edge(g.clone(),vx0,vx1);
+        func.cflow_graph.add_edge(g.negation(),vx0,vx2);
+        func.cflow_graph.add_edge(Guard::always(),vx1,vx2);
+        func.entry_point = Some(vx0);
+
+        ssa_convertion(&mut func);
+
+        let frame = recover_frame(&func,&var("SP",32));
+        let deltas = frame.deltas.iter().map(|(&a,&d)| (a,d)).collect::<Vec<_>>();
+
+        assert_eq!(deltas,vec![(0,0),(1,-4),(2,-4),(3,-12),(4,-12),(5,-12),(6,-4),(7,0)]);
+        assert!(frame.is_balanced());
+        assert_eq!(frame.size,12);
+        assert_eq!(frame.slots,vec![
+            StackSlot{ offset: -12, size: 32, name: "var_c".to_string(), kind: SlotKind::Local },
+            StackSlot{ offset: -4, size: 32, name: "var_4".to_string(), kind: SlotKind::Spill("FP".to_string()) },
+            StackSlot{ offset: 4, size: 16, name: "arg_4".to_string(), kind: SlotKind::Argument },
+        ]);
+        assert_eq!(frame.slot(3,"t").map(|s| s.name.clone()),Some("var_c".to_string()));
+        assert_eq!(frame.slot(4,"u").map(|s| s.name.clone()),Some("arg_4".to_string()));
+        assert_eq!(frame.slot(6,"SP").map(|s| s.offset),Some(-4));
+        assert_eq!(frame.slot(3,"[FP-8]"),None);
+        assert_eq!(frame.slot(6,"FP"),None);
+        assert_eq!(frame.slot(5,"FP"),None);
+    }
+
+    #[test]
+    fn unbalanced() {
+        let mne0 = mne(0,vec![
+            Statement{ op: Operation::Subtract(var("SP",32).into(),Rvalue::new_u32(4)), assignee: var("SP",32) },
+        ]);
+        let mne1 = mne(1,vec![
+            Statement{ op: Operation::Move(Rvalue::Undefined), assignee: var("SP",32) },
+        ]);
+        let mne2 = mne(2,vec![]);
+        let mut func = Function::new("test".to_string(),"ram".to_string());
+        let vx0 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne0,mne1,mne2])));
+
+        func.entry_point = Some(vx0);
+        ssa_convertion(&mut func);
+
+        let frame = recover_frame(&func,&var("SP",32));
+
+        assert_eq!(frame.deltas.get(&1),Some(&-4));
+        assert_eq!(frame.deltas.get(&2),None);
+        assert_eq!(frame.unbalanced,vec![(2,None)]);
+        assert!(!frame.is_balanced());
+    }
+}
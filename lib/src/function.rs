@@ -61,6 +61,7 @@ use {
     Mnemonic,
     Statement,
     Operation,
+    Frame,
 };
 
 /// Node of the function graph.
@@ -94,6 +95,8 @@ pub struct Function {
     pub entry_point: Option<ControlFlowRef>,
     /// Name of the memory region the function is part of
     pub region: String,
+    /// Recovered stack frame layout. None if the architecture has no stack pointer.
+    pub frame: Option<Frame>,
 }
 
 #[derive(Clone,PartialEq,Eq,Debug)]
@@ -111,6 +114,7 @@ impl Function {
             cflow_graph: AdjacencyList::new(),
             entry_point: None,
             region: reg,
+            frame: None,
         }
     }
 
@@ -122,6 +126,7 @@ impl Function {
             cflow_graph: AdjacencyList::new(),
             entry_point: None,
             region: reg,
+            frame: None,
         }
     }
 
@@ -440,23 +445,34 @@ impl Function {
             cflow_graph: cfg,
             entry_point: e,
             region: reg.name().clone(),
+            frame: None,
         }
     }
 
     /// Returns all call targets.
     pub fn collect_calls(&self) -> Vec<Rvalue> {
+        let ret = self.collect_call_sites().into_iter().map(|(_,t)| t).collect::<Vec<_>>();
+
+        debug!("collected calls: {:?}",ret);
+        ret
+    }
+
+    /// Returns the address of the calling mnemonic and the call target of all calls.
+    pub fn collect_call_sites(&self) -> Vec<(u64,Rvalue)> {
         let mut ret = Vec::new();
 
         for vx in self.cflow_graph.vertices() {
             if let Some(&ControlFlowTarget::Resolved(ref bb)) = self.cflow_graph.vertex_label(vx) {
-                bb.execute(|i| match i {
-                    &Statement{ op: Operation::Call(ref t), ..} => ret.push(t.clone()),
-                    _ => {}
-                });
+                for mne in bb.mnemonics.iter() {
+                    for i in mne.instructions.iter() {
+                        if let &Statement{ op: Operation::Call(ref t), ..} = i {
+                            ret.push((mne.area.start,t.clone()));
+                        }
+                    }
+                }
             }
         }
 
-        debug!("collected calls: {:?}",ret);
         ret
     }
 
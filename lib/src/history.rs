@@ -0,0 +1,452 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Undo and redo of user edits.
+//!
+//! Every change the user makes to a project is an `Edit`. Applying an edit returns its inverse,
+//! which is pushed onto the undo stack of the project's `History`. Undoing an edit applies the
+//! inverse and pushes the inverse of that onto the redo stack. Results of automatic analysis
+//! like newly disassembled functions are not part of the history.
+//!
+//! The history is saved with the project.
+
+use graph_algos::{GraphTrait,VertexListGraphTrait,MutableGraphTrait,IncidenceGraphTrait,BidirectionalGraphTrait};
+use uuid::Uuid;
+
+use project::Project;
+use program::CallTarget;
+use function::Function;
+use result::Result;
+
+/// Maximal number of edits kept on the undo stack.
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// A single, reversible change to a project.
+#[derive(RustcDecodable,RustcEncodable,Debug)]
+pub enum Edit {
+    /// Renames the function with UUID `function` to `name`.
+    Rename{
+        /// UUID of the function
+        function: Uuid,
+        /// New name
+        name: String,
+    },
+    /// Sets the comment at `offset` in region `region`. `None` removes the comment.
+    Comment{
+        /// Name of the memory region
+        region: String,
+        /// Offset inside the region
+        offset: u64,
+        /// New comment
+        comment: Option<String>,
+    },
+    /// Adds `function` to the program with UUID `program`. Fails if there already is a call
+    /// target with the same UUID.
+    CreateFunction{
+        /// UUID of the program
+        program: Uuid,
+        /// New function
+        function: Function,
+    },
+    /// Removes the function with UUID `function` from the program with UUID `program`. Unnamed,
+    /// not yet disassembled call targets that are only called by the function are removed too.
+    DeleteFunction{
+        /// UUID of the program
+        program: Uuid,
+        /// UUID of the function
+        function: Uuid,
+    },
+    /// Replaces the function with the same UUID as `function` in the program with UUID
+    /// `program`. Used to change the bounds of a function by replacing it with a version that
+    /// was disassembled with different entry points.
+    ReplaceFunction{
+        /// UUID of the program
+        program: Uuid,
+        /// New version of the function
+        function: Function,
+    },
+}
+
+impl Edit {
+    /// Returns an error if the edit can't be applied to `proj`.
+    pub fn check(&self, proj: &Project) -> Result<()> {
+        match self {
+            &Edit::Rename{ ref function,.. } => {
+                try!(proj.find_function_by_uuid(function).ok_or(format!("no function with UUID {}",function)));
+            },
+            &Edit::Comment{ .. } => {},
+            &Edit::CreateFunction{ ref program, ref function } => {
+                let prog = try!(proj.find_program_by_uuid(program).ok_or(format!("no program with UUID {}",program)));
+
+                if prog.find_call_target_by_uuid(&function.uuid).is_some() {
+                    return Err(format!("program already has a call target with UUID {}",function.uuid).into());
+                }
+            },
+            &Edit::DeleteFunction{ ref program, function: uu } |
+            &Edit::ReplaceFunction{ ref program, function: Function{ uuid: uu,.. } } => {
+                let prog = try!(proj.find_program_by_uuid(program).ok_or(format!("no program with UUID {}",program)));
+                let vx = try!(prog.find_call_target_by_uuid(&uu).ok_or(format!("no function with UUID {}",uu)));
+
+                match prog.call_graph.vertex_label(vx) {
+                    Some(&CallTarget::Concrete(_)) => {},
+                    _ => return Err(format!("call target {} is not a function",uu).into()),
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Applies the edit to `proj` and returns its inverse.
+    pub fn apply(self, proj: &mut Project) -> Result<Edit> {
+        try!(self.check(proj));
+
+        match self {
+            Edit::Rename{ function, name } => {
+                let func = try!(proj.find_function_by_uuid_mut(&function).ok_or(format!("no function with UUID {}",function)));
+                let old = ::std::mem::replace(&mut func.name,name);
+
+                Ok(Edit::Rename{ function: function, name: old })
+            },
+            Edit::Comment{ region, offset, comment } => {
+                let key = (region.clone(),offset);
+                let old = match comment {
+                    Some(c) => proj.comments.insert(key,c),
+                    None => proj.comments.remove(&key),
+                };
+
+                Ok(Edit::Comment{ region: region, offset: offset, comment: old })
+            },
+            Edit::CreateFunction{ program, function } => {
+                let prog = try!(proj.find_program_by_uuid_mut(&program).ok_or(format!("no program with UUID {}",program)));
+                let uu = function.uuid;
+
+                prog.insert(CallTarget::Concrete(function));
+                Ok(Edit::DeleteFunction{ program: program, function: uu })
+            },
+            Edit::DeleteFunction{ program, function } => {
+                let prog = try!(proj.find_program_by_uuid_mut(&program).ok_or(format!("no program with UUID {}",program)));
+                let vx = try!(prog.find_call_target_by_uuid(&function).ok_or(format!("no function with UUID {}",function)));
+                let orphans = prog.call_graph.out_edges(vx).map(|e| prog.call_graph.target(e)).filter_map(|w| {
+                    match prog.call_graph.vertex_label(w) {
+                        Some(&CallTarget::Todo(_,None,uu)) if prog.call_graph.in_degree(w) == 1 => Some(uu),
+                        _ => None,
+                    }
+                }).collect::<Vec<_>>();
+
+                let func = match prog.call_graph.remove_vertex(vx) {
+                    Some(CallTarget::Concrete(f)) => f,
+                    _ => return Err(format!("failed to remove function {}",function).into()),
+                };
+
+                // vertex descriptors aren't stable across removals
+                for uu in orphans {
+                    let maybe_vx = prog.find_call_target_by_uuid(&uu);
+
+                    if let Some(w) = maybe_vx {
+                        prog.call_graph.remove_vertex(w);
+                    }
+                }
+
+                Ok(Edit::CreateFunction{ program: program, function: func })
+            },
+            Edit::ReplaceFunction{ program, function } => {
+                let prog = try!(proj.find_program_by_uuid_mut(&program).ok_or(format!("no program with UUID {}",program)));
+                let uu = function.uuid;
+                let vx = try!(prog.find_call_target_by_uuid(&uu).ok_or(format!("no function with UUID {}",uu)));
+                let old = match prog.call_graph.vertex_label_mut(vx) {
+                    Some(&mut CallTarget::Concrete(ref mut f)) => ::std::mem::replace(f,function),
+                    _ => return Err(format!("call target {} is not a function",uu).into()),
+                };
+
+                Ok(Edit::ReplaceFunction{ program: program, function: old })
+            },
+        }
+    }
+}
+
+/// Undo and redo stacks of a project.
+#[derive(RustcDecodable,RustcEncodable,Debug)]
+pub struct History {
+    /// Inverses of the applied edits, most recent last
+    pub undo: Vec<Edit>,
+    /// Inverses of the undone edits, most recent last
+    pub redo: Vec<Edit>,
+}
+
+impl History {
+    /// Returns an empty history.
+    pub fn new() -> History {
+        History{ undo: vec![], redo: vec![] }
+    }
+
+    /// True if there is an edit to undo.
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    /// True if there is an edit to redo.
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Records the inverse of a new edit. Clears the redo stack.
+    fn push(&mut self, inv: Edit) {
+        self.undo.push(inv);
+        self.redo.clear();
+
+        if self.undo.len() > HISTORY_LIMIT {
+            let excess = self.undo.len() - HISTORY_LIMIT;
+            self.undo.drain(0..excess);
+        }
+    }
+}
+
+impl Project {
+    /// Applies `e` and makes it undoable.
+    pub fn edit(&mut self, e: Edit) -> Result<()> {
+        let inv = try!(e.apply(self));
+
+        self.history.push(inv);
+        Ok(())
+    }
+
+    /// Reverts the last edit. Returns false if there is nothing to undo. If the edit can't be
+    /// reverted it stays on the undo stack.
+    pub fn undo(&mut self) -> Result<bool> {
+        if let Some(inv) = self.history.undo.last() {
+            try!(inv.check(self));
+        }
+
+        match self.history.undo.pop() {
+            Some(inv) => {
+                let redo = try!(inv.apply(self));
+
+                self.history.redo.push(redo);
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Applies the last undone edit again. Returns false if there is nothing to redo. If the
+    /// edit can't be applied it stays on the redo stack.
+    pub fn redo(&mut self) -> Result<bool> {
+        if let Some(e) = self.history.redo.last() {
+            try!(e.check(self));
+        }
+
+        match self.history.redo.pop() {
+            Some(e) => {
+                let inv = try!(e.apply(self));
+
+                self.history.undo.push(inv);
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph_algos::{GraphTrait,MutableGraphTrait,VertexListGraphTrait,EdgeListGraphTrait};
+    use {
+        Project,
+        Rvalue,
+        Lvalue,
+        Statement,
+        Operation,
+        Program,
+        Function,
+        CallTarget,
+        ControlFlowTarget,
+        BasicBlock,
+        Mnemonic,
+        Region,
+    };
+
+    fn function(name: &str, start: u64, end: u64) -> Function {
+        let mut func = Function::new(name.to_string(),"base".to_string());
+        let mne = Mnemonic::new(start..end,"nop".to_string(),"".to_string(),vec![].iter(),vec![].iter()).unwrap();
+        let vx = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne])));
+
+        func.entry_point = Some(vx);
+        func
+    }
+
+    fn caller(name: &str, start: u64, end: u64, callee: u64) -> Function {
+        let mut func = Function::new(name.to_string(),"base".to_string());
+        let i = vec![Statement{ op: Operation::Call(Rvalue::new_u64(callee)), assignee: Lvalue::Undefined }];
+        let mne = Mnemonic::new(start..end,"call".to_string(),"".to_string(),vec![].iter(),i.iter()).unwrap();
+        let vx = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne])));
+
+        func.entry_point = Some(vx);
+        func
+    }
+
+    fn project() -> (Project,::uuid::Uuid,::uuid::Uuid) {
+        let mut proj = Project::new("test".to_string(),Region::undefined("base".to_string(),0x100));
+        let mut prog = Program::new("prog0");
+        let func = function("main",0,4);
+        let ret = (prog.uuid,func.uuid);
+
+        prog.call_graph.add_vertex(CallTarget::Concrete(func));
+        proj.code.push(prog);
+        (proj,ret.0,ret.1)
+    }
+
+    #[test]
+    fn rename_and_comment() {
+        let (mut proj,_,func) = project();
+
+        proj.edit(Edit::Rename{ function: func, name: "start".to_string() }).unwrap();
+        proj.edit(Edit::Comment{ region: "base".to_string(), offset: 2, comment: Some("a".to_string()) }).unwrap();
+        proj.edit(Edit::Comment{ region: "base".to_string(), offset: 2, comment: Some("b".to_string()) }).unwrap();
+
+        assert_eq!(proj.find_function_by_uuid(&func).unwrap().name,"start");
+        assert_eq!(proj.comments.get(&("base".to_string(),2)),Some(&"b".to_string()));
+
+        assert!(proj.undo().unwrap());
+        assert_eq!(proj.comments.get(&("base".to_string(),2)),Some(&"a".to_string()));
+        assert!(proj.undo().unwrap());
+        assert!(proj.comments.is_empty());
+        assert!(proj.undo().unwrap());
+        assert_eq!(proj.find_function_by_uuid(&func).unwrap().name,"main");
+        assert!(!proj.undo().unwrap());
+
+        assert!(proj.redo().unwrap());
+        assert!(proj.redo().unwrap());
+        assert_eq!(proj.find_function_by_uuid(&func).unwrap().name,"start");
+        assert_eq!(proj.comments.get(&("base".to_string(),2)),Some(&"a".to_string()));
+
+        // a new edit discards the redo stack
+        proj.edit(Edit::Comment{ region: "base".to_string(), offset: 3, comment: None }).unwrap();
+        assert!(!proj.history.can_redo());
+        assert!(!proj.redo().unwrap());
+    }
+
+    #[test]
+    fn create_and_delete_function() {
+        let (mut proj,prog,func) = project();
+        let new = function("helper",0x10,0x14);
+        let new_uu = new.uuid;
+
+        proj.edit(Edit::CreateFunction{ program: prog, function: new }).unwrap();
+        assert!(proj.find_function_by_uuid(&new_uu).is_some());
+
+        proj.edit(Edit::DeleteFunction{ program: prog, function: func }).unwrap();
+        assert!(proj.find_function_by_uuid(&func).is_none());
+        assert_eq!(proj.code[0].call_graph.num_vertices(),1);
+
+        assert!(proj.undo().unwrap());
+        assert_eq!(proj.find_function_by_uuid(&func).map(|f| f.name.clone()),Some("main".to_string()));
+        assert!(proj.undo().unwrap());
+        assert!(proj.find_function_by_uuid(&new_uu).is_none());
+        assert!(proj.redo().unwrap());
+        assert!(proj.find_function_by_uuid(&new_uu).is_some());
+
+        assert!(proj.edit(Edit::DeleteFunction{ program: prog, function: ::uuid::Uuid::new_v4() }).is_err());
+        assert_eq!(proj.history.undo.len(),1);
+    }
+
+    #[test]
+    fn undo_delete_restores_callers() {
+        let (mut proj,prog,_) = project();
+        let helper = function("helper",0x10,0x14);
+        let helper_uu = helper.uuid;
+
+        proj.edit(Edit::CreateFunction{ program: prog, function: helper }).unwrap();
+        proj.edit(Edit::CreateFunction{ program: prog, function: caller("start",0x20,0x24,0x10) }).unwrap();
+        assert_eq!(proj.code[0].call_graph.num_vertices(),3);
+        assert_eq!(proj.code[0].call_graph.num_edges(),1);
+
+        proj.edit(Edit::DeleteFunction{ program: prog, function: helper_uu }).unwrap();
+        assert_eq!(proj.code[0].call_graph.num_vertices(),2);
+        assert_eq!(proj.code[0].call_graph.num_edges(),0);
+
+        assert!(proj.undo().unwrap());
+        assert_eq!(proj.code[0].call_graph.num_vertices(),3);
+        assert_eq!(proj.code[0].call_graph.num_edges(),1);
+    }
+
+    #[test]
+    fn undo_create_removes_callees() {
+        let (mut proj,prog,_) = project();
+        let start = caller("start",0x20,0x24,0x30);
+
+        proj.edit(Edit::CreateFunction{ program: prog, function: start }).unwrap();
+        assert_eq!(proj.code[0].call_graph.num_vertices(),3);
+        assert_eq!(proj.code[0].call_graph.num_edges(),1);
+        assert!(proj.code[0].next_todo().is_some());
+
+        assert!(proj.undo().unwrap());
+        assert_eq!(proj.code[0].call_graph.num_vertices(),1);
+        assert_eq!(proj.code[0].call_graph.num_edges(),0);
+        assert!(proj.code[0].next_todo().is_none());
+    }
+
+    #[test]
+    fn failed_undo_keeps_edit() {
+        let (mut proj,_,func) = project();
+
+        proj.edit(Edit::Rename{ function: func, name: "start".to_string() }).unwrap();
+
+        let vx = proj.code[0].find_call_target_by_uuid(&func).unwrap();
+        proj.code[0].call_graph.remove_vertex(vx);
+
+        assert!(proj.undo().is_err());
+        assert_eq!(proj.history.undo.len(),1);
+        assert!(!proj.history.can_redo());
+    }
+
+    #[test]
+    fn replace_function() {
+        let (mut proj,prog,func) = project();
+        let mut bigger = function("main",0,8);
+
+        bigger.uuid = func;
+        proj.edit(Edit::ReplaceFunction{ program: prog, function: bigger }).unwrap();
+
+        let end = |proj: &Project| {
+            let f = proj.find_function_by_uuid(&func).unwrap();
+            f.cflow_graph.vertices().filter_map(|vx| match f.cflow_graph.vertex_label(vx) {
+                Some(&ControlFlowTarget::Resolved(ref bb)) => Some(bb.area.end),
+                _ => None,
+            }).max()
+        };
+
+        assert_eq!(end(&proj),Some(8));
+        assert!(proj.undo().unwrap());
+        assert_eq!(end(&proj),Some(4));
+        assert!(proj.redo().unwrap());
+        assert_eq!(end(&proj),Some(8));
+    }
+
+    #[test]
+    fn limit() {
+        let (mut proj,_,_) = project();
+
+        for i in 0..HISTORY_LIMIT + 10 {
+            proj.edit(Edit::Comment{ region: "base".to_string(), offset: 0, comment: Some(format!("{}",i)) }).unwrap();
+        }
+
+        assert_eq!(proj.history.undo.len(),HISTORY_LIMIT);
+    }
+}
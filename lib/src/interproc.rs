@@ -0,0 +1,647 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Inter-procedural abstract interpretation.
+//!
+//! `approximate` in the `abstractinterp` module analyzes a single function and ignores the
+//! effects of `Operation::Call`. This module analyzes all functions of a `Program` and computes a
+//! `Summary` for each of them: the variables the function reads before writing them (its
+//! inputs), the variables it writes, their values when the function returns and how much the
+//! function moves the stack pointer.
+//!
+//! The effects of a call to a function with a summary are made explicit in the caller by
+//! inserting a `__call` mnemonic after the mnemonic doing the call. Like the `__phi` mnemonics of
+//! the SSA form, it has no size. It reads all inputs of the callee, sets all variables written by
+//! the callee to their return value and adjusts the stack pointer. Values of the callee inputs at
+//! all call sites are combined and used as the values of the variables at the callee's entry.
+//!
+//! Functions are re-analyzed until no summary or input changes anymore. Calls to functions without
+//! a summary, like imports, have no effect. Recursion is handled by widening summaries and inputs
+//! after a function was analyzed `WIDENING_DELAY` times.
+
+use std::borrow::Cow;
+use std::collections::{HashMap,HashSet};
+
+use graph_algos::{
+    GraphTrait,
+    VertexListGraphTrait,
+    IncidenceGraphTrait,
+};
+use graph_algos::dominator::immediate_dominator;
+use uuid::Uuid;
+
+use {
+    Program,
+    CallTarget,
+    Function,
+    ControlFlowTarget,
+    ControlFlowRef,
+    Mnemonic,
+    Statement,
+    Operation,
+    Rvalue,
+    Lvalue,
+    Kset,
+    Result,
+    ssa_convertion,
+    ssa_deconstruction,
+};
+use abstractinterp::{
+    Avalue,
+    approximate_with,
+};
+
+/// Number of times a function is analyzed before its summary and inputs are widened.
+pub const WIDENING_DELAY: usize = 3;
+
+/// Effects of calling a function.
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub struct Summary<A: Avalue> {
+    /// Variables read before they are written by the function or its callees, with their size in
+    /// bits.
+    pub inputs: HashMap<Cow<'static,str>,usize>,
+    /// Variables written by the function or its callees, with their size in bits.
+    pub clobbered: HashMap<Cow<'static,str>,usize>,
+    /// Values of written variables when the function returns. Variables that aren't written on
+    /// all paths to a return are missing.
+    pub returns: HashMap<Cow<'static,str>,A>,
+    /// Change of the stack pointer between function entry and return. None if it's not the same
+    /// constant for all returns.
+    pub stack_delta: Option<i64>,
+}
+
+impl<A: Avalue> Summary<A> {
+    /// Upper bound of `self` and `other` that widens the return values.
+    fn widen(&self, other: &Summary<A>) -> Summary<A> {
+        let mut inputs = self.inputs.clone();
+        let mut clobbered = self.clobbered.clone();
+        let mut returns = HashMap::new();
+
+        inputs.extend(other.inputs.iter().map(|(k,v)| (k.clone(),*v)));
+        clobbered.extend(other.clobbered.iter().map(|(k,v)| (k.clone(),*v)));
+
+        for (name,a) in self.returns.iter() {
+            if let Some(b) = other.returns.get(name) {
+                returns.insert(name.clone(),a.widen(b));
+            }
+        }
+
+        Summary{
+            inputs: inputs,
+            clobbered: clobbered,
+            returns: returns,
+            stack_delta: if self.stack_delta == other.stack_delta { self.stack_delta } else { None },
+        }
+    }
+}
+
+/// Result of an inter-procedural abstract interpretation.
+#[derive(Clone,Debug)]
+pub struct Approximation<A: Avalue> {
+    /// Summary of each function, by UUID.
+    pub summaries: HashMap<Uuid,Summary<A>>,
+    /// Values of the function inputs, combined over all call sites.
+    pub inputs: HashMap<Uuid,HashMap<Cow<'static,str>,A>>,
+    /// Values of the SSA variables of each function.
+    pub values: HashMap<Uuid,HashMap<Lvalue,A>>,
+}
+
+/// Results of analyzing a single function.
+struct Analysis<A: Avalue> {
+    summary: Summary<A>,
+    arguments: HashMap<Uuid,HashMap<Cow<'static,str>,A>>,
+    values: HashMap<Lvalue,A>,
+}
+
+type Name = (Cow<'static,str>,usize);
+
+fn mask(size: usize) -> u64 {
+    if size >= 64 { !0 } else { (1 << size) - 1 }
+}
+
+/// Returns the UUID of the function called by `stmt`.
+fn callee(stmt: &Statement, entries: &HashMap<u64,Uuid>) -> Option<Uuid> {
+    match stmt {
+        &Statement{ op: Operation::Call(Rvalue::Constant{ value,.. }),.. } => entries.get(&value).cloned(),
+        _ => None,
+    }
+}
+
+/// Inserts a `__call` mnemonic after each call to a function with a summary.
+fn insert_call_effects<A: Avalue>(func: &mut Function, entries: &HashMap<u64,Uuid>,
+                                  summaries: &HashMap<Uuid,Summary<A>>,
+                                  stack_pointer: Option<&Lvalue>) -> Result<()> {
+    let vxs = func.cflow_graph.vertices().collect::<Vec<_>>();
+    let sp = match stack_pointer {
+        Some(&Lvalue::Variable{ ref name, size,.. }) => Some((name.clone(),size)),
+        _ => None,
+    };
+
+    for vx in vxs {
+        if let Some(&mut ControlFlowTarget::Resolved(ref mut bb)) = func.cflow_graph.vertex_label_mut(vx) {
+            let mut effects = vec![];
+
+            for (idx,mne) in bb.mnemonics.iter().enumerate() {
+                let sum = mne.instructions.iter().filter_map(|s| callee(s,entries)).next().and_then(|uu| summaries.get(&uu));
+
+                if let Some(sum) = sum {
+                    let mut inputs = sum.inputs.iter().collect::<Vec<_>>();
+                    let mut clobbered = sum.clobbered.iter().collect::<Vec<_>>();
+                    let mut stmts = vec![];
+
+                    inputs.sort();
+                    clobbered.sort();
+
+                    for (name,&size) in inputs {
+                        stmts.push(Statement{
+                            op: Operation::Move(Rvalue::Variable{ name: name.clone(), size: size, offset: 0, subscript: None }),
+                            assignee: Lvalue::Undefined,
+                        });
+                    }
+
+                    for (name,&size) in clobbered {
+                        let assignee = Lvalue::Variable{ name: name.clone(), size: size, subscript: None };
+                        let op = match (&sp,sum.stack_delta) {
+                            (&Some((ref n,sz)),Some(delta)) if n == name && sz == size => {
+                                let sp = Rvalue::Variable{ name: name.clone(), size: size, offset: 0, subscript: None };
+                                Operation::Add(sp,Rvalue::Constant{ value: (delta as u64) & mask(size), size: size })
+                            },
+                            _ => Operation::Move(Rvalue::Undefined),
+                        };
+
+                        stmts.push(Statement{ op: op, assignee: assignee });
+                    }
+
+                    let end = mne.area.end;
+                    let call = try!(Mnemonic::new(end..end,"__call".to_string(),"".to_string(),vec![].iter(),stmts.iter()));
+
+                    effects.push((idx + 1,call));
+                }
+            }
+
+            for (idx,call) in effects.into_iter().rev() {
+                bb.mnemonics.insert(idx,call);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Values of the variables in `names` at all returns of `func`, combined. Variables not
+/// written on all paths to a return are missing.
+fn exit_values<A: Avalue>(func: &Function, vals: &HashMap<Name,A>, names: &HashMap<Cow<'static,str>,usize>) -> HashMap<Cow<'static,str>,A> {
+    let cfg = &func.cflow_graph;
+    let idom = immediate_dominator(func.entry_point.unwrap(),cfg);
+    let mut ret = HashMap::<Cow<'static,str>,A>::new();
+    let mut missing = HashSet::<Cow<'static,str>>::new();
+    let exits = cfg.vertices().filter(|&vx| {
+        cfg.out_degree(vx) == 0 && match cfg.vertex_label(vx) {
+            Some(&ControlFlowTarget::Resolved(_)) => true,
+            _ => false,
+        }
+    }).collect::<Vec<ControlFlowRef>>();
+
+    for vx in exits {
+        for name in names.keys() {
+            let mut cur = vx;
+            let mut def = None;
+
+            loop {
+                if let Some(&ControlFlowTarget::Resolved(ref bb)) = cfg.vertex_label(cur) {
+                    bb.execute(|i| {
+                        if let Lvalue::Variable{ name: ref n, subscript: Some(subscript),.. } = i.assignee {
+                            if n == name {
+                                def = Some(subscript);
+                            }
+                        }
+                    });
+                }
+
+                match (def,idom.get(&cur)) {
+                    (None,Some(&next)) if next != cur => cur = next,
+                    _ => break,
+                }
+            }
+
+            match def.and_then(|s| vals.get(&(name.clone(),s))) {
+                Some(v) => {
+                    let v = match ret.get(name) {
+                        Some(prev) => prev.combine(v),
+                        None => v.clone(),
+                    };
+                    ret.insert(name.clone(),v);
+                },
+                None => { missing.insert(name.clone()); },
+            }
+        }
+    }
+
+    for name in missing {
+        ret.remove(&name);
+    }
+
+    ret
+}
+
+/// Computes the stack pointer change between entry and the returns of `func`.
+fn stack_delta(func: &Function, stack_pointer: &Lvalue) -> Result<Option<i64>> {
+    let (name,size) = match stack_pointer {
+        &Lvalue::Variable{ ref name, size,.. } => (name.clone(),size),
+        _ => return Ok(None),
+    };
+    let mut entry = HashMap::new();
+
+    entry.insert(name.clone(),Kset::abstract_value(&Rvalue::Constant{ value: 0, size: size }));
+
+    let vals = try!(approximate_with::<Kset>(func,&entry,&HashMap::new(),Some(stack_pointer)));
+    let vals = vals.into_iter().filter_map(|(lv,a)| match lv {
+        Lvalue::Variable{ name, subscript: Some(s),.. } => Some(((name,s),a)),
+        _ => None,
+    }).collect::<HashMap<_,_>>();
+    let mut names = HashMap::new();
+
+    names.insert(name.clone(),size);
+
+    match exit_values(func,&vals,&names).get(&name) {
+        Some(&Kset::Set(ref v)) if v.len() == 1 => {
+            let (d,sz) = v[0];
+            let d = if sz > 0 && sz < 64 && d & (1 << (sz - 1)) != 0 { d | !mask(sz) } else { d };
+
+            Ok(Some(d as i64))
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Analyzes `func` using the current `summaries` and the values of its inputs in `inputs`.
+fn analyze<A: Avalue>(func: &mut Function, entries: &HashMap<u64,Uuid>, summaries: &HashMap<Uuid,Summary<A>>,
+                      inputs: &HashMap<Cow<'static,str>,A>, stack_pointer: Option<&Lvalue>) -> Result<Analysis<A>> {
+    ssa_deconstruction(func);
+    try!(insert_call_effects(func,entries,summaries,stack_pointer));
+    ssa_convertion(func);
+
+    let mut fixed = HashMap::<Name,A>::new();
+    let mut call_args = vec![];
+    let mut init = HashSet::<Name>::new();
+    let mut used = HashMap::<Cow<'static,str>,usize>::new();
+    let mut clobbered = HashMap::<Cow<'static,str>,usize>::new();
+
+    for vx in func.cflow_graph.vertices() {
+        if let Some(&ControlFlowTarget::Resolved(ref bb)) = func.cflow_graph.vertex_label(vx) {
+            for mne in bb.mnemonics.iter().filter(|m| m.opcode == "__init") {
+                for stmt in mne.instructions.iter() {
+                    if let Lvalue::Variable{ ref name, subscript: Some(s),.. } = stmt.assignee {
+                        init.insert((name.clone(),s));
+                    }
+                }
+            }
+        }
+    }
+
+    for vx in func.cflow_graph.vertices() {
+        if let Some(&ControlFlowTarget::Resolved(ref bb)) = func.cflow_graph.vertex_label(vx) {
+            let mut last_callee = None;
+
+            for mne in bb.mnemonics.iter() {
+                if mne.opcode == "__call" {
+                    let sum = last_callee.and_then(|uu| summaries.get(&uu).map(|s| (uu,s)));
+
+                    for stmt in mne.instructions.iter() {
+                        match (stmt,sum) {
+                            (&Statement{ op: Operation::Move(ref v@Rvalue::Variable{ .. }), assignee: Lvalue::Undefined },Some((uu,_))) =>
+                                call_args.push((uu,v.clone())),
+                            (&Statement{ assignee: Lvalue::Variable{ ref name, subscript: Some(s),.. },.. },Some((_,sum))) =>
+                                if let Some(r) = sum.returns.get(name) {
+                                    fixed.insert((name.clone(),s),r.clone());
+                                },
+                            _ => {},
+                        }
+                    }
+                } else {
+                    last_callee = mne.instructions.iter().filter_map(|s| callee(s,entries)).next();
+                }
+
+                for stmt in mne.instructions.iter() {
+                    // uses of the values set by __init or of variables never defined are inputs
+                    let mut reads = stmt.op.operands().into_iter().cloned().collect::<Vec<Rvalue>>();
+
+                    if let &Statement{ op: Operation::Store(..), ref assignee } = stmt {
+                        reads.push(assignee.clone().into());
+                    }
+
+                    for o in reads.iter() {
+                        if let &Rvalue::Variable{ ref name, subscript, size,.. } = o {
+                            if subscript.map(|s| init.contains(&(name.clone(),s))).unwrap_or(true) {
+                                let sz = *used.get(name).unwrap_or(&size);
+                                used.insert(name.clone(),::std::cmp::max(sz,size));
+                            }
+                        }
+                    }
+
+                    match stmt {
+                        _ if mne.opcode == "__init" || mne.opcode == "__phi" => {},
+                        &Statement{ op: Operation::Store(..),.. } => {},
+                        &Statement{ assignee: Lvalue::Variable{ ref name, size,.. },.. } => {
+                            let sz = *clobbered.get(name).unwrap_or(&size);
+                            clobbered.insert(name.clone(),::std::cmp::max(sz,size));
+                        },
+                        _ => {},
+                    }
+                }
+            }
+        }
+    }
+
+    let vals = try!(approximate_with(func,inputs,&fixed,stack_pointer));
+    let by_name = vals.iter().filter_map(|(lv,a)| match lv {
+        &Lvalue::Variable{ ref name, subscript: Some(s),.. } => Some(((name.clone(),s),a.clone())),
+        _ => None,
+    }).collect::<HashMap<Name,A>>();
+    let mut arguments = HashMap::<Uuid,HashMap<Cow<'static,str>,A>>::new();
+
+    for (uu,v) in call_args {
+        if let Rvalue::Variable{ name, subscript, size,.. } = v {
+            let val = match subscript {
+                Some(s) => by_name.get(&(name.clone(),s)).cloned().unwrap_or(A::initial()),
+                None => inputs.get(&name).cloned().unwrap_or(A::abstract_value(&Rvalue::Variable{ name: name.clone(), size: size, offset: 0, subscript: None })),
+            };
+            let args = arguments.entry(uu).or_insert(HashMap::new());
+            let val = match args.get(&name) {
+                Some(prev) => prev.combine(&val),
+                None => val,
+            };
+
+            args.insert(name,val);
+        }
+    }
+
+    let returns = exit_values(func,&by_name,&clobbered);
+    let delta = match stack_pointer {
+        Some(&Lvalue::Variable{ ref name,.. }) if !clobbered.contains_key(name) => Some(0),
+        Some(sp) => try!(stack_delta(func,sp)),
+        None => None,
+    };
+
+    Ok(Analysis{
+        summary: Summary{
+            inputs: used,
+            clobbered: clobbered,
+            returns: returns,
+            stack_delta: delta,
+        },
+        arguments: arguments,
+        values: vals,
+    })
+}
+
+/// Does an inter-procedural abstract interpretation of all functions in `prog` using the abstract
+/// domain `A`. `stack_pointer` is the stack pointer register of the architecture, if there is
+/// one. Afterwards, all functions are in SSA form and include `__call` mnemonics with the effects
+/// of calls. The values in the returned `Approximation` refer to this form.
+/// `ssa_deconstruction` removes the `__call` mnemonics again.
+pub fn approximate_program<A: Avalue>(prog: &mut Program, stack_pointer: Option<&Lvalue>) -> Result<Approximation<A>> {
+    let mut entries = HashMap::<u64,Uuid>::new();
+    let mut callers = HashMap::<Uuid,HashSet<Uuid>>::new();
+    let mut todo = vec![];
+
+    for vx in prog.call_graph.vertices() {
+        if let Some(&CallTarget::Concrete(ref func)) = prog.call_graph.vertex_label(vx) {
+            if let Some(Some(&ControlFlowTarget::Resolved(ref bb))) = func.entry_point.map(|e| func.cflow_graph.vertex_label(e)) {
+                entries.insert(bb.area.start,func.uuid);
+                todo.push(func.uuid);
+            }
+        }
+    }
+
+    for vx in prog.call_graph.vertices() {
+        if let Some(&CallTarget::Concrete(ref func)) = prog.call_graph.vertex_label(vx) {
+            for bb_vx in func.cflow_graph.vertices() {
+                if let Some(&ControlFlowTarget::Resolved(ref bb)) = func.cflow_graph.vertex_label(bb_vx) {
+                    bb.execute(|stmt| {
+                        if let Some(uu) = callee(stmt,&entries) {
+                            callers.entry(uu).or_insert(HashSet::new()).insert(func.uuid);
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    let mut summaries = HashMap::<Uuid,Summary<A>>::new();
+    let mut inputs = HashMap::<Uuid,HashMap<Cow<'static,str>,A>>::new();
+    let mut values = HashMap::<Uuid,HashMap<Lvalue,A>>::new();
+    let mut iterations = HashMap::<Uuid,usize>::new();
+
+    while let Some(uu) = todo.pop() {
+        let no_inputs = HashMap::new();
+        let res = {
+            let func = match prog.find_function_by_uuid_mut(&uu) {
+                Some(f) => f,
+                None => continue,
+            };
+            let inp = inputs.get(&uu).unwrap_or(&no_inputs);
+
+            try!(analyze(func,&entries,&summaries,inp,stack_pointer))
+        };
+        let iter = {
+            let i = iterations.entry(uu).or_insert(0);
+            *i += 1;
+            *i
+        };
+
+        values.insert(uu,res.values);
+
+        let summary = match summaries.get(&uu) {
+            Some(old) if iter > WIDENING_DELAY => old.widen(&res.summary),
+            _ => res.summary,
+        };
+
+        if summaries.get(&uu) != Some(&summary) {
+            summaries.insert(uu,summary);
+
+            for c in callers.get(&uu).cloned().unwrap_or(HashSet::new()) {
+                if !todo.contains(&c) {
+                    todo.push(c);
+                }
+            }
+        }
+
+        for (callee,args) in res.arguments {
+            let widen = *iterations.get(&callee).unwrap_or(&0) >= WIDENING_DELAY;
+            let mut changed = false;
+            let cur = inputs.entry(callee).or_insert(HashMap::new());
+
+            for (name,val) in args {
+                let new = match cur.get(&name) {
+                    Some(old) if widen => old.widen(&old.combine(&val)),
+                    Some(old) => old.combine(&val),
+                    None => val,
+                };
+
+                if cur.get(&name) != Some(&new) {
+                    cur.insert(name,new);
+                    changed = true;
+                }
+            }
+
+            if changed && !todo.contains(&callee) {
+                todo.push(callee);
+            }
+        }
+    }
+
+    Ok(Approximation{
+        summaries: summaries,
+        inputs: inputs,
+        values: values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use graph_algos::MutableGraphTrait;
+    use {
+        Program,
+        CallTarget,
+        Function,
+        ControlFlowTarget,
+        BasicBlock,
+        Mnemonic,
+        Statement,
+        Operation,
+        Rvalue,
+        Lvalue,
+        Kset,
+    };
+    use test_util::var;
+
+    fn function(name: &str, start: u64, stmts: Vec<Vec<Statement>>) -> Function {
+        let mut func = Function::new(name.to_string(),"ram".to_string());
+        let mnes = stmts.iter().enumerate().map(|(i,s)| {
+            let a = start + i as u64;
+            Mnemonic::new(a..a + 1,"test".to_string(),"".to_string(),vec![].iter(),s.iter()).unwrap()
+        }).collect();
+        let vx = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(mnes)));
+
+        func.entry_point = Some(vx);
+        func
+    }
+
+    fn value<'a>(vals: &'a HashMap<Lvalue,Kset>, name: &str) -> Vec<&'a Kset> {
+        vals.iter().filter_map(|(lv,a)| match lv {
+            &Lvalue::Variable{ name: ref n,.. } if n == name => Some(a),
+            _ => None,
+        }).collect()
+    }
+
+    /*
+     * main:
+     *   b = 41
+     *   call f
+     *   c = a
+     *
+     * f:
+     *   a = b + 1
+     *   SP = SP - 2
+     */
+    #[test]
+    fn arguments_and_returns() {
+        let main = function("main",0,vec![
+            vec![Statement{ op: Operation::Move(Rvalue::new_u32(41)), assignee: var("b",32) }],
+            vec![Statement{ op: Operation::Call(Rvalue::new_u64(0x100)), assignee: Lvalue::Undefined }],
+            vec![Statement{ op: Operation::Move(var("a",32).into()), assignee: var("c",32) }],
+        ]);
+        let f = function("f",0x100,vec![
+            vec![Statement{ op: Operation::Add(var("b",32).into(),Rvalue::new_u32(1)), assignee: var("a",32) }],
+            vec![Statement{ op: Operation::Subtract(var("SP",16).into(),Rvalue::new_u16(2)), assignee: var("SP",16) }],
+        ]);
+        let main_uu = main.uuid;
+        let f_uu = f.uuid;
+        let mut prog = Program::new("prog");
+
+        prog.call_graph.add_vertex(CallTarget::Concrete(main));
+        prog.call_graph.add_vertex(CallTarget::Concrete(f));
+
+        let res = approximate_program::<Kset>(&mut prog,Some(&var("SP",16))).unwrap();
+        let f_sum = &res.summaries[&f_uu];
+        let main_sum = &res.summaries[&main_uu];
+
+        assert_eq!(f_sum.inputs.get("b"),Some(&32));
+        assert_eq!(f_sum.clobbered.get("a"),Some(&32));
+        assert_eq!(f_sum.returns.get("a"),Some(&Kset::Set(vec![(42,32)])));
+        assert_eq!(f_sum.stack_delta,Some(-2));
+        assert_eq!(res.inputs[&f_uu].get("b"),Some(&Kset::Set(vec![(41,32)])));
+
+        assert_eq!(main_sum.stack_delta,Some(-2));
+        assert!(main_sum.clobbered.contains_key("a"));
+        assert_eq!(value(&res.values[&main_uu],"c"),vec![&Kset::Set(vec![(42,32)])]);
+    }
+
+    /*
+     * r:
+     *   x = x + 1
+     *   call r
+     */
+    #[test]
+    fn recursion() {
+        let r = function("r",0x200,vec![
+            vec![Statement{ op: Operation::Add(var("x",32).into(),Rvalue::new_u32(1)), assignee: var("x",32) }],
+            vec![Statement{ op: Operation::Call(Rvalue::new_u64(0x200)), assignee: Lvalue::Undefined }],
+        ]);
+        let uu = r.uuid;
+        let mut prog = Program::new("prog");
+
+        prog.call_graph.add_vertex(CallTarget::Concrete(r));
+
+        let res = approximate_program::<Kset>(&mut prog,None).unwrap();
+        let sum = &res.summaries[&uu];
+
+        assert!(sum.inputs.contains_key("x"));
+        assert!(sum.clobbered.contains_key("x"));
+        assert_eq!(sum.stack_delta,None);
+    }
+
+    #[test]
+    fn unknown_callee() {
+        let main = function("main",0,vec![
+            vec![Statement{ op: Operation::Move(Rvalue::new_u32(1)), assignee: var("a",32) }],
+            vec![Statement{ op: Operation::Call(Rvalue::new_u64(0x300)), assignee: Lvalue::Undefined }],
+            vec![Statement{ op: Operation::Move(var("a",32).into()), assignee: var("c",32) }],
+        ]);
+        let uu = main.uuid;
+        let mut prog = Program::new("prog");
+
+        prog.call_graph.add_vertex(CallTarget::Concrete(main));
+
+        let res = approximate_program::<Kset>(&mut prog,None).unwrap();
+
+        // calls to functions without summary don't change anything
+        assert_eq!(value(&res.values[&uu],"c"),vec![&Kset::Set(vec![(1,32)])]);
+
+        let func = prog.find_function_by_uuid(&uu).unwrap();
+        let bb = match func.cflow_graph.vertex_label(func.entry_point.unwrap()) {
+            Some(&ControlFlowTarget::Resolved(ref bb)) => bb,
+            _ => unreachable!(),
+        };
+
+        assert!(bb.mnemonics.iter().all(|m| m.opcode != "__call"));
+    }
+}
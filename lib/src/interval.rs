@@ -0,0 +1,962 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Interval and strided interval abstract domains.
+//!
+//! An `Interval` approximates a set of integers by its smallest and largest element.
+//! `UnsignedInterval` orders values as unsigned integers, `SignedInterval` as two's complement
+//! signed integers. A `StridedInterval` additionally records the distance between the elements
+//! of the set: `4[0x100,0x10c]` is {0x100, 0x104, 0x108, 0x10c}. This is the value-set domain
+//! of Balakrishnan and Reps. It's able to express offsets into tables like `base + i * 4`.
+//! Strided intervals are unsigned.
+//!
+//! Operations that could wrap around yield `Join`. Widening moves bounds that are still growing
+//! to the smallest or largest value, the branch conditions in `Constraint` bring them back using
+//! `narrow`.
+
+use std::hash::Hash;
+use std::fmt::Debug;
+use std::cmp::{min,max};
+use std::{u64,i64};
+
+use rustc_serialize::{Encodable,Decodable};
+
+use il::{Rvalue,Operation,execute,lift};
+use abstractinterp::{Avalue,Constraint,ProgramPoint};
+
+/// Largest number of values `StridedInterval::concretize` enumerates.
+const MAX_CONCRETE_VALUES: u64 = 16;
+
+/// Returns a value with the lower `size` bits set.
+fn mask(size: usize) -> u64 {
+    if size >= 64 { u64::MAX } else { (1 << size) - 1 }
+}
+
+/// Smallest value of the form 2^n - 1 that is larger or equal to `x`.
+fn fill(x: u64) -> u64 {
+    let mut x = x;
+
+    x |= x >> 1;
+    x |= x >> 2;
+    x |= x >> 4;
+    x |= x >> 8;
+    x |= x >> 16;
+    x |= x >> 32;
+    x
+}
+
+/// Greatest common divisor. `gcd(0,x) = x`.
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b,a % b) }
+}
+
+/// Executes `op` if all operands are constants. Operands of binary operations are extended to
+/// the largest operand size first.
+fn exact<A: Avalue, F: Fn(&A) -> Option<Rvalue>>(op: &Operation<A>, constant: F) -> Option<Rvalue> {
+    let unify = match *op {
+        Operation::Phi(_) | Operation::Load(..) | Operation::Store(..) | Operation::Call(_) => return None,
+        Operation::Select(..) | Operation::ZeroExtend(..) | Operation::SignExtend(..) | Operation::Move(_) => false,
+        _ => true,
+    };
+    let args = match op.operands().into_iter().map(|x| constant(x)).collect::<Option<Vec<Rvalue>>>() {
+        Some(args) => args,
+        None => return None,
+    };
+    let size = args.iter().filter_map(|x| x.size()).max().unwrap_or(0);
+    let res = execute(lift(op,&|x: &A| match constant(x) {
+        Some(Rvalue::Constant{ value,.. }) if unify => Rvalue::Constant{ value: value, size: size },
+        Some(c) => c,
+        None => Rvalue::Undefined,
+    }));
+
+    match res {
+        c@Rvalue::Constant{ .. } => Some(c),
+        _ => None,
+    }
+}
+
+/// Integer type used as interval bound.
+pub trait IntervalBound: Copy + Ord + Hash + Debug + Encodable + Decodable {
+    /// True if the type is a signed integer.
+    fn is_signed() -> bool;
+    /// Interprets the lower `size` bits of `value`.
+    fn from_bits(value: u64, size: usize) -> Self;
+    /// Two's complement representation of `self`, truncated to `size` bits.
+    fn to_bits(self, size: usize) -> u64;
+    /// Smallest value of a `size` bit integer.
+    fn min_value(size: usize) -> Self;
+    /// Largest value of a `size` bit integer.
+    fn max_value(size: usize) -> Self;
+    /// Addition. None on overflow.
+    fn add_checked(self, other: Self) -> Option<Self>;
+    /// Subtraction. None on overflow.
+    fn sub_checked(self, other: Self) -> Option<Self>;
+    /// Multiplication. None on overflow.
+    fn mul_checked(self, other: Self) -> Option<Self>;
+
+    /// Zero
+    fn zero() -> Self {
+        Self::from_bits(0,64)
+    }
+
+    /// Largest value that is non-negative as both signed and unsigned `size` bit integer.
+    fn max_positive(size: usize) -> Self {
+        Self::from_bits(mask(size) >> 1,64)
+    }
+}
+
+impl IntervalBound for u64 {
+    fn is_signed() -> bool { false }
+    fn from_bits(value: u64, size: usize) -> u64 { value & mask(size) }
+    fn to_bits(self, size: usize) -> u64 { self & mask(size) }
+    fn min_value(_: usize) -> u64 { 0 }
+    fn max_value(size: usize) -> u64 { mask(size) }
+    fn add_checked(self, other: u64) -> Option<u64> { self.checked_add(other) }
+    fn sub_checked(self, other: u64) -> Option<u64> { self.checked_sub(other) }
+    fn mul_checked(self, other: u64) -> Option<u64> { self.checked_mul(other) }
+}
+
+impl IntervalBound for i64 {
+    fn is_signed() -> bool { true }
+
+    fn from_bits(value: u64, size: usize) -> i64 {
+        let v = value & mask(size);
+
+        if size > 0 && size < 64 && v & (1 << (size - 1)) != 0 {
+            (v | !mask(size)) as i64
+        } else {
+            v as i64
+        }
+    }
+
+    fn to_bits(self, size: usize) -> u64 { (self as u64) & mask(size) }
+
+    fn min_value(size: usize) -> i64 {
+        match size {
+            0 => 0,
+            s if s >= 64 => i64::MIN,
+            s => -(1i64 << (s - 1)),
+        }
+    }
+
+    fn max_value(size: usize) -> i64 {
+        match size {
+            0 => 0,
+            s if s >= 64 => i64::MAX,
+            s => (1i64 << (s - 1)) - 1,
+        }
+    }
+
+    fn add_checked(self, other: i64) -> Option<i64> { self.checked_add(other) }
+    fn sub_checked(self, other: i64) -> Option<i64> { self.checked_sub(other) }
+    fn mul_checked(self, other: i64) -> Option<i64> { self.checked_mul(other) }
+}
+
+/// Interval domain. Elements are the sets of all integers between two bounds. The partial order
+/// is set inclusion.
+#[derive(Debug,PartialEq,Eq,Clone,Hash,RustcDecodable,RustcEncodable)]
+pub enum Interval<T: IntervalBound> {
+    /// Lattice join. Any value.
+    Join,
+    /// All values from `lower` to `upper` (inclusive) of a `size` bit integer. Never empty and
+    /// never all values.
+    Range{
+        /// Smallest element
+        lower: T,
+        /// Largest element
+        upper: T,
+        /// Size of the values in bits
+        size: usize,
+    },
+    /// Lattice meet, equal to the empty set.
+    Meet,
+}
+
+/// Interval of unsigned integers.
+pub type UnsignedInterval = Interval<u64>;
+
+/// Interval of two's complement signed integers.
+pub type SignedInterval = Interval<i64>;
+
+impl<T: IntervalBound> Interval<T> {
+    /// All `size` bit integers from `lower` to `upper`. Empty if `lower > upper`, `Join` if the
+    /// bounds are outside of the range of `size` bit integers.
+    pub fn range(lower: T, upper: T, size: usize) -> Interval<T> {
+        if size == 0 || size > 64 {
+            Interval::Join
+        } else if lower > upper {
+            Interval::Meet
+        } else if lower < T::min_value(size) || upper > T::max_value(size) {
+            Interval::Join
+        } else if lower == T::min_value(size) && upper == T::max_value(size) {
+            Interval::Join
+        } else {
+            Interval::Range{ lower: lower, upper: upper, size: size }
+        }
+    }
+
+    /// Interval containing only the `size` bit integer with the bit pattern `value`.
+    pub fn constant(value: u64, size: usize) -> Interval<T> {
+        let v = T::from_bits(value,size);
+        Self::range(v,v,size)
+    }
+
+    /// Returns the only element of the interval.
+    pub fn as_constant(&self) -> Option<Rvalue> {
+        match self {
+            &Interval::Range{ lower, upper, size } if lower == upper =>
+                Some(Rvalue::Constant{ value: lower.to_bits(size), size: size }),
+            _ => None,
+        }
+    }
+
+    /// True if all elements of `other` are in `self`.
+    pub fn contains(&self, other: &Interval<T>) -> bool {
+        match (self,other) {
+            (&Interval::Join,_) => true,
+            (_,&Interval::Meet) => true,
+            (&Interval::Meet,_) => false,
+            (_,&Interval::Join) => false,
+            (&Interval::Range{ lower: l1, upper: u1,.. },&Interval::Range{ lower: l2, upper: u2,.. }) =>
+                l1 <= l2 && u2 <= u1,
+        }
+    }
+
+    fn arith<F: Fn(T,T,T,T) -> Option<(T,T)>>(&self, other: &Interval<T>, f: F) -> Interval<T> {
+        match (self,other) {
+            (&Interval::Meet,_) | (_,&Interval::Meet) => Interval::Meet,
+            (&Interval::Range{ lower: l1, upper: u1, size: s1 },&Interval::Range{ lower: l2, upper: u2, size: s2 }) =>
+                match f(l1,u1,l2,u2) {
+                    Some((l,u)) => Self::range(l,u,max(s1,s2)),
+                    None => Interval::Join,
+                },
+            _ => Interval::Join,
+        }
+    }
+
+    /// Applies `f` to the unsigned bounds if all elements of `self` and `other` are
+    /// non-negative.
+    fn positive<F: Fn(u64,u64,u64,u64) -> Option<(u64,u64)>>(&self, other: &Interval<T>, f: F) -> Interval<T> {
+        self.arith(other,|l1,u1,l2,u2| {
+            if l1 < T::zero() || l2 < T::zero() {
+                None
+            } else {
+                f(l1.to_bits(64),u1.to_bits(64),l2.to_bits(64),u2.to_bits(64))
+                    .map(|(l,u)| (T::from_bits(l,64),T::from_bits(u,64)))
+            }
+        })
+    }
+
+    /// Result of comparing all elements of `self` with all elements of `other`.
+    fn compare(&self, other: &Interval<T>, strict: bool, signed: bool) -> Interval<T> {
+        match (self,other) {
+            (&Interval::Meet,_) | (_,&Interval::Meet) => Interval::Meet,
+            (&Interval::Range{ lower: l1, upper: u1, size: s1 },&Interval::Range{ lower: l2, upper: u2, size: s2 }) => {
+                let size = max(s1,s2);
+                let zero = T::zero();
+
+                // signed and unsigned order only coincide for non-negative values
+                if signed != T::is_signed() && (l1 < zero || l2 < zero || u1 > T::max_positive(size) || u2 > T::max_positive(size)) {
+                    Interval::Join
+                } else if (strict && u1 < l2) || (!strict && u1 <= l2) {
+                    Self::constant(1,1)
+                } else if (strict && l1 >= u2) || (!strict && l1 > u2) {
+                    Self::constant(0,1)
+                } else {
+                    Interval::Join
+                }
+            },
+            _ => Interval::Join,
+        }
+    }
+
+    /// All `size` bit values that are less than or equal to `v` if compared as unsigned.
+    fn unsigned_at_most(v: u64, size: usize) -> Interval<T> {
+        if !T::is_signed() || v <= mask(size) >> 1 {
+            Self::range(T::zero(),T::from_bits(v,size),size)
+        } else {
+            Interval::Join
+        }
+    }
+
+    /// All `size` bit values that are less than or equal to `v` if compared as signed.
+    fn signed_at_most(v: u64, size: usize) -> Interval<T> {
+        let sign = 1u64 << (size - 1);
+
+        if T::is_signed() {
+            Self::range(T::min_value(size),T::from_bits(v,size),size)
+        } else if v & sign != 0 {
+            Self::range(T::from_bits(sign,size),T::from_bits(v,size),size)
+        } else {
+            Interval::Join
+        }
+    }
+}
+
+impl<T: IntervalBound> Avalue for Interval<T> {
+    fn abstract_value(v: &Rvalue) -> Self {
+        match v {
+            &Rvalue::Constant{ value, size } => Self::constant(value,size),
+            _ => Interval::Join,
+        }
+    }
+
+    fn abstract_constraint(constr: &Constraint) -> Self {
+        match constr {
+            &Constraint::Equal(Rvalue::Constant{ value, size }) => Self::constant(value,size),
+            &Constraint::LessUnsigned(Rvalue::Constant{ value, size }) if size > 0 && size <= 64 =>
+                if value & mask(size) == 0 {
+                    Interval::Meet
+                } else {
+                    Self::unsigned_at_most((value & mask(size)) - 1,size)
+                },
+            &Constraint::LessOrEqualUnsigned(Rvalue::Constant{ value, size }) if size > 0 && size <= 64 =>
+                Self::unsigned_at_most(value & mask(size),size),
+            &Constraint::LessSigned(Rvalue::Constant{ value, size }) if size > 0 && size <= 64 =>
+                if value & mask(size) == 1 << (size - 1) {
+                    Interval::Meet
+                } else {
+                    Self::signed_at_most(value.wrapping_sub(1) & mask(size),size)
+                },
+            &Constraint::LessOrEqualSigned(Rvalue::Constant{ value, size }) if size > 0 && size <= 64 =>
+                Self::signed_at_most(value & mask(size),size),
+            _ => Interval::Join,
+        }
+    }
+
+    fn execute(_: &ProgramPoint, op: &Operation<Self>) -> Self {
+        if let Some(c) = exact(op,|x: &Self| x.as_constant()) {
+            return Self::abstract_value(&c);
+        }
+
+        match *op {
+            Operation::Add(ref a,ref b) =>
+                a.arith(b,|l1,u1,l2,u2| l1.add_checked(l2).and_then(|l| u1.add_checked(u2).map(|u| (l,u)))),
+            Operation::Subtract(ref a,ref b) =>
+                a.arith(b,|l1,u1,l2,u2| l1.sub_checked(u2).and_then(|l| u1.sub_checked(l2).map(|u| (l,u)))),
+            Operation::Multiply(ref a,ref b) =>
+                a.arith(b,|l1,u1,l2,u2| {
+                    [l1.mul_checked(l2),l1.mul_checked(u2),u1.mul_checked(l2),u1.mul_checked(u2)]
+                        .iter().cloned().collect::<Option<Vec<T>>>()
+                        .map(|v| (*v.iter().min().unwrap(),*v.iter().max().unwrap()))
+                }),
+            Operation::ShiftLeft(ref a,ref b) =>
+                match (a,b.as_constant()) {
+                    (&Interval::Range{ lower, upper, size },Some(Rvalue::Constant{ value: k,.. })) if k < 62 => {
+                        let f = T::from_bits(1 << k,64);
+
+                        match (lower.mul_checked(f),upper.mul_checked(f)) {
+                            (Some(l),Some(u)) => Self::range(l,u,size),
+                            _ => Interval::Join,
+                        }
+                    },
+                    _ => a.arith(b,|_,_,_,_| None),
+                },
+            Operation::ShiftRightUnsigned(ref a,ref b) =>
+                a.positive(b,|l1,u1,l2,u2| Some((if u2 < 64 { l1 >> u2 } else { 0 },u1 >> min(l2,63)))),
+            Operation::DivideUnsigned(ref a,ref b) =>
+                a.positive(b,|l1,u1,l2,u2| if l2 > 0 { Some((l1 / u2,u1 / l2)) } else { None }),
+            Operation::Modulo(ref a,ref b) =>
+                a.positive(b,|l1,u1,l2,u2| if l2 == 0 { None } else if u1 < l2 { Some((l1,u1)) } else { Some((0,min(u1,u2 - 1))) }),
+            Operation::And(ref a,ref b) =>
+                a.positive(b,|_,u1,_,u2| Some((0,min(u1,u2)))),
+            Operation::InclusiveOr(ref a,ref b) =>
+                a.positive(b,|l1,u1,l2,u2| Some((max(l1,l2),fill(max(u1,u2))))),
+            Operation::ExclusiveOr(ref a,ref b) =>
+                a.positive(b,|_,u1,_,u2| Some((0,fill(max(u1,u2))))),
+
+            Operation::LessUnsigned(ref a,ref b) => a.compare(b,true,false),
+            Operation::LessOrEqualUnsigned(ref a,ref b) => a.compare(b,false,false),
+            Operation::LessSigned(ref a,ref b) => a.compare(b,true,true),
+            Operation::LessOrEqualSigned(ref a,ref b) => a.compare(b,false,true),
+            Operation::Equal(ref a,ref b) =>
+                if *a == Interval::Meet || *b == Interval::Meet {
+                    Interval::Meet
+                } else if a.narrow(b) == Interval::Meet {
+                    Self::constant(0,1)
+                } else {
+                    Interval::Join
+                },
+
+            Operation::ZeroExtend(sz,ref a) =>
+                match a {
+                    &Interval::Range{ lower, upper,.. } if !T::is_signed() || lower >= T::zero() => Self::range(lower,upper,sz),
+                    &Interval::Meet => Interval::Meet,
+                    _ => Interval::Join,
+                },
+            Operation::SignExtend(sz,ref a) =>
+                match a {
+                    &Interval::Range{ lower, upper, size } if T::is_signed() || upper <= T::max_positive(size) => Self::range(lower,upper,sz),
+                    &Interval::Meet => Interval::Meet,
+                    _ => Interval::Join,
+                },
+            Operation::Move(ref a) => a.clone(),
+            Operation::Phi(ref ops) => ops.iter().fold(Interval::Meet,|acc,x| acc.combine(x)),
+            _ => Interval::Join,
+        }
+    }
+
+    fn narrow(&self, other: &Self) -> Self {
+        match (self,other) {
+            (&Interval::Meet,_) | (_,&Interval::Meet) => Interval::Meet,
+            (x,&Interval::Join) => x.clone(),
+            (&Interval::Join,y) => y.clone(),
+            (&Interval::Range{ lower: l1, upper: u1, size },&Interval::Range{ lower: l2, upper: u2,.. }) =>
+                Self::range(max(l1,l2),min(u1,u2),size),
+        }
+    }
+
+    fn widen(&self, other: &Self) -> Self {
+        match (self,other) {
+            (&Interval::Meet,x) | (x,&Interval::Meet) => x.clone(),
+            (&Interval::Range{ lower: l1, upper: u1, size: s1 },&Interval::Range{ lower: l2, upper: u2, size: s2 }) => {
+                let size = max(s1,s2);
+                let lower = if l2 < l1 { T::min_value(size) } else { l1 };
+                let upper = if u2 > u1 { T::max_value(size) } else { u1 };
+
+                Self::range(lower,upper,size)
+            },
+            _ => Interval::Join,
+        }
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (self,other) {
+            (&Interval::Meet,x) | (x,&Interval::Meet) => x.clone(),
+            (&Interval::Range{ lower: l1, upper: u1, size: s1 },&Interval::Range{ lower: l2, upper: u2, size: s2 }) =>
+                Self::range(min(l1,l2),max(u1,u2),max(s1,s2)),
+            _ => Interval::Join,
+        }
+    }
+
+    fn more_exact(&self, other: &Self) -> bool {
+        self != other && self.contains(other)
+    }
+
+    fn initial() -> Self {
+        Interval::Meet
+    }
+
+    fn extract(&self, size: usize, offset: usize) -> Self {
+        match self {
+            &Interval::Meet => Interval::Meet,
+            &Interval::Range{ lower, upper, size: sz } if lower == upper && offset < 64 =>
+                Self::constant(lower.to_bits(sz) >> offset,size),
+            &Interval::Range{ lower, upper,.. } if offset == 0 && lower >= T::min_value(size) && upper <= T::max_value(size) =>
+                Self::range(lower,upper,size),
+            _ => Interval::Join,
+        }
+    }
+    fn concretize(&self) -> Option<Vec<u64>> {
+        match self {
+            &Interval::Meet => Some(vec![]),
+            &Interval::Range{ lower, upper, size } if lower == upper => Some(vec![lower.to_bits(size)]),
+            _ => None,
+        }
+    }
+}
+
+/// Strided interval domain. Elements are sets of unsigned integers between two bounds that are
+/// all congruent modulo a stride. The partial order is set inclusion.
+#[derive(Debug,PartialEq,Eq,Clone,Hash,RustcDecodable,RustcEncodable)]
+pub enum StridedInterval {
+    /// Lattice join. Any value.
+    Join,
+    /// Every `stride`-th value from `lower` to `upper` (inclusive) of a `size` bit integer.
+    /// `stride` is zero if and only if `lower == upper`. Never empty and never all values.
+    Range{
+        /// Distance between two elements
+        stride: u64,
+        /// Smallest element
+        lower: u64,
+        /// Largest element
+        upper: u64,
+        /// Size of the values in bits
+        size: usize,
+    },
+    /// Lattice meet, equal to the empty set.
+    Meet,
+}
+
+impl StridedInterval {
+    /// Every `stride`-th `size` bit integer starting at `lower` and not larger than `upper`.
+    /// Empty if `lower > upper`, `Join` if `upper` is larger than the largest `size` bit integer.
+    pub fn new(stride: u64, lower: u64, upper: u64, size: usize) -> StridedInterval {
+        if size == 0 || size > 64 || upper > mask(size) {
+            StridedInterval::Join
+        } else if lower > upper {
+            StridedInterval::Meet
+        } else if lower == upper {
+            StridedInterval::Range{ stride: 0, lower: lower, upper: upper, size: size }
+        } else {
+            let stride = if stride == 0 { 1 } else { stride };
+            let upper = lower + ((upper - lower) / stride) * stride;
+
+            if stride == 1 && lower == 0 && upper == mask(size) {
+                StridedInterval::Join
+            } else if lower == upper {
+                StridedInterval::Range{ stride: 0, lower: lower, upper: upper, size: size }
+            } else {
+                StridedInterval::Range{ stride: stride, lower: lower, upper: upper, size: size }
+            }
+        }
+    }
+
+    /// Set containing only the `size` bit integer `value`.
+    pub fn constant(value: u64, size: usize) -> StridedInterval {
+        Self::new(0,value & mask(size),value & mask(size),size)
+    }
+
+    /// Returns the only element of the set.
+    pub fn as_constant(&self) -> Option<Rvalue> {
+        match self {
+            &StridedInterval::Range{ stride: 0, lower, size,.. } => Some(Rvalue::Constant{ value: lower, size: size }),
+            _ => None,
+        }
+    }
+
+    /// Returns all elements in ascending order if there are at most `max` of them.
+    pub fn values(&self, max: u64) -> Option<Vec<u64>> {
+        match self {
+            &StridedInterval::Meet => Some(vec![]),
+            &StridedInterval::Range{ stride: 0, lower,.. } if max > 0 => Some(vec![lower]),
+            &StridedInterval::Range{ stride, lower, upper,.. } if stride > 0 && (upper - lower) / stride < max =>
+                Some((0..((upper - lower) / stride + 1)).map(|i| lower + i * stride).collect()),
+            _ => None,
+        }
+    }
+
+    /// True if all elements of `other` are in `self`.
+    pub fn contains(&self, other: &StridedInterval) -> bool {
+        match (self,other) {
+            (&StridedInterval::Join,_) => true,
+            (_,&StridedInterval::Meet) => true,
+            (&StridedInterval::Meet,_) => false,
+            (_,&StridedInterval::Join) => false,
+            (&StridedInterval::Range{ stride: s1, lower: l1, upper: u1,.. },&StridedInterval::Range{ stride: s2, lower: l2, upper: u2,.. }) =>
+                l1 <= l2 && u2 <= u1 && if s1 == 0 {
+                    l1 == l2 && u1 == u2
+                } else {
+                    (l2 - l1) % s1 == 0 && s2 % s1 == 0
+                },
+        }
+    }
+
+    fn arith<F: Fn(u64,u64,u64,u64,u64,u64) -> Option<(u64,u64,u64)>>(&self, other: &StridedInterval, f: F) -> StridedInterval {
+        match (self,other) {
+            (&StridedInterval::Meet,_) | (_,&StridedInterval::Meet) => StridedInterval::Meet,
+            (&StridedInterval::Range{ stride: s1, lower: l1, upper: u1, size: z1 },
+             &StridedInterval::Range{ stride: s2, lower: l2, upper: u2, size: z2 }) =>
+                match f(s1,l1,u1,s2,l2,u2) {
+                    Some((s,l,u)) => Self::new(s,l,u,max(z1,z2)),
+                    None => StridedInterval::Join,
+                },
+            _ => StridedInterval::Join,
+        }
+    }
+
+    /// Multiplies all elements with `c`.
+    fn scale(&self, c: u64) -> StridedInterval {
+        match self {
+            &StridedInterval::Range{ stride, lower, upper, size } =>
+                match (stride.checked_mul(c),lower.checked_mul(c),upper.checked_mul(c)) {
+                    (Some(s),Some(l),Some(u)) => Self::new(s,l,u,size),
+                    _ => StridedInterval::Join,
+                },
+            x => x.clone(),
+        }
+    }
+
+    /// Result of comparing all elements of `self` with all elements of `other` as unsigned
+    /// integers.
+    fn compare(&self, other: &StridedInterval, strict: bool) -> StridedInterval {
+        match self.arith(other,|_,l1,u1,_,l2,u2| {
+            if (strict && u1 < l2) || (!strict && u1 <= l2) {
+                Some((0,1,1))
+            } else if (strict && l1 >= u2) || (!strict && l1 > u2) {
+                Some((0,0,0))
+            } else {
+                None
+            }
+        }) {
+            StridedInterval::Range{ lower, .. } => Self::constant(lower,1),
+            x => x,
+        }
+    }
+}
+
+impl Avalue for StridedInterval {
+    fn abstract_value(v: &Rvalue) -> Self {
+        match v {
+            &Rvalue::Constant{ value, size } => Self::constant(value,size),
+            _ => StridedInterval::Join,
+        }
+    }
+
+    fn abstract_constraint(constr: &Constraint) -> Self {
+        match constr {
+            &Constraint::Equal(Rvalue::Constant{ value, size }) => Self::constant(value,size),
+            &Constraint::LessUnsigned(Rvalue::Constant{ value, size }) if size > 0 && size <= 64 =>
+                if value & mask(size) == 0 {
+                    StridedInterval::Meet
+                } else {
+                    Self::new(1,0,(value & mask(size)) - 1,size)
+                },
+            &Constraint::LessOrEqualUnsigned(Rvalue::Constant{ value, size }) if size > 0 && size <= 64 =>
+                Self::new(1,0,value & mask(size),size),
+            // only negative bounds exclude the positive values
+            &Constraint::LessSigned(Rvalue::Constant{ value, size }) if size > 0 && size <= 64 && value & (1 << (size - 1)) != 0 =>
+                Self::new(1,1 << (size - 1),(value & mask(size)) - 1,size),
+            &Constraint::LessOrEqualSigned(Rvalue::Constant{ value, size }) if size > 0 && size <= 64 && value & (1 << (size - 1)) != 0 =>
+                Self::new(1,1 << (size - 1),value & mask(size),size),
+            _ => StridedInterval::Join,
+        }
+    }
+
+    fn execute(_: &ProgramPoint, op: &Operation<Self>) -> Self {
+        if let Some(c) = exact(op,|x: &Self| x.as_constant()) {
+            return Self::abstract_value(&c);
+        }
+
+        match *op {
+            Operation::Add(ref a,ref b) =>
+                a.arith(b,|s1,l1,u1,s2,l2,u2| {
+                    match (l1.checked_add(l2),u1.checked_add(u2)) {
+                        (Some(l),Some(u)) => Some((gcd(s1,s2),l,u)),
+                        _ => None,
+                    }
+                }),
+            Operation::Subtract(ref a,ref b) =>
+                a.arith(b,|s1,l1,u1,s2,l2,u2| if l1 >= u2 { Some((gcd(s1,s2),l1 - u2,u1 - l2)) } else { None }),
+            Operation::Multiply(ref a,ref b) =>
+                match (a.as_constant(),b.as_constant()) {
+                    (_,Some(Rvalue::Constant{ value,.. })) => a.scale(value),
+                    (Some(Rvalue::Constant{ value,.. }),_) => b.scale(value),
+                    _ => a.arith(b,|_,l1,u1,_,l2,u2| {
+                        match (l1.checked_mul(l2),u1.checked_mul(u2)) {
+                            (Some(l),Some(u)) => Some((1,l,u)),
+                            _ => None,
+                        }
+                    }),
+                },
+            Operation::ShiftLeft(ref a,ref b) =>
+                match b.as_constant() {
+                    Some(Rvalue::Constant{ value,.. }) if value < 64 => a.scale(1 << value),
+                    _ => a.arith(b,|_,_,_,_,_,_| None),
+                },
+            Operation::ShiftRightUnsigned(ref a,ref b) =>
+                match b.as_constant() {
+                    Some(Rvalue::Constant{ value,.. }) if value < 64 =>
+                        a.arith(b,|s,l,u,_,_,_| {
+                            let c = 1 << value;
+                            Some((if s % c == 0 { s / c } else { 1 },l >> value,u >> value))
+                        }),
+                    _ => a.arith(b,|_,_,u1,_,_,_| Some((1,0,u1))),
+                },
+            Operation::DivideUnsigned(ref a,ref b) =>
+                a.arith(b,|s1,l1,u1,s2,l2,u2| {
+                    if l2 == 0 {
+                        None
+                    } else if s2 == 0 && s1 % l2 == 0 {
+                        // l1 + k * s1 divided by l2 is l1 / l2 + k * (s1 / l2)
+                        Some((s1 / l2,l1 / l2,u1 / l2))
+                    } else {
+                        Some((1,l1 / u2,u1 / l2))
+                    }
+                }),
+            Operation::Modulo(ref a,ref b) =>
+                a.arith(b,|s1,l1,u1,s2,l2,u2| {
+                    if l2 == 0 {
+                        None
+                    } else if u1 < l2 {
+                        Some((s1,l1,u1))
+                    } else if s2 == 0 {
+                        // all elements are congruent to l1 modulo gcd(s1,l2)
+                        let g = gcd(s1,l2);
+                        Some((g,l1 % g,l2 - 1))
+                    } else {
+                        Some((1,0,min(u1,u2 - 1)))
+                    }
+                }),
+            Operation::And(ref a,ref b) =>
+                a.arith(b,|_,_,u1,_,_,u2| Some((1,0,min(u1,u2)))),
+            Operation::InclusiveOr(ref a,ref b) =>
+                a.arith(b,|_,l1,u1,_,l2,u2| Some((1,max(l1,l2),fill(max(u1,u2))))),
+            Operation::ExclusiveOr(ref a,ref b) =>
+                a.arith(b,|_,_,u1,_,_,u2| Some((1,0,fill(max(u1,u2))))),
+
+            Operation::LessUnsigned(ref a,ref b) => a.compare(b,true),
+            Operation::LessOrEqualUnsigned(ref a,ref b) => a.compare(b,false),
+            Operation::Equal(ref a,ref b) =>
+                if *a == StridedInterval::Meet || *b == StridedInterval::Meet {
+                    StridedInterval::Meet
+                } else if a.narrow(b) == StridedInterval::Meet && b.narrow(a) == StridedInterval::Meet {
+                    Self::constant(0,1)
+                } else {
+                    StridedInterval::Join
+                },
+
+            Operation::ZeroExtend(sz,ref a) =>
+                match a {
+                    &StridedInterval::Range{ stride, lower, upper,.. } => Self::new(stride,lower,upper,sz),
+                    x => x.clone(),
+                },
+            Operation::SignExtend(sz,ref a) =>
+                match a {
+                    &StridedInterval::Range{ stride, lower, upper, size } if upper <= mask(size) >> 1 => Self::new(stride,lower,upper,sz),
+                    &StridedInterval::Meet => StridedInterval::Meet,
+                    _ => StridedInterval::Join,
+                },
+            Operation::Move(ref a) => a.clone(),
+            Operation::Phi(ref ops) => ops.iter().fold(StridedInterval::Meet,|acc,x| acc.combine(x)),
+            _ => StridedInterval::Join,
+        }
+    }
+
+    fn narrow(&self, other: &Self) -> Self {
+        match (self,other) {
+            (&StridedInterval::Meet,_) | (_,&StridedInterval::Meet) => StridedInterval::Meet,
+            (x,&StridedInterval::Join) => x.clone(),
+            (&StridedInterval::Join,y) => y.clone(),
+            (&StridedInterval::Range{ stride, lower: l1, upper: u1, size },&StridedInterval::Range{ lower: l2, upper: u2,.. }) => {
+                // keeps the stride of `self`. The result may contain values not in `other`.
+                let lo = max(l1,l2);
+                let hi = min(u1,u2);
+
+                if lo > hi {
+                    StridedInterval::Meet
+                } else if stride == 0 {
+                    self.clone()
+                } else {
+                    let first = l1 + ((lo - l1 + stride - 1) / stride) * stride;
+                    Self::new(stride,first,hi,size)
+                }
+            },
+        }
+    }
+
+    fn widen(&self, other: &Self) -> Self {
+        match (self,other) {
+            (&StridedInterval::Meet,x) | (x,&StridedInterval::Meet) => x.clone(),
+            (&StridedInterval::Range{ lower: l1, upper: u1,.. },&StridedInterval::Range{ lower: l2, upper: u2,.. }) =>
+                match self.combine(other) {
+                    StridedInterval::Range{ stride, size,.. } if stride > 0 => {
+                        // keep the bounds congruent to the elements
+                        let lower = if l2 < l1 { l1 % stride } else { l1 };
+                        let upper = if u2 > u1 { mask(size) } else { u1 };
+
+                        Self::new(stride,lower,upper,size)
+                    },
+                    x => x,
+                },
+            _ => StridedInterval::Join,
+        }
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (self,other) {
+            (&StridedInterval::Meet,x) | (x,&StridedInterval::Meet) => x.clone(),
+            (&StridedInterval::Range{ stride: s1, lower: l1, upper: u1, size: z1 },
+             &StridedInterval::Range{ stride: s2, lower: l2, upper: u2, size: z2 }) => {
+                let stride = gcd(gcd(s1,s2),max(l1,l2) - min(l1,l2));
+                Self::new(stride,min(l1,l2),max(u1,u2),max(z1,z2))
+            },
+            _ => StridedInterval::Join,
+        }
+    }
+
+    fn more_exact(&self, other: &Self) -> bool {
+        self != other && self.contains(other)
+    }
+
+    fn initial() -> Self {
+        StridedInterval::Meet
+    }
+
+    fn extract(&self, size: usize, offset: usize) -> Self {
+        match self {
+            &StridedInterval::Meet => StridedInterval::Meet,
+            &StridedInterval::Range{ stride: 0, lower,.. } if offset < 64 => Self::constant(lower >> offset,size),
+            &StridedInterval::Range{ stride, lower, upper,.. } if offset == 0 && upper <= mask(size) => Self::new(stride,lower,upper,size),
+            _ => StridedInterval::Join,
+        }
+    }
+    fn concretize(&self) -> Option<Vec<u64>> {
+        self.values(MAX_CONCRETE_VALUES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use abstractinterp::{Avalue,Constraint,ProgramPoint,approximate};
+    use {
+        Rvalue,
+        Lvalue,
+        Operation,
+        Statement,
+        Guard,
+        Mnemonic,
+        BasicBlock,
+        Function,
+        ControlFlowTarget,
+        ControlFlowGraph,
+        ssa_convertion,
+    };
+    use graph_algos::MutableGraphTrait;
+    use std::borrow::Cow;
+
+    fn pp() -> ProgramPoint {
+        ProgramPoint::new(0,0)
+    }
+
+    fn u(l: u64, h: u64) -> UnsignedInterval {
+        Interval::range(l,h,32)
+    }
+
+    fn s(l: i64, h: i64) -> SignedInterval {
+        Interval::range(l,h,32)
+    }
+
+    fn si(stride: u64, l: u64, h: u64) -> StridedInterval {
+        StridedInterval::new(stride,l,h,32)
+    }
+
+    #[test]
+    fn interval_arith() {
+        assert_eq!(UnsignedInterval::execute(&pp(),&Operation::Add(u(1,2),u(10,20))),u(11,22));
+        assert_eq!(UnsignedInterval::execute(&pp(),&Operation::Add(u(1,2),u(0xfffffffe,0xfffffffe))),Interval::Join);
+        assert_eq!(UnsignedInterval::execute(&pp(),&Operation::Subtract(u(10,20),u(1,2))),u(8,19));
+        assert_eq!(UnsignedInterval::execute(&pp(),&Operation::Subtract(u(1,2),u(1,3))),Interval::Join);
+        assert_eq!(UnsignedInterval::execute(&pp(),&Operation::ShiftLeft(u(0,9),u(2,2))),u(0,36));
+        assert_eq!(UnsignedInterval::execute(&pp(),&Operation::And(u(0,1000),u(7,7))),u(0,7));
+        assert_eq!(UnsignedInterval::execute(&pp(),&Operation::Modulo(u(0,1000),u(10,10))),u(0,9));
+        assert_eq!(UnsignedInterval::execute(&pp(),&Operation::Add(u(3,3),u(4,4))),u(7,7));
+        assert_eq!(UnsignedInterval::execute(&pp(),&Operation::LessUnsigned(u(0,9),u(10,20))),Interval::constant(1,1));
+        assert_eq!(UnsignedInterval::execute(&pp(),&Operation::ZeroExtend(64,u(0,9))),Interval::range(0,9,64));
+
+        assert_eq!(SignedInterval::execute(&pp(),&Operation::Add(s(-5,5),s(-1,1))),s(-6,6));
+        assert_eq!(SignedInterval::execute(&pp(),&Operation::Multiply(s(-2,3),s(-4,5))),s(-12,15));
+        assert_eq!(SignedInterval::execute(&pp(),&Operation::LessSigned(s(-9,-1),s(0,10))),Interval::constant(1,1));
+        assert_eq!(SignedInterval::execute(&pp(),&Operation::SignExtend(64,s(-9,-1))),Interval::range(-9,-1,64));
+        assert_eq!(SignedInterval::abstract_value(&Rvalue::Constant{ value: 0xffffffff, size: 32 }),s(-1,-1));
+    }
+
+    #[test]
+    fn interval_lattice() {
+        assert_eq!(u(0,5).combine(&u(10,20)),u(0,20));
+        assert_eq!(u(0,5).combine(&Interval::Meet),u(0,5));
+        assert_eq!(u(0,5).narrow(&u(3,20)),u(3,5));
+        assert_eq!(u(0,5).narrow(&u(6,20)),Interval::Meet);
+        assert_eq!(Interval::Join.narrow(&u(6,20)),u(6,20));
+        assert_eq!(u(0,5).widen(&u(0,6)),Interval::Join);
+        assert_eq!(u(2,5).widen(&u(1,5)),u(0,5));
+        assert_eq!(s(0,5).widen(&s(-1,5)),Interval::range(-0x80000000,5,32));
+        assert!(u(0,10).more_exact(&u(2,3)));
+        assert!(!u(2,3).more_exact(&u(0,10)));
+        assert!(!u(2,3).more_exact(&u(2,3)));
+        assert_eq!(u(0,0x1ff).extract(8,0),Interval::Join);
+        assert_eq!(u(0,0x7f).extract(8,0),Interval::range(0,0x7f,8));
+    }
+
+    #[test]
+    fn interval_constraints() {
+        let c = |v| Rvalue::Constant{ value: v, size: 32 };
+
+        assert_eq!(UnsignedInterval::abstract_constraint(&Constraint::LessUnsigned(c(10))),u(0,9));
+        assert_eq!(UnsignedInterval::abstract_constraint(&Constraint::LessOrEqualUnsigned(c(10))),u(0,10));
+        assert_eq!(UnsignedInterval::abstract_constraint(&Constraint::LessUnsigned(c(0))),Interval::Meet);
+        assert_eq!(UnsignedInterval::abstract_constraint(&Constraint::LessSigned(c(10))),Interval::Join);
+        assert_eq!(UnsignedInterval::abstract_constraint(&Constraint::LessSigned(c(0xffffffff))),u(0x80000000,0xfffffffe));
+        assert_eq!(SignedInterval::abstract_constraint(&Constraint::LessSigned(c(10))),Interval::range(-0x80000000,9,32));
+        assert_eq!(SignedInterval::abstract_constraint(&Constraint::LessUnsigned(c(10))),s(0,9));
+        assert_eq!(SignedInterval::abstract_constraint(&Constraint::Equal(c(0xfffffffe))),s(-2,-2));
+    }
+
+    #[test]
+    fn strided_arith() {
+        assert_eq!(StridedInterval::execute(&pp(),&Operation::Multiply(si(1,0,9),si(0,4,4))),si(4,0,36));
+        assert_eq!(StridedInterval::execute(&pp(),&Operation::ShiftLeft(si(1,0,9),si(0,2,2))),si(4,0,36));
+        assert_eq!(StridedInterval::execute(&pp(),&Operation::Add(si(4,0,36),si(0,0x1000,0x1000))),si(4,0x1000,0x1024));
+        assert_eq!(StridedInterval::execute(&pp(),&Operation::Add(si(4,0,36),si(6,0,12))),si(2,0,48));
+        assert_eq!(StridedInterval::execute(&pp(),&Operation::DivideUnsigned(si(8,0,32),si(0,4,4))),si(2,0,8));
+        assert_eq!(StridedInterval::execute(&pp(),&Operation::Modulo(si(4,1,41),si(0,8,8))),si(4,1,7));
+        assert_eq!(StridedInterval::execute(&pp(),&Operation::Subtract(si(4,8,16),si(0,8,8))),si(4,0,8));
+        assert_eq!(StridedInterval::execute(&pp(),&Operation::Subtract(si(4,0,16),si(0,8,8))),StridedInterval::Join);
+    }
+
+    #[test]
+    fn strided_lattice() {
+        assert_eq!(si(4,0,8).combine(&si(4,2,10)),si(2,0,10));
+        assert_eq!(si(0,3,3).combine(&si(0,7,7)),si(4,3,7));
+        assert_eq!(si(4,0,100).narrow(&si(1,5,50)),si(4,8,48));
+        assert_eq!(si(4,0,100).narrow(&si(1,101,150)),StridedInterval::Meet);
+        assert_eq!(si(4,0,8).widen(&si(4,0,12)),si(4,0,0xfffffffc));
+        assert!(si(2,0,10).more_exact(&si(4,2,10)));
+        assert!(!si(4,0,8).more_exact(&si(2,0,8)));
+        assert_eq!(StridedInterval::abstract_constraint(&Constraint::LessUnsigned(Rvalue::Constant{ value: 10, size: 32 })),si(1,0,9));
+        assert_eq!(si(4,0x40,0x48).values(3),Some(vec![0x40,0x44,0x48]));
+        assert_eq!(si(4,0x40,0x48).values(2),None);
+        assert_eq!(StridedInterval::Join.values(100),None);
+    }
+
+    /*
+     * i = 0
+     * do {
+     *   i = i + 1
+     * } while(i < 10)
+     */
+    #[test]
+    fn loop_bound() {
+        let i_var = Lvalue::Variable{ name: Cow::Borrowed("i"), size: 32, subscript: None };
+        let flag = Lvalue::Variable{ name: Cow::Borrowed("flag"), size: 1, subscript: None };
+        let bb0 = BasicBlock::from_vec(vec![
+            Mnemonic::new(0..1,"assign i".to_string(),"".to_string(),vec![].iter(),vec![
+                Statement{ op: Operation::Move(Rvalue::new_u32(0)), assignee: i_var.clone() }].iter()).ok().unwrap()]);
+        let bb1 = BasicBlock::from_vec(vec![
+            Mnemonic::new(1..2,"inc i".to_string(),"".to_string(),vec![].iter(),vec![
+                Statement{ op: Operation::Add(i_var.clone().into(),Rvalue::new_u32(1)), assignee: i_var.clone() }].iter()).ok().unwrap(),
+            Mnemonic::new(2..3,"cmp i".to_string(),"".to_string(),vec![].iter(),vec![
+                Statement{ op: Operation::LessUnsigned(i_var.clone().into(),Rvalue::new_u32(10)), assignee: flag.clone() }].iter()).ok().unwrap()]);
+        let bb2 = BasicBlock::from_vec(vec![
+            Mnemonic::new(3..4,"use i".to_string(),"".to_string(),vec![].iter(),vec![
+                Statement{ op: Operation::Move(i_var.clone().into()), assignee: i_var.clone() }].iter()).ok().unwrap()]);
+        let mut cfg = ControlFlowGraph::new();
+        let v0 = cfg.add_vertex(ControlFlowTarget::Resolved(bb0));
+        let v1 = cfg.add_vertex(ControlFlowTarget::Resolved(bb1));
+        let v2 = cfg.add_vertex(ControlFlowTarget::Resolved(bb2));
+        let g = Guard::from_flag(&flag.clone().into()).ok().unwrap();
+
+        cfg.add_edge(Guard::always(),v0,v1);
+        cfg.add_edge(g.clone(),v1,v1);
+        cfg.add_edge(g.negation(),v1,v2);
+
+        let mut func = Function::new("func".to_string(),"ram".to_string());
+
+        func.cflow_graph = cfg;
+        func.entry_point = Some(v0);
+
+        ssa_convertion(&mut func);
+
+        let vals = approximate::<UnsignedInterval>(&func).ok().unwrap();
+
+        // the incremented counter is bounded by the loop condition
+        assert!(vals.values().any(|x| *x == u(0,9)));
+        assert!(vals.values().all(|x| *x != Interval::Meet));
+    }
+}
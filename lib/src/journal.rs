@@ -0,0 +1,426 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Log of changes made to a project since it was last saved.
+//!
+//! Writing a complete snapshot of a large project takes a while. Instead, small edits are
+//! appended to a journal file next to the session file ("foo.panop" has the journal
+//! "foo.panop.journal"). `Project::open` replays the journal on top of the snapshot and
+//! `Project::snapshot` removes it after the new snapshot is in place.
+//!
+//! The journal starts with the magic number "PANOPJNL" and the session format version as big
+//! endian u32. Each entry is a big endian u32 length, a big endian u32 Adler-32 checksum and the
+//! MsgPack encoded `Change`. An entry that is cut short or fails the checksum marks the end of
+//! the journal, anything after it is lost. This happens if Panopticon crashes while appending.
+//!
+//! All changes can be applied more than once without harm. A journal left behind by a crash
+//! between writing a snapshot and removing the journal only repeats changes already in the
+//! snapshot.
+
+use std::path::{Path,PathBuf};
+use std::ffi::OsString;
+use std::fs::{self,File,OpenOptions};
+use std::io::{self,Read,Write,Seek,SeekFrom};
+
+use uuid::Uuid;
+use rmp_serialize::{Encoder,Decoder};
+use rustc_serialize::{Decodable,Encodable};
+use byteorder::{
+    ReadBytesExt,
+    WriteBytesExt,
+    BigEndian,
+};
+
+use function::Function;
+use session::{FORMAT_VERSION,FunctionV4};
+use result::{Result,Error};
+
+/// Magic number every journal starts with.
+pub const JOURNAL_MAGIC: &'static [u8; 8] = b"PANOPJNL";
+/// Size of the journal header.
+const HEADER_SIZE: u64 = 12;
+/// Oldest format version with a compatible journal.
+const MIN_VERSION: u32 = 2;
+
+/// Single edit of a project.
+#[derive(RustcDecodable,RustcEncodable,Debug)]
+pub enum Change {
+    /// Sets the comment at `offset` in region `region`. An empty comment removes it.
+    Comment{
+        /// Name of the memory region
+        region: String,
+        /// Offset inside the region
+        offset: u64,
+        /// New comment
+        comment: String,
+    },
+    /// Renames the function with UUID `function`.
+    Rename{
+        /// UUID of the function
+        function: Uuid,
+        /// New name
+        name: String,
+    },
+    /// Adds `function` to the program with UUID `program`, replacing the call target with the
+    /// same UUID.
+    Function{
+        /// UUID of the program
+        program: Uuid,
+        /// New function
+        function: Function,
+    },
+}
+
+/// `Change` as written by versions 2 to 4.
+#[derive(RustcDecodable)]
+enum ChangeV4 {
+    Comment{ region: String, offset: u64, comment: String },
+    Rename{ function: Uuid, name: String },
+    Function{ program: Uuid, function: FunctionV4 },
+}
+
+impl From<ChangeV4> for Change {
+    fn from(c: ChangeV4) -> Change {
+        match c {
+            ChangeV4::Comment{ region, offset, comment } => Change::Comment{ region: region, offset: offset, comment: comment },
+            ChangeV4::Rename{ function, name } => Change::Rename{ function: function, name: name },
+            ChangeV4::Function{ program, function } => Change::Function{ program: program, function: function.into() },
+        }
+    }
+}
+
+/// Returns the path of the journal that belongs to the session file `p`.
+pub fn journal_path(p: &Path) -> PathBuf {
+    let mut name = p.file_name().map(|x| x.to_os_string()).unwrap_or(OsString::new());
+
+    name.push(".journal");
+    p.with_file_name(name)
+}
+
+/// Adler-32 checksum of `buf`.
+fn checksum(buf: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+
+    for &x in buf.iter() {
+        a = (a + x as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+/// Reads all intact entries of the journal `fd`. Returns the changes and the offset of the first
+/// byte after the last intact entry.
+fn read_entries<R: Read>(fd: &mut R) -> Result<(Vec<Change>,u64)> {
+    let mut magic = [0u8; 8];
+
+    if fd.read_exact(&mut magic).is_err() || magic != *JOURNAL_MAGIC {
+        return Err(Error::format_at(0,"wrong magic number in journal"));
+    }
+
+    let version = try!(fd.read_u32::<BigEndian>());
+
+    if version < MIN_VERSION || version > FORMAT_VERSION {
+        return Err(Error::format_at(8,format!("journal was written for format version {}, expected {} to {}",version,MIN_VERSION,FORMAT_VERSION)));
+    }
+
+    let mut ret = vec![];
+    let mut end = HEADER_SIZE;
+
+    loop {
+        let (len,sum) = match (fd.read_u32::<BigEndian>(),fd.read_u32::<BigEndian>()) {
+            (Ok(len),Ok(sum)) => (len,sum),
+            _ => break,
+        };
+        let mut buf = vec![];
+
+        if try!(fd.by_ref().take(len as u64).read_to_end(&mut buf)) != len as usize || checksum(&buf) != sum {
+            warn!("journal entry at offset {} is damaged, ignoring the rest of the journal",end);
+            break;
+        }
+
+        // functions gained a stack frame in version 5
+        let change = if version < 5 {
+            ChangeV4::decode(&mut Decoder::new(&buf[..])).map(|c| c.into())
+        } else {
+            Change::decode(&mut Decoder::new(&buf[..]))
+        };
+
+        match change {
+            Ok(c) => ret.push(c),
+            Err(e) => {
+                warn!("failed to decode journal entry at offset {}: {:?}",end,e);
+                break;
+            }
+        }
+
+        end += 8 + len as u64;
+    }
+
+    Ok((ret,end))
+}
+
+/// Returns all changes recorded in the journal of the session file `p`, oldest first. A missing
+/// journal is empty.
+pub fn read(p: &Path) -> Result<Vec<Change>> {
+    match File::open(journal_path(p)) {
+        Ok(mut fd) => read_entries(&mut fd).map(|x| x.0),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Deletes the journal of the session file `p`, if there is one.
+pub fn remove(p: &Path) -> Result<()> {
+    match fs::remove_file(journal_path(p)) {
+        Ok(()) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Journal opened for appending.
+pub struct Journal {
+    fd: File,
+}
+
+impl Journal {
+    /// Opens the journal of the session file `p`, creating it if needed. A damaged entry at the
+    /// end is cut off. Journals of older format versions are rewritten in the current one.
+    pub fn open(p: &Path) -> Result<Journal> {
+        let mut fd = try!(OpenOptions::new().read(true).write(true).create(true).open(journal_path(p)));
+
+        if try!(fd.metadata()).len() == 0 {
+            try!(fd.write_all(JOURNAL_MAGIC));
+            try!(fd.write_u32::<BigEndian>(FORMAT_VERSION));
+            return Ok(Journal{ fd: fd });
+        }
+
+        let (changes,end) = try!(read_entries(&mut fd));
+
+        try!(fd.seek(SeekFrom::Start(8)));
+
+        if try!(fd.read_u32::<BigEndian>()) == FORMAT_VERSION {
+            try!(fd.set_len(end));
+            try!(fd.seek(SeekFrom::Start(end)));
+            Ok(Journal{ fd: fd })
+        } else {
+            // entries of different versions can't be mixed in one journal. The upgraded journal
+            // replaces the old one only after it reached the disk.
+            drop(fd);
+
+            let path = journal_path(p);
+            let mut name = path.file_name().map(|x| x.to_os_string()).unwrap_or(OsString::new());
+
+            name.push(".tmp");
+
+            let tmp = path.with_file_name(name);
+            let res = File::create(&tmp).map_err(|e| e.into()).and_then(|fd| {
+                let mut ret = Journal{ fd: fd };
+
+                try!(ret.fd.write_all(JOURNAL_MAGIC));
+                try!(ret.fd.write_u32::<BigEndian>(FORMAT_VERSION));
+
+                for c in changes.iter() {
+                    try!(ret.write(c));
+                }
+
+                try!(ret.sync());
+                Ok(ret)
+            }).and_then(|ret| {
+                try!(fs::rename(&tmp,&path));
+                Ok(ret)
+            });
+
+            if res.is_err() {
+                let _ = fs::remove_file(&tmp);
+            }
+
+            res
+        }
+    }
+
+    /// Appends `c` to the journal. Returns after the entry reached the disk.
+    pub fn append(&mut self, c: &Change) -> Result<()> {
+        try!(self.write(c));
+        self.sync()
+    }
+
+    /// Appends `c` to the journal without waiting for it to reach the disk. Used to record a
+    /// batch of changes followed by a single `sync`. Entries lost in a crash before the `sync`
+    /// are cut off as damaged.
+    pub fn write(&mut self, c: &Change) -> Result<()> {
+        let mut buf = vec![];
+
+        if c.encode(&mut Encoder::new(&mut buf)).is_err() {
+            return Err(Error::serialization("failed to encode journal entry"));
+        }
+
+        let mut entry = Vec::with_capacity(buf.len() + 8);
+
+        try!(entry.write_u32::<BigEndian>(buf.len() as u32));
+        try!(entry.write_u32::<BigEndian>(checksum(&buf)));
+        entry.extend_from_slice(&buf);
+
+        try!(self.fd.write_all(&entry));
+        Ok(())
+    }
+
+    /// Returns after all entries written so far reached the disk.
+    pub fn sync(&mut self) -> Result<()> {
+        try!(self.fd.sync_data());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{File,OpenOptions};
+    use std::io::{Write,Seek,SeekFrom};
+    use uuid::Uuid;
+    use tempdir::TempDir;
+    use rmp_serialize::Encoder;
+    use rustc_serialize::Encodable;
+    use byteorder::{ReadBytesExt,WriteBytesExt,BigEndian};
+    use session::FORMAT_VERSION;
+
+    #[test]
+    fn adler32() {
+        assert_eq!(checksum(b""),1);
+        assert_eq!(checksum(b"Wikipedia"),0x11e60398);
+    }
+
+    #[test]
+    fn path() {
+        assert_eq!(journal_path(Path::new("/tmp/foo.panop")),Path::new("/tmp/foo.panop.journal"));
+    }
+
+    #[test]
+    fn append_and_read() {
+        let tmp = TempDir::new("panop-journal").unwrap();
+        let p = tmp.path().join("test.panop");
+        let uu = Uuid::new_v4();
+
+        assert_eq!(read(&p).unwrap().len(),0);
+
+        {
+            let mut j = Journal::open(&p).unwrap();
+
+            j.append(&Change::Comment{ region: "base".to_string(), offset: 1, comment: "a".to_string() }).unwrap();
+            j.append(&Change::Rename{ function: uu, name: "main".to_string() }).unwrap();
+        }
+
+        {
+            let mut j = Journal::open(&p).unwrap();
+
+            j.append(&Change::Comment{ region: "base".to_string(), offset: 2, comment: "b".to_string() }).unwrap();
+        }
+
+        let changes = read(&p).unwrap();
+
+        assert_eq!(changes.len(),3);
+        match &changes[1] {
+            &Change::Rename{ ref function, ref name } => {
+                assert_eq!(*function,uu);
+                assert_eq!(name,"main");
+            },
+            _ => unreachable!(),
+        }
+
+        remove(&p).unwrap();
+        assert_eq!(read(&p).unwrap().len(),0);
+        assert!(remove(&p).is_ok());
+    }
+
+    #[test]
+    fn batch() {
+        let tmp = TempDir::new("panop-journal").unwrap();
+        let p = tmp.path().join("test.panop");
+
+        {
+            let mut j = Journal::open(&p).unwrap();
+
+            for i in 0..10 {
+                j.write(&Change::Comment{ region: "base".to_string(), offset: i, comment: "a".to_string() }).unwrap();
+            }
+
+            j.sync().unwrap();
+        }
+
+        assert_eq!(read(&p).unwrap().len(),10);
+    }
+
+    #[test]
+    fn damaged_tail() {
+        let tmp = TempDir::new("panop-journal").unwrap();
+        let p = tmp.path().join("test.panop");
+
+        {
+            let mut j = Journal::open(&p).unwrap();
+
+            j.append(&Change::Comment{ region: "base".to_string(), offset: 1, comment: "a".to_string() }).unwrap();
+        }
+
+        // half written entry
+        OpenOptions::new().append(true).open(journal_path(&p)).unwrap().write_all(b"\x00\x00\x00\x20\x00\x00").unwrap();
+        assert_eq!(read(&p).unwrap().len(),1);
+
+        {
+            let mut j = Journal::open(&p).unwrap();
+
+            j.append(&Change::Comment{ region: "base".to_string(), offset: 2, comment: "b".to_string() }).unwrap();
+        }
+
+        assert_eq!(read(&p).unwrap().len(),2);
+    }
+
+    #[test]
+    fn old_version() {
+        let tmp = TempDir::new("panop-journal").unwrap();
+        let p = tmp.path().join("test.panop");
+
+        {
+            let mut buf = vec![];
+            let mut fd = File::create(journal_path(&p)).unwrap();
+
+            Change::Comment{ region: "base".to_string(), offset: 1, comment: "a".to_string() }.encode(&mut Encoder::new(&mut buf)).unwrap();
+            fd.write_all(JOURNAL_MAGIC).unwrap();
+            fd.write_u32::<BigEndian>(4).unwrap();
+            fd.write_u32::<BigEndian>(buf.len() as u32).unwrap();
+            fd.write_u32::<BigEndian>(checksum(&buf)).unwrap();
+            fd.write_all(&buf).unwrap();
+        }
+
+        assert_eq!(read(&p).unwrap().len(),1);
+
+        {
+            let mut j = Journal::open(&p).unwrap();
+
+            j.append(&Change::Comment{ region: "base".to_string(), offset: 2, comment: "b".to_string() }).unwrap();
+        }
+
+        let mut fd = File::open(journal_path(&p)).unwrap();
+
+        fd.seek(SeekFrom::Start(8)).unwrap();
+        assert_eq!(fd.read_u32::<BigEndian>().unwrap(),FORMAT_VERSION);
+        assert!(!tmp.path().join("test.panop.journal.tmp").exists());
+        assert_eq!(read(&p).unwrap().len(),2);
+    }
+}
@@ -0,0 +1,467 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Resolution of indirect jumps.
+//!
+//! Switch statements are usually compiled into a bounds check followed by a jump through a table
+//! of addresses: `if i < n { goto *table[i] }`. The disassembler can't follow these jumps and
+//! inserts `ControlFlowTarget::Unresolved` nodes instead.
+//!
+//! `resolve_indirect_jumps` computes the set of possible targets of each unresolved jump in a
+//! function in SSA form. Targets that abstract interpretation with the `Kset` domain narrows down
+//! to a few values are used directly. Otherwise the jump target is traced back to a memory load.
+//! The address of the load is approximated using the `StridedInterval` domain, taking the branch
+//! conditions of the function into account. If it's bounded the table entries are read from the
+//! memory region of the function. Entries are little endian and as large as the loaded value.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use graph_algos::{
+    GraphTrait,
+    MutableGraphTrait,
+    VertexListGraphTrait,
+    BidirectionalGraphTrait,
+};
+
+use {
+    Function,
+    ControlFlowTarget,
+    ControlFlowRef,
+    Region,
+    Rvalue,
+    Lvalue,
+    Statement,
+    Operation,
+    Guard,
+    Result,
+    Kset,
+    StridedInterval,
+    lift,
+};
+use abstractinterp::{
+    Avalue,
+    approximate_with,
+    ProgramPoint,
+    constraints,
+};
+
+/// Largest number of targets an indirect jump is resolved to.
+pub const MAX_JUMP_TABLE_ENTRIES: u64 = 1024;
+
+/// Maximal number of definitions followed backwards when approximating a jump target.
+const MAX_SLICE_DEPTH: usize = 16;
+
+type Name = (Cow<'static,str>,usize);
+
+/// Backward slice through the definitions of SSA variables.
+struct Slice<'a> {
+    definitions: HashMap<Name,(ProgramPoint,&'a Statement)>,
+    values: HashMap<Name,StridedInterval>,
+    constraints: HashMap<Name,StridedInterval>,
+}
+
+fn ssa_name(lv: &Lvalue) -> Option<Name> {
+    match lv {
+        &Lvalue::Variable{ ref name, subscript: Some(subscript),.. } => Some((name.clone(),subscript)),
+        _ => None,
+    }
+}
+
+impl<'a> Slice<'a> {
+    fn new(func: &'a Function, stack_pointer: Option<&Lvalue>) -> Result<Slice<'a>> {
+        let mut definitions = HashMap::new();
+        let values = try!(approximate_with::<StridedInterval>(func,&HashMap::new(),&HashMap::new(),stack_pointer));
+        let constr = constraints::<StridedInterval>(func);
+
+        for vx in func.cflow_graph.vertices() {
+            if let Some(&ControlFlowTarget::Resolved(ref bb)) = func.cflow_graph.vertex_label(vx) {
+                let mut pos = 0usize;
+
+                bb.execute(|stmt| {
+                    match (&stmt.op,ssa_name(&stmt.assignee)) {
+                        (&Operation::Store(..),_) | (_,None) => {},
+                        (_,Some(n)) => { definitions.insert(n,(ProgramPoint::new(bb.area.start,pos),stmt)); },
+                    }
+                    pos += 1;
+                });
+            }
+        }
+
+        Ok(Slice{
+            definitions: definitions,
+            values: values.into_iter().filter_map(|(lv,a)| ssa_name(&lv).map(|n| (n,a))).collect(),
+            constraints: constr.into_iter().filter_map(|(lv,a)| ssa_name(&lv).map(|n| (n,a))).collect(),
+        })
+    }
+
+    /// Returns the statement defining `v`.
+    fn definition(&self, v: &Rvalue) -> Option<&'a Statement> {
+        match v {
+            &Rvalue::Variable{ ref name, subscript: Some(subscript), offset: 0,.. } =>
+                self.definitions.get(&(name.clone(),subscript)).map(|&(_,stmt)| stmt),
+            _ => None,
+        }
+    }
+
+    /// Approximates `v` by re-executing its definitions up to `depth` levels deep, applying the
+    /// branch conditions on the way.
+    fn evaluate(&self, v: &Rvalue, depth: usize) -> StridedInterval {
+        match v {
+            &Rvalue::Variable{ ref name, subscript: Some(subscript), size, offset } => {
+                let n = (name.clone(),subscript);
+                let def = self.definitions.get(&n);
+                let val = match def {
+                    Some(&(_,&Statement{ op: Operation::Phi(_),.. })) |
+                    Some(&(_,&Statement{ op: Operation::Load(..),.. })) |
+                    Some(&(_,&Statement{ op: Operation::Call(_),.. })) | None =>
+                        self.values.get(&n).cloned().unwrap_or(StridedInterval::Join),
+                    Some(&(ref pp,ref stmt)) if depth < MAX_SLICE_DEPTH =>
+                        StridedInterval::execute(pp,&lift(&stmt.op,&|x: &Rvalue| self.evaluate(x,depth + 1))),
+                    Some(_) => self.values.get(&n).cloned().unwrap_or(StridedInterval::Join),
+                };
+                let val = match self.constraints.get(&n) {
+                    Some(c) => val.narrow(c),
+                    None => val,
+                };
+                let def_size = match def {
+                    Some(&(_,&Statement{ assignee: Lvalue::Variable{ size,.. },.. })) => size,
+                    _ => size,
+                };
+
+                if offset > 0 || size != def_size {
+                    val.extract(size,offset)
+                } else {
+                    val
+                }
+            },
+            &Rvalue::Variable{ .. } | &Rvalue::Undefined => StridedInterval::Join,
+            c => StridedInterval::abstract_value(c),
+        }
+    }
+
+    /// Returns the value loaded into `v` and the size of the load if `v` is the result of a
+    /// memory load, possibly copied or zero extended.
+    fn load_address(&self, v: &Rvalue) -> Option<(Rvalue,usize)> {
+        let mut v = v.clone();
+
+        for _ in 0..MAX_SLICE_DEPTH {
+            match self.definition(&v) {
+                Some(&Statement{ op: Operation::Load(_,ref addr), assignee: Lvalue::Variable{ size,.. } }) =>
+                    return Some((addr.clone(),size)),
+                Some(&Statement{ op: Operation::Move(ref a),.. }) |
+                Some(&Statement{ op: Operation::ZeroExtend(_,ref a),.. }) =>
+                    v = a.clone(),
+                _ => return None,
+            }
+        }
+
+        None
+    }
+}
+
+/// Reads a `bytes` long little endian integer at `addr`.
+fn read_entry(reg: &Region, addr: u64, bytes: usize) -> Option<u64> {
+    if addr >= reg.size() {
+        return None;
+    }
+
+    let cells = reg.iter().seek(addr).take(bytes).collect::<Vec<_>>();
+
+    if cells.len() != bytes {
+        return None;
+    }
+
+    cells.iter().enumerate().fold(Some(0),|acc,(i,c)| match (acc,*c) {
+        (Some(acc),Some(b)) => Some(acc | ((b as u64) << (i * 8))),
+        _ => None,
+    })
+}
+
+/// Returns the possible values of the jump target `tgt`.
+fn jump_targets(slice: &Slice, ksets: &HashMap<Lvalue,Kset>, reg: &Region, tgt: &Rvalue) -> Option<Vec<u64>> {
+    if let Some(&Kset::Set(ref v)) = Lvalue::from_rvalue(tgt.clone()).and_then(|lv| ksets.get(&lv)) {
+        return Some(v.iter().map(|&(val,_)| val).collect());
+    }
+
+    match slice.evaluate(tgt,0).values(MAX_JUMP_TABLE_ENTRIES) {
+        Some(ref v) if !v.is_empty() => return Some(v.clone()),
+        _ => {},
+    }
+
+    match slice.load_address(tgt) {
+        Some((addr,size)) if size > 0 && size <= 64 && size % 8 == 0 =>
+            slice.evaluate(&addr,0).values(MAX_JUMP_TABLE_ENTRIES).and_then(|entries| {
+                entries.iter().map(|&a| read_entry(reg,a,size / 8)).collect::<Option<Vec<u64>>>()
+            }),
+        _ => None,
+    }
+}
+
+/// Resolves the indirect jumps of `func` to concrete addresses where possible. The function must
+/// be in SSA form. Each resolved `ControlFlowTarget::Unresolved` node is replaced with one
+/// `ControlFlowTarget::Unresolved` node per target address with a constant value. The caller is
+/// expected to continue disassembly at the returned addresses, using `Function::disassemble`.
+/// If `stack_pointer` is given, values spilled to the stack are tracked across loads and stores.
+pub fn resolve_indirect_jumps(func: &mut Function, reg: &Region, stack_pointer: Option<&Lvalue>) -> Result<Vec<u64>> {
+    if func.entry_point.is_none() {
+        return Ok(vec![]);
+    }
+
+    let resolved = {
+        let slice = try!(Slice::new(func,stack_pointer));
+        let ksets = try!(approximate_with::<Kset>(func,&HashMap::new(),&HashMap::new(),stack_pointer));
+
+        func.cflow_graph.vertices().filter_map(|vx| {
+            match func.cflow_graph.vertex_label(vx) {
+                Some(&ControlFlowTarget::Unresolved(ref tgt@Rvalue::Variable{ .. })) =>
+                    jump_targets(&slice,&ksets,reg,tgt).and_then(|mut addrs| {
+                        let size = tgt.size().unwrap_or(64);
+
+                        addrs.sort();
+                        addrs.dedup();
+
+                        if addrs.is_empty() {
+                            None
+                        } else {
+                            Some((vx,size,addrs))
+                        }
+                    }),
+                _ => None,
+            }
+        }).collect::<Vec<(ControlFlowRef,usize,Vec<u64>)>>()
+    };
+    let mut ret = vec![];
+
+    for (vx,size,addrs) in resolved {
+        let preds = func.cflow_graph.in_edges(vx).filter_map(|e| {
+            func.cflow_graph.edge_label(e).map(|g| (func.cflow_graph.source(e),g.clone()))
+        }).collect::<Vec<(ControlFlowRef,Guard)>>();
+
+        debug!("resolved indirect jump to {:?}",addrs);
+
+        for (i,&addr) in addrs.iter().enumerate() {
+            let c = Rvalue::Constant{ value: addr, size: size };
+
+            if i == 0 {
+                if let Some(lb) = func.cflow_graph.vertex_label_mut(vx) {
+                    *lb = ControlFlowTarget::Unresolved(c);
+                }
+            } else {
+                let new_vx = func.cflow_graph.add_vertex(ControlFlowTarget::Unresolved(c));
+
+                for &(from,ref g) in preds.iter() {
+                    func.cflow_graph.add_edge(g.clone(),from,new_vx);
+                }
+            }
+
+            ret.push(addr);
+        }
+    }
+
+    ret.sort();
+    ret.dedup();
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use std::sync::Arc;
+    use graph_algos::{GraphTrait,VertexListGraphTrait,IncidenceGraphTrait};
+    use {
+        Architecture,
+        Disassembler,
+        ControlFlowTarget,
+        Function,
+        Guard,
+        Match,
+        OpaqueLayer,
+        Operation,
+        Program,
+        Region,
+        Result,
+        Rvalue,
+        State,
+        Statement,
+    };
+    use test_util::var;
+
+    #[derive(Clone,Debug)]
+    enum TestArch {}
+    impl Architecture for TestArch {
+        type Token = u8;
+        type Configuration = Arc<Disassembler<TestArch>>;
+
+        fn prepare(_: &Region,_: &Self::Configuration) -> Result<Vec<(&'static str,u64,&'static str)>> {
+            unimplemented!()
+        }
+
+        fn decode(reg: &Region, addr: u64, cfg: &Self::Configuration) -> Result<Match<Self>> {
+            if let Some(s) = cfg.next_match(&mut reg.iter().seek(addr),addr,cfg.clone()) {
+                Ok(s.into())
+            } else {
+                Err("No match".into())
+            }
+        }
+    }
+
+    /*
+     * 0x00: i = load(0x80)
+     * 0x01: if i < 3 goto 0x02 else goto 0x18
+     * 0x02: goto *table1[i]
+     * 0x10: ret
+     * 0x11: ret
+     * 0x12: goto *table2[0]
+     * 0x14: ret
+     * 0x18: ret
+     *
+     * table1 at 0x40: 0x10, 0x11, 0x12
+     * table2 at 0x60: 0x14
+     */
+    fn switch() -> (Arc<Disassembler<TestArch>>,Region) {
+        let main = new_disassembler!(TestArch =>
+            [ 1 ] = |st: &mut State<TestArch>| {
+                st.mnemonic(1,"load","",vec!(),&|_| {
+                    Ok(vec![Statement{ op: Operation::Load(Cow::Borrowed("ram"),Rvalue::new_u32(0x80)), assignee: var("i",32) }])
+                });
+                st.jump(Rvalue::new_u32(0x01),Guard::always());
+                true
+            },
+            [ 2 ] = |st: &mut State<TestArch>| {
+                let g = Guard::from_flag(&var("f",1).into()).ok().unwrap();
+
+                st.mnemonic(1,"cmp","",vec!(),&|_| {
+                    Ok(vec![Statement{ op: Operation::LessUnsigned(var("i",32).into(),Rvalue::new_u32(3)), assignee: var("f",1) }])
+                });
+                st.jump(Rvalue::new_u32(0x02),g.clone());
+                st.jump(Rvalue::new_u32(0x18),g.negation());
+                true
+            },
+            [ 3 ] = |st: &mut State<TestArch>| {
+                st.mnemonic(1,"jmp","",vec!(),&|_| {
+                    Ok(vec![
+                        Statement{ op: Operation::Multiply(var("i",32).into(),Rvalue::new_u32(4)), assignee: var("o",32) },
+                        Statement{ op: Operation::Add(var("o",32).into(),Rvalue::new_u32(0x40)), assignee: var("a",32) },
+                        Statement{ op: Operation::Load(Cow::Borrowed("ram"),var("a",32).into()), assignee: var("t",32) },
+                    ])
+                });
+                st.jump(var("t",32).into(),Guard::always());
+                true
+            },
+            [ 4 ] = |st: &mut State<TestArch>| {
+                st.mnemonic(1,"ret","",vec!(),&|_| { Ok(vec![]) });
+                true
+            },
+            [ 5 ] = |st: &mut State<TestArch>| {
+                st.mnemonic(1,"jmp","",vec!(),&|_| {
+                    Ok(vec![Statement{ op: Operation::Load(Cow::Borrowed("ram"),Rvalue::new_u32(0x60)), assignee: var("u",32) }])
+                });
+                st.jump(var("u",32).into(),Guard::always());
+                true
+            }
+        );
+        let mut data = vec![0u8; 0x100];
+
+        data[0x00] = 1;
+        data[0x01] = 2;
+        data[0x02] = 3;
+        data[0x10] = 4;
+        data[0x11] = 4;
+        data[0x12] = 5;
+        data[0x14] = 4;
+        data[0x18] = 4;
+        data[0x40] = 0x10;
+        data[0x44] = 0x11;
+        data[0x48] = 0x12;
+        data[0x60] = 0x14;
+
+        (main,Region::new("ram".to_string(),OpaqueLayer::wrap(data)))
+    }
+
+    fn block_starts(func: &Function) -> Vec<u64> {
+        let mut ret = func.cflow_graph.vertices().filter_map(|vx| match func.cflow_graph.vertex_label(vx) {
+            Some(&ControlFlowTarget::Resolved(ref bb)) => Some(bb.area.start),
+            _ => None,
+        }).collect::<Vec<_>>();
+
+        ret.sort();
+        ret
+    }
+
+    fn unresolved(func: &Function) -> usize {
+        func.cflow_graph.vertices().filter(|&vx| match func.cflow_graph.vertex_label(vx) {
+            Some(&ControlFlowTarget::Unresolved(_)) => true,
+            _ => false,
+        }).count()
+    }
+
+    #[test]
+    fn single_table() {
+        let (main,reg) = switch();
+        let mut func = Function::disassemble::<TestArch>(None,main,&reg,0);
+
+        assert_eq!(unresolved(&func),1);
+
+        ::ssa_convertion(&mut func);
+
+        let targets = resolve_indirect_jumps(&mut func,&reg,None).unwrap();
+
+        assert_eq!(targets,vec![0x10,0x11,0x12]);
+        assert_eq!(unresolved(&func),3);
+    }
+
+    #[test]
+    fn fixpoint() {
+        let (main,reg) = switch();
+        let func = Function::new("switch".to_string(),"ram".to_string());
+        let func = Program::disassemble_function::<TestArch>(func,main,&reg,0).unwrap();
+
+        assert_eq!(block_starts(&func),vec![0x00,0x02,0x10,0x11,0x12,0x14,0x18]);
+        assert_eq!(unresolved(&func),0);
+
+        let jmp = func.find_basic_block_at_address(0x02).unwrap();
+        assert_eq!(func.cflow_graph.out_degree(jmp),3);
+    }
+
+    #[test]
+    fn unbounded() {
+        let main = new_disassembler!(TestArch =>
+            [ 3 ] = |st: &mut State<TestArch>| {
+                st.mnemonic(1,"jmp","",vec!(),&|_| {
+                    Ok(vec![
+                        Statement{ op: Operation::Load(Cow::Borrowed("ram"),Rvalue::new_u32(0x80)), assignee: var("i",32) },
+                        Statement{ op: Operation::Multiply(var("i",32).into(),Rvalue::new_u32(4)), assignee: var("o",32) },
+                        Statement{ op: Operation::Add(var("o",32).into(),Rvalue::new_u32(0x40)), assignee: var("a",32) },
+                        Statement{ op: Operation::Load(Cow::Borrowed("ram"),var("a",32).into()), assignee: var("t",32) },
+                    ])
+                });
+                st.jump(var("t",32).into(),Guard::always());
+                true
+            }
+        );
+        let reg = Region::new("ram".to_string(),OpaqueLayer::wrap(vec![3]));
+        let mut func = Function::disassemble::<TestArch>(None,main,&reg,0);
+
+        ::ssa_convertion(&mut func);
+
+        assert!(resolve_indirect_jumps(&mut func,&reg,None).unwrap().is_empty());
+        assert_eq!(unresolved(&func),1);
+    }
+}
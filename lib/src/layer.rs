@@ -38,17 +38,31 @@
 //! reg.cover(Bound::new(0x100,0x100 + mapping.len()),Layer::Opaque(mapping));
 //! ```
 //! Loading a Windows COM file.
+//!
+//! Large files are not read into memory. `OpaqueLayer::open` and `OpaqueLayer::open_range`
+//! map files larger than `MAP_THRESHOLD` into memory. A mapped layer is saved as path, range and
+//! SHA-1 hash of its contents. Loading a project fails if the file was moved or changed since.
 
 use std::collections::HashMap;
-use std::path::Path;
-use std::fs::File;
-use std::io::Read;
+use std::path::{Path,PathBuf};
+use std::fs::{self,File};
+use std::io::{Read,Seek,SeekFrom};
 use std::ops::Range;
+use std::fmt;
+use std::sync::Mutex;
+
+use memmap::{Mmap,Protection};
+use sha1::Sha1;
+use rustc_serialize::{Encodable,Encoder,Decodable,Decoder};
 
 use {
-    Result
+    Result,
+    Error,
 };
 
+/// Files and file ranges of at least this many bytes are mapped instead of read into memory.
+pub const MAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
 /// A cell represents a single, possible undefined, byte.
 pub type Cell = Option<u8>;
 
@@ -59,6 +73,114 @@ pub enum OpaqueLayer {
     Undefined(u64),
     /// Layer consisting of fixed byte values.
     Defined(Box<Vec<u8>>),
+    /// Layer consisting of a part of a file mapped into memory.
+    Mapped(FileMap),
+}
+
+/// Read only mapping of a part of a file.
+///
+/// Only path, range and hash are serialized. Decoding maps the file again and fails if the
+/// contents changed. The hash is computed the first time it's needed, usually when the project is
+/// saved.
+pub struct FileMap {
+    path: PathBuf,
+    offset: u64,
+    len: u64,
+    hash: Mutex<Option<String>>,
+    map: Mmap,
+}
+
+impl FileMap {
+    /// Maps `len` bytes starting at `offset` of the file at `p`.
+    pub fn open(p: &Path, offset: u64, len: u64) -> Result<FileMap> {
+        let path = try!(fs::canonicalize(p));
+        let map = try!(Mmap::open_path(&path,Protection::Read));
+
+        match offset.checked_add(len) {
+            Some(end) if end <= map.len() as u64 => {},
+            _ => return Err(Error::format_at(offset,format!("{} bytes at {:#x} are outside of {}",len,offset,path.display()))),
+        }
+
+        Ok(FileMap{
+            path: path,
+            offset: offset,
+            len: len,
+            hash: Mutex::new(None),
+            map: map,
+        })
+    }
+
+    /// Path of the mapped file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Offset of the mapped part inside the file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// SHA-1 hash of the mapped part of the file as hex string.
+    pub fn hash(&self) -> String {
+        let mut hash = self.hash.lock().unwrap();
+
+        if hash.is_none() {
+            *hash = Some(self.digest());
+        }
+
+        hash.clone().unwrap()
+    }
+
+    /// The mapped part of the file.
+    pub fn as_slice(&self) -> &[u8] {
+        let all = unsafe { self.map.as_slice() };
+        &all[self.offset as usize..(self.offset + self.len) as usize]
+    }
+
+    fn digest(&self) -> String {
+        let mut sha = Sha1::new();
+
+        sha.update(self.as_slice());
+        sha.hexdigest()
+    }
+}
+
+impl fmt::Debug for FileMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"FileMap{{ path: {:?}, offset: {}, len: {} }}",self.path,self.offset,self.len)
+    }
+}
+
+impl Encodable for FileMap {
+    fn encode<S: Encoder>(&self, s: &mut S) -> ::std::result::Result<(),S::Error> {
+        s.emit_struct("FileMap",4,|s| {
+            try!(s.emit_struct_field("path",0,|s| self.path.to_string_lossy().encode(s)));
+            try!(s.emit_struct_field("offset",1,|s| self.offset.encode(s)));
+            try!(s.emit_struct_field("len",2,|s| self.len.encode(s)));
+            s.emit_struct_field("hash",3,|s| self.hash().encode(s))
+        })
+    }
+}
+
+impl Decodable for FileMap {
+    fn decode<D: Decoder>(d: &mut D) -> ::std::result::Result<FileMap,D::Error> {
+        d.read_struct("FileMap",4,|d| {
+            let path = try!(d.read_struct_field("path",0,|d| String::decode(d)));
+            let offset = try!(d.read_struct_field("offset",1,|d| u64::decode(d)));
+            let len = try!(d.read_struct_field("len",2,|d| u64::decode(d)));
+            let hash = try!(d.read_struct_field("hash",3,|d| String::decode(d)));
+            let map = match FileMap::open(Path::new(&path),offset,len) {
+                Ok(m) => m,
+                Err(e) => return Err(d.error(&format!("can't map {}: {}",path,e))),
+            };
+
+            if map.hash() != hash {
+                return Err(d.error(&format!("{} changed since the project was saved",path)));
+            }
+
+            Ok(map)
+        })
+    }
 }
 
 /// Iterator over a range of `Cell`s.
@@ -229,6 +351,7 @@ impl OpaqueLayer {
         match *self {
             OpaqueLayer::Undefined(ref len) => LayerIter::Undefined(*len),
             OpaqueLayer::Defined(ref v) => LayerIter::Defined(Some(v)),
+            OpaqueLayer::Mapped(ref m) => LayerIter::Defined(Some(m.as_slice())),
         }
     }
 
@@ -237,15 +360,42 @@ impl OpaqueLayer {
         match *self {
             OpaqueLayer::Undefined(ref len) => *len,
             OpaqueLayer::Defined(ref v) => v.len() as u64,
+            OpaqueLayer::Mapped(ref m) => m.len,
         }
     }
 
     /// Create a new `Layer` that replaces overlapped `Cell`s with the contents of the file at
-    /// `path`. The `Layer` will have the size of the file.
+    /// `path`. The `Layer` will have the size of the file. Files of at least `MAP_THRESHOLD`
+    /// bytes are mapped into memory.
     pub fn open(p: &Path) -> Result<OpaqueLayer> {
-        let mut buf: Vec<u8> = Vec::new();
-        try!(File::open(p).map(|ref mut f| f.read_to_end(&mut buf)));
-        Ok(Self::wrap(buf))
+        let len = try!(fs::metadata(p)).len();
+        Self::open_range(p,0,len)
+    }
+
+    /// Create a new `Layer` that replaces overlapped `Cell`s with `len` bytes starting at
+    /// `offset` of the file at `path`. Ranges of at least `MAP_THRESHOLD` bytes are mapped into
+    /// memory.
+    pub fn open_range(p: &Path, offset: u64, len: u64) -> Result<OpaqueLayer> {
+        if len >= MAP_THRESHOLD {
+            Self::map(p,offset,len)
+        } else {
+            let mut fd = try!(File::open(p));
+            let mut buf = vec![0; len as usize];
+
+            try!(fd.seek(SeekFrom::Start(offset)));
+            try!(fd.read_exact(&mut buf));
+            Ok(Self::wrap(buf))
+        }
+    }
+
+    /// Create a new `Layer` backed by `len` bytes starting at `offset` of the file at `path`,
+    /// regardless of size. Empty ranges are not mapped.
+    pub fn map(p: &Path, offset: u64, len: u64) -> Result<OpaqueLayer> {
+        if len == 0 {
+            Ok(Self::wrap(vec![]))
+        } else {
+            FileMap::open(p,offset,len).map(OpaqueLayer::Mapped)
+        }
     }
 
     /// Create a new `Layer` that replaces overlapped `Cell`s with the contents of `data`.
@@ -390,6 +540,46 @@ mod tests {
         assert_eq!(s.collect::<Vec<Cell>>(), e);
     }
 
+    #[test]
+    fn mapped() {
+        use std::io::Write;
+        use rmp_serialize::{Encoder,Decoder};
+        use rustc_serialize::{Encodable,Decodable};
+        use tempdir::TempDir;
+
+        let tmpdir = TempDir::new("test-layer").unwrap();
+        let p = tmpdir.path().join("image.bin");
+
+        File::create(&p).unwrap().write_all(&[1,2,3,4,5,6,7,8]).unwrap();
+
+        let l = OpaqueLayer::map(&p,2,4).unwrap();
+
+        assert_eq!(l.len(),4);
+        assert_eq!(l.iter().collect::<Vec<Cell>>(),vec![Some(3),Some(4),Some(5),Some(6)]);
+        assert!(OpaqueLayer::map(&p,6,4).is_err());
+
+        // small files are read into memory
+        match OpaqueLayer::open(&p).unwrap() {
+            OpaqueLayer::Defined(ref v) => assert_eq!(v.len(),8),
+            _ => unreachable!(),
+        }
+
+        let mut buf = Vec::<u8>::new();
+        l.encode(&mut Encoder::new(&mut buf)).unwrap();
+
+        match OpaqueLayer::decode(&mut Decoder::new(&buf[..])) {
+            Ok(OpaqueLayer::Mapped(ref m)) => {
+                assert_eq!(m.offset(),2);
+                assert_eq!(m.as_slice(),&[3,4,5,6]);
+            },
+            _ => unreachable!(),
+        }
+
+        // changing the mapped bytes invalidates the saved layer
+        File::create(&p).unwrap().write_all(&[1,2,3,0,5,6,7,8]).unwrap();
+        assert!(OpaqueLayer::decode(&mut Decoder::new(&buf[..])).is_err());
+    }
+
     #[test]
     fn random_access_iter() {
         let l1 = OpaqueLayer::undefined(0xffffffff);
@@ -91,6 +91,8 @@ extern crate lazy_static;
 
 extern crate byteorder;
 extern crate goblin;
+extern crate memmap;
+extern crate sha1;
 
 // core
 pub mod disassembler;
@@ -139,11 +141,45 @@ pub use program::{
     CallTarget,
     CallGraph,
     CallGraphRef,
+    DisassemblyEvent,
 };
 
 pub mod project;
 pub use project::Project;
 
+pub mod session;
+pub use session::{
+    Header,
+    Metadata,
+};
+
+pub mod journal;
+pub use journal::{
+    Change,
+    Journal,
+};
+
+pub mod history;
+pub use history::{
+    Edit,
+    History,
+};
+
+pub mod export;
+
+pub mod annotations;
+pub use annotations::{
+    Annotations,
+    Merge,
+};
+
+pub mod xref;
+pub use xref::{
+    Xref,
+    XrefKind,
+    XrefIndex,
+};
+
 pub mod region;
 pub use region::{
     Region,
@@ -155,6 +191,7 @@ pub use layer::{
     Layer,
     OpaqueLayer,
     LayerIter,
+    FileMap,
 };
 
 pub mod result;
@@ -172,6 +209,41 @@ pub use abstractinterp::{
     approximate,
 };
 
+pub mod memory;
+pub use memory::{
+    MemoryLocation,
+    AbstractStore,
+};
+
+pub mod interval;
+pub use interval::{
+    Interval,
+    UnsignedInterval,
+    SignedInterval,
+    StridedInterval,
+};
+
+pub mod jump_table;
+pub use jump_table::resolve_indirect_jumps;
+
+pub mod interproc;
+pub use interproc::{
+    Summary,
+    Approximation,
+    approximate_program,
+};
+
+pub mod frame;
+pub use frame::{
+    Frame,
+    StackSlot,
+    SlotKind,
+    recover_frame,
+};
+
+#[cfg(test)]
+mod test_util;
+
 // disassembler
 pub mod avr;
 pub mod amd64;
@@ -180,3 +252,12 @@ pub mod mos;
 // file formats
 pub mod pe;
 pub mod elf;
+pub mod macho;
+pub mod dos;
+pub mod firmware;
+pub mod loader;
+pub use loader::{
+    Format,
+    Target,
+    LoadError,
+};
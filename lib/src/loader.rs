@@ -0,0 +1,259 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! File format detection.
+//!
+//! Guesses the format of a file from its first bytes and calls the right loader. Files that
+//! match no known magic number need to be loaded as raw memory images using `Project::raw`.
+
+use std::path::{Path,PathBuf};
+use std::fs::File;
+use std::io::{self,Read};
+use std::fmt;
+use std::error;
+
+use project::Project;
+use result;
+use {amd64,avr,mos,elf,pe,macho,firmware};
+
+/// File formats `load` knows about.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Format {
+    /// Saved Panopticon session
+    Panopticon,
+    /// ELF executable or shared object
+    Elf,
+    /// Windows PE file
+    Pe,
+    /// Mach-O or universal binary
+    MachO,
+    /// Intel HEX firmware image
+    IntelHex,
+    /// Motorola S-record firmware image
+    SRecord,
+}
+
+impl Format {
+    /// Short, lower case name of the format.
+    pub fn name(&self) -> &'static str {
+        match self {
+            &Format::Panopticon => "panop",
+            &Format::Elf => "elf",
+            &Format::Pe => "pe",
+            &Format::MachO => "macho",
+            &Format::IntelHex => "ihex",
+            &Format::SRecord => "srec",
+        }
+    }
+}
+
+/// CPU and its configuration a file is intended for.
+#[derive(Clone,Debug)]
+pub enum Target {
+    /// IA-32 or AMD64 in the given mode
+    Amd64(amd64::Mode),
+    /// AVR microcontroller
+    Avr(avr::Mcu),
+    /// MOS 6502
+    Mos(mos::Variant),
+}
+
+/// Error returned by `load`.
+#[derive(Debug)]
+pub enum LoadError {
+    /// Reading the file failed
+    Io(PathBuf,io::Error),
+    /// No loader accepted the file. Lists the formats that were tried, in order, and why each of
+    /// them failed. Empty if no magic number matched.
+    Unrecognized(PathBuf,Vec<(Format,result::Error)>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &LoadError::Io(ref p,ref e) => write!(f,"{}: {}",p.display(),e),
+            &LoadError::Unrecognized(ref p,ref tried) if tried.is_empty() =>
+                write!(f,"{}: unknown file format",p.display()),
+            &LoadError::Unrecognized(ref p,ref tried) => {
+                try!(write!(f,"{}: failed to load file.",p.display()));
+
+                for &(format,ref e) in tried.iter() {
+                    try!(write!(f," Not a {} file: {}.",format.name(),e));
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for LoadError {
+    fn description(&self) -> &str {
+        "failed to load file"
+    }
+}
+
+impl From<LoadError> for result::Error {
+    fn from(e: LoadError) -> result::Error {
+        match e {
+            LoadError::Io(p,e) => result::Error::Io(io::Error::new(e.kind(),format!("{}: {}",p.display(),e))),
+            e@LoadError::Unrecognized(..) => result::Error::format(format!("{}",e)),
+        }
+    }
+}
+
+/// Universal binary and 32/64 bit big and little endian Mach-O magic numbers.
+const MACHO_MAGIC: [&'static [u8]; 5] = [
+    b"\xca\xfe\xba\xbe",
+    b"\xfe\xed\xfa\xce",
+    b"\xfe\xed\xfa\xcf",
+    b"\xce\xfa\xed\xfe",
+    b"\xcf\xfa\xed\xfe",
+];
+
+/// Returns all formats whose magic number matches the start of `buf`, most likely first.
+pub fn sniff(buf: &[u8]) -> Vec<Format> {
+    let mut ret = vec![];
+
+    if buf.starts_with(b"PANOPTICON") {
+        ret.push(Format::Panopticon);
+    }
+
+    if buf.starts_with(b"\x7fELF") {
+        ret.push(Format::Elf);
+    }
+
+    if buf.starts_with(b"MZ") {
+        ret.push(Format::Pe);
+    }
+
+    if MACHO_MAGIC.iter().any(|m| buf.starts_with(m)) {
+        ret.push(Format::MachO);
+    }
+
+    let text = buf.iter().skip_while(|&&x| x == b' ' || x == b'\t' || x == b'\r' || x == b'\n').cloned().collect::<Vec<u8>>();
+
+    if text.starts_with(b":") {
+        ret.push(Format::IntelHex);
+    }
+
+    if text.len() >= 2 && text[0] == b'S' && text[1] >= b'0' && text[1] <= b'9' {
+        ret.push(Format::SRecord);
+    }
+
+    ret
+}
+
+/// Guesses the format of `p` and loads it. Returns the new `Project` and, if it could be
+/// determined, the CPU the file is intended for.
+pub fn load(p: &Path) -> ::std::result::Result<(Project,Option<Target>),LoadError> {
+    let mut tried = vec![];
+    let mut buf = [0u8; 512];
+    let len = match File::open(p).and_then(|mut fd| fd.read(&mut buf)) {
+        Ok(len) => len,
+        Err(e) => return Err(LoadError::Io(p.to_path_buf(),e)),
+    };
+
+    for format in sniff(&buf[0..len]) {
+        let res = match format {
+            Format::Panopticon => Project::open(p).map(|proj| (proj,None)),
+            Format::Elf => elf::load(p).map(|(proj,m)| {
+                let tgt = match m {
+                    elf::Machine::Amd64 => Some(Target::Amd64(amd64::Mode::Long)),
+                    elf::Machine::Ia32 => Some(Target::Amd64(amd64::Mode::Protected)),
+                    elf::Machine::Avr => Some(Target::Avr(avr::Mcu::atmega88())),
+                    _ => None,
+                };
+
+                (proj,tgt)
+            }),
+            Format::Pe => pe::load(p).map(|(proj,m)| {
+                let tgt = match m {
+                    pe::Machine::Amd64 => Target::Amd64(amd64::Mode::Long),
+                    pe::Machine::Ia32 => Target::Amd64(amd64::Mode::Protected),
+                };
+
+                (proj,Some(tgt))
+            }),
+            Format::MachO => macho::load(p,None).map(|(proj,m)| {
+                let tgt = match m {
+                    macho::Machine::Amd64 => Some(Target::Amd64(amd64::Mode::Long)),
+                    macho::Machine::Ia32 => Some(Target::Amd64(amd64::Mode::Protected)),
+                    _ => None,
+                };
+
+                (proj,tgt)
+            }),
+            Format::IntelHex => firmware::ihex(p).map(|proj| (proj,None)),
+            Format::SRecord => firmware::srec(p).map(|proj| (proj,None)),
+        };
+
+        match res {
+            Ok(ret) => return Ok(ret),
+            Err(e) => {
+                debug!("{} is not a {} file: {}",p.display(),format.name(),e);
+                tried.push((format,e));
+            }
+        }
+    }
+
+    Err(LoadError::Unrecognized(p.to_path_buf(),tried))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn sniff_magic() {
+        assert_eq!(sniff(b"\x7fELF\x02\x01"),vec![Format::Elf]);
+        assert_eq!(sniff(b"MZ\x90\x00"),vec![Format::Pe]);
+        assert_eq!(sniff(b"PANOPTICON\x00\x00\x00\x01"),vec![Format::Panopticon]);
+        assert_eq!(sniff(b"\xcf\xfa\xed\xfe\x07\x00\x00\x01"),vec![Format::MachO]);
+        assert_eq!(sniff(b"\xca\xfe\xba\xbe\x00\x00\x00\x02"),vec![Format::MachO]);
+        assert_eq!(sniff(b":100000000C942A00"),vec![Format::IntelHex]);
+        assert_eq!(sniff(b"\r\nS00D0000736F"),vec![Format::SRecord]);
+        assert_eq!(sniff(b"\x0c\x94\x2a\x00"),vec![]);
+        assert_eq!(sniff(b""),vec![]);
+    }
+
+    #[test]
+    fn load_elf() {
+        match load(Path::new("tests/data/static")) {
+            Ok((_,Some(Target::Amd64(amd64::Mode::Long)))) => {},
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn load_unknown() {
+        match load(Path::new("tests/data/sosse")) {
+            Err(LoadError::Unrecognized(_,ref tried)) if tried.is_empty() => {},
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn load_missing() {
+        match load(Path::new("tests/data/does-not-exist")) {
+            Err(LoadError::Io(..)) => {},
+            _ => unreachable!(),
+        }
+    }
+}
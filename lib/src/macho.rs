@@ -0,0 +1,823 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Loader for Mach-O files.
+//!
+//! Supports 32 and 64 bit Mach-O files as well as universal ("fat") binaries that contain
+//! Mach-O files for multiple CPUs. Segments are mapped into a single `Region`. The entry point
+//! (`LC_MAIN` or `LC_UNIXTHREAD`) and all symbols of the export trie are added as
+//! `CallTarget::Todo`. Symbols bound by dyld are added as `CallTarget::Symbolic` and the address
+//! of the pointer they're bound to is recorded in `Program::imports`. Code calls the symbol
+//! stubs jumping through these pointers, their addresses are recorded too.
+
+use std::path::Path;
+use std::fs;
+use std::io::{Read,Cursor,Seek,SeekFrom};
+use std::collections::{HashSet,HashMap,BTreeMap};
+
+use byteorder::{ByteOrder,ReadBytesExt,LittleEndian,BigEndian};
+use graph_algos::MutableGraphTrait;
+use uuid::Uuid;
+
+use project::Project;
+use program::{Program,CallTarget};
+use region::Region;
+use mnemonic::Bound;
+use layer::{Layer,OpaqueLayer,FileMap};
+use il::Rvalue;
+use result::{Result,Error};
+
+const FAT_MAGIC: u32 = 0xcafebabe;
+const MH_MAGIC: u32 = 0xfeedface;
+const MH_MAGIC_64: u32 = 0xfeedfacf;
+const MH_CIGAM: u32 = 0xcefaedfe;
+const MH_CIGAM_64: u32 = 0xcffaedfe;
+
+const LC_SEGMENT: u32 = 0x1;
+const LC_UNIXTHREAD: u32 = 0x5;
+const LC_DYSYMTAB: u32 = 0xb;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_DYLD_INFO: u32 = 0x22;
+const LC_DYLD_INFO_ONLY: u32 = 0x80000022;
+const LC_MAIN: u32 = 0x80000028;
+
+const EXPORT_SYMBOL_FLAGS_REEXPORT: u64 = 0x08;
+const EXPORT_SYMBOL_FLAGS_STUB_AND_RESOLVER: u64 = 0x10;
+
+const SECTION_TYPE: u32 = 0xff;
+const S_NON_LAZY_SYMBOL_POINTERS: u32 = 0x6;
+const S_LAZY_SYMBOL_POINTERS: u32 = 0x7;
+const S_SYMBOL_STUBS: u32 = 0x8;
+
+const INDIRECT_SYMBOL_LOCAL: u32 = 0x80000000;
+const INDIRECT_SYMBOL_ABS: u32 = 0x40000000;
+
+/// CPU the Mach-O file is intended for.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Machine {
+    /// Intel x86
+    Ia32,
+    /// AMD64
+    Amd64,
+    /// 32-bit ARM
+    Arm,
+    /// 64-bit ARM
+    Aarch64,
+    /// 32-bit PowerPC
+    PowerPc,
+    /// 64-bit PowerPC
+    PowerPc64,
+    /// Any other value of `cputype`
+    Unknown(u32),
+}
+
+impl Machine {
+    /// Returns the CPU for the `cputype` field of the Mach-O header.
+    pub fn from_cputype(t: u32) -> Machine {
+        match t {
+            7 => Machine::Ia32,
+            0x01000007 => Machine::Amd64,
+            12 => Machine::Arm,
+            0x0100000c => Machine::Aarch64,
+            18 => Machine::PowerPc,
+            0x01000012 => Machine::PowerPc64,
+            t => Machine::Unknown(t),
+        }
+    }
+}
+
+/// Segment to map into memory.
+struct Segment {
+    name: String,
+    vmaddr: u64,
+    fileoff: u64,
+    filesize: u64,
+}
+
+/// Section of a segment. Only the fields needed to find symbol stubs and pointers are kept.
+struct Section {
+    addr: u64,
+    size: u64,
+    flags: u32,
+    /// Index of the first entry in the indirect symbol table
+    reserved1: u32,
+    /// Size of a symbol stub
+    reserved2: u32,
+}
+
+/// Everything read from the load commands.
+struct Commands {
+    segments: Vec<Segment>,
+    sections: Vec<Section>,
+    main: Option<u64>,
+    thread_pc: Option<u64>,
+    bind: Vec<(u32,u32)>,
+    export: Option<(u32,u32)>,
+    indirect_symbols: Vec<u32>,
+}
+
+/// Reads an unsigned LEB128 value.
+fn read_uleb(buf: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut ret = 0u64;
+    let mut shift = 0;
+
+    loop {
+        let b = try!(buf.get(*pos).cloned().ok_or(Error::format("Truncated LEB128 value")));
+
+        *pos += 1;
+
+        if shift < 64 {
+            ret |= ((b & 0x7f) as u64) << shift;
+        }
+        shift += 7;
+
+        if b & 0x80 == 0 {
+            return Ok(ret);
+        }
+    }
+}
+
+/// Reads a signed LEB128 value.
+fn read_sleb(buf: &[u8], pos: &mut usize) -> Result<i64> {
+    let mut ret = 0i64;
+    let mut shift = 0;
+
+    loop {
+        let b = try!(buf.get(*pos).cloned().ok_or(Error::format("Truncated LEB128 value")));
+
+        *pos += 1;
+
+        if shift < 64 {
+            ret |= ((b & 0x7f) as i64) << shift;
+        }
+        shift += 7;
+
+        if b & 0x80 == 0 {
+            if shift < 64 && b & 0x40 != 0 {
+                ret |= -1i64 << shift;
+            }
+            return Ok(ret);
+        }
+    }
+}
+
+/// Reads a NUL terminated string.
+fn read_cstr(buf: &[u8], pos: &mut usize) -> Result<String> {
+    let start = *pos;
+
+    while *pos < buf.len() && buf[*pos] != 0 {
+        *pos += 1;
+    }
+
+    if *pos >= buf.len() {
+        return Err(Error::format("Unterminated string"));
+    }
+
+    let ret = String::from_utf8_lossy(&buf[start..*pos]).to_string();
+
+    *pos += 1;
+    Ok(ret)
+}
+
+fn read_commands<E: ByteOrder>(buf: &[u8], cpu: Machine, is_64: bool) -> Result<Commands> {
+    let mut strm = Cursor::new(buf);
+    let _magic = try!(strm.read_u32::<E>());
+    let _cputype = try!(strm.read_u32::<E>());
+    let _cpusubtype = try!(strm.read_u32::<E>());
+    let _filetype = try!(strm.read_u32::<E>());
+    let ncmds = try!(strm.read_u32::<E>());
+    let _sizeofcmds = try!(strm.read_u32::<E>());
+    let _flags = try!(strm.read_u32::<E>());
+
+    if is_64 {
+        let _reserved = try!(strm.read_u32::<E>());
+    }
+
+    let mut ret = Commands{
+        segments: vec![],
+        sections: vec![],
+        main: None,
+        thread_pc: None,
+        bind: vec![],
+        export: None,
+        indirect_symbols: vec![],
+    };
+    let mut indirect = None;
+
+    for _ in 0..ncmds {
+        let start = strm.position();
+        let cmd = try!(strm.read_u32::<E>());
+        let cmdsize = try!(strm.read_u32::<E>());
+
+        if cmdsize < 8 {
+            return Err(Error::format(format!("Invalid size of load command {:#x}",cmd)));
+        }
+
+        match cmd {
+            LC_SEGMENT | LC_SEGMENT_64 => {
+                let mut segname = [0u8; 16];
+
+                try!(strm.read_exact(&mut segname));
+
+                let (vmaddr,fileoff,filesize) = if cmd == LC_SEGMENT_64 {
+                    let vmaddr = try!(strm.read_u64::<E>());
+                    let _vmsize = try!(strm.read_u64::<E>());
+                    let fileoff = try!(strm.read_u64::<E>());
+                    let filesize = try!(strm.read_u64::<E>());
+
+                    (vmaddr,fileoff,filesize)
+                } else {
+                    let vmaddr = try!(strm.read_u32::<E>()) as u64;
+                    let _vmsize = try!(strm.read_u32::<E>());
+                    let fileoff = try!(strm.read_u32::<E>()) as u64;
+                    let filesize = try!(strm.read_u32::<E>()) as u64;
+
+                    (vmaddr,fileoff,filesize)
+                };
+                let name = String::from_utf8_lossy(&segname).trim_right_matches('\0').to_string();
+                let _maxprot = try!(strm.read_u32::<E>());
+                let _initprot = try!(strm.read_u32::<E>());
+                let nsects = try!(strm.read_u32::<E>()) as u64;
+                let _flags = try!(strm.read_u32::<E>());
+                let sect_size = if cmd == LC_SEGMENT_64 { 80 } else { 68 };
+
+                if strm.position() - start + nsects * sect_size > cmdsize as u64 {
+                    return Err(Error::format(format!("Sections of segment '{}' exceed its load command",name)));
+                }
+
+                for _ in 0..nsects {
+                    let mut names = [0u8; 32];
+
+                    try!(strm.read_exact(&mut names));
+
+                    let (addr,size) = if cmd == LC_SEGMENT_64 {
+                        (try!(strm.read_u64::<E>()),try!(strm.read_u64::<E>()))
+                    } else {
+                        (try!(strm.read_u32::<E>()) as u64,try!(strm.read_u32::<E>()) as u64)
+                    };
+                    let _offset = try!(strm.read_u32::<E>());
+                    let _align = try!(strm.read_u32::<E>());
+                    let _reloff = try!(strm.read_u32::<E>());
+                    let _nreloc = try!(strm.read_u32::<E>());
+                    let flags = try!(strm.read_u32::<E>());
+                    let reserved1 = try!(strm.read_u32::<E>());
+                    let reserved2 = try!(strm.read_u32::<E>());
+
+                    ret.sections.push(Section{
+                        addr: addr,
+                        size: size,
+                        flags: flags,
+                        reserved1: reserved1,
+                        reserved2: reserved2,
+                    });
+                }
+
+                ret.segments.push(Segment{
+                    name: name,
+                    vmaddr: vmaddr,
+                    fileoff: fileoff,
+                    filesize: filesize,
+                });
+            },
+            LC_MAIN => {
+                ret.main = Some(try!(strm.read_u64::<E>()));
+            },
+            LC_UNIXTHREAD => {
+                let flavor = try!(strm.read_u32::<E>());
+                let _count = try!(strm.read_u32::<E>());
+
+                // Position of the program counter in the thread state of each flavor.
+                ret.thread_pc = match (cpu,flavor) {
+                    // x86_THREAD_STATE32: eax ... ss, eip is the 11th register
+                    (Machine::Ia32,1) => {
+                        try!(strm.seek(SeekFrom::Current(10 * 4)));
+                        Some(try!(strm.read_u32::<E>()) as u64)
+                    },
+                    // ARM_THREAD_STATE: r0 ... r12, sp, lr, pc
+                    (Machine::Arm,1) => {
+                        try!(strm.seek(SeekFrom::Current(15 * 4)));
+                        Some(try!(strm.read_u32::<E>()) as u64)
+                    },
+                    // x86_THREAD_STATE64: rax ... r15, rip is the 17th register
+                    (Machine::Amd64,4) => {
+                        try!(strm.seek(SeekFrom::Current(16 * 8)));
+                        Some(try!(strm.read_u64::<E>()))
+                    },
+                    // ARM_THREAD_STATE64: x0 ... x28, fp, lr, sp, pc
+                    (Machine::Aarch64,6) => {
+                        try!(strm.seek(SeekFrom::Current(32 * 8)));
+                        Some(try!(strm.read_u64::<E>()))
+                    },
+                    (_,f) => {
+                        warn!("Unsupported thread state flavor {} for {:?}",f,cpu);
+                        None
+                    }
+                };
+            },
+            LC_DYLD_INFO | LC_DYLD_INFO_ONLY => {
+                let _rebase_off = try!(strm.read_u32::<E>());
+                let _rebase_size = try!(strm.read_u32::<E>());
+                let bind_off = try!(strm.read_u32::<E>());
+                let bind_size = try!(strm.read_u32::<E>());
+                let _weak_bind_off = try!(strm.read_u32::<E>());
+                let _weak_bind_size = try!(strm.read_u32::<E>());
+                let lazy_bind_off = try!(strm.read_u32::<E>());
+                let lazy_bind_size = try!(strm.read_u32::<E>());
+                let export_off = try!(strm.read_u32::<E>());
+                let export_size = try!(strm.read_u32::<E>());
+
+                ret.bind.push((bind_off,bind_size));
+                ret.bind.push((lazy_bind_off,lazy_bind_size));
+                ret.export = Some((export_off,export_size));
+            },
+            LC_DYSYMTAB => {
+                // ilocalsym ... nextrefsyms
+                try!(strm.seek(SeekFrom::Current(12 * 4)));
+
+                let indirectsymoff = try!(strm.read_u32::<E>());
+                let nindirectsyms = try!(strm.read_u32::<E>());
+
+                indirect = Some((indirectsymoff,nindirectsyms));
+            },
+            _ => {}
+        }
+
+        try!(strm.seek(SeekFrom::Start(start + cmdsize as u64)));
+    }
+
+    if let Some((off,num)) = indirect {
+        let size = try!(num.checked_mul(4).ok_or(Error::format("Indirect symbol table too large")));
+        let table = try!(slice(buf,(off,size)));
+
+        ret.indirect_symbols = table.chunks(4).map(|x| E::read_u32(x)).collect();
+    }
+
+    Ok(ret)
+}
+
+/// Returns the part of `buf` described by `(offset,size)`.
+fn slice(buf: &[u8], (off,sz): (u32,u32)) -> Result<&[u8]> {
+    let start = off as usize;
+
+    match start.checked_add(sz as usize) {
+        Some(end) if end <= buf.len() => Ok(&buf[start..end]),
+        _ => Err(Error::format_at(off as u64,"Dyld information outside of the file")),
+    }
+}
+
+/// Decodes the export trie. Returns all exported functions relative to the image base. Re-exports
+/// are ignored.
+fn exports(trie: &[u8]) -> Result<Vec<(u64,String)>> {
+    let mut ret = vec![];
+    let mut todo = vec![(0usize,String::new())];
+    let mut seen = HashSet::new();
+
+    while let Some((node,prefix)) = todo.pop() {
+        if !seen.insert(node) {
+            return Err(Error::format("Loop in export trie"));
+        }
+
+        let mut pos = node;
+        let term_size = try!(read_uleb(trie,&mut pos)) as usize;
+        let children = try!(pos.checked_add(term_size).ok_or(Error::format("Truncated export trie")));
+
+        if term_size > 0 {
+            let flags = try!(read_uleb(trie,&mut pos));
+
+            if flags & EXPORT_SYMBOL_FLAGS_REEXPORT == 0 {
+                let addr = try!(read_uleb(trie,&mut pos));
+
+                if flags & EXPORT_SYMBOL_FLAGS_STUB_AND_RESOLVER != 0 {
+                    let _resolver = try!(read_uleb(trie,&mut pos));
+                }
+
+                ret.push((addr,prefix.clone()));
+            }
+        }
+
+        pos = children;
+
+        let num_children = try!(trie.get(pos).cloned().ok_or(Error::format("Truncated export trie")));
+
+        pos += 1;
+
+        for _ in 0..num_children {
+            let edge = try!(read_cstr(trie,&mut pos));
+            let child = try!(read_uleb(trie,&mut pos)) as usize;
+
+            todo.push((child,format!("{}{}",prefix,edge)));
+        }
+    }
+
+    ret.sort();
+    Ok(ret)
+}
+
+/// Maximal number of pointers bound by a single bind opcode stream.
+const MAX_BINDS: usize = 0x10_0000;
+
+/// Runs the bind opcodes in `ops`. Returns the address of each bound pointer and the name of
+/// the symbol.
+fn binds(ops: &[u8], segments: &[Segment], ptr_size: u64) -> Result<Vec<(u64,String)>> {
+    let mut ret = vec![];
+    let mut pos = 0;
+    let mut sym = String::new();
+    let mut addr = 0u64;
+
+    macro_rules! bind {
+        () => {{
+            if sym.is_empty() {
+                return Err(Error::format("Bind without a symbol"));
+            }
+            if ret.len() >= MAX_BINDS {
+                return Err(Error::format(format!("More than {} binds",MAX_BINDS)));
+            }
+            ret.push((addr,sym.clone()));
+            addr = addr.wrapping_add(ptr_size);
+        }}
+    }
+
+    while pos < ops.len() {
+        let op = ops[pos] & 0xf0;
+        let imm = (ops[pos] & 0x0f) as u64;
+
+        pos += 1;
+
+        match op {
+            // DONE: separates the entries of the lazy bind information
+            0x00 => {},
+            // SET_DYLIB_ORDINAL_IMM, SET_DYLIB_SPECIAL_IMM, SET_TYPE_IMM
+            0x10 | 0x30 | 0x50 => {},
+            // SET_DYLIB_ORDINAL_ULEB
+            0x20 => { try!(read_uleb(ops,&mut pos)); },
+            // SET_SYMBOL_TRAILING_FLAGS_IMM
+            0x40 => sym = try!(read_cstr(ops,&mut pos)),
+            // SET_ADDEND_SLEB
+            0x60 => { try!(read_sleb(ops,&mut pos)); },
+            // SET_SEGMENT_AND_OFFSET_ULEB
+            0x70 => {
+                let seg = try!(segments.get(imm as usize).ok_or(Error::format("Bind to unknown segment")));
+                let off = try!(read_uleb(ops,&mut pos));
+
+                addr = seg.vmaddr.wrapping_add(off);
+            },
+            // ADD_ADDR_ULEB
+            0x80 => addr = addr.wrapping_add(try!(read_uleb(ops,&mut pos))),
+            // DO_BIND
+            0x90 => bind!(),
+            // DO_BIND_ADD_ADDR_ULEB
+            0xa0 => {
+                bind!();
+                addr = addr.wrapping_add(try!(read_uleb(ops,&mut pos)));
+            },
+            // DO_BIND_ADD_ADDR_IMM_SCALED
+            0xb0 => {
+                bind!();
+                addr = addr.wrapping_add(imm * ptr_size);
+            },
+            // DO_BIND_ULEB_TIMES_SKIPPING_ULEB
+            0xc0 => {
+                let count = try!(read_uleb(ops,&mut pos));
+                let skip = try!(read_uleb(ops,&mut pos));
+
+                for _ in 0..count {
+                    bind!();
+                    addr = addr.wrapping_add(skip);
+                }
+            },
+            op => return Err(Error::format(format!("Unknown bind opcode {:#x}",op))),
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Returns the address of each symbol stub and the name of the symbol it jumps to. The stubs in
+/// `S_SYMBOL_STUBS` sections jump through the pointers in the (non-)lazy symbol pointer sections.
+/// Both refer to the symbol using the indirect symbol table, the name is taken from `bound`, the
+/// symbols bound to each pointer.
+fn stubs(cmds: &Commands, bound: &HashMap<u64,String>, ptr_size: u64) -> Vec<(u64,String)> {
+    let mut names = HashMap::<u32,String>::new();
+    let mut ret = vec![];
+
+    for sect in cmds.sections.iter() {
+        match sect.flags & SECTION_TYPE {
+            S_NON_LAZY_SYMBOL_POINTERS | S_LAZY_SYMBOL_POINTERS => {
+                let num = (sect.size / ptr_size) as usize;
+
+                for (i,&sym) in cmds.indirect_symbols.iter().skip(sect.reserved1 as usize).take(num).enumerate() {
+                    let addr = sect.addr.wrapping_add(i as u64 * ptr_size);
+
+                    if sym & (INDIRECT_SYMBOL_LOCAL | INDIRECT_SYMBOL_ABS) == 0 {
+                        if let Some(name) = bound.get(&addr) {
+                            names.insert(sym,name.clone());
+                        }
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    for sect in cmds.sections.iter() {
+        if sect.flags & SECTION_TYPE == S_SYMBOL_STUBS && sect.reserved2 > 0 {
+            let stub_size = sect.reserved2 as u64;
+            let num = (sect.size / stub_size) as usize;
+
+            for (i,sym) in cmds.indirect_symbols.iter().skip(sect.reserved1 as usize).take(num).enumerate() {
+                if let Some(name) = names.get(sym) {
+                    ret.push((sect.addr.wrapping_add(i as u64 * stub_size),name.clone()));
+                }
+            }
+        }
+    }
+
+    ret
+}
+
+/// Returns the CPU and the position of each Mach-O file inside a universal binary.
+fn fat_slices(buf: &[u8]) -> Result<Vec<(Machine,usize,usize)>> {
+    let mut strm = Cursor::new(buf);
+    let _magic = try!(strm.read_u32::<BigEndian>());
+    let num = try!(strm.read_u32::<BigEndian>());
+    let mut ret = vec![];
+
+    for _ in 0..num {
+        let cputype = try!(strm.read_u32::<BigEndian>());
+        let _cpusubtype = try!(strm.read_u32::<BigEndian>());
+        let offset = try!(strm.read_u32::<BigEndian>()) as usize;
+        let size = try!(strm.read_u32::<BigEndian>()) as usize;
+        let _align = try!(strm.read_u32::<BigEndian>());
+
+        if offset.checked_add(size).map(|x| x > buf.len()).unwrap_or(true) {
+            return Err(Error::format("Universal binary slice outside of the file"));
+        }
+
+        ret.push((Machine::from_cputype(cputype),offset,size));
+    }
+
+    Ok(ret)
+}
+
+/// Maps the whole file at `p` into memory.
+fn map_file(p: &Path) -> Result<FileMap> {
+    let len = try!(fs::metadata(p)).len();
+
+    if len < 8 {
+        return Err(Error::format_at(0,"File too small for a Mach-O header"));
+    }
+
+    FileMap::open(p,0,len)
+}
+
+/// Returns the CPUs of all Mach-O files in the universal binary at `p`. Returns a single element
+/// for non-universal Mach-O files.
+pub fn slices(p: &Path) -> Result<Vec<Machine>> {
+    let map = try!(map_file(p));
+    let buf = map.as_slice();
+
+    if buf.len() >= 4 && BigEndian::read_u32(&buf[0..4]) == FAT_MAGIC {
+        Ok(try!(fat_slices(buf)).into_iter().map(|x| x.0).collect())
+    } else {
+        let (machine,_,_) = try!(thin_header(buf));
+        Ok(vec![machine])
+    }
+}
+
+/// Returns the CPU, byte order (true if big endian) and bitness (true if 64 bit) of a Mach-O
+/// file.
+fn thin_header(buf: &[u8]) -> Result<(Machine,bool,bool)> {
+    if buf.len() < 8 {
+        return Err(Error::format_at(0,"File too small for a Mach-O header"));
+    }
+
+    match BigEndian::read_u32(&buf[0..4]) {
+        MH_MAGIC => Ok((Machine::from_cputype(BigEndian::read_u32(&buf[4..8])),true,false)),
+        MH_MAGIC_64 => Ok((Machine::from_cputype(BigEndian::read_u32(&buf[4..8])),true,true)),
+        MH_CIGAM => Ok((Machine::from_cputype(LittleEndian::read_u32(&buf[4..8])),false,false)),
+        MH_CIGAM_64 => Ok((Machine::from_cputype(LittleEndian::read_u32(&buf[4..8])),false,true)),
+        _ => Err(Error::format_at(0,"Invalid Mach-O magic number")),
+    }
+}
+
+/// Load a Mach-O file from disk and creates a `Project` from it. Returns the `Project` instance
+/// and the CPU its intended for.
+///
+/// For universal binaries `machine` selects the slice to load. If it's `None` the first slice is
+/// used. Fails if no slice for `machine` exists.
+pub fn load(p: &Path, machine: Option<Machine>) -> Result<(Project,Machine)> {
+    let map = try!(map_file(p));
+    let buf = map.as_slice();
+
+    let (start,end) = if buf.len() >= 4 && BigEndian::read_u32(&buf[0..4]) == FAT_MAGIC {
+        let slices = try!(fat_slices(buf));
+        let maybe_slice = match machine {
+            Some(m) => slices.iter().find(|x| x.0 == m),
+            None => slices.first(),
+        };
+
+        match maybe_slice {
+            Some(&(m,off,sz)) => {
+                debug!("using {:?} slice at {:#x}",m,off);
+                (off,off + sz)
+            },
+            None => {
+                let avail = slices.iter().map(|x| format!("{:?}",x.0)).collect::<Vec<_>>();
+                return Err(Error::unsupported_architecture(format!("No slice for {:?}, available: {}",machine,avail.join(", "))));
+            }
+        }
+    } else {
+        (0,buf.len())
+    };
+    let buf = &buf[start..end];
+    let file_start = start;
+    let (cpu,is_be,is_64) = try!(thin_header(buf));
+
+    match machine {
+        Some(m) if m != cpu => return Err(Error::unsupported_architecture(format!("File is for {:?}, not {:?}",cpu,m))),
+        _ => {},
+    }
+
+    let cmds = try!(if is_be {
+        read_commands::<BigEndian>(buf,cpu,is_64)
+    } else {
+        read_commands::<LittleEndian>(buf,cpu,is_64)
+    });
+
+    debug!("{} Mach-O file for {:?}",if is_64 { "64 bit" } else { "32 bit" },cpu);
+
+    let mut reg = if is_64 {
+        Region::undefined("RAM".to_string(),0xFFFF_FFFF_FFFF_FFFF)
+    } else {
+        Region::undefined("RAM".to_string(),0x1_0000_0000)
+    };
+    let mut image_base = None;
+    let mut mapped = Vec::<Bound>::new();
+
+    for seg in cmds.segments.iter() {
+        if seg.fileoff == 0 && seg.filesize > 0 && image_base.is_none() {
+            image_base = Some(seg.vmaddr);
+        }
+
+        if seg.filesize == 0 {
+            continue;
+        }
+
+        let start = seg.fileoff as usize;
+        let end = try!(start.checked_add(seg.filesize as usize).ok_or(Error::format("Segment size overflow")));
+
+        if end > buf.len() {
+            return Err(Error::format_at(seg.fileoff,format!("Segment '{}' is outside of the file",seg.name)));
+        }
+
+        let area = match seg.vmaddr.checked_add(seg.filesize) {
+            Some(e) => Bound::new(seg.vmaddr,e),
+            None => return Err(Error::format(format!("Segment '{}' is outside of the address space",seg.name))),
+        };
+
+        if let Some(other) = mapped.iter().find(|b| b.overlaps(&area)) {
+            return Err(Error::format(format!("Segment '{}' at {:#x} overlaps segment at {:#x}",seg.name,area.start,other.start)));
+        }
+
+        debug!("Load Mach-O segment '{}' to {:#x}",seg.name,seg.vmaddr);
+
+        // `buf` is the slice of a universal binary, `file_start` its position in the file
+        let l = try!(OpaqueLayer::open_range(p,(file_start + start) as u64,seg.filesize));
+
+        if !reg.cover(area.clone(),Layer::Opaque(l)) {
+            return Err(Error::format(format!("Segment '{}' is outside of the address space",seg.name)));
+        }
+
+        mapped.push(area);
+    }
+
+    let image_base = image_base.unwrap_or(0);
+    let name = p.file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or("(encoding error)".to_string());
+    let mut prog = Program::new("prog0");
+    let mut proj = Project::new(name.clone(),reg);
+    let mut functions = BTreeMap::<u64,String>::new();
+
+    if let Some(trie) = cmds.export {
+        for (off,sym) in try!(exports(try!(slice(buf,trie)))) {
+            functions.insert(image_base.wrapping_add(off),sym);
+        }
+    }
+
+    let entry = match (cmds.main,cmds.thread_pc) {
+        (Some(off),_) => Some(image_base.wrapping_add(off)),
+        (None,Some(pc)) => Some(pc),
+        (None,None) => None,
+    };
+
+    if let Some(entry) = entry {
+        let entry_name = functions.get(&entry).cloned().unwrap_or(name);
+
+        prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(entry),Some(entry_name),Uuid::new_v4()));
+        proj.comments.insert(("RAM".to_string(),entry),"main".to_string());
+    }
+
+    for (addr,func) in functions.iter() {
+        if Some(*addr) != entry {
+            prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(*addr),Some(func.clone()),Uuid::new_v4()));
+        }
+    }
+
+    let mut imports = HashSet::<String>::new();
+    let ptr_size = if is_64 { 8 } else { 4 };
+
+    for &b in cmds.bind.iter() {
+        for (addr,sym) in try!(binds(try!(slice(buf,b)),&cmds.segments,ptr_size)) {
+            debug!("bind {} at {:#x}",sym,addr);
+            prog.imports.insert(addr,sym.clone());
+
+            if imports.insert(sym.clone()) {
+                prog.call_graph.add_vertex(CallTarget::Symbolic(sym,Uuid::new_v4()));
+            }
+        }
+    }
+
+    let stub_syms = stubs(&cmds,&prog.imports,ptr_size);
+
+    for (addr,sym) in stub_syms {
+        debug!("stub for {} at {:#x}",sym,addr);
+        prog.imports.insert(addr,sym);
+    }
+
+    proj.code.push(prog);
+
+    Ok((proj,cpu))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::{read_uleb,read_sleb,exports,binds,MAX_BINDS,Segment};
+
+    #[test]
+    fn leb128() {
+        let mut pos = 0;
+        assert_eq!(read_uleb(&[0xe5,0x8e,0x26],&mut pos).unwrap(),624485);
+        assert_eq!(pos,3);
+
+        let mut pos = 0;
+        assert_eq!(read_sleb(&[0x7f],&mut pos).unwrap(),-1);
+
+        let mut pos = 0;
+        assert!(read_uleb(&[0x80,0x80],&mut pos).is_err());
+    }
+
+    #[test]
+    fn export_trie() {
+        // "_" -> { "a" at 0x10, "b" at 0x20 }
+        let trie = [
+            0x00,0x01,b'_',0x00,0x05,
+            0x00,0x02,b'a',0x00,0x0d,b'b',0x00,0x11,
+            0x02,0x00,0x10,0x00,
+            0x02,0x00,0x20,0x00,
+        ];
+
+        assert_eq!(exports(&trie).unwrap(),vec![(0x10,"_a".to_string()),(0x20,"_b".to_string())]);
+    }
+
+    #[test]
+    fn export_trie_loop() {
+        let trie = [0x00,0x01,b'_',0x00,0x00];
+
+        assert!(exports(&trie).is_err());
+    }
+
+    #[test]
+    fn bind_count() {
+        let segs = vec![Segment{ name: "__DATA".to_string(), vmaddr: 0x1000, fileoff: 0, filesize: 0x1000 }];
+        // SET_SYMBOL "_f", SET_SEGMENT_AND_OFFSET 0, DO_BIND_ULEB_TIMES_SKIPPING_ULEB 2^63 times
+        let ops = [0x40,b'_',b'f',0x00,0x70,0x00,0xc0,0x80,0x80,0x80,0x80,0x80,0x80,0x80,0x80,0x80,0x01,0x00];
+        let ok = [0x40,b'_',b'f',0x00,0x70,0x00,0xc0,0x03,0x00];
+
+        assert!(binds(&ops,&segs,8).is_err());
+        assert_eq!(binds(&ok,&segs,8).unwrap().len(),3);
+        assert!(MAX_BINDS > 3);
+    }
+
+    #[test]
+    fn machine() {
+        assert_eq!(Machine::from_cputype(0x01000007),Machine::Amd64);
+        assert_eq!(Machine::from_cputype(0x0100000c),Machine::Aarch64);
+    }
+}
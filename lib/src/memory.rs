@@ -0,0 +1,427 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Abstract memory for the abstract interpretation framework.
+//!
+//! Memory cells are addressed by their memory bank and an abstract address. Addresses that are a
+//! constant offset from the stack pointer value at function entry are stack slots, addresses
+//! the abstract domain narrows down to a few concrete values are globals. All other addresses are
+//! unknown.
+//!
+//! An `AbstractStore` maps memory cells to the abstract value last written to them. Writing to a
+//! single known address replaces the value of the cell (strong update). Writing to one of several
+//! known addresses combines the new value with the old values (weak update). Writing to an unknown
+//! address could change any cell of the memory bank and forgets all of them. Calls forget all
+//! globals. Cells are assumed to be 8 bits wide.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use {
+    Function,
+    ControlFlowTarget,
+    Statement,
+    Operation,
+    Rvalue,
+    Lvalue,
+};
+use abstractinterp::Avalue;
+use graph_algos::{
+    GraphTrait,
+    VertexListGraphTrait,
+};
+
+/// Abstract address of a memory cell.
+#[derive(Debug,PartialEq,Eq,Clone,Hash,PartialOrd,Ord,RustcEncodable,RustcDecodable)]
+pub enum MemoryLocation {
+    /// Offset relative to the value of the stack pointer at function entry.
+    Stack(i64),
+    /// Absolute address.
+    Global(u64),
+}
+
+impl MemoryLocation {
+    /// True if the `size` bit values at `self` and at `other` share at least one cell.
+    fn overlaps(&self, size: usize, other: &MemoryLocation, other_size: usize) -> bool {
+        let (a,b) = match (self,other) {
+            (&MemoryLocation::Stack(a),&MemoryLocation::Stack(b)) => (a,b),
+            (&MemoryLocation::Global(a),&MemoryLocation::Global(b)) => (a as i64,b as i64),
+            _ => return false,
+        };
+
+        a < b.wrapping_add(cells(other_size)) && b < a.wrapping_add(cells(size))
+    }
+}
+
+/// Number of cells a value of `size` bits occupies.
+fn cells(size: usize) -> i64 {
+    ((size + 7) / 8) as i64
+}
+
+/// Abstract memory state. Cells not in the store have an unknown value.
+#[derive(Debug,PartialEq,Eq,Clone)]
+pub struct AbstractStore<A: Avalue> {
+    cells: HashMap<(Cow<'static,str>,MemoryLocation),(A,usize)>,
+}
+
+impl<A: Avalue> AbstractStore<A> {
+    /// Memory state with all cells unknown.
+    pub fn new() -> AbstractStore<A> {
+        AbstractStore{ cells: HashMap::new() }
+    }
+
+    /// Value and size of the value stored at `loc` in memory bank `bank`.
+    pub fn get(&self, bank: &str, loc: &MemoryLocation) -> Option<&(A,usize)> {
+        self.cells.get(&(Cow::Owned(bank.to_string()),loc.clone()))
+    }
+
+    /// All known cells.
+    pub fn iter(&self) -> ::std::collections::hash_map::Iter<(Cow<'static,str>,MemoryLocation),(A,usize)> {
+        self.cells.iter()
+    }
+
+    /// Reads a `size` bit value from one of `locs` in memory bank `bank`. Returns None if the
+    /// value isn't known.
+    pub fn load(&self, bank: &Cow<'static,str>, locs: &Option<Vec<MemoryLocation>>, size: usize) -> Option<A> {
+        let locs = match locs {
+            &Some(ref l) => l,
+            &None => return None,
+        };
+        let mut ret = A::initial();
+
+        for loc in locs.iter() {
+            match self.cells.get(&(bank.clone(),loc.clone())) {
+                Some(&(ref v,sz)) if sz == size => ret = ret.combine(v),
+                Some(&(ref v,sz)) if sz > size => ret = ret.combine(&v.extract(size,0)),
+                _ => return None,
+            }
+        }
+
+        Some(ret)
+    }
+
+    /// Writes the `size` bit value `val` to one of `locs` in memory bank `bank`. `None` means the
+    /// address is unknown.
+    pub fn store(&mut self, bank: &Cow<'static,str>, locs: &Option<Vec<MemoryLocation>>, val: A, size: usize) {
+        match locs {
+            &None => {
+                self.forget(|b,_,_| b == bank);
+            },
+            &Some(ref locs) if locs.len() == 1 => {
+                let loc = &locs[0];
+
+                self.forget(|b,l,sz| b == bank && l.overlaps(sz,loc,size));
+                self.cells.insert((bank.clone(),loc.clone()),(val,size));
+            },
+            &Some(ref locs) => {
+                for loc in locs.iter() {
+                    let key = (bank.clone(),loc.clone());
+                    let old = match self.cells.get(&key) {
+                        Some(&(ref v,sz)) if sz == size => Some(v.combine(&val)),
+                        _ => None,
+                    };
+
+                    self.forget(|b,l,sz| b == bank && l.overlaps(sz,loc,size));
+
+                    if let Some(v) = old {
+                        self.cells.insert(key,(v,size));
+                    }
+                }
+            },
+        }
+    }
+
+    /// Forgets all global cells. Called functions may change global memory but are assumed not to
+    /// write into the stack frame of the caller.
+    pub fn call(&mut self) {
+        self.forget(|_,l,_| match l {
+            &MemoryLocation::Global(_) => true,
+            &MemoryLocation::Stack(_) => false,
+        });
+    }
+
+    /// Least upper bound of `self` and `other`.
+    pub fn combine(&self, other: &AbstractStore<A>) -> AbstractStore<A> {
+        self.zip(other,|a,b| a.combine(b))
+    }
+
+    /// Widens `self` with `other`.
+    pub fn widen(&self, other: &AbstractStore<A>) -> AbstractStore<A> {
+        self.zip(other,|a,b| a.widen(b))
+    }
+
+    /// Removes all cells `f` returns true for.
+    fn forget<F: Fn(&Cow<'static,str>,&MemoryLocation,usize) -> bool>(&mut self, f: F) {
+        let keys = self.cells.iter().filter(|&(&(ref b,ref l),&(_,sz))| f(b,l,sz)).map(|(k,_)| k.clone()).collect::<Vec<_>>();
+
+        for k in keys {
+            self.cells.remove(&k);
+        }
+    }
+
+    fn zip<F: Fn(&A,&A) -> A>(&self, other: &AbstractStore<A>, f: F) -> AbstractStore<A> {
+        let mut cells = HashMap::new();
+
+        for (k,&(ref a,sz)) in self.cells.iter() {
+            if let Some(&(ref b,other_sz)) = other.cells.get(k) {
+                if sz == other_sz {
+                    cells.insert(k.clone(),(f(a,b),sz));
+                }
+            }
+        }
+
+        AbstractStore{ cells: cells }
+    }
+}
+
+/// Interprets the `size` bit constant `value` as signed.
+fn signed(value: u64, size: usize) -> i64 {
+    if size > 0 && size < 64 && value & (1 << (size - 1)) != 0 {
+        (value | !((1 << size) - 1)) as i64
+    } else {
+        value as i64
+    }
+}
+
+/// Computes the offset of all SSA variables in `func` that point into its stack frame. Offsets
+/// are relative to the value of `stack_pointer` at function entry. Only copies of the stack
+/// pointer and the results of adding or subtracting constants are tracked. `func` must be in SSA
+/// form.
+pub fn stack_offsets(func: &Function, stack_pointer: &Lvalue) -> HashMap<(Cow<'static,str>,usize),i64> {
+    let sp = match stack_pointer {
+        &Lvalue::Variable{ ref name,.. } => name.clone(),
+        _ => return HashMap::new(),
+    };
+    // None if the variable has different offsets on different paths
+    let mut offsets = HashMap::<(Cow<'static,str>,usize),Option<i64>>::new();
+    let mut stmts = vec![];
+
+    for vx in func.cflow_graph.vertices() {
+        if let Some(&ControlFlowTarget::Resolved(ref bb)) = func.cflow_graph.vertex_label(vx) {
+            for mne in bb.mnemonics.iter() {
+                for stmt in mne.instructions.iter() {
+                    stmts.push((mne.opcode == "__init",stmt));
+                }
+            }
+        }
+    }
+
+    fn offset(v: &Rvalue, sp: &Cow<'static,str>, offsets: &HashMap<(Cow<'static,str>,usize),Option<i64>>) -> Option<Option<i64>> {
+        match v {
+            &Rvalue::Variable{ ref name, subscript: Some(s), offset: 0,.. } => offsets.get(&(name.clone(),s)).cloned(),
+            &Rvalue::Variable{ ref name, subscript: None, offset: 0,.. } if name == sp => Some(Some(0)),
+            _ => None,
+        }
+    }
+
+    loop {
+        let mut change = false;
+
+        for &(init,stmt) in stmts.iter() {
+            let key = match stmt {
+                &Statement{ op: Operation::Store(..),.. } => continue,
+                &Statement{ assignee: Lvalue::Variable{ ref name, subscript: Some(s),.. },.. } => (name.clone(),s),
+                _ => continue,
+            };
+            let new = match &stmt.op {
+                _ if init => if key.0 == sp { Some(Some(0)) } else { None },
+                &Operation::Move(ref a) => offset(a,&sp,&offsets),
+                &Operation::Add(ref a,Rvalue::Constant{ value, size }) | &Operation::Add(Rvalue::Constant{ value, size },ref a) =>
+                    offset(a,&sp,&offsets).map(|o| o.map(|o| o.wrapping_add(signed(value,size)))),
+                &Operation::Subtract(ref a,Rvalue::Constant{ value, size }) =>
+                    offset(a,&sp,&offsets).map(|o| o.map(|o| o.wrapping_sub(signed(value,size)))),
+                &Operation::Phi(ref ops) => {
+                    // operands without an offset yet are ignored
+                    let mut ret = None;
+
+                    for o in ops.iter().filter_map(|o| offset(o,&sp,&offsets)) {
+                        ret = match (ret,o) {
+                            (None,o) => Some(o),
+                            (Some(Some(a)),Some(b)) if a == b => Some(Some(a)),
+                            _ => Some(None),
+                        };
+                    }
+
+                    ret
+                },
+                _ => None,
+            };
+            let new = match (offsets.get(&key).cloned(),new) {
+                (_,None) => continue,
+                (None,n) => n,
+                (Some(Some(a)),Some(Some(b))) if a == b => continue,
+                (Some(None),_) => continue,
+                (Some(_),_) => Some(None),
+            };
+
+            offsets.insert(key,new.unwrap());
+            change = true;
+        }
+
+        if !change {
+            break;
+        }
+    }
+
+    offsets.into_iter().filter_map(|(k,v)| v.map(|v| (k,v))).collect()
+}
+
+/// Abstract addresses `addr` could point to. `val` is the abstract value of `addr` and `offsets`
+/// the result of `stack_offsets`. Returns None if the address is unknown.
+pub fn locations<A: Avalue>(addr: &Rvalue, val: &A, offsets: &HashMap<(Cow<'static,str>,usize),i64>) -> Option<Vec<MemoryLocation>> {
+    match addr {
+        &Rvalue::Constant{ value,.. } => Some(vec![MemoryLocation::Global(value)]),
+        &Rvalue::Variable{ ref name, subscript: Some(s), offset: 0,.. } if offsets.contains_key(&(name.clone(),s)) =>
+            Some(vec![MemoryLocation::Stack(offsets[&(name.clone(),s)])]),
+        _ => val.concretize().map(|v| v.into_iter().map(MemoryLocation::Global).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use graph_algos::MutableGraphTrait;
+    use {
+        Function,
+        ControlFlowTarget,
+        BasicBlock,
+        Mnemonic,
+        Statement,
+        Operation,
+        Rvalue,
+        Kset,
+        ssa_convertion,
+    };
+    use test_util::var;
+
+    fn ram() -> Cow<'static,str> {
+        Cow::Borrowed("ram")
+    }
+
+    fn set(v: u64) -> Kset {
+        Kset::Set(vec![(v,32)])
+    }
+
+    #[test]
+    fn strong_update() {
+        let mut st = AbstractStore::<Kset>::new();
+        let loc = Some(vec![MemoryLocation::Stack(-8)]);
+
+        st.store(&ram(),&loc,set(1),32);
+        assert_eq!(st.load(&ram(),&loc,32),Some(set(1)));
+
+        st.store(&ram(),&loc,set(2),32);
+        assert_eq!(st.load(&ram(),&loc,32),Some(set(2)));
+        assert_eq!(st.load(&ram(),&Some(vec![MemoryLocation::Stack(-6)]),16),None);
+        assert_eq!(st.load(&ram(),&Some(vec![MemoryLocation::Global(-8i64 as u64)]),32),None);
+        assert_eq!(st.load(&Cow::Borrowed("io"),&loc,32),None);
+
+        // partially overwritten
+        st.store(&ram(),&Some(vec![MemoryLocation::Stack(-6)]),Kset::Set(vec![(3,16)]),16);
+        assert_eq!(st.load(&ram(),&loc,32),None);
+    }
+
+    #[test]
+    fn weak_update() {
+        let mut st = AbstractStore::<Kset>::new();
+        let a = Some(vec![MemoryLocation::Global(0x100)]);
+        let b = Some(vec![MemoryLocation::Global(0x104)]);
+        let ab = Some(vec![MemoryLocation::Global(0x100),MemoryLocation::Global(0x104)]);
+
+        st.store(&ram(),&a,set(1),32);
+        st.store(&ram(),&ab,set(2),32);
+        assert_eq!(st.load(&ram(),&a,32),Some(Kset::Set(vec![(1,32),(2,32)])));
+        assert_eq!(st.load(&ram(),&b,32),None);
+
+        st.store(&ram(),&None,set(3),32);
+        assert_eq!(st.load(&ram(),&a,32),None);
+
+        let sp = Some(vec![MemoryLocation::Stack(-4)]);
+
+        st.store(&ram(),&a,set(1),32);
+        st.store(&ram(),&sp,set(1),32);
+        st.call();
+        assert_eq!(st.load(&ram(),&a,32),None);
+        assert_eq!(st.load(&ram(),&sp,32),Some(set(1)));
+    }
+
+    #[test]
+    fn lattice() {
+        let mut a = AbstractStore::<Kset>::new();
+        let mut b = AbstractStore::<Kset>::new();
+        let x = Some(vec![MemoryLocation::Stack(0)]);
+        let y = Some(vec![MemoryLocation::Stack(8)]);
+
+        a.store(&ram(),&x,set(1),32);
+        a.store(&ram(),&y,set(1),32);
+        b.store(&ram(),&x,set(2),32);
+
+        let c = a.combine(&b);
+
+        assert_eq!(c.load(&ram(),&x,32),Some(Kset::Set(vec![(1,32),(2,32)])));
+        assert_eq!(c.load(&ram(),&y,32),None);
+        assert_eq!(a.widen(&a),a);
+    }
+
+    /*
+     * bb0:
+     *   SP = SP - 8
+     *   FP = SP
+     *   f = ?
+     * bb1 (if f):
+     *   SP = SP - 4
+     * bb2:
+     *   X = FP + 4
+     */
+    #[test]
+    fn offsets() {
+        let mne0 = Mnemonic::new(0..1,"test".to_string(),"".to_string(),vec![].iter(),vec![
+            Statement{ op: Operation::Subtract(var("SP",32).into(),Rvalue::new_u32(8)), assignee: var("SP",32) },
+            Statement{ op: Operation::Move(var("SP",32).into()), assignee: var("FP",32) },
+            Statement{ op: Operation::Move(Rvalue::Undefined), assignee: var("f",1) },
+        ].iter()).unwrap();
+        let mne1 = Mnemonic::new(1..2,"test".to_string(),"".to_string(),vec![].iter(),vec![
+            Statement{ op: Operation::Subtract(var("SP",32).into(),Rvalue::new_u32(4)), assignee: var("SP",32) },
+        ].iter()).unwrap();
+        let mne2 = Mnemonic::new(2..3,"test".to_string(),"".to_string(),vec![].iter(),vec![
+            Statement{ op: Operation::Add(var("FP",32).into(),Rvalue::new_u32(4)), assignee: var("X",32) },
+            Statement{ op: Operation::Move(var("SP",32).into()), assignee: var("Y",32) },
+        ].iter()).unwrap();
+        let mut func = Function::new("test".to_string(),"ram".to_string());
+        let vx0 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne0])));
+        let vx1 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne1])));
+        let vx2 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(BasicBlock::from_vec(vec![mne2])));
+        let g = ::Guard::from_flag(&var("f",1).into()).unwrap();
+
+        func.cflow_graph.add_edge(g.clone(),vx0,vx1);
+        func.cflow_graph.add_edge(g.negation(),vx0,vx2);
+        func.cflow_graph.add_edge(::Guard::always(),vx1,vx2);
+        func.entry_point = Some(vx0);
+
+        ssa_convertion(&mut func);
+
+        let offs = stack_offsets(&func,&var("SP",32));
+        let get = |n: &str| offs.iter().filter(|&(k,_)| k.0 == n).map(|(_,v)| *v).collect::<Vec<_>>();
+
+        assert_eq!(get("FP"),vec![-8]);
+        assert_eq!(get("X"),vec![-4]);
+        // SP differs between the two paths to bb2
+        assert!(get("Y").is_empty());
+    }
+}
@@ -56,6 +56,12 @@ impl Bound {
     pub fn len(&self) -> u64 {
         self.end - self.start
     }
+
+    /// True if both ranges have at least one address in common. Empty ranges overlap nothing.
+    pub fn overlaps(&self, other: &Bound) -> bool {
+        self.start < self.end && other.start < other.end &&
+        self.start < other.end && other.start < self.end
+    }
 }
 
 /// Internal to `Mnemonic`
@@ -188,6 +194,15 @@ mod tests {
         Statement,
     };
 
+    #[test]
+    fn bound_overlaps() {
+        assert!(Bound::new(0,10).overlaps(&Bound::new(9,20)));
+        assert!(Bound::new(5,6).overlaps(&Bound::new(0,10)));
+        assert!(!Bound::new(0,10).overlaps(&Bound::new(10,20)));
+        assert!(!Bound::new(10,20).overlaps(&Bound::new(0,10)));
+        assert!(!Bound::new(5,5).overlaps(&Bound::new(0,10)));
+    }
+
     #[test]
     fn parse_format_string() {
         let fmt = "doe{u}io{s}øiq{s}   {p:te33} sasq {c:test}".to_string();
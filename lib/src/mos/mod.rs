@@ -50,7 +50,7 @@ impl Architecture for Mos {
     type Configuration = Variant;
 
     fn prepare(reg: &Region,_: &Self::Configuration) -> Result<Vec<(&'static str,u64,&'static str)>> {
-        let mut i = reg.iter();
+        let i = reg.iter();
         let iv = vec![
             ("NMI",0xfffa, "NMI vector"),
             ("RESET",0xfffc, "Reset routine"),
@@ -59,9 +59,7 @@ impl Architecture for Mos {
         let mut ret = vec![];
 
         for v in iv {
-            let mut j = i.clone();
-
-            j.seek(v.1);
+            let mut j = i.seek(v.1);
             let maybe_lo = j.next();
             let maybe_hi = j.next();
             if let (Some(Some(hi)),Some(Some(lo))) = (maybe_hi,maybe_lo) {
@@ -82,7 +80,7 @@ impl Architecture for Mos {
             info!("    res: {:?}",st);
             Ok(st.into())
         } else {
-            Err("Unrecognized instruction".into())
+            Err(::result::Error::decode(addr,"Unrecognized instruction"))
         }
     }
 }
@@ -631,4 +629,19 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn prepare_vectors() {
+        let mut buf = vec![0u8; 0x10000];
+
+        buf[0xfffa] = 0x00; buf[0xfffb] = 0x90;
+        buf[0xfffc] = 0x00; buf[0xfffd] = 0x80;
+        buf[0xfffe] = 0x34; buf[0xffff] = 0x12;
+
+        let reg = Region::wrap("ram".to_string(),buf);
+        let iv = Mos::prepare(&reg,&Variant::mos6502()).unwrap();
+
+        assert_eq!(iv.iter().map(|x| (x.0,x.1)).collect::<Vec<_>>(),
+                   vec![("NMI",0x9000),("RESET",0x8000),("IRQ/BRK",0x1234)]);
+    }
 }
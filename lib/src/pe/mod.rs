@@ -19,279 +19,525 @@
 //! Loader for PE32 and PE32+ files.
 
 use std::path::Path;
-use std::mem;
 use std::fs::File;
 use std::io::{Read,Seek,SeekFrom};
+use std::collections::HashSet;
+
+use byteorder::{ReadBytesExt,LittleEndian};
+use graph_algos::MutableGraphTrait;
+use uuid::Uuid;
 
 use project::Project;
+use program::{Program,CallTarget};
 use region::Region;
 use mnemonic::Bound;
-use layer::Layer;
+use layer::{Layer,OpaqueLayer};
+use il::Rvalue;
+use result::{Result,Error};
 
-#[repr(C,packed)]
+/// Fields of the MZ header needed to find the PE header.
 struct Mz {
     signature: u16,
-    extra_bytes: u16,
-    pages: u16,
-    reloc_items: u16,
-    header_size: u16,
-    min_alloc: u16,
-    max_alloc: u16,
-    initial_ss: u16,
-    initial_sp: u16,
-    checksum: u16,
-    initial_ip: u16,
-    initial_cs: u16,
-    reloc_table: u16,
-    overlay: u16,
-    reserved1: [u16; 4],
-    oem_id: u16,
-    oem_info: u16,
-    reserved2: [u16; 10],
     e_lfanew: u32,
 }
 
 const MZ_MAGIC: u16 = 0x5a4d;
-/*
-#[repr(C,packed)]
-struct MzReloc {
-    offset: u16,
-    segment: u16,
-}*/
+const MZ_SIZE: u64 = 64;
+
+impl Mz {
+    fn read<R: Read>(strm: &mut R) -> Result<Mz> {
+        let signature = try!(strm.read_u16::<LittleEndian>());
+        let mut skip = [0; 58];
+
+        try!(strm.read_exact(&mut skip));
+
+        let e_lfanew = try!(strm.read_u32::<LittleEndian>());
 
-#[repr(C,packed)]
+        Ok(Mz{ signature: signature, e_lfanew: e_lfanew })
+    }
+}
+
+/// Fields of the PE (COFF) header.
 struct Pe {
     magic: u32,
     machine: u16,
     num_section: u16,
-    timestamp: u32,
-    symbol_table: u32,
-    num_symbols: u32,
     opthdr_size: u16,
     characteristics: u16,
 }
 
-#[repr(C,packed)]
-struct PeOptional32 {
-    magic: u16,
-    major: u8,
-    minor: u8,
-    text_size: u32,
-    data_size: u32,
-    bss_size: u32,
-    entry_point: u32,
-    text_base: u32,
-    data_base: u32, // w
-    image_base: u32, // w
-    section_align: u32,
-    file_align: u32,
-    os_major: u16,
-    os_minor: u16,
-    imgae_major: u16,
-    image_minor: u16,
-    subsys_major: u16,
-    subsys_minor: u16,
-    win32_ver: u32,
-    image_size: u32,
-    header_size: u32,
-    checksum: u32,
-    subsys: u16,
-    dll_flags: u16,
-    stack_reserve: u32, // w
-    stack_commit: u32, // w
-    heap_reserve: u32, // w
-    heap_commit: u32, // w
-    loader_flags: u32,
-    datadir_entries: u32,
+const PE_MAGIC: u32 = 0x00004550;
+const PE_SIZE: u64 = 24;
+
+impl Pe {
+    fn read<R: Read>(strm: &mut R) -> Result<Pe> {
+        let magic = try!(strm.read_u32::<LittleEndian>());
+        let machine = try!(strm.read_u16::<LittleEndian>());
+        let num_section = try!(strm.read_u16::<LittleEndian>());
+        let _timestamp = try!(strm.read_u32::<LittleEndian>());
+        let _symbol_table = try!(strm.read_u32::<LittleEndian>());
+        let _num_symbols = try!(strm.read_u32::<LittleEndian>());
+        let opthdr_size = try!(strm.read_u16::<LittleEndian>());
+        let characteristics = try!(strm.read_u16::<LittleEndian>());
+
+        Ok(Pe{
+            magic: magic,
+            machine: machine,
+            num_section: num_section,
+            opthdr_size: opthdr_size,
+            characteristics: characteristics,
+        })
+    }
 }
 
-#[repr(C,packed)]
-struct PeOptional64 {
-    magic: u16,
-    major: u8,
-    minor: u8,
-    text_size: u32,
-    data_size: u32,
-    bss_size: u32,
+/// Fields of the PE32 and PE32+ optional header.
+struct PeOptional {
     entry_point: u32,
-    text_base: u32,
-    image_base: u64, // w
-    section_align: u32,
-    file_align: u32,
-    os_major: u16,
-    os_minor: u16,
-    image_major: u16,
-    image_minor: u16,
-    subsys_major: u16,
-    subsys_minor: u16,
-    win32_ver: u32,
-    image_size: u32,
-    header_size: u32,
-    checksum: u32,
-    subsys: u16,
-    dll_flags: u16,
-    stack_reserve: u64, // w
-    stack_commit: u64, // w
-    heap_reserve: u64, // w
-    heap_commit: u64, // w
-    loader_flags: u32,
+    image_base: u64,
     datadir_entries: u32,
+    is_pe64: bool,
 }
-/*
-#[repr(C,packed)]
+
+const PE32_MAGIC: u16 = 0x10b;
+const PE64_MAGIC: u16 = 0x20b;
+
+/// Maximal number of data directory entries. All entries after the 16th are reserved.
+const MAX_DATADIR_ENTRIES: u32 = 16;
+
+impl PeOptional {
+    fn read<R: Read>(strm: &mut R) -> Result<PeOptional> {
+        let magic = try!(strm.read_u16::<LittleEndian>());
+        let is_pe64 = match magic {
+            PE32_MAGIC => false,
+            PE64_MAGIC => true,
+            _ => return Err(Error::format("Unknown optional header format")),
+        };
+        let _major = try!(strm.read_u8());
+        let _minor = try!(strm.read_u8());
+        let _text_size = try!(strm.read_u32::<LittleEndian>());
+        let _data_size = try!(strm.read_u32::<LittleEndian>());
+        let _bss_size = try!(strm.read_u32::<LittleEndian>());
+        let entry_point = try!(strm.read_u32::<LittleEndian>());
+        let _text_base = try!(strm.read_u32::<LittleEndian>());
+        let image_base = if is_pe64 {
+            try!(strm.read_u64::<LittleEndian>())
+        } else {
+            let _data_base = try!(strm.read_u32::<LittleEndian>());
+            try!(strm.read_u32::<LittleEndian>()) as u64
+        };
+        // alignment, versions, sizes, checksum, subsystem and dll flags
+        let mut skip = [0; 40];
+
+        try!(strm.read_exact(&mut skip));
+
+        // stack and heap reserve and commit
+        if is_pe64 {
+            let mut skip = [0; 32];
+            try!(strm.read_exact(&mut skip));
+        } else {
+            let mut skip = [0; 16];
+            try!(strm.read_exact(&mut skip));
+        }
+
+        let _loader_flags = try!(strm.read_u32::<LittleEndian>());
+        let datadir_entries = try!(strm.read_u32::<LittleEndian>());
+
+        Ok(PeOptional{
+            entry_point: entry_point,
+            image_base: image_base,
+            datadir_entries: datadir_entries,
+            is_pe64: is_pe64,
+        })
+    }
+}
+
+#[derive(Clone,Copy,Debug)]
 struct PeDataDirectory {
-    rva: i32,
+    rva: u32,
     size: u32,
-}*/
+}
+
+const EXPORT_DIRECTORY: usize = 0;
+const IMPORT_DIRECTORY: usize = 1;
 
-#[repr(C,packed)]
+/// Fields of a section header.
 struct PeSection {
     name: [u8; 8],
     virt_sz_or_phy_addr: u32,
     virt_address: u32,
     raw_sz: u32,
     raw_ptr: u32,
-    reloc_ptr: u32,
-    linenr_ptr: u32,
-    reloc_count: u16,
-    linenr_cout: u16,
-    flags: u32,
 }
 
-/// Loads a PE file from disk and create a project from it.
-pub fn pe(p: &Path) -> Option<Project> {
-    let name = p.file_name().and_then(|x| x.to_str()).or(p.to_str()).unwrap_or("unknown pe");
+const PESEC_SIZE: u64 = 40;
+
+impl PeSection {
+    fn read<R: Read>(strm: &mut R) -> Result<PeSection> {
+        let mut name = [0; 8];
+
+        try!(strm.read_exact(&mut name));
 
-    if let Some(mut fd) = File::open(p).ok() {
-        // read MZ header
-        const MZ_SIZE: usize = 64;
-        assert_eq!(MZ_SIZE, mem::size_of::<Mz>());
-        let mut mz_raw = [0; MZ_SIZE];
+        let virt_sz_or_phy_addr = try!(strm.read_u32::<LittleEndian>());
+        let virt_address = try!(strm.read_u32::<LittleEndian>());
+        let raw_sz = try!(strm.read_u32::<LittleEndian>());
+        let raw_ptr = try!(strm.read_u32::<LittleEndian>());
 
-        if Some(MZ_SIZE) != fd.read(&mut mz_raw).ok() {
-            return None;
+        Ok(PeSection{
+            name: name,
+            virt_sz_or_phy_addr: virt_sz_or_phy_addr,
+            virt_address: virt_address,
+            raw_sz: raw_sz,
+            raw_ptr: raw_ptr,
+        })
+    }
+}
+
+/// Section as mapped into memory
+struct Mapping {
+    virt_address: u32,
+    virt_size: u32,
+    raw_ptr: u32,
+    raw_sz: u32,
+}
+
+/// Translates the relative virtual address `rva` into a file offset.
+fn rva_to_offset(secs: &[Mapping], rva: u32) -> Result<u64> {
+    for sec in secs.iter() {
+        let len = if sec.raw_sz > sec.virt_size && sec.virt_size > 0 { sec.virt_size } else { sec.raw_sz };
+
+        if rva >= sec.virt_address && (rva as u64) < sec.virt_address as u64 + len as u64 {
+            return Ok(sec.raw_ptr as u64 + (rva - sec.virt_address) as u64);
         }
+    }
+
+    Err(Error::format(format!("RVA {:#x} is not backed by the file",rva)))
+}
 
-        let mz: Mz = unsafe { mem::transmute(mz_raw) };
+/// Reads the zero terminated string at `rva`.
+fn read_string(fd: &mut File, secs: &[Mapping], rva: u32) -> Result<String> {
+    let off = try!(rva_to_offset(secs,rva));
+    let mut ret = Vec::new();
 
-        // check MZ signature
-        if mz.signature != MZ_MAGIC {
-            return None;
+    try!(fd.seek(SeekFrom::Start(off)));
+
+    loop {
+        let b = try!(fd.read_u8());
+
+        if b == 0 || ret.len() >= 0x1000 {
+            break;
         }
 
-        // read PE header
-        const PE_SIZE: usize = 24;
-        assert_eq!(PE_SIZE, mem::size_of::<Pe>());
-        let mut pe_raw = [0; PE_SIZE];
+        ret.push(b);
+    }
+
+    Ok(String::from_utf8_lossy(&ret).to_string())
+}
+
+/// Reads the export directory. Returns address and name of all exported functions.
+fn exports(fd: &mut File, secs: &[Mapping], dir: PeDataDirectory, file_len: u64) -> Result<Vec<(u32,String)>> {
+    try!(fd.seek(SeekFrom::Start(try!(rva_to_offset(secs,dir.rva)))));
+
+    let _characteristics = try!(fd.read_u32::<LittleEndian>());
+    let _timestamp = try!(fd.read_u32::<LittleEndian>());
+    let _major = try!(fd.read_u16::<LittleEndian>());
+    let _minor = try!(fd.read_u16::<LittleEndian>());
+    let _name = try!(fd.read_u32::<LittleEndian>());
+    let ordinal_base = try!(fd.read_u32::<LittleEndian>());
+    let num_functions = try!(fd.read_u32::<LittleEndian>());
+    let num_names = try!(fd.read_u32::<LittleEndian>());
+    let functions_rva = try!(fd.read_u32::<LittleEndian>());
+    let names_rva = try!(fd.read_u32::<LittleEndian>());
+    let ordinals_rva = try!(fd.read_u32::<LittleEndian>());
+
+    // each function needs at least 4 bytes in the file
+    if num_functions as u64 * 4 > file_len || num_names as u64 * 4 > file_len {
+        return Err(Error::format(format!("Export directory with {} functions and {} names is larger than the file",num_functions,num_names)));
+    }
+
+    let mut functions = Vec::new();
+    let mut names = Vec::new();
+    let mut ordinals = Vec::new();
 
-        if Some(mz.e_lfanew as u64) != fd.seek(SeekFrom::Start(mz.e_lfanew as u64)).ok() {
-            return None;
+    try!(fd.seek(SeekFrom::Start(try!(rva_to_offset(secs,functions_rva)))));
+    for _ in 0..num_functions {
+        functions.push(try!(fd.read_u32::<LittleEndian>()));
+    }
+
+    if num_names > 0 {
+        try!(fd.seek(SeekFrom::Start(try!(rva_to_offset(secs,names_rva)))));
+        for _ in 0..num_names {
+            names.push(try!(fd.read_u32::<LittleEndian>()));
         }
 
-        if Some(PE_SIZE) != fd.read(&mut pe_raw).ok() {
-            return None;
+        try!(fd.seek(SeekFrom::Start(try!(rva_to_offset(secs,ordinals_rva)))));
+        for _ in 0..num_names {
+            ordinals.push(try!(fd.read_u16::<LittleEndian>()));
         }
+    }
 
-        let pe: Pe = unsafe { mem::transmute(pe_raw) };
+    let mut ret = Vec::new();
 
-        if pe.magic != 0x00004550 {
-            return None;
+    for (idx,&rva) in functions.iter().enumerate() {
+        // unused slot or forwarder string pointing into the export directory
+        if rva == 0 || (rva >= dir.rva && rva < dir.rva.saturating_add(dir.size)) {
+            continue;
         }
 
-        match pe.machine {
-            0x8664 => println!("AMD64"),
-            0x014c => println!("IA32"),
-            _ => return None
+        let name = match ordinals.iter().position(|&o| o as usize == idx) {
+            Some(i) => try!(read_string(fd,secs,names[i])),
+            None => format!("#{}",ordinal_base as u64 + idx as u64),
+        };
+
+        ret.push((rva,name));
+    }
+
+    Ok(ret)
+}
+
+/// Reads the import directory. Returns the address of the IAT slot and the name of each imported
+/// function as `DLL!Function`.
+fn imports(fd: &mut File, secs: &[Mapping], dir: PeDataDirectory, is_pe64: bool) -> Result<Vec<(u32,String)>> {
+    let mut descriptors = Vec::new();
+    let mut off = try!(rva_to_offset(secs,dir.rva));
+
+    loop {
+        try!(fd.seek(SeekFrom::Start(off)));
+
+        let lookup_rva = try!(fd.read_u32::<LittleEndian>());
+        let _timestamp = try!(fd.read_u32::<LittleEndian>());
+        let _forwarder = try!(fd.read_u32::<LittleEndian>());
+        let name_rva = try!(fd.read_u32::<LittleEndian>());
+        let iat_rva = try!(fd.read_u32::<LittleEndian>());
+
+        if lookup_rva == 0 && name_rva == 0 && iat_rva == 0 {
+            break;
         }
 
-        if pe.characteristics & 2 == 0 {
-            return None;
+        descriptors.push((if lookup_rva != 0 { lookup_rva } else { iat_rva },name_rva,iat_rva));
+        off += 20;
+    }
+
+    let thunk_sz = if is_pe64 { 8 } else { 4 };
+    let ordinal_flag = if is_pe64 { 1u64 << 63 } else { 1u64 << 31 };
+    let mut ret = Vec::new();
+
+    for (lookup_rva,name_rva,iat_rva) in descriptors {
+        let dll = try!(read_string(fd,secs,name_rva));
+        let mut thunks = Vec::new();
+
+        try!(fd.seek(SeekFrom::Start(try!(rva_to_offset(secs,lookup_rva)))));
+        loop {
+            let thunk = if is_pe64 {
+                try!(fd.read_u64::<LittleEndian>())
+            } else {
+                try!(fd.read_u32::<LittleEndian>()) as u64
+            };
+
+            if thunk == 0 {
+                break;
+            }
+
+            thunks.push(thunk);
         }
 
-        // read optional PE header
-        let mut opt_magic = [0; 2];
+        for (idx,thunk) in thunks.into_iter().enumerate() {
+            let func = if thunk & ordinal_flag != 0 {
+                format!("#{}",thunk & 0xffff)
+            } else {
+                // skip the u16 hint
+                try!(read_string(fd,secs,(thunk as u32).wrapping_add(2)))
+            };
+            let slot = iat_rva.wrapping_add((idx * thunk_sz) as u32);
 
-        if Some(2) != fd.read(&mut opt_magic).ok() {
-            return None;
+            ret.push((slot,format!("{}!{}",dll,func)));
         }
+    }
+
+    Ok(ret)
+}
+
+/// CPU the PE file is intended for.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Machine {
+    /// Intel x86
+    Ia32,
+    /// AMD64
+    Amd64,
+}
 
-        const PE32_SIZE: usize = 96;
-        const PE64_SIZE: usize = 112;
+/// Loads a PE file from disk and create a project from it.
+pub fn pe(p: &Path) -> Result<Project> {
+    load(p).map(|(proj,_)| proj)
+}
 
-        assert_eq!(PE32_SIZE, mem::size_of::<PeOptional32>());
-        assert_eq!(PE64_SIZE, mem::size_of::<PeOptional64>());
+/// Loads a PE file from disk and create a project from it. Returns the `Project` instance and
+/// the CPU its intended for.
+///
+/// The entry point and all exported functions are added to the call graph as
+/// `CallTarget::Todo`, imported functions as `CallTarget::Symbolic` named `DLL!Function`. The
+/// address of the import address table slot of each function is recorded in `Program::imports`.
+pub fn load(p: &Path) -> Result<(Project,Machine)> {
+    let name = p.file_name().and_then(|x| x.to_str()).or(p.to_str()).unwrap_or("unknown pe");
+    let mut fd = try!(File::open(p));
 
-        let (img_base,_) = if opt_magic == [0xb,0x1] {
-            let mut peopt_raw = [0; PE32_SIZE];
+    let file_len = try!(fd.metadata()).len();
 
-            if Some(PE32_SIZE) != fd.read(&mut peopt_raw).ok() {
-                return None;
-            }
+    // read MZ header
+    let mz = try!(Mz::read(&mut fd));
 
-            let peopt: PeOptional32 = unsafe { mem::transmute(peopt_raw) };
-            (peopt.image_base as u64,peopt.datadir_entries)
-        } else if opt_magic == [0xb,0x2] {
-            let mut peopt_raw = [0; PE64_SIZE];
+    // check MZ signature
+    if mz.signature != MZ_MAGIC {
+        return Err(Error::format_at(0,"Invalid MZ signature"));
+    }
 
-            if Some(PE64_SIZE) != fd.read(&mut peopt_raw).ok() {
-                return None;
-            }
+    if mz.e_lfanew as u64 + PE_SIZE > file_len {
+        return Err(Error::format_at(MZ_SIZE - 4,format!("PE header at {:#x} is outside of the file",mz.e_lfanew)));
+    }
 
-            let peopt: PeOptional64 = unsafe { mem::transmute(peopt_raw) };
-            (peopt.image_base,peopt.datadir_entries)
-        } else {
-            return None
-        };
+    // read PE header
+    try!(fd.seek(SeekFrom::Start(mz.e_lfanew as u64)));
+
+    let pe = try!(Pe::read(&mut fd));
 
-        // XXX: data directory
+    if pe.magic != PE_MAGIC {
+        return Err(Error::format_at(mz.e_lfanew as u64,"Invalid PE signature"));
+    }
 
-        // read sections
-        const PESEC_SIZE: usize = 40;
-        assert_eq!(PESEC_SIZE, mem::size_of::<PeSection>());
-        let mut ram = Region::undefined("ram".to_string(),0xc0000000);
+    let machine = match pe.machine {
+        0x8664 => Machine::Amd64,
+        0x014c => Machine::Ia32,
+        m => return Err(Error::unsupported_architecture(format!("PE machine type {:#x}",m))),
+    };
 
-        for i in 0..pe.num_section {
-            let sec_off = (mz.e_lfanew as u64) + (PE_SIZE as u64) + (PESEC_SIZE as u64) * (i as u64) + (pe.opthdr_size as u64);
-            let mut sec_raw = [0; PESEC_SIZE];
+    debug!("{:?} PE file",machine);
 
-            if Some(sec_off) != fd.seek(SeekFrom::Start(sec_off)).ok() {
-                return None;
-            }
+    if pe.characteristics & 2 == 0 {
+        return Err(Error::format_at(mz.e_lfanew as u64,"PE file is not executable"));
+    }
+
+    // read optional PE header
+    let peopt = try!(PeOptional::read(&mut fd));
+    let (img_base,entry_point,is_pe64) = (peopt.image_base,peopt.entry_point,peopt.is_pe64);
+
+    // read data directory
+    let mut datadir = Vec::new();
+
+    for _ in 0..::std::cmp::min(peopt.datadir_entries,MAX_DATADIR_ENTRIES) {
+        let rva = try!(fd.read_u32::<LittleEndian>());
+        let size = try!(fd.read_u32::<LittleEndian>());
 
-            if Some(PESEC_SIZE) != fd.read(&mut sec_raw).ok() {
-                return None;
+        datadir.push(PeDataDirectory{ rva: rva, size: size });
+    }
+
+    // read sections
+    let sec_table = mz.e_lfanew as u64 + PE_SIZE + pe.opthdr_size as u64;
+
+    if sec_table + PESEC_SIZE * pe.num_section as u64 > file_len {
+        return Err(Error::format_at(sec_table,format!("Section table with {} entries is outside of the file",pe.num_section)));
+    }
+
+    let mut ram = Region::undefined("ram".to_string(),if is_pe64 { 0xFFFF_FFFF_FFFF_FFFF } else { 0x1_0000_0000 });
+    let mut mapped = Vec::<Bound>::new();
+    let mut mappings = Vec::new();
+
+    let mut sections = Vec::with_capacity(pe.num_section as usize);
+
+    try!(fd.seek(SeekFrom::Start(sec_table)));
+
+    for _ in 0..pe.num_section {
+        sections.push(try!(PeSection::read(&mut fd)));
+    }
+
+    for (i,sec) in sections.iter().enumerate() {
+        let sec_off = sec_table + PESEC_SIZE * (i as u64);
+        let name = String::from_utf8_lossy(&sec.name).to_string();
+
+        // Linkers round SizeOfRawData up to the file alignment, only the virtual size is
+        // guaranteed to stay clear of the next section.
+        let virt_len = if sec.virt_sz_or_phy_addr > 0 { sec.virt_sz_or_phy_addr as u64 } else { sec.raw_sz as u64 };
+        let area = match img_base.checked_add(sec.virt_address as u64).and_then(|s| s.checked_add(virt_len).map(|e| (s,e))) {
+            Some((start,end)) => Bound::new(start,end),
+            None => return Err(Error::format_at(sec_off,format!("Section '{}' is outside of the address space",name))),
+        };
+
+        if let Some(other) = mapped.iter().find(|b| b.overlaps(&area)) {
+            return Err(Error::format_at(sec_off,format!("Section '{}' at {:#x} overlaps section at {:#x}",name,area.start,other.start)));
+        }
+
+        // map max(VirtualSize,SizeOfRawData) bytes but never past the start of the next section
+        let next = sections.iter()
+            .map(|s| s.virt_address as u64)
+            .filter(|&rva| rva > sec.virt_address as u64)
+            .min()
+            .map(|rva| rva - sec.virt_address as u64);
+        let len = ::std::cmp::max(virt_len,sec.raw_sz as u64);
+        let len = next.map(|n| ::std::cmp::min(n,len)).unwrap_or(len);
+        let raw_len = ::std::cmp::min(sec.raw_sz as u64,len);
+
+        // the part of the section not backed by the file stays undefined
+        let (l,cov) = if raw_len > 0 {
+            if sec.raw_ptr as u64 + raw_len > file_len {
+                return Err(Error::format_at(sec_off,format!("Contents of section '{}' are outside of the file",name)));
             }
 
-            let sec: PeSection = unsafe { mem::transmute(sec_raw) };
-            let name = String::from_utf8_lossy(&sec.name);
+            debug!("mapped '{}'",name);
+            (Layer::Opaque(try!(OpaqueLayer::open_range(p,sec.raw_ptr as u64,raw_len))),raw_len)
+        } else {
+            debug!("not mapped '{}'",name);
+            (Layer::undefined(len),len)
+        };
+
+        if !ram.cover(Bound::new(area.start,area.start + cov),l) {
+            return Err(Error::format_at(sec_off,format!("Section '{}' is outside of the address space",name)));
+        }
 
-            println!("{}",name);
+        mapped.push(area);
+        mappings.push(Mapping{
+            virt_address: sec.virt_address,
+            virt_size: sec.virt_sz_or_phy_addr,
+            raw_ptr: sec.raw_ptr,
+            raw_sz: sec.raw_sz,
+        });
+    }
 
-            let l = if sec.raw_sz > 0 {
-                let mut buf = vec![0; sec.raw_sz as usize];
+    // The image is mapped at its preferred base address, base relocations don't need to be applied.
+    let mut prog = Program::new("prog0");
+    let mut proj = Project::new(name.to_string(),ram);
+    let mut seen = HashSet::<u64>::new();
 
-                if Some(sec.raw_ptr as u64) != fd.seek(SeekFrom::Start(sec.raw_ptr as u64)).ok() {
-                    return None;
-                }
+    if entry_point != 0 {
+        let entry = img_base.wrapping_add(entry_point as u64);
 
-                if Some(sec.raw_sz as usize) != fd.read(&mut buf).ok() {
-                    return None;
-                }
+        prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(entry),Some(name.to_string()),Uuid::new_v4()));
+        proj.comments.insert(("ram".to_string(),entry),"Entry point".to_string());
+        seen.insert(entry);
+    }
 
-                println!("mapped '{}'",name);
-                Layer::wrap(buf.to_vec())
-            } else {
-                println!("not mapped '{}'",name);
-                Layer::undefined(sec.virt_sz_or_phy_addr as u64)
-            };
+    if let Some(&dir) = datadir.get(EXPORT_DIRECTORY) {
+        if dir.rva != 0 && dir.size != 0 {
+            for (rva,func) in try!(exports(&mut fd,&mappings,dir,file_len)) {
+                let addr = img_base.wrapping_add(rva as u64);
 
-            if !ram.cover(Bound::new(img_base + (sec.virt_address as u64),img_base + (sec.virt_address as u64) + (sec.raw_sz as u64)),l) {
-                return None;
+                if seen.insert(addr) {
+                    prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(addr),Some(func),Uuid::new_v4()));
+                }
             }
         }
+    }
+
+    if let Some(&dir) = datadir.get(IMPORT_DIRECTORY) {
+        if dir.rva != 0 && dir.size != 0 {
+            for (slot,func) in try!(imports(&mut fd,&mappings,dir,is_pe64)) {
+                let addr = img_base.wrapping_add(slot as u64);
 
-        Some(Project::new(name.to_string(),ram))
-    } else {
-        None
+                debug!("import {} at {:#x}",func,addr);
+                prog.imports.insert(addr,func.clone());
+                prog.call_graph.add_vertex(CallTarget::Symbolic(func,Uuid::new_v4()));
+            }
+        }
     }
+
+    proj.code.push(prog);
+
+    Ok((proj,machine))
 }
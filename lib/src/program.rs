@@ -34,14 +34,30 @@ use graph_algos::{
     MutableGraphTrait,
     AdjacencyMatrixGraphTrait,
     VertexListGraphTrait,
+    IncidenceGraphTrait,
 };
 use graph_algos::adjacency_list::AdjacencyListVertexDescriptor;
 use uuid::Uuid;
+use std::collections::HashMap;
+use std::fmt::Debug;
 
 use {
     ControlFlowTarget,
     Function,
-    Rvalue
+    Rvalue,
+    Lvalue,
+    Statement,
+    Operation,
+    Region,
+    Architecture,
+    ssa_convertion,
+    ssa_deconstruction,
+    resolve_indirect_jumps,
+    recover_frame,
+    approximate_program,
+    Approximation,
+    Kset,
+    Result,
 };
 
 /// Node of the program call graph.
@@ -66,6 +82,17 @@ impl CallTarget {
     }
 }
 
+/// Progress report of `Program::disassemble_all`.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum DisassemblyEvent {
+    /// A new function was added to the call graph.
+    Discovered(Uuid),
+    /// Started to disassemble a function.
+    Started(Uuid),
+    /// Function was disassembled and inserted into the call graph.
+    Finished(Uuid),
+}
+
 /// Graph of functions/symbolic references
 pub type CallGraph = AdjacencyList<CallTarget,()>;
 /// Stable reference to a call graph node
@@ -80,6 +107,9 @@ pub struct Program {
     pub name: String,
     /// Graph of functions
     pub call_graph: CallGraph,
+    /// Addresses of import stubs (PLT entries) or import address table slots and the name of the
+    /// imported symbol
+    pub imports: HashMap<u64,String>,
 }
 
 impl Program {
@@ -89,6 +119,7 @@ impl Program {
             uuid: Uuid::new_v4(),
             name: n.to_string(),
             call_graph: CallGraph::new(),
+            imports: HashMap::new(),
         }
     }
 
@@ -139,7 +170,8 @@ impl Program {
     }
 
     /// Puts function/reference `new_ct` into the call graph, returning the UUIDs of all functions
-    /// that are called by `new_ct` and call `new_ct`.
+    /// that are called by `new_ct` and call `new_ct`. If `new_ct` doesn't replace a call target
+    /// with the same UUID, functions already calling its entry point are linked to it too.
     pub fn insert(&mut self, new_ct: CallTarget) -> Vec<Uuid> {
         let new_uu = new_ct.uuid();
         let maybe_vx = self.call_graph.vertices().find(|ct| {
@@ -156,7 +188,7 @@ impl Program {
         let mut other_funs = Vec::new();
         let mut ret = Vec::new();
         let calls = if let Some(&CallTarget::Concrete(ref fun)) = self.call_graph.vertex_label(new_vx) {
-            fun.collect_calls()
+            call_targets(fun,&self.imports)
         } else {
             vec![]
         };
@@ -164,6 +196,27 @@ impl Program {
         for a in calls {
             let l = other_funs.len();
 
+            // calls to import stubs point to the symbolic reference
+            if let Rvalue::Constant{ value,.. } = a {
+                if let Some(sym) = self.imports.get(&value).cloned() {
+                    let maybe_vx = self.call_graph.vertices().find(|&w| match self.call_graph.vertex_label(w) {
+                        Some(&CallTarget::Symbolic(ref s,_)) => *s == sym,
+                        _ => false,
+                    });
+                    let vx = match maybe_vx {
+                        Some(vx) => vx,
+                        None => {
+                            let uu = Uuid::new_v4();
+                            ret.push(uu);
+                            self.call_graph.add_vertex(CallTarget::Symbolic(sym,uu))
+                        }
+                    };
+
+                    other_funs.push(vx);
+                    continue;
+                }
+            }
+
             for w in self.call_graph.vertices() {
                 match self.call_graph.vertex_label(w) {
                     Some(&CallTarget::Concrete(Function{ cflow_graph: ref cg, entry_point: Some(ent),.. })) => {
@@ -202,9 +255,128 @@ impl Program {
             }
         }
 
+        // a replaced Todo node keeps the edges from its callers
+        if maybe_vx.is_none() {
+            let entry = match self.call_graph.vertex_label(new_vx) {
+                Some(&CallTarget::Concrete(Function{ cflow_graph: ref cg, entry_point: Some(ent),.. })) => {
+                    match cg.vertex_label(ent) {
+                        Some(&ControlFlowTarget::Resolved(ref bb)) => Some(bb.area.start),
+                        _ => None,
+                    }
+                },
+                _ => None,
+            };
+
+            if let Some(entry) = entry {
+                let callers = self.call_graph.vertices().filter(|&w| w != new_vx && match self.call_graph.vertex_label(w) {
+                    Some(&CallTarget::Concrete(ref f)) => call_targets(f,&self.imports).iter().any(|a| match a {
+                        &Rvalue::Constant{ value,.. } => value == entry,
+                        _ => false,
+                    }),
+                    _ => false,
+                }).collect::<Vec<_>>();
+
+                for caller in callers {
+                    if self.call_graph.edge(caller,new_vx) == None {
+                        self.call_graph.add_edge((),caller,new_vx);
+                    }
+                }
+            }
+        }
+
         ret
     }
 
+    /// Returns address, name and UUID of a `CallTarget::Todo` node with a constant address.
+    pub fn next_todo(&self) -> Option<(u64,Option<String>,Uuid)> {
+        self.call_graph.vertices().filter_map(|x| {
+            if let Some(&CallTarget::Todo(Rvalue::Constant{ value,.. },ref name,uuid)) = self.call_graph.vertex_label(x) {
+                Some((value,name.clone(),uuid))
+            } else {
+                None
+            }
+        }).next()
+    }
+
+    /// Disassembles `func` starting at `entry` inside `reg` and tries to resolve indirect jumps
+    /// using abstract interpretation. Disassembly continues at the resolved targets until no new
+    /// indirect jumps can be resolved. The entry point of the returned function is set to the
+    /// basic block at `entry`. If the architecture has a stack pointer, the stack frame of the
+    /// function is recovered.
+    pub fn disassemble_function<A>(mut func: Function, cfg: A::Configuration, reg: &Region, entry: u64) -> Result<Function>
+    where A: Architecture + Debug, A::Configuration: Debug {
+        ssa_deconstruction(&mut func);
+
+        let mut func = Function::disassemble::<A>(Some(func),cfg.clone(),reg,entry);
+
+        func.entry_point = func.find_basic_block_at_address(entry);
+
+        if func.cflow_graph.num_vertices() == 0 || func.entry_point.is_none() {
+            debug!("failed to disassemble for {}",func.name);
+            return Ok(func);
+        }
+
+        debug!("primary pass done");
+
+        let stack_pointer = A::stack_pointer(&cfg);
+
+        loop {
+            ssa_convertion(&mut func);
+
+            let resolved_jumps = try!(resolve_indirect_jumps(&mut func,reg,stack_pointer.as_ref()));
+
+            if resolved_jumps.is_empty() {
+                break;
+            }
+
+            // continue disassembly on the original code, SSA form is recomputed in the next round
+            ssa_deconstruction(&mut func);
+
+            for addr in resolved_jumps {
+                debug!("continue at {:?}",addr);
+                func = Function::disassemble::<A>(Some(func),cfg.clone(),reg,addr);
+                func.entry_point = func.find_basic_block_at_address(entry);
+            }
+        }
+
+        debug!("secondary pass done");
+
+        func.frame = stack_pointer.map(|sp| recover_frame(&func,&sp));
+
+        Ok(func)
+    }
+
+    /// Disassembles all `CallTarget::Todo` nodes with constant addresses inside `reg` and every
+    /// function called by them until no new functions are found. Reports progress by calling
+    /// `progress` with the UUIDs of discovered, started and finished functions. Afterwards the
+    /// effects of calls are made explicit using `approximate_program`, its result is returned.
+    pub fn disassemble_all<A,F>(&mut self, reg: &Region, cfg: A::Configuration, mut progress: F) -> Result<Approximation<Kset>>
+    where A: Architecture + Debug, A::Configuration: Debug, F: FnMut(DisassemblyEvent) {
+        for vx in self.call_graph.vertices() {
+            if let Some(&CallTarget::Todo(_,_,uuid)) = self.call_graph.vertex_label(vx) {
+                progress(DisassemblyEvent::Discovered(uuid));
+            }
+        }
+
+        while let Some((entry,maybe_name,uuid)) = self.next_todo() {
+            progress(DisassemblyEvent::Started(uuid));
+
+            let name = maybe_name.unwrap_or(format!("func_{:x}",entry));
+            let func = Function::with_uuid(name,uuid,reg.name().clone());
+            let func = try!(Self::disassemble_function::<A>(func,cfg.clone(),reg,entry));
+            let new_functions = self.insert(CallTarget::Concrete(func));
+
+            progress(DisassemblyEvent::Finished(uuid));
+
+            for uu in new_functions {
+                debug!("found new func at {:?}",uu);
+                progress(DisassemblyEvent::Discovered(uu));
+            }
+        }
+
+        approximate_program::<Kset>(self,A::stack_pointer(&cfg).as_ref())
+    }
+
     /// Returns the function, todo item or symbolic reference with UUID `uu`.
     pub fn find_call_target_by_uuid<'a>(&'a self,uu: &Uuid) -> Option<CallGraphRef> {
         for vx in self.call_graph.vertices() {
@@ -221,6 +393,53 @@ impl Program {
     }
 }
 
+/// Returns the targets of all calls in `fun`. Calls and jumps through a slot in `imports`, like
+/// `call [slot]` or the `jmp [slot]` of an import thunk, load their target from the slot first.
+/// Their target is returned as the address of the slot.
+fn call_targets(fun: &Function, imports: &HashMap<u64,String>) -> Vec<Rvalue> {
+    let cfg = &fun.cflow_graph;
+    let mut ret = vec![];
+
+    for vx in cfg.vertices() {
+        if let Some(&ControlFlowTarget::Resolved(ref bb)) = cfg.vertex_label(vx) {
+            let indirect = cfg.out_edges(vx).any(|e| match cfg.vertex_label(cfg.target(e)) {
+                Some(&ControlFlowTarget::Unresolved(_)) => true,
+                _ => false,
+            });
+
+            for (idx,mne) in bb.mnemonics.iter().enumerate() {
+                let mut slots = HashMap::new();
+
+                for stmt in mne.instructions.iter() {
+                    match stmt {
+                        &Statement{ op: Operation::Load(_,Rvalue::Constant{ value,.. }), assignee: Lvalue::Variable{ ref name, subscript,.. } } => {
+                            if imports.contains_key(&value) {
+                                slots.insert((name.clone(),subscript),value);
+                            }
+                        },
+                        &Statement{ op: Operation::Call(ref tgt),.. } => {
+                            let slot = match tgt {
+                                &Rvalue::Variable{ ref name, subscript,.. } => slots.remove(&(name.clone(),subscript)),
+                                _ => None,
+                            };
+
+                            ret.push(slot.map(Rvalue::new_u64).unwrap_or(tgt.clone()));
+                        },
+                        _ => {},
+                    }
+                }
+
+                if indirect && idx + 1 == bb.mnemonics.len() {
+                    ret.extend(slots.values().map(|&s| Rvalue::new_u64(s)));
+                }
+            }
+        }
+    }
+
+    debug!("collected calls: {:?}",ret);
+    ret
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -240,7 +459,11 @@ mod tests {
         Lvalue,Rvalue,
         Operation,
         Statement,
+        Region,
+        Guard,
     };
+    use mos::{Mos,Variant};
+    use std::borrow::Cow;
 
     #[test]
     fn find_by_entry() {
@@ -320,4 +543,110 @@ mod tests {
         assert_eq!(prog.call_graph.num_edges(),1);
         assert_eq!(prog.call_graph.num_vertices(),2);
     }
+
+    #[test]
+    fn disassemble_all() {
+        // 0: jsr 5; 3: rts; 5: rts
+        let reg = Region::wrap("ram".to_string(),vec![0x20,0x05,0x00,0x60,0x00,0x60]);
+        let uu = Uuid::new_v4();
+        let mut prog = Program::new("prog_test");
+        let mut events = vec![];
+
+        prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(0),Some("main".to_string()),uu));
+        assert!(prog.disassemble_all::<Mos,_>(&reg,Variant::mos6502(),|ev| events.push(ev)).is_ok());
+
+        assert_eq!(prog.call_graph.num_vertices(),2);
+        assert_eq!(prog.call_graph.num_edges(),1);
+        assert!(prog.next_todo().is_none());
+        assert!(prog.find_function_by_entry(0).is_some());
+        assert!(prog.find_function_by_entry(5).is_some());
+
+        assert_eq!(events.len(),6);
+        assert_eq!(events[0],DisassemblyEvent::Discovered(uu));
+        assert_eq!(events[1],DisassemblyEvent::Started(uu));
+        assert_eq!(events[2],DisassemblyEvent::Finished(uu));
+
+        if let DisassemblyEvent::Discovered(uu2) = events[3] {
+            assert_eq!(events[4],DisassemblyEvent::Started(uu2));
+            assert_eq!(events[5],DisassemblyEvent::Finished(uu2));
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn insert_resolves_imports() {
+        let mut prog = Program::new("prog_test");
+        let sym_uu = Uuid::new_v4();
+
+        prog.imports.insert(0x100,"printf".to_string());
+        prog.imports.insert(0x110,"puts".to_string());
+        let svx = prog.call_graph.add_vertex(CallTarget::Symbolic("printf".to_string(),sym_uu));
+
+        let mut func = Function::new("main".to_string(),"ram".to_string());
+        let i1 = vec![
+            Statement{ op: Operation::Call(Rvalue::new_u64(0x100)), assignee: Lvalue::Undefined},
+            Statement{ op: Operation::Call(Rvalue::new_u64(0x110)), assignee: Lvalue::Undefined},
+        ];
+        let mne1 = Mnemonic::new(0..10,"call".to_string(),"".to_string(),vec![].iter(),i1.iter()).ok().unwrap();
+        let bb0 = BasicBlock::from_vec(vec!(mne1));
+        func.entry_point = Some(func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(bb0)));
+
+        let new = prog.insert(CallTarget::Concrete(func));
+        let fvx = prog.find_function_by_entry(0).unwrap();
+
+        assert_eq!(new.len(),1);
+        assert_eq!(prog.call_graph.num_vertices(),3);
+        assert_eq!(prog.call_graph.num_edges(),2);
+        assert!(prog.call_graph.edge(fvx,svx).is_some());
+        assert!(prog.next_todo().is_none());
+
+        let pvx = prog.find_call_target_by_uuid(&new[0]).unwrap();
+        if let Some(&CallTarget::Symbolic(ref s,_)) = prog.call_graph.vertex_label(pvx) {
+            assert_eq!(s,"puts");
+        } else {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn insert_resolves_import_slots() {
+        let mut prog = Program::new("prog_test");
+        let printf_uu = Uuid::new_v4();
+        let puts_uu = Uuid::new_v4();
+
+        prog.imports.insert(0x100,"printf".to_string());
+        prog.imports.insert(0x110,"puts".to_string());
+        let pvx = prog.call_graph.add_vertex(CallTarget::Symbolic("printf".to_string(),printf_uu));
+        let svx = prog.call_graph.add_vertex(CallTarget::Symbolic("puts".to_string(),puts_uu));
+
+        // call [0x100]; jmp [0x110]
+        let mut func = Function::new("main".to_string(),"ram".to_string());
+        let tgt = Rvalue::Variable{ name: Cow::Borrowed("tgt"), size: 64, offset: 0, subscript: None };
+        let i1 = vec![
+            Statement{ op: Operation::Load(Cow::Borrowed("ram"),Rvalue::new_u64(0x100)), assignee: Lvalue::from_rvalue(tgt.clone()).unwrap() },
+            Statement{ op: Operation::Call(tgt.clone()), assignee: Lvalue::Undefined },
+        ];
+        let i2 = vec![
+            Statement{ op: Operation::Load(Cow::Borrowed("ram"),Rvalue::new_u64(0x110)), assignee: Lvalue::from_rvalue(tgt.clone()).unwrap() },
+        ];
+        let mne1 = Mnemonic::new(0..6,"call".to_string(),"".to_string(),vec![].iter(),i1.iter()).ok().unwrap();
+        let mne2 = Mnemonic::new(6..12,"jmp".to_string(),"".to_string(),vec![].iter(),i2.iter()).ok().unwrap();
+        let bb0 = BasicBlock::from_vec(vec!(mne1,mne2));
+        let vx0 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(bb0));
+        let vx1 = func.cflow_graph.add_vertex(ControlFlowTarget::Unresolved(tgt));
+
+        func.cflow_graph.add_edge(Guard::always(),vx0,vx1);
+        func.entry_point = Some(vx0);
+
+        let new = prog.insert(CallTarget::Concrete(func));
+        let fvx = prog.find_function_by_entry(0).unwrap();
+
+        assert!(new.is_empty());
+        assert_eq!(prog.call_graph.num_vertices(),3);
+        assert_eq!(prog.call_graph.num_edges(),2);
+        assert!(prog.call_graph.edge(fvx,pvx).is_some());
+        assert!(prog.call_graph.edge(fvx,svx).is_some());
+        assert!(prog.next_todo().is_none());
+    }
 }
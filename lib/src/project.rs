@@ -22,29 +22,32 @@
 
 use std::path::Path;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{Read,Write};
 
 use uuid::Uuid;
-use rmp_serialize::{Encoder,Decoder};
-use rustc_serialize::{Decodable,Encodable};
-use flate2::write::ZlibEncoder;
-use flate2::read::ZlibDecoder;
-use flate2::Compression;
-use byteorder::{
-    ReadBytesExt,
-    WriteBytesExt,
-    BigEndian,
-};
+use graph_algos::{GraphTrait,MutableGraphTrait};
 
 use {
     Program,
     CallGraphRef,
+    CallTarget,
     Region,World,
     Function,
+    Architecture,
+    OpaqueLayer,
+    Layer,
+    Bound,
+    Rvalue,
     Result,
+    Target,
+    LoadError,
+    loader,
+    session,
+    journal,
     pe
 };
+use session::Metadata;
+use journal::Change;
+use history::{History,Edit};
 
 /// Complete Panopticon session
 #[derive(RustcDecodable,RustcEncodable)]
@@ -57,6 +60,8 @@ pub struct Project {
     pub data: World,
     /// Comments
     pub comments: HashMap<(String,u64),String>,
+    /// Undo/redo stacks of user edits
+    pub history: History,
 }
 
 impl Project {
@@ -67,42 +72,83 @@ impl Project {
             code: Vec::new(),
             data: World::new(r),
             comments: HashMap::new(),
+            history: History::new(),
         }
     }
 
-    /// Reads a serialized project from disk.
+    /// Reads a serialized project from disk. Files saved by older versions of Panopticon are
+    /// migrated to the current format. Changes recorded in the journal since the file was written
+    /// are applied to the project. See `session` and `journal`.
     pub fn open(p: &Path) -> Result<Project> {
-        let mut fd = match File::open(p) {
-            Ok(fd) => fd,
-            Err(e) => return Err(format!("failed to open file: {:?}",e).into())
-        };
-        let mut magic = [0u8;10];
-
-        if try!(fd.read(&mut magic)) == 10 && magic == *b"PANOPTICON" {
-            let version = try!(fd.read_u32::<BigEndian>());
+        let mut proj = try!(session::read(p));
+        let changes = try!(journal::read(p));
 
-            if version == 0 {
-                let mut z = ZlibDecoder::new(fd);
-                let mut rmp = Decoder::new(&mut z);
-                let res = <Project as Decodable>::decode(&mut rmp);
+        if !changes.is_empty() {
+            debug!("replaying {} journal entries",changes.len());
+        }
 
-                match res {
-                    Ok(p) => Ok(p),
-                    Err(_) => Err("project decoding failed".into())
-                }
-            } else {
-                Err("wrong version".into())
+        for c in changes {
+            if let Err(e) = proj.apply(c) {
+                warn!("failed to replay journal entry: {}",e);
             }
-        } else {
-            Err("wrong magic number".into())
         }
+
+        Ok(proj)
+    }
+
+    /// Reads the summary of the project saved at `p` without decoding all of it.
+    pub fn metadata(p: &Path) -> Result<Metadata> {
+        session::read_metadata(p)
+    }
+
+    /// Guesses the format of the file at `p` and creates a new project from it. Returns the
+    /// project and the CPU the file is intended for, if it's known. See `loader::load`.
+    pub fn load(p: &Path) -> ::std::result::Result<(Project,Option<Target>),LoadError> {
+        loader::load(p)
     }
 
     /// Creates a new project from a Windows PE file.
-    pub fn pe(p: &Path) -> Option<Project> {
+    pub fn pe(p: &Path) -> Result<Project> {
         pe::pe(p)
     }
 
+    /// Creates a new project from a memory image. The contents of `p` are mapped at `base` into a
+    /// `Region` named after the file. The project has a single, empty `Program`.
+    pub fn raw(p: &Path, base: u64) -> Result<Project> {
+        let name = p.file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or("(encoding error)".to_string());
+        let b = try!(OpaqueLayer::open(p));
+        let len = b.iter().len();
+        let mut reg = Region::undefined(name.clone(),len + base);
+
+        if !reg.cover(Bound::new(base,base + len),Layer::Opaque(b)) {
+            return Err("Failed to map the memory image".into());
+        }
+
+        let mut proj = Project::new(name,reg);
+        proj.code.push(Program::new("prog0"));
+
+        Ok(proj)
+    }
+
+    /// Adds a `CallTarget::Todo` and a comment for each entry point `A::prepare` reports for the
+    /// root region to the first program. Returns the number of entry points added.
+    pub fn prepare<A: Architecture>(&mut self, cfg: &A::Configuration) -> Result<usize> {
+        let (iv,reg_name) = {
+            let root = try!(self.data.dependencies.vertex_label(self.data.root).ok_or("Project has no root region"));
+            (try!(A::prepare(root,cfg)),root.name().clone())
+        };
+        let prog = try!(self.code.first_mut().ok_or("Project has no program"));
+
+        for &(name,off,cmnt) in iv.iter() {
+            prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(off),Some(name.to_string()),Uuid::new_v4()));
+            self.comments.insert((reg_name.clone(),off),cmnt.to_string());
+        }
+
+        Ok(iv.len())
+    }
+
     /// Returns the program with UUID `uu`
     pub fn find_program_by_uuid(&self,uu: &Uuid) -> Option<&Program> {
         self.code.iter().find(|x| x.uuid == *uu)
@@ -157,23 +203,35 @@ impl Project {
         None
     }
 
-    /// Serializes the project into the file at `p`. The format looks like this:
-    /// [u8;10] magic = "PANOPTICON"
-    /// u32     version = 0
-    /// zlib compressed MsgPack
+    /// Serializes the project into the file at `p` using the current format version and removes
+    /// the journal. See `session`.
     pub fn snapshot(&self,p: &Path) -> Result<()> {
-        println!("snapshot to {:?}",p);
-        let mut fd = try!(File::create(p));
-
-        try!(fd.write(b"PANOPTICON"));
-        try!(fd.write_u32::<BigEndian>(0));
+        debug!("snapshot to {:?}",p);
+        try!(session::write(self,p));
+        journal::remove(p)
+    }
 
-        let mut z = ZlibEncoder::new(fd,Compression::Default);
-        let mut enc = Encoder::new(&mut z);
+    /// Applies `c` to the project. Returns the UUIDs of all call targets created by it. Use
+    /// `Journal::append` to record the change until the next snapshot. Comments and renames can
+    /// be undone, see `history`.
+    pub fn apply(&mut self, c: Change) -> Result<Vec<Uuid>> {
+        match c {
+            Change::Comment{ region, offset, comment } => {
+                let comment = if comment.is_empty() { None } else { Some(comment) };
 
-        match self.encode(&mut enc) {
-            Ok(()) => Ok(()),
-            Err(_) => Err("failed to write to save file".into())
+                try!(self.edit(Edit::Comment{ region: region, offset: offset, comment: comment }));
+                Ok(vec![])
+            },
+            Change::Rename{ function, name } => {
+                try!(self.edit(Edit::Rename{ function: function, name: name }));
+                Ok(vec![])
+            },
+            Change::Function{ program, function } => {
+                match self.find_program_by_uuid_mut(&program) {
+                    Some(prog) => Ok(prog.insert(CallTarget::Concrete(function))),
+                    None => Err(format!("no program with UUID {}",program).into()),
+                }
+            },
         }
     }
 }
@@ -182,6 +240,11 @@ impl Project {
 mod tests {
     use super::*;
     use region::Region;
+    use program::Program;
+    use function::Function;
+    use journal::{Change,Journal,journal_path};
+    use uuid::Uuid;
+    use tempdir::TempDir;
 
     #[test]
     fn new() {
@@ -190,4 +253,63 @@ mod tests {
         assert_eq!(p.name, "test".to_string());
         assert_eq!(p.code.len(), 0);
     }
+
+    #[test]
+    fn snapshot_and_open() {
+        let tmp = TempDir::new("panop-project").unwrap();
+        let path = tmp.path().join("test.panop");
+        let mut p = Project::new("test".to_string(),Region::undefined("base".to_string(),128));
+        let mut prog = Program::new("prog0");
+
+        prog.imports.insert(0x1000,"printf".to_string());
+        p.code.push(prog);
+        p.comments.insert(("base".to_string(),12),"test".to_string());
+
+        assert!(p.snapshot(&path).is_ok());
+
+        let q = Project::open(&path).unwrap();
+
+        assert_eq!(q.name, "test".to_string());
+        assert_eq!(q.code.len(), 1);
+        assert_eq!(q.code[0].imports.get(&0x1000), Some(&"printf".to_string()));
+        assert_eq!(q.comments.get(&("base".to_string(),12)), Some(&"test".to_string()));
+    }
+
+    #[test]
+    fn journal_replay() {
+        let tmp = TempDir::new("panop-project").unwrap();
+        let path = tmp.path().join("test.panop");
+        let mut p = Project::new("test".to_string(),Region::undefined("base".to_string(),128));
+        let prog = Program::new("prog0");
+        let prog_uu = prog.uuid;
+        let func = Function::new("func_0".to_string(),"base".to_string());
+        let func_uu = func.uuid;
+
+        p.code.push(prog);
+        p.snapshot(&path).unwrap();
+
+        {
+            let mut j = Journal::open(&path).unwrap();
+
+            j.append(&Change::Function{ program: prog_uu, function: func }).unwrap();
+            j.append(&Change::Rename{ function: func_uu, name: "main".to_string() }).unwrap();
+            j.append(&Change::Comment{ region: "base".to_string(), offset: 1, comment: "test".to_string() }).unwrap();
+        }
+
+        let mut q = Project::open(&path).unwrap();
+
+        assert_eq!(q.find_function_by_uuid(&func_uu).map(|f| f.name.clone()),Some("main".to_string()));
+        assert_eq!(q.comments.get(&("base".to_string(),1)),Some(&"test".to_string()));
+
+        assert!(q.apply(Change::Comment{ region: "base".to_string(), offset: 1, comment: "".to_string() }).is_ok());
+        assert!(q.apply(Change::Rename{ function: Uuid::new_v4(), name: "x".to_string() }).is_err());
+        assert!(q.comments.is_empty());
+        assert!(q.undo().unwrap());
+        assert_eq!(q.comments.get(&("base".to_string(),1)),Some(&"test".to_string()));
+
+        q.snapshot(&path).unwrap();
+
+        assert!(!journal_path(&path).exists());
+        assert!(Project::open(&path).unwrap().find_function_by_uuid(&func_uu).is_some());
+    }
 }
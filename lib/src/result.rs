@@ -18,7 +18,9 @@
 
 //! Result type used throughout the library.
 //!
-//! The error type is simply a string.
+//! Errors are grouped by their cause: failed I/O, malformed input files, files for unsupported
+//! CPUs, undecodable instructions, failed analyses and (de)serialization errors. Errors created from strings,
+//! for example using `"message".into()`, end up in `Error::Generic`.
 
 use std::borrow::Cow;
 use std::error;
@@ -37,63 +39,193 @@ use rustc_serialize::json::{
 
 /// Panopticon error type
 #[derive(Debug)]
-pub struct Error(pub Cow<'static,str>);
+pub enum Error {
+    /// Reading or writing a file failed
+    Io(io::Error),
+    /// Input file is malformed, truncated or uses an unsupported feature
+    Format{
+        /// What is wrong
+        message: Cow<'static,str>,
+        /// Position in the file, if known
+        offset: Option<u64>,
+    },
+    /// Input file is for a CPU architecture that is not supported
+    UnsupportedArchitecture(Cow<'static,str>),
+    /// Instruction could not be decoded
+    Decode{
+        /// Address of the instruction
+        address: u64,
+        /// What is wrong
+        message: Cow<'static,str>,
+    },
+    /// Program analysis failed
+    Analysis(Cow<'static,str>),
+    /// Encoding or decoding a project, journal or JSON document failed
+    Serialization{
+        /// What is wrong
+        message: Cow<'static,str>,
+        /// Error reported by the encoder or decoder, if any
+        source: Option<Box<error::Error + Send + Sync>>,
+    },
+    /// Everything else
+    Generic(Cow<'static,str>),
+}
+
 /// Panopticon result type
 pub type Result<T> = result::Result<T,Error>;
 
+impl Error {
+    /// Returns a new `Error::Format` without file position.
+    pub fn format<S: Into<Cow<'static,str>>>(msg: S) -> Error {
+        Error::Format{ message: msg.into(), offset: None }
+    }
+
+    /// Returns a new `Error::Format` for the file position `offset`.
+    pub fn format_at<S: Into<Cow<'static,str>>>(offset: u64, msg: S) -> Error {
+        Error::Format{ message: msg.into(), offset: Some(offset) }
+    }
+
+    /// Returns a new `Error::UnsupportedArchitecture` for the architecture `arch`.
+    pub fn unsupported_architecture<S: Into<Cow<'static,str>>>(arch: S) -> Error {
+        Error::UnsupportedArchitecture(arch.into())
+    }
+
+    /// Returns a new `Error::Decode` for the instruction at `address`.
+    pub fn decode<S: Into<Cow<'static,str>>>(address: u64, msg: S) -> Error {
+        Error::Decode{ address: address, message: msg.into() }
+    }
+
+    /// Returns a new `Error::Analysis`.
+    pub fn analysis<S: Into<Cow<'static,str>>>(msg: S) -> Error {
+        Error::Analysis(msg.into())
+    }
+
+    /// Returns a new `Error::Serialization` without a source.
+    pub fn serialization<S: Into<Cow<'static,str>>>(msg: S) -> Error {
+        Error::Serialization{ message: msg.into(), source: None }
+    }
+
+    /// Address or file position the error refers to, if known.
+    pub fn address(&self) -> Option<u64> {
+        match self {
+            &Error::Format{ offset,.. } => offset,
+            &Error::Decode{ address,.. } => Some(address),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            &Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            &Error::Format{ ref message, offset: Some(o) } => write!(f, "{} (at offset {:#x})", message, o),
+            &Error::Format{ ref message, offset: None } => write!(f, "{}", message),
+            &Error::UnsupportedArchitecture(ref s) => write!(f, "unsupported architecture: {}", s),
+            &Error::Decode{ ref message, address } => write!(f, "{} (at {:#x})", message, address),
+            &Error::Analysis(ref s) => write!(f, "{}", s),
+            &Error::Serialization{ ref message, source: Some(ref e) } => write!(f, "{}: {}", message, e),
+            &Error::Serialization{ ref message, source: None } => write!(f, "{}", message),
+            &Error::Generic(ref s) => write!(f, "{}", s),
+        }
     }
 }
 
 impl error::Error for Error {
     fn description<'a>(&'a self) -> &'a str {
-        match &self.0 {
-            &Cow::Borrowed(s) => s,
-            &Cow::Owned(ref s)=> s,
+        match self {
+            &Error::Io(ref e) => e.description(),
+            &Error::Format{ ref message,.. } => &**message,
+            &Error::UnsupportedArchitecture(_) => "unsupported architecture",
+            &Error::Decode{ ref message,.. } => &**message,
+            &Error::Analysis(ref s) => &**s,
+            &Error::Serialization{ ref message,.. } => &**message,
+            &Error::Generic(ref s) => &**s,
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match self {
+            &Error::Io(ref e) => Some(e),
+            &Error::Serialization{ source: Some(ref e),.. } => Some(&**e),
+            _ => None,
         }
     }
 }
 
 impl From<String> for Error {
     fn from(s: String) -> Error {
-        Error(Cow::Owned(s))
+        Error::Generic(Cow::Owned(s))
     }
 }
 
 impl From<&'static str> for Error {
     fn from(s: &'static str) -> Error {
-        Error(Cow::Borrowed(s))
+        Error::Generic(Cow::Borrowed(s))
     }
 }
 
 impl From<Cow<'static,str>> for Error {
     fn from(s: Cow<'static,str>) -> Error {
-        Error(s)
+        Error::Generic(s)
     }
 }
 
 impl<T> From<PoisonError<T>> for Error {
     fn from(_: PoisonError<T>) -> Error {
-        Error(Cow::Borrowed("Lock poisoned"))
+        Error::Generic(Cow::Borrowed("Lock poisoned"))
     }
 }
 
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Error {
-        Error(Cow::Owned(format!("I/O error: {:?}",e)))
+        Error::Io(e)
     }
 }
 
 impl From<DecoderError> for Error {
     fn from(e: DecoderError) -> Error {
-        Error(Cow::Owned(format!("JSON decoder error: {}",e)))
+        Error::Serialization{ message: Cow::Borrowed("JSON decoder error"), source: Some(Box::new(e)) }
     }
 }
 
 impl From<EncoderError> for Error {
     fn from(e: EncoderError) -> Error {
-        Error(Cow::Owned(format!("JSON encoder error: {}",e)))
+        Error::Serialization{ message: Cow::Borrowed("JSON encoder error"), source: Some(Box::new(e)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as StdError;
+    use std::io;
+    use rustc_serialize::json;
+
+    #[test]
+    fn conversions() {
+        let e: Error = "test".into();
+        assert_eq!(format!("{}",e),"test");
+        assert!(match e { Error::Generic(_) => true, _ => false });
+
+        let e: Error = format!("test {}",1).into();
+        assert_eq!(e.description(),"test 1");
+
+        let e: Error = io::Error::new(io::ErrorKind::UnexpectedEof,"short read").into();
+        assert!(match e { Error::Io(ref e) => e.kind() == io::ErrorKind::UnexpectedEof, _ => false });
+        assert!(e.cause().is_some());
+
+        let e: Error = json::decode::<u32>("\"x\"").unwrap_err().into();
+        assert!(match e { Error::Serialization{ source: Some(_),.. } => true, _ => false });
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(Error::decode(0x1000,"Unknown instruction").address(),Some(0x1000));
+        assert_eq!(format!("{}",Error::decode(0x1000,"Unknown instruction")),"Unknown instruction (at 0x1000)");
+        assert_eq!(Error::format_at(12,"truncated header").address(),Some(12));
+        assert_eq!(Error::format("truncated header").address(),None);
+        assert_eq!(Error::analysis("no entry point").address(),None);
+        assert_eq!(format!("{}",Error::unsupported_architecture("MIPS")),"unsupported architecture: MIPS");
     }
 }
@@ -0,0 +1,552 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! On-disk format of saved projects.
+//!
+//! A session file starts with the magic number "PANOPTICON" followed by the format version as
+//! big endian u32. The rest of the file depends on the version:
+//!
+//! - Version 0: zlib compressed MsgPack encoding of a `Project` without import stubs.
+//! - Version 1: zlib compressed MsgPack encoding of `Project`.
+//! - Version 2: length prefixed (big endian u32), uncompressed MsgPack encoding of `Metadata`,
+//!   followed by the same data as version 1.
+//! - Version 3: same as version 2, `Project` gained the undo/redo `History`.
+//! - Version 4: same as version 3, `OpaqueLayer` gained the file backed `Mapped` variant. Version
+//!   3 files are valid version 4 files.
+//! - Version 5: same as version 4, `Function` gained the recovered stack `frame`.
+//!
+//! The MsgPack encoding follows the layout of the Rust structures, so changing `Project` or any
+//! type it contains requires a new version. The old layout is kept as a private `...Vn` struct
+//! and `read` converts it using a `migrate_vn` function. Migrations are chained, a file is
+//! upgraded one version at a time until it reaches `FORMAT_VERSION`. Files from a newer version
+//! of Panopticon are rejected with an error instead of being decoded as garbage.
+
+use std::path::Path;
+use std::fs::{self,File};
+use std::ffi::OsString;
+use std::io::{Read,Write};
+use std::collections::HashMap;
+
+use uuid::Uuid;
+use graph_algos::{
+    AdjacencyList,
+    GraphTrait,
+    VertexListGraphTrait,
+    EdgeListGraphTrait,
+    MutableGraphTrait,
+};
+use rmp_serialize::{Encoder,Decoder};
+use rustc_serialize::{Decodable,Encodable};
+use flate2::write::ZlibEncoder;
+use flate2::read::ZlibDecoder;
+use flate2::Compression;
+use byteorder::{
+    ReadBytesExt,
+    WriteBytesExt,
+    BigEndian,
+};
+
+use project::Project;
+use program::{Program,CallGraph,CallTarget};
+use function::{Function,ControlFlowGraph,ControlFlowRef};
+use history::{History,Edit};
+use il::Rvalue;
+use region::World;
+use result::{Result,Error};
+
+/// Magic number every session file starts with.
+pub const MAGIC: &'static [u8; 10] = b"PANOPTICON";
+/// Version written by `write`. `read` accepts this and all earlier versions.
+pub const FORMAT_VERSION: u32 = 5;
+/// Upper bound for the size of the metadata block. Anything larger is a corrupted file.
+const MAX_METADATA_SIZE: u32 = 0x10_0000;
+
+/// Fixed size start of a session file.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct Header {
+    /// Format version the file was written with
+    pub version: u32,
+}
+
+impl Header {
+    /// True if this version of Panopticon can read the file.
+    pub fn is_supported(&self) -> bool {
+        self.version <= FORMAT_VERSION
+    }
+}
+
+/// Summary of a saved project. Stored in front of the compressed project data so that it can be
+/// read without decoding the whole file.
+#[derive(RustcDecodable,RustcEncodable,Clone,Debug,PartialEq)]
+pub struct Metadata {
+    /// Human-readable name of the project
+    pub name: String,
+    /// Panopticon version that wrote the file
+    pub generator: String,
+    /// Names of all programs
+    pub programs: Vec<String>,
+    /// Names of all memory regions, sorted
+    pub regions: Vec<String>,
+    /// Total number of disassembled functions
+    pub functions: usize,
+    /// Number of comments
+    pub comments: usize,
+}
+
+impl Metadata {
+    /// Summarizes `proj`.
+    pub fn new(proj: &Project) -> Metadata {
+        let functions = proj.code.iter().map(|prog| {
+            prog.call_graph.vertices().filter(|&vx| match prog.call_graph.vertex_label(vx) {
+                Some(&CallTarget::Concrete(_)) => true,
+                _ => false,
+            }).count()
+        }).sum();
+
+        let mut regions = proj.data.dependencies.vertices().filter_map(|vx| {
+            proj.data.dependencies.vertex_label(vx).map(|r| r.name().clone())
+        }).collect::<Vec<_>>();
+
+        regions.sort();
+
+        Metadata{
+            name: proj.name.clone(),
+            generator: format!("panopticon {}",env!("CARGO_PKG_VERSION")),
+            programs: proj.code.iter().map(|x| x.name.clone()).collect(),
+            regions: regions,
+            functions: functions,
+            comments: proj.comments.len(),
+        }
+    }
+}
+
+/// `Function` as saved by versions 0 to 4, before stack frames were recovered. Also used by
+/// journals of these versions.
+#[derive(RustcDecodable)]
+pub struct FunctionV4 {
+    uuid: Uuid,
+    name: String,
+    cflow_graph: ControlFlowGraph,
+    entry_point: Option<ControlFlowRef>,
+    region: String,
+}
+
+impl From<FunctionV4> for Function {
+    fn from(f: FunctionV4) -> Function {
+        Function{
+            uuid: f.uuid,
+            name: f.name,
+            cflow_graph: f.cflow_graph,
+            entry_point: f.entry_point,
+            region: f.region,
+            frame: None,
+        }
+    }
+}
+
+/// `CallTarget` as saved by versions 0 to 4.
+#[derive(RustcDecodable)]
+enum CallTargetV4 {
+    Concrete(FunctionV4),
+    Symbolic(String,Uuid),
+    Todo(Rvalue,Option<String>,Uuid),
+}
+
+/// `CallGraph` as saved by versions 0 to 4.
+type CallGraphV4 = AdjacencyList<CallTargetV4,()>;
+
+/// `Program` as saved by versions 1 to 4.
+#[derive(RustcDecodable)]
+struct ProgramV4 {
+    uuid: Uuid,
+    name: String,
+    call_graph: CallGraphV4,
+    imports: HashMap<u64,String>,
+}
+
+/// `Edit` as saved by versions 3 and 4.
+#[derive(RustcDecodable)]
+enum EditV4 {
+    Rename{ function: Uuid, name: String },
+    Comment{ region: String, offset: u64, comment: Option<String> },
+    CreateFunction{ program: Uuid, function: FunctionV4 },
+    DeleteFunction{ program: Uuid, function: Uuid },
+    ReplaceFunction{ program: Uuid, function: FunctionV4 },
+}
+
+/// `History` as saved by versions 3 and 4.
+#[derive(RustcDecodable)]
+struct HistoryV4 {
+    undo: Vec<EditV4>,
+    redo: Vec<EditV4>,
+}
+
+/// `Project` as saved by versions 3 and 4.
+#[derive(RustcDecodable)]
+struct ProjectV4 {
+    name: String,
+    code: Vec<ProgramV4>,
+    data: World,
+    comments: HashMap<(String,u64),String>,
+    history: HistoryV4,
+}
+
+/// `Program` as saved by version 0, before import stubs were recorded.
+#[derive(RustcDecodable)]
+struct ProgramV0 {
+    uuid: Uuid,
+    name: String,
+    call_graph: CallGraphV4,
+}
+
+/// `Project` as saved by version 0.
+#[derive(RustcDecodable)]
+struct ProjectV0 {
+    name: String,
+    code: Vec<ProgramV0>,
+    data: World,
+    comments: HashMap<(String,u64),String>,
+}
+
+/// `Project` as saved by versions 1 and 2, before the edit history was recorded.
+#[derive(RustcDecodable)]
+struct ProjectV1 {
+    name: String,
+    code: Vec<ProgramV4>,
+    data: World,
+    comments: HashMap<(String,u64),String>,
+}
+
+/// Version 0 to 1: Programs gained a map of import stubs. Old files have none.
+fn migrate_v0(p: ProjectV0) -> ProjectV1 {
+    ProjectV1{
+        name: p.name,
+        code: p.code.into_iter().map(|x| ProgramV4{
+            uuid: x.uuid,
+            name: x.name,
+            call_graph: x.call_graph,
+            imports: HashMap::new(),
+        }).collect(),
+        data: p.data,
+        comments: p.comments,
+    }
+}
+
+/// Version 1 to 2: Only the metadata block was added, the project data is unchanged.
+fn migrate_v1(p: ProjectV1) -> ProjectV1 {
+    p
+}
+
+/// Version 2 to 3: Projects gained an undo/redo history. Old files start with an empty one.
+fn migrate_v2(p: ProjectV1) -> ProjectV4 {
+    ProjectV4{
+        name: p.name,
+        code: p.code,
+        data: p.data,
+        comments: p.comments,
+        history: HistoryV4{ undo: vec![], redo: vec![] },
+    }
+}
+
+/// Converts the call graph of a version 4 program. Vertex descriptors are not stable across the
+/// conversion, the edges are re-added between the new vertices.
+fn migrate_call_graph(mut g: CallGraphV4) -> CallGraph {
+    let edges = g.edges().map(|e| (g.source(e),g.target(e))).collect::<Vec<_>>();
+    let vertices = g.vertices().collect::<Vec<_>>();
+    let mut ret = CallGraph::new();
+    let mut map = HashMap::new();
+
+    for vx in vertices {
+        let ct = match g.remove_vertex(vx) {
+            Some(CallTargetV4::Concrete(f)) => CallTarget::Concrete(f.into()),
+            Some(CallTargetV4::Symbolic(n,uu)) => CallTarget::Symbolic(n,uu),
+            Some(CallTargetV4::Todo(a,n,uu)) => CallTarget::Todo(a,n,uu),
+            None => continue,
+        };
+
+        map.insert(vx,ret.add_vertex(ct));
+    }
+
+    for (from,to) in edges {
+        if let (Some(&from),Some(&to)) = (map.get(&from),map.get(&to)) {
+            ret.add_edge((),from,to);
+        }
+    }
+
+    ret
+}
+
+fn migrate_edit(e: EditV4) -> Edit {
+    match e {
+        EditV4::Rename{ function, name } => Edit::Rename{ function: function, name: name },
+        EditV4::Comment{ region, offset, comment } => Edit::Comment{ region: region, offset: offset, comment: comment },
+        EditV4::CreateFunction{ program, function } => Edit::CreateFunction{ program: program, function: function.into() },
+        EditV4::DeleteFunction{ program, function } => Edit::DeleteFunction{ program: program, function: function },
+        EditV4::ReplaceFunction{ program, function } => Edit::ReplaceFunction{ program: program, function: function.into() },
+    }
+}
+
+/// Version 4 to 5: Functions gained a stack frame. Old files have none, it's recovered the next
+/// time the function is disassembled.
+fn migrate_v4(p: ProjectV4) -> Project {
+    Project{
+        name: p.name,
+        code: p.code.into_iter().map(|x| Program{
+            uuid: x.uuid,
+            name: x.name,
+            call_graph: migrate_call_graph(x.call_graph),
+            imports: x.imports,
+        }).collect(),
+        data: p.data,
+        comments: p.comments,
+        history: History{
+            undo: p.history.undo.into_iter().map(migrate_edit).collect(),
+            redo: p.history.redo.into_iter().map(migrate_edit).collect(),
+        },
+    }
+}
+
+fn decode<T: Decodable, R: Read>(r: R) -> Result<T> {
+    let mut z = ZlibDecoder::new(r);
+    let mut rmp = Decoder::new(&mut z);
+
+    match T::decode(&mut rmp) {
+        Ok(t) => Ok(t),
+        Err(e) => Err(Error::serialization(format!("project decoding failed: {:?}",e))),
+    }
+}
+
+fn read_header_from<R: Read>(r: &mut R) -> Result<Header> {
+    let mut magic = [0u8; 10];
+
+    if r.read_exact(&mut magic).is_err() || magic != *MAGIC {
+        return Err(Error::format_at(0,"wrong magic number"));
+    }
+
+    let version = try!(r.read_u32::<BigEndian>());
+
+    Ok(Header{ version: version })
+}
+
+fn check_version(hdr: &Header) -> Result<()> {
+    if hdr.is_supported() {
+        Ok(())
+    } else {
+        Err(Error::format(format!("file was saved with a newer version of Panopticon (format version {}, this version supports up to {})",hdr.version,FORMAT_VERSION)))
+    }
+}
+
+fn read_metadata_from<R: Read>(r: &mut R) -> Result<Metadata> {
+    let len = try!(r.read_u32::<BigEndian>());
+
+    if len > MAX_METADATA_SIZE {
+        return Err(Error::format(format!("metadata block too large: {} bytes",len)));
+    }
+
+    let mut buf = vec![0u8; len as usize];
+
+    try!(r.read_exact(&mut buf));
+
+    let mut rmp = Decoder::new(&buf[..]);
+
+    match Metadata::decode(&mut rmp) {
+        Ok(m) => Ok(m),
+        Err(e) => Err(Error::serialization(format!("metadata decoding failed: {:?}",e))),
+    }
+}
+
+/// Reads the header of the session file at `p`.
+pub fn read_header(p: &Path) -> Result<Header> {
+    let mut fd = try!(File::open(p));
+
+    read_header_from(&mut fd)
+}
+
+/// Reads the metadata of the session file at `p`. Files written before version 2 have no
+/// metadata block. These are decoded completely and the metadata is computed from the project.
+pub fn read_metadata(p: &Path) -> Result<Metadata> {
+    let mut fd = try!(File::open(p));
+    let hdr = try!(read_header_from(&mut fd));
+
+    try!(check_version(&hdr));
+
+    if hdr.version >= 2 {
+        read_metadata_from(&mut fd)
+    } else {
+        read(p).map(|proj| Metadata::new(&proj))
+    }
+}
+
+/// Reads the session file at `p` and migrates it to the current version.
+pub fn read(p: &Path) -> Result<Project> {
+    let mut fd = try!(File::open(p));
+    let hdr = try!(read_header_from(&mut fd));
+
+    try!(check_version(&hdr));
+
+    if hdr.version >= 2 {
+        try!(read_metadata_from(&mut fd));
+    }
+
+    let proj = match hdr.version {
+        0 => migrate_v4(migrate_v2(migrate_v1(migrate_v0(try!(decode::<ProjectV0,_>(fd)))))),
+        1 => migrate_v4(migrate_v2(migrate_v1(try!(decode::<ProjectV1,_>(fd))))),
+        2 => migrate_v4(migrate_v2(try!(decode::<ProjectV1,_>(fd)))),
+        3 | 4 => migrate_v4(try!(decode::<ProjectV4,_>(fd))),
+        _ => try!(decode::<Project,_>(fd)),
+    };
+
+    Ok(proj)
+}
+
+fn write_to(proj: &Project, mut fd: File) -> Result<()> {
+    let mut meta = vec![];
+
+    if Metadata::new(proj).encode(&mut Encoder::new(&mut meta)).is_err() {
+        return Err(Error::serialization("failed to encode metadata"));
+    }
+
+    try!(fd.write_all(MAGIC));
+    try!(fd.write_u32::<BigEndian>(FORMAT_VERSION));
+    try!(fd.write_u32::<BigEndian>(meta.len() as u32));
+    try!(fd.write_all(&meta));
+
+    let mut z = ZlibEncoder::new(fd,Compression::Default);
+
+    if proj.encode(&mut Encoder::new(&mut z)).is_err() {
+        return Err(Error::serialization("failed to write to save file"));
+    }
+
+    try!(try!(z.finish()).sync_all());
+    Ok(())
+}
+
+/// Writes `proj` to `p` using the current format version. The data is written to a temporary
+/// file next to `p` first, which then replaces `p`. If writing fails the old file is left
+/// untouched.
+pub fn write(proj: &Project, p: &Path) -> Result<()> {
+    let mut name = p.file_name().map(|x| x.to_os_string()).unwrap_or(OsString::new());
+
+    name.push(".tmp");
+
+    let tmp = p.with_file_name(name);
+    let res = File::create(&tmp).map_err(|e| e.into()).and_then(|fd| {
+        write_to(proj,fd)
+    }).and_then(|_| {
+        fs::rename(&tmp,p).map_err(|e| e.into())
+    });
+
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::fs::File;
+    use std::io::Write;
+    use byteorder::{WriteBytesExt,BigEndian};
+    use tempdir::TempDir;
+    use project::Project;
+    use program::Program;
+    use region::Region;
+    use history::Edit;
+
+    #[test]
+    fn header() {
+        assert_eq!(read_header(Path::new("tests/data/save.panop")).unwrap(),Header{ version: 0 });
+        assert!(read_header(Path::new("tests/data/empty.panop")).is_err());
+        assert!(read_header(Path::new("tests/data/sosse")).is_err());
+    }
+
+    #[test]
+    fn legacy_metadata() {
+        let meta = read_metadata(Path::new("tests/data/save.panop")).unwrap();
+
+        assert_eq!(meta.name,"sosse");
+        assert_eq!(meta.programs.len(),1);
+        assert!(meta.functions > 0);
+    }
+
+    #[test]
+    fn write_and_read() {
+        let tmp = TempDir::new("panop-session").unwrap();
+        let path = tmp.path().join("test.panop");
+        let mut proj = Project::new("test".to_string(),Region::undefined("base".to_string(),128));
+
+        proj.code.push(Program::new("prog0"));
+        proj.edit(Edit::Comment{ region: "base".to_string(), offset: 1, comment: Some("test".to_string()) }).unwrap();
+        write(&proj,&path).unwrap();
+
+        assert_eq!(read_header(&path).unwrap(),Header{ version: FORMAT_VERSION });
+
+        let meta = read_metadata(&path).unwrap();
+
+        assert_eq!(meta,Metadata::new(&proj));
+        assert_eq!(meta.regions,vec!["base".to_string()]);
+        assert_eq!(meta.comments,1);
+
+        let q = read(&path).unwrap();
+
+        assert_eq!(q.name,"test");
+        assert_eq!(q.code.len(),1);
+        assert_eq!(q.history.undo.len(),1);
+    }
+
+    #[test]
+    fn write_replaces_file() {
+        let tmp = TempDir::new("panop-session").unwrap();
+        let path = tmp.path().join("test.panop");
+        let proj = Project::new("test".to_string(),Region::undefined("base".to_string(),128));
+
+        File::create(&path).unwrap().write_all(b"old contents").unwrap();
+        write(&proj,&path).unwrap();
+
+        assert_eq!(read(&path).unwrap().name,"test");
+        assert!(!tmp.path().join("test.panop.tmp").exists());
+
+        // writing into a missing directory fails without leaving anything behind
+        assert!(write(&proj,&tmp.path().join("missing").join("test.panop")).is_err());
+        assert_eq!(read(&path).unwrap().name,"test");
+    }
+
+    #[test]
+    fn newer_version() {
+        let tmp = TempDir::new("panop-session").unwrap();
+        let path = tmp.path().join("future.panop");
+
+        {
+            let mut fd = File::create(&path).unwrap();
+
+            fd.write_all(MAGIC).unwrap();
+            fd.write_u32::<BigEndian>(FORMAT_VERSION + 1).unwrap();
+            fd.write_all(b"whatever comes next").unwrap();
+        }
+
+        let hdr = read_header(&path).unwrap();
+
+        assert_eq!(hdr.version,FORMAT_VERSION + 1);
+        assert!(!hdr.is_supported());
+        assert!(read_metadata(&path).is_err());
+        assert!(read(&path).is_err());
+    }
+}
@@ -0,0 +1,28 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Helpers shared by the unit tests.
+
+use std::borrow::Cow;
+
+use Lvalue;
+
+/// Variable `n` of `size` bits without SSA subscript.
+pub fn var(n: &'static str, size: usize) -> Lvalue {
+    Lvalue::Variable{ name: Cow::Borrowed(n), size: size, subscript: None }
+}
@@ -0,0 +1,355 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+//! Cross references between code and data.
+//!
+//! An `XrefIndex` records every place a function calls, jumps to, reads or writes a constant
+//! address and answers the question "who references this address". Calls are taken from
+//! `Operation::Call` statements, jumps from the edges of the control flow graph and reads and
+//! writes from `Operation::Load` and `Operation::Store` statements with a constant operand.
+//! Fall-through edges between adjacent basic blocks are not references.
+//!
+//! References are keyed by the memory region of the referencing function and the target address.
+//! All targets are assumed to be in the same region as the function.
+//!
+//! The index is not saved with the project. It's built with `XrefIndex::new` and kept up to
+//! date by calling `insert_function` and `remove_function` whenever a function is disassembled,
+//! changed or deleted.
+
+use std::collections::{BTreeMap,HashMap};
+use std::ops::Range;
+
+use graph_algos::{GraphTrait,VertexListGraphTrait,EdgeListGraphTrait};
+use uuid::Uuid;
+
+use project::Project;
+use program::CallTarget;
+use function::{Function,ControlFlowTarget};
+use il::{Statement,Operation,Rvalue};
+
+/// Type of reference.
+#[derive(Clone,Copy,PartialEq,Eq,Debug,RustcEncodable,RustcDecodable)]
+pub enum XrefKind {
+    /// Function call
+    Call,
+    /// Jump or branch
+    Jump,
+    /// Memory read
+    Read,
+    /// Memory write
+    Write,
+}
+
+/// Reference from an instruction to an address.
+#[derive(Clone,PartialEq,Eq,Debug,RustcEncodable,RustcDecodable)]
+pub struct Xref {
+    /// Type of reference
+    pub kind: XrefKind,
+    /// Address of the referencing instruction
+    pub source: u64,
+    /// Referenced address
+    pub target: u64,
+    /// Function the referencing instruction is part of
+    pub function: Uuid,
+    /// Memory region of the function
+    pub region: String,
+}
+
+/// Index of all cross references in a project, queryable by referenced address.
+#[derive(Clone,Debug)]
+pub struct XrefIndex {
+    by_target: BTreeMap<(String,u64),Vec<Xref>>,
+    by_function: HashMap<Uuid,Vec<(String,u64)>>,
+}
+
+/// Returns the constant value of `v`, if it has one.
+fn constant(v: &Rvalue) -> Option<u64> {
+    match v {
+        &Rvalue::Constant{ value,.. } => Some(value),
+        _ => None,
+    }
+}
+
+/// Returns all references made by `func`, sorted by source address.
+pub fn collect_xrefs(func: &Function) -> Vec<Xref> {
+    let mut ret = vec![];
+    let xref = |kind,source,target| Xref{ kind: kind, source: source, target: target, function: func.uuid, region: func.region.clone() };
+
+    for (source,t) in func.collect_call_sites() {
+        if let Some(a) = constant(&t) {
+            ret.push(xref(XrefKind::Call,source,a));
+        }
+    }
+
+    for vx in func.cflow_graph.vertices() {
+        if let Some(&ControlFlowTarget::Resolved(ref bb)) = func.cflow_graph.vertex_label(vx) {
+            for mne in bb.mnemonics.iter() {
+                for stmt in mne.instructions.iter() {
+                    match stmt {
+                        &Statement{ op: Operation::Load(_,ref t),.. } =>
+                            if let Some(a) = constant(t) { ret.push(xref(XrefKind::Read,mne.area.start,a)) },
+                        &Statement{ op: Operation::Store(_,ref t),.. } =>
+                            if let Some(a) = constant(t) { ret.push(xref(XrefKind::Write,mne.area.start,a)) },
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    for e in func.cflow_graph.edges() {
+        let from = match func.cflow_graph.vertex_label(func.cflow_graph.source(e)) {
+            Some(&ControlFlowTarget::Resolved(ref bb)) => bb,
+            _ => continue,
+        };
+        let to = match func.cflow_graph.vertex_label(func.cflow_graph.target(e)) {
+            Some(&ControlFlowTarget::Resolved(ref bb)) => Some(bb.area.start),
+            Some(&ControlFlowTarget::Unresolved(ref v)) => constant(v),
+            _ => None,
+        };
+
+        if let (Some(to),Some(last)) = (to,from.mnemonics.last()) {
+            if to != from.area.end {
+                ret.push(xref(XrefKind::Jump,last.area.start,to));
+            }
+        }
+    }
+
+    ret.sort_by(|a,b| (a.source,a.target).cmp(&(b.source,b.target)));
+    ret
+}
+
+impl XrefIndex {
+    /// Indexes all functions in `proj`.
+    pub fn new(proj: &Project) -> XrefIndex {
+        let mut ret = XrefIndex::empty();
+
+        for prog in proj.code.iter() {
+            for vx in prog.call_graph.vertices() {
+                if let Some(&CallTarget::Concrete(ref f)) = prog.call_graph.vertex_label(vx) {
+                    ret.insert_function(f);
+                }
+            }
+        }
+
+        ret
+    }
+
+    /// Returns an index without any references.
+    pub fn empty() -> XrefIndex {
+        XrefIndex{
+            by_target: BTreeMap::new(),
+            by_function: HashMap::new(),
+        }
+    }
+
+    /// Adds all references made by `func`. References from an older version of the same
+    /// function are removed first.
+    pub fn insert_function(&mut self, func: &Function) {
+        self.remove_function(&func.uuid);
+
+        let xrefs = collect_xrefs(func);
+        let mut targets = xrefs.iter().map(|x| (x.region.clone(),x.target)).collect::<Vec<_>>();
+
+        targets.sort();
+        targets.dedup();
+
+        for x in xrefs {
+            self.by_target.entry((x.region.clone(),x.target)).or_insert(vec![]).push(x);
+        }
+
+        if !targets.is_empty() {
+            self.by_function.insert(func.uuid,targets);
+        }
+    }
+
+    /// Removes all references made by the function with UUID `uu`.
+    pub fn remove_function(&mut self, uu: &Uuid) {
+        if let Some(targets) = self.by_function.remove(uu) {
+            for t in targets {
+                let empty = match self.by_target.get_mut(&t) {
+                    Some(v) => {
+                        v.retain(|x| x.function != *uu);
+                        v.is_empty()
+                    },
+                    None => false,
+                };
+
+                if empty {
+                    self.by_target.remove(&t);
+                }
+            }
+        }
+    }
+
+    /// Returns all references to `addr` in memory region `region`.
+    pub fn to(&self, region: &str, addr: u64) -> &[Xref] {
+        self.by_target.get(&(region.to_string(),addr)).map(|v| &v[..]).unwrap_or(&[])
+    }
+
+    /// Returns all references to addresses in `range` of memory region `region`, sorted by
+    /// target.
+    pub fn to_range(&self, region: &str, range: Range<u64>) -> Vec<&Xref> {
+        self.by_target.iter()
+            .skip_while(|&(&(ref r,t),_)| &r[..] < region || (r == region && t < range.start))
+            .take_while(|&(&(ref r,t),_)| r == region && t < range.end)
+            .flat_map(|(_,v)| v.iter())
+            .collect()
+    }
+
+    /// Returns all calls to `addr` in memory region `region`.
+    pub fn callers(&self, region: &str, addr: u64) -> Vec<&Xref> {
+        self.to(region,addr).iter().filter(|x| x.kind == XrefKind::Call).collect()
+    }
+
+    /// Returns all references made by the function with UUID `uu`.
+    pub fn from_function(&self, uu: &Uuid) -> Vec<&Xref> {
+        match self.by_function.get(uu) {
+            Some(targets) => targets.iter()
+                .flat_map(|&(ref r,t)| self.to(r,t).iter())
+                .filter(|x| x.function == *uu)
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Number of references in the index.
+    pub fn len(&self) -> usize {
+        self.by_target.values().fold(0,|acc,v| acc + v.len())
+    }
+
+    /// True if the index has no references.
+    pub fn is_empty(&self) -> bool {
+        self.by_target.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use graph_algos::MutableGraphTrait;
+    use {
+        Project,
+        Program,
+        Function,
+        CallTarget,
+        ControlFlowTarget,
+        BasicBlock,
+        Mnemonic,
+        Region,
+        Statement,
+        Operation,
+        Rvalue,
+        Lvalue,
+        Guard,
+    };
+    use test_util::var;
+
+    fn mnemonic(start: u64, end: u64, stmts: Vec<Statement>) -> Mnemonic {
+        Mnemonic::new(start..end,"test".to_string(),"".to_string(),vec![].iter(),stmts.iter()).unwrap()
+    }
+
+    // 0: call 0x100; load [0x200]
+    // 2: jump 8 (conditional), falls through to 4
+    // 4: store [0x202]
+    // 8: ret
+    fn function() -> Function {
+        let mut func = Function::new("main".to_string(),"base".to_string());
+        let bb0 = BasicBlock::from_vec(vec![
+            mnemonic(0,1,vec![Statement{ op: Operation::Call(Rvalue::new_u16(0x100)), assignee: Lvalue::Undefined }]),
+            mnemonic(1,2,vec![Statement{ op: Operation::Load(Cow::Borrowed("ram"),Rvalue::new_u16(0x200)), assignee: var("a",16) }]),
+            mnemonic(2,4,vec![]),
+        ]);
+        let bb1 = BasicBlock::from_vec(vec![
+            mnemonic(4,8,vec![Statement{ op: Operation::Store(Cow::Borrowed("ram"),Rvalue::new_u16(0x202)), assignee: var("a",16) }]),
+        ]);
+        let bb2 = BasicBlock::from_vec(vec![mnemonic(8,9,vec![])]);
+        let vx0 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(bb0));
+        let vx1 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(bb1));
+        let vx2 = func.cflow_graph.add_vertex(ControlFlowTarget::Resolved(bb2));
+
+        func.cflow_graph.add_edge(Guard::always(),vx0,vx1);
+        func.cflow_graph.add_edge(Guard::always(),vx0,vx2);
+        func.cflow_graph.add_edge(Guard::always(),vx1,vx2);
+        func.entry_point = Some(vx0);
+        func
+    }
+
+    #[test]
+    fn collect() {
+        let func = function();
+        let xrefs = collect_xrefs(&func);
+        let summary = xrefs.iter().map(|x| (x.kind,x.source,x.target)).collect::<Vec<_>>();
+
+        assert_eq!(summary,vec![
+            (XrefKind::Call,0,0x100),
+            (XrefKind::Read,1,0x200),
+            (XrefKind::Jump,2,8),
+            (XrefKind::Write,4,0x202),
+        ]);
+        assert!(xrefs.iter().all(|x| x.function == func.uuid));
+    }
+
+    #[test]
+    fn index() {
+        let mut proj = Project::new("test".to_string(),Region::undefined("base".to_string(),0x1000));
+        let mut prog = Program::new("prog0");
+        let func = function();
+        let uu = func.uuid;
+
+        prog.call_graph.add_vertex(CallTarget::Concrete(func));
+        proj.code.push(prog);
+
+        let mut idx = XrefIndex::new(&proj);
+
+        assert_eq!(idx.len(),4);
+        assert_eq!(idx.callers("base",0x100).len(),1);
+        assert_eq!(idx.to("base",0x200)[0].kind,XrefKind::Read);
+        assert!(idx.to("base",4).is_empty());
+        assert_eq!(idx.to_range("base",0x200..0x300).len(),2);
+        assert_eq!(idx.from_function(&uu).len(),4);
+
+        // re-inserting a function replaces its references
+        let mut other = function();
+        other.uuid = uu;
+        idx.insert_function(&other);
+        assert_eq!(idx.len(),4);
+
+        let second = function();
+        idx.insert_function(&second);
+        assert_eq!(idx.callers("base",0x100).len(),2);
+
+        // same addresses in a different region
+        let mut third = function();
+        third.region = "other".to_string();
+        idx.insert_function(&third);
+        assert_eq!(idx.callers("base",0x100).len(),2);
+        assert_eq!(idx.callers("other",0x100).len(),1);
+        assert_eq!(idx.to_range("base",0..0x1000).len(),8);
+        idx.remove_function(&third.uuid);
+
+        idx.remove_function(&uu);
+        assert_eq!(idx.len(),4);
+        assert!(idx.from_function(&uu).is_empty());
+        assert_eq!(idx.callers("base",0x100)[0].function,second.uuid);
+
+        idx.remove_function(&second.uuid);
+        assert!(idx.is_empty());
+    }
+}
@@ -38,20 +38,17 @@ Ctrl.ApplicationWindow {
 				}
 				break;
 			}
-			case "elf": {
-				var res = JSON.parse(Panopticon.createElfProject(req.path))
-				if(res.status == "err") {
-					console.exception(res.error);
-				}
-				break;
-			}
-			case "pe": {
-				var res = JSON.parse(Panopticon.createPeProject(req.path))
+			case "elf":
+			case "pe":
+			case "macho": {
+				var res = JSON.parse(Panopticon.createProject(req.path))
 				if(res.status == "err") {
 					console.exception(res.error);
 				}
 				break;
 			}
+			case "ihex":
+			case "srec":
 			case "raw": {
 				targetSelect.visible = true;
 				break;
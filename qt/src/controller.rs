@@ -23,7 +23,6 @@ use std::collections::BTreeMap;
 use std::path::{PathBuf,Path};
 use std::iter::FromIterator;
 use std::fs::{remove_file,DirBuilder};
-use std::error::Error;
 use std::borrow::Cow;
 use std::convert::Into;
 
@@ -31,11 +30,15 @@ use libc::c_int;
 use qmlrs::{ffi,MetaObject,Variant,Object,ToQVariant,unpack_varlist};
 use rustc_serialize::{json,Encodable};
 use tempdir::TempDir;
+use uuid::Uuid;
 
 use panopticon::result;
 use panopticon::{
     Project,
     Result,
+    Change,
+    Journal,
+    XrefIndex,
 };
 
 use project;
@@ -49,6 +52,7 @@ extern "C" fn controller_slot(_: *mut ffi::QObject, id: c_int, a: *const ffi::QV
         (CREATE_ELF_PROJECT,1) => project::create_elf_project(&args[0]).to_qvariant(ret),
         (CREATE_PE_PROJECT,1) => project::create_pe_project(&args[0]).to_qvariant(ret),
         (OPEN_PROJECT,1) => project::open_project(&args[0]).to_qvariant(ret),
+        (CREATE_PROJECT,1) => project::create_project(&args[0]).to_qvariant(ret),
 
         // State transition: DIRTY -> SYNC
         (SNAPSHOT_PROJECT,1) => project::snapshot_project(&args[0]).to_qvariant(ret),
@@ -75,6 +79,18 @@ extern "C" fn controller_slot(_: *mut ffi::QObject, id: c_int, a: *const ffi::QV
         // Session handling
         (SESSIONS,0) => ::function::sessions().to_qvariant(ret),
         (DELETE_SESSION,1) => ::function::delete_session(&args[0]).to_qvariant(ret),
+
+        // Annotation databases
+        (EXPORT_ANNOTATIONS,1) => ::function::export_annotations(&args[0]).to_qvariant(ret),
+        (IMPORT_ANNOTATIONS,2) => ::function::import_annotations(&args[0],&args[1]).to_qvariant(ret),
+
+        // Edit history. State transitions: SYNC -> DIRTY or DIRTY -> DIRTY
+        (UNDO,0) => ::function::undo().to_qvariant(ret),
+        (REDO,0) => ::function::redo().to_qvariant(ret),
+
+        // Cross references
+        (XREFS_TO_ADDRESS,1) => ::function::xrefs_to_address(&args[0]).to_qvariant(ret),
+        (XREFS_TO_FUNCTION,1) => ::function::xrefs_to_function(&args[0]).to_qvariant(ret),
         _ => panic!("Unknown controller call id '{}' with {} arguments.",id,args.len())
     }
 }
@@ -114,6 +130,13 @@ pub const SET_REQUEST: isize = 22;
 pub const SESSIONS: isize = 23;
 pub const DELETE_SESSION: isize = 24;
 pub const FIND_DATA_FILE: isize = 25;
+pub const CREATE_PROJECT: isize = 26;
+pub const EXPORT_ANNOTATIONS: isize = 27;
+pub const IMPORT_ANNOTATIONS: isize = 28;
+pub const UNDO: isize = 29;
+pub const REDO: isize = 30;
+pub const XREFS_TO_ADDRESS: isize = 31;
+pub const XREFS_TO_FUNCTION: isize = 32;
 
 pub extern "C" fn create_singleton(_: *mut ffi::QQmlEngine, _: *mut ffi::QJSEngine) -> *mut ffi::QObject {
     let mut metaobj = MetaObject::new("Panopticon",controller_slot);
@@ -168,6 +191,18 @@ pub extern "C" fn create_singleton(_: *mut ffi::QQmlEngine, _: *mut ffi::QJSEngi
     assert_eq!(metaobj.add_method("deleteSession(QString)","QString"),DELETE_SESSION);
 
     assert_eq!(metaobj.add_method("findDataFile(QString)","QString"),FIND_DATA_FILE);
+    assert_eq!(metaobj.add_method("createProject(QString)","QString"),CREATE_PROJECT);
+
+    assert_eq!(metaobj.add_method("exportAnnotations(QString)","QString"),EXPORT_ANNOTATIONS);
+    assert_eq!(metaobj.add_method("importAnnotations(QString,QString)","QString"),IMPORT_ANNOTATIONS);
+
+    // state = (WORKING,DONE), dirty = -> true
+    assert_eq!(metaobj.add_method("undo()","QString"),UNDO);
+    assert_eq!(metaobj.add_method("redo()","QString"),REDO);
+
+    // getter
+    assert_eq!(metaobj.add_method("xrefsToAddress(QString)","QString"),XREFS_TO_ADDRESS);
+    assert_eq!(metaobj.add_method("xrefsToFunction(QString)","QString"),XREFS_TO_FUNCTION);
 
     let mut obj = metaobj.instantiate();
 
@@ -197,7 +232,7 @@ pub fn return_json<T: Encodable>(r: Result<T>) -> String {
     match r {
         Ok(t) => json::encode(&Return::<T>{ status: "ok".to_string(), payload: t }),
         Err(e) => json::encode(&BTreeMap::from_iter(vec![("status".to_string(),"err".to_string()),
-                                                        ("error".to_string(),e.description().to_string())])),
+                                                        ("error".to_string(),e.to_string())])),
     }.unwrap_or(format!("{{ \"status\": \"err\", \"error\": \"Failed to render JSON response\"}}"))
 }
 
@@ -238,8 +273,10 @@ pub enum Controller {
         //metaObject: MetaObject,
         singleton_object: Object,
         project: Project,
+        xrefs: XrefIndex,
         backing_file: Backing,
         is_dirty: bool,
+        journal: Option<Journal>,
     },
 }
 
@@ -326,6 +363,18 @@ impl Controller {
         }
     }
 
+    /// Calls `f` with the project and its cross reference index.
+    pub fn read_xrefs<A,F: FnOnce(&Project,&XrefIndex) -> A>(f: F) -> Result<A> {
+        let guard = try!(CONTROLLER.read());
+        if let &Controller::Set{ ref project, ref xrefs,.. } = &*guard {
+            Ok(f(project,xrefs))
+        } else {
+            Err("Controller in wrong state (read_xrefs)".into())
+        }
+    }
+
+    /// Calls `f` with the project and marks it dirty. The cross reference index is not updated,
+    /// `f` must not change the code of functions. Use `record` for that.
     pub fn modify<A,F: FnOnce(&mut Project) -> A>(f: F) -> Result<A> {
         {
             let mut guard = try!(CONTROLLER.write());
@@ -343,10 +392,103 @@ impl Controller {
         })
     }
 
+    /// Like `modify`, for changes that have no journal entry, like undo and redo. If the project
+    /// is journaled, a new snapshot is written instead of marking the project dirty. Otherwise
+    /// replaying the journal after a crash would yield a different project.
+    pub fn modify_and_snapshot<A,F: FnOnce(&mut Project) -> A>(f: F) -> Result<A> {
+        {
+            let mut guard = try!(CONTROLLER.write());
+            if let &mut Controller::Set{ ref mut project, ref mut xrefs, ref mut is_dirty, ref backing_file, ref mut journal,.. } = &mut *guard {
+                let path = backing_file.path();
+                let journaled = !*is_dirty && path.exists();
+                let ret = f(project);
+
+                *xrefs = XrefIndex::new(project);
+
+                if journaled {
+                    // the snapshot removes the journal
+                    *journal = None;
+                    try!(project.snapshot(path));
+                } else {
+                    *is_dirty = true;
+                }
+
+                Ok(ret)
+            } else {
+                Err("Controller in wrong state (modify_and_snapshot)".into())
+            }
+        }.and_then(|a| {
+            try!(Controller::update_state());
+            Ok(a)
+        })
+    }
+
+    /// Applies `c` to the project. If the project was saved before, the change is appended to
+    /// the journal of the backing file instead of marking the project dirty.
+    pub fn record(c: Change) -> Result<Vec<Uuid>> {
+        Controller::record_change(c,true)
+    }
+
+    /// Like `record`, but doesn't wait for the journal entry to reach the disk. Used for results
+    /// of automatic analysis, call `sync_journal` after the last change of a batch.
+    pub fn record_deferred(c: Change) -> Result<Vec<Uuid>> {
+        Controller::record_change(c,false)
+    }
+
+    /// Waits until all journal entries reached the disk.
+    pub fn sync_journal() -> Result<()> {
+        let mut guard = try!(CONTROLLER.write());
+        if let &mut Controller::Set{ journal: Some(ref mut journal),.. } = &mut *guard {
+            try!(journal.sync());
+        }
+        Ok(())
+    }
+
+    fn record_change(c: Change, sync: bool) -> Result<Vec<Uuid>> {
+        {
+            let mut guard = try!(CONTROLLER.write());
+            if let &mut Controller::Set{ ref mut project, ref mut xrefs, ref mut is_dirty, ref backing_file, ref mut journal,.. } = &mut *guard {
+                let path = backing_file.path();
+                let func_uu = if let Change::Function{ ref function,.. } = c { Some(function.uuid) } else { None };
+
+                if !*is_dirty && path.exists() {
+                    if journal.is_none() {
+                        *journal = Some(try!(Journal::open(path)));
+                    }
+
+                    let j = journal.as_mut().unwrap();
+
+                    if sync {
+                        try!(j.append(&c));
+                    } else {
+                        try!(j.write(&c));
+                    }
+                } else {
+                    *is_dirty = true;
+                }
+
+                let ret = try!(project.apply(c));
+
+                if let Some(func) = func_uu.and_then(|uu| project.find_function_by_uuid(&uu)) {
+                    xrefs.insert_function(func);
+                }
+
+                Ok(ret)
+            } else {
+                Err("Controller in wrong state (record)".into())
+            }
+        }.and_then(|a| {
+            try!(Controller::update_state());
+            Ok(a)
+        })
+    }
+
     pub fn sync() -> Result<()> {
         {
             let mut guard = try!(CONTROLLER.write());
-            if let &mut Controller::Set{ ref mut project, ref mut is_dirty, ref backing_file,.. } = &mut *guard {
+            if let &mut Controller::Set{ ref mut project, ref mut is_dirty, ref backing_file, ref mut journal,.. } = &mut *guard {
+                // the snapshot removes the journal
+                *journal = None;
                 try!(project.snapshot(&backing_file.path()));
                 *is_dirty = false;
                 Ok(())
@@ -379,10 +521,12 @@ impl Controller {
             };
 
             match &mut *guard {
-                &mut Controller::Set{ ref mut project, ref mut is_dirty, ref mut backing_file,.. } => {
+                &mut Controller::Set{ ref mut project, ref mut xrefs, ref mut is_dirty, ref mut backing_file, ref mut journal,.. } => {
+                    *xrefs = XrefIndex::new(&p);
                     *project = p;
                     *is_dirty = false;
                     *backing_file = bf;
+                    *journal = None;
                     Ok(())
                 },
                 ctrl@&mut Controller::New{ .. } => {
@@ -395,9 +539,11 @@ impl Controller {
                     *ctrl = Controller::Set{
                         //metaObject: metaObject,
                         singleton_object: Object::from_ptr(so),
+                        xrefs: XrefIndex::new(&p),
                         project: p,
                         is_dirty: false,
                         backing_file: bf,
+                        journal: None,
                     };
                     Ok(())
                 },
@@ -413,12 +559,13 @@ impl Controller {
     pub fn set_backing(p: &Path) -> Result<()> {
         {
             let mut guard = try!(CONTROLLER.write());
-            if let &mut Controller::Set{ ref mut is_dirty, ref mut backing_file,.. } = &mut *guard {
+            if let &mut Controller::Set{ ref mut is_dirty, ref mut backing_file, ref mut journal,.. } = &mut *guard {
                 if let &mut Backing::Unnamed(ref p) = backing_file {
                     let _ = remove_file(p);
                 }
                 *backing_file = Backing::Named(p.to_path_buf());
                 *is_dirty = true;
+                *journal = None;
                 Ok(())
             } else {
                 Err("Controller is in empty state".into())
@@ -21,10 +21,18 @@ use panopticon::{
     Rvalue,Lvalue,
     Function,ControlFlowTarget,
     CallTarget,
+    Project,
     MnemonicFormatToken,
-    Error,
     Result,
     elf,
+    loader,
+    session,
+    journal,
+    Change,
+    Annotations,
+    Merge,
+    Xref,
+    XrefKind,
     Kset,
 };
 
@@ -50,15 +58,17 @@ use graph_algos::{
 };
 use uuid::Uuid;
 use rustc_serialize::json;
-use byteorder::{BigEndian,ReadBytesExt};
 use controller::{
     LAYOUTED_FUNCTION,
     CHANGED_FUNCTION,
+    FINISHED_FUNCTION,
+    REMOVED_FUNCTION,
     return_json,
     Controller,
 };
 
 use sugiyama;
+use project::APPROXIMATION;
 
 #[derive(RustcEncodable)]
 struct Metainfo {
@@ -246,6 +256,14 @@ pub fn control_flow_graph(arg: &Variant) -> Variant {
                                         } else {
                                             let mut ops = x.operands.clone();
                                             ops.reverse();
+                                            // show pointers to stack slots as var_<n>/arg_<n>
+                                            let addr = x.area.start;
+                                            let slot_name = |name: &str| {
+                                                match fun.frame.as_ref().and_then(|f| f.slot(addr,name)) {
+                                                    Some(slot) => slot.name.clone(),
+                                                    None => name.to_string(),
+                                                }
+                                            };
                                             let args = x.format_string.iter().map(|x| match x {
                                                 &MnemonicFormatToken::Literal(ref s) =>
                                                     CfgOperand{
@@ -306,10 +324,10 @@ pub fn control_flow_graph(arg: &Variant) -> Variant {
                                                                 data: data,
                                                             }
                                                         },
-                                                        Some(Rvalue::Variable{ ref name, subscript: Some(_),.. }) =>
+                                                        Some(Rvalue::Variable{ ref name,.. }) =>
                                                             CfgOperand{
                                                                 kind: "pointer",
-                                                                display: name.to_string(),
+                                                                display: slot_name(&name[..]),
                                                                 data: "".to_string(),
                                                             },
                                                         _ =>
@@ -400,7 +418,14 @@ pub fn approximate(arg: &Variant) -> Variant {
             let ret = Controller::read(|proj| {
                 if let Some((vx,prog)) = proj.find_call_target_by_uuid(&tgt_uuid) {
                     if let Some(&CallTarget::Concrete(ref fun)) = prog.call_graph.vertex_label(vx) {
-                        return_json(panopticon::approximate::<Kset>(&fun).and_then(|x| Ok(x.iter().filter_map(|(k,v)| {
+                        // values computed with the callers' arguments, if there was a disassembly pass
+                        let cached = APPROXIMATION.read().ok().and_then(|a| a.as_ref().and_then(|a| a.values.get(&tgt_uuid).cloned()));
+                        let vals = match cached {
+                            Some(v) => Ok(v),
+                            None => panopticon::approximate::<Kset>(&fun),
+                        };
+
+                        return_json(vals.and_then(|x| Ok(x.iter().filter_map(|(k,v)| {
                             if let &Lvalue::Variable{ ref name, subscript: Some(ref subscript),.. } = k {
                                 if let &Kset::Set(ref s) = v {
                                     if s.len() == 1 {
@@ -494,7 +519,7 @@ pub fn read_directory(arg: &Variant) -> Variant {
 #[derive(RustcEncodable)]
 struct FileDetails {
     state: String, // writable, readable, directory, free, inaccessable
-    format: Option<String>, // elf, pe, raw
+    format: Option<String>, // panop, elf, pe, macho, ihex, srec, raw
     info: Vec<String>,
 }
 
@@ -512,46 +537,42 @@ pub fn file_details(arg: &Variant) -> Variant {
                 })
             } else {
                 let ro = meta.permissions().readonly();
-
-                if let Ok(id) = elf::Ident::read(&mut fd) {
-                    Ok(FileDetails{
-                        state: if ro { "readable" } else { "writable" }.to_string(),
-                        format: Some("elf".to_string()),
-                        info: vec![format!("{:?}, {:?}",id.class,id.data)],
-                    })
-                } else {
-                    let mut buf = [0u8;2];
-
-                    try!(fd.seek(SeekFrom::Start(0)));
-                    try!(fd.read(&mut buf));
-
-                    if buf == [0x4d,0x5a] {
-                        Ok(FileDetails{
-                            state: if ro { "readable" } else { "writable" }.to_string(),
-                            format: Some("pe".to_string()),
-                            info: vec!["PE".to_string()],
-                        })
-                    } else {
-                        let mut magic = [0u8;10];
-
+                let mut head = [0u8;512];
+                let len = try!(fd.read(&mut head));
+                let format = loader::sniff(&head[0..len]).first().cloned();
+                let info = match format {
+                    Some(loader::Format::Elf) => {
                         try!(fd.seek(SeekFrom::Start(0)));
-                        if try!(fd.read(&mut magic)) == 10 && magic == *b"PANOPTICON" {
-                            let version = try!(fd.read_u32::<BigEndian>());
-
-                            Ok(FileDetails{
-                                state: if ro { "readable" } else { "writable" }.to_string(),
-                                format: Some("panop".to_string()),
-                                info: vec![format!("Version {}",version)],
-                            })
-                        } else {
-                            Ok(FileDetails{
-                                state: if ro { "readable" } else { "writable" }.to_string(),
-                                format: Some("raw".to_string()),
-                                info: vec![],
-                            })
+                        match elf::Ident::read(&mut fd) {
+                            Ok(id) => vec![format!("{:?}, {:?}",id.class,id.data)],
+                            Err(_) => vec![],
                         }
-                    }
-                }
+                    },
+                    Some(loader::Format::Pe) => vec!["PE".to_string()],
+                    Some(loader::Format::Panopticon) => {
+                        match session::read_header(&path) {
+                            Ok(ref hdr) if !hdr.is_supported() =>
+                                vec![format!("Version {}",hdr.version),"Saved by a newer version of Panopticon".to_string()],
+                            Ok(ref hdr) if hdr.version >= 2 => match session::read_metadata(&path) {
+                                Ok(meta) => vec![
+                                    format!("Version {}",hdr.version),
+                                    format!("Project {}",meta.name),
+                                    format!("{} functions",meta.functions),
+                                ],
+                                Err(_) => vec![format!("Version {}",hdr.version)],
+                            },
+                            Ok(hdr) => vec![format!("Version {}",hdr.version)],
+                            Err(_) => vec![],
+                        }
+                    },
+                    _ => vec![],
+                };
+
+                Ok(FileDetails{
+                    state: if ro { "readable" } else { "writable" }.to_string(),
+                    format: Some(format.map(|x| x.name()).unwrap_or("raw").to_string()),
+                    info: info,
+                })
             }
         }).or_else(|_| {
             if let Some(parent) = path.parent() {
@@ -760,7 +781,7 @@ pub fn layout(arg0: &Variant, arg1: &Variant, arg2: &Variant, arg3: &Variant, ar
                         // XXX tell the frontend
                         Err(e) => {
                             println!("layouting thread failed with '{:?}'",e);
-                            Err(Error(e.into()))
+                            Err(e.into())
                         },
                     }
                 });
@@ -796,8 +817,10 @@ pub fn comment(arg0: &Variant, arg1: &Variant, arg2: &Variant) -> Variant {
     };
 
     // write comment
-    Variant::String(return_json(Controller::modify(|proj| {
-        proj.comments.insert((reg.clone(),offset),cmnt);
+    Variant::String(return_json(Controller::record(Change::Comment{
+        region: reg.clone(),
+        offset: offset,
+        comment: cmnt,
     }).and(Controller::read(|proj| {
         for prog in proj.code.iter() {
             for ct in prog.call_graph.vertices() {
@@ -825,14 +848,7 @@ pub fn rename(arg0: &Variant, arg1: &Variant) -> Variant {
 
     let maybe_uu = if let &Variant::String(ref st) = arg0 {
         if let Some(uuid) = Uuid::parse_str(st).ok() {
-            Controller::modify(|proj| {
-                if let Some(func) = proj.find_function_by_uuid_mut(&uuid) {
-                    func.name = name;
-                    Some(uuid.clone())
-                } else {
-                    None
-                }
-            }).ok()
+            Controller::record(Change::Rename{ function: uuid, name: name }).ok().map(|_| Some(uuid))
         } else {
             None
         }
@@ -847,6 +863,56 @@ pub fn rename(arg0: &Variant, arg1: &Variant) -> Variant {
     }
 }
 
+fn function_uuids(proj: &Project) -> HashSet<Uuid> {
+    let mut ret = HashSet::new();
+
+    for prog in proj.code.iter() {
+        for ct in prog.call_graph.vertices() {
+            if let Some(&CallTarget::Concrete(ref func)) = prog.call_graph.vertex_label(ct) {
+                ret.insert(func.uuid);
+            }
+        }
+    }
+
+    ret
+}
+
+/// Undoes (`undo` is true) or redoes the last edit. Returns false if there was nothing to do.
+/// Functions that appeared are announced as finished, removed ones as removed and all others as
+/// changed. Undo and redo are not journaled, the project is snapshotted instead.
+fn step_history(undo: bool) -> Result<bool> {
+    let (done,before,after) = try!(try!(Controller::modify_and_snapshot(|proj| -> Result<(bool,HashSet<Uuid>,HashSet<Uuid>)> {
+        let before = function_uuids(proj);
+        let done = try!(if undo { proj.undo() } else { proj.redo() });
+
+        Ok((done,before,function_uuids(proj)))
+    })));
+
+    if done {
+        for uu in after.iter() {
+            if before.contains(uu) {
+                try!(Controller::emit(CHANGED_FUNCTION,&uu.to_string()));
+            } else {
+                try!(Controller::emit(FINISHED_FUNCTION,&uu.to_string()));
+            }
+        }
+
+        for uu in before.difference(&after) {
+            try!(Controller::emit(REMOVED_FUNCTION,&uu.to_string()));
+        }
+    }
+
+    Ok(done)
+}
+
+pub fn undo() -> Variant {
+    Variant::String(return_json(step_history(true)))
+}
+
+pub fn redo() -> Variant {
+    Variant::String(return_json(step_history(false)))
+}
+
 #[derive(RustcEncodable,Debug)]
 struct SessionInfo {
     title: String,
@@ -865,6 +931,7 @@ pub fn sessions() -> Variant {
         fs::read_dir(p).and_then(|dir| {
             Ok(dir.filter_map(|f| {
                 match f {
+                    Ok(ref f) if f.path().extension().map(|x| x == "journal" || x == "tmp").unwrap_or(false) => None,
                     Ok(f) => {
                         let ts = f.metadata().ok().and_then(|t| {
                             t.modified().ok().and_then(|t| {
@@ -876,9 +943,15 @@ pub fn sessions() -> Variant {
                             })
                         });
 
+                        // Only read metadata blocks, older files would need to be decoded completely.
+                        let title = session::read_header(&f.path()).ok()
+                            .and_then(|hdr| if hdr.version >= 2 && hdr.is_supported() { session::read_metadata(&f.path()).ok() } else { None })
+                            .map(|meta| meta.name)
+                            .unwrap_or(f.file_name().to_str().unwrap_or("(error)").to_string());
+
                         match ts {
                             Some(ts) => Some(SessionInfo{
-                                title: f.file_name().to_str().unwrap_or("(error)").to_string(),
+                                title: title,
                                 age: format!("{}",HumanTime::from(ts)),
                                 file: f.file_name().to_str().unwrap_or("(error)").to_string(),
                                 path: f.path().to_str().unwrap_or("(error)").to_string(),
@@ -909,12 +982,147 @@ pub fn delete_session(arg0: &Variant) -> Variant {
 
     let p: Result<()> = session_directory().and_then(|mut p| {
         p.push(name);
-        fs::remove_file(p).map_err(|e| e.into())
+        try!(fs::remove_file(&p));
+        journal::remove(&p)
     });
 
     Variant::String(return_json::<()>(p))
 }
 
+/// Writes names, function boundaries and comments of the current project into the annotation
+/// database at `arg0`.
+pub fn export_annotations(arg0: &Variant) -> Variant {
+    let path = if let &Variant::String(ref x) = arg0 {
+        PathBuf::from(x)
+    } else {
+        return Variant::String(return_json::<()>(Err("1st argument is not a string".into())));
+    };
+
+    Variant::String(return_json(Controller::read(|proj| {
+        Annotations::from_project(proj).write(&path)
+    }).and_then(|x| x)))
+}
+
+/// Applies the annotation database at `arg0` to the current project. Conflicts are resolved
+/// according to `arg1`, one of "keep", "replace" or "append". Returns a `Report`.
+pub fn import_annotations(arg0: &Variant, arg1: &Variant) -> Variant {
+    let path = if let &Variant::String(ref x) = arg0 {
+        PathBuf::from(x)
+    } else {
+        return Variant::String(return_json::<()>(Err("1st argument is not a string".into())));
+    };
+
+    let rule = match arg1 {
+        &Variant::String(ref x) if x == "keep" => Merge::Keep,
+        &Variant::String(ref x) if x == "replace" => Merge::Replace,
+        &Variant::String(ref x) if x == "append" => Merge::Append,
+        _ => return Variant::String(return_json::<()>(Err("2nd argument is not a merge rule".into()))),
+    };
+
+    Variant::String(return_json(Annotations::read(&path).and_then(|ann| {
+        try!(Controller::modify(|proj| ann.apply(proj,rule))).and_then(|report| {
+            try!(Controller::read(|proj| {
+                for prog in proj.code.iter() {
+                    for ct in prog.call_graph.vertices() {
+                        if let Some(&CallTarget::Concrete(ref func)) = prog.call_graph.vertex_label(ct) {
+                            try!(Controller::emit(CHANGED_FUNCTION,&func.uuid.to_string()));
+                        }
+                    }
+                }
+                Ok(())
+            }).and_then(|x: Result<()>| x));
+
+            Ok(report)
+        })
+    })))
+}
+
+#[derive(RustcEncodable)]
+struct XrefInfo {
+    kind: &'static str,
+    source: u64,
+    target: u64,
+    function: String,
+    name: String,
+}
+
+fn xref_info(proj: &Project, xrefs: &[&Xref]) -> Vec<XrefInfo> {
+    xrefs.iter().map(|x| XrefInfo{
+        kind: match x.kind {
+            XrefKind::Call => "call",
+            XrefKind::Jump => "jump",
+            XrefKind::Read => "read",
+            XrefKind::Write => "write",
+        },
+        source: x.source,
+        target: x.target,
+        function: x.function.to_string(),
+        name: proj.find_function_by_uuid(&x.function).map(|f| f.name.clone()).unwrap_or("".to_string()),
+    }).collect()
+}
+
+/// JSON list of all references to the address `arg0` in the root memory region. The address is a
+/// decimal number or a hexadecimal one prefixed with "0x".
+///
+/// The JSON looks like this:
+/// ```json
+/// [{
+///     "kind": "call",         // or "jump", "read" or "write"
+///     "source": 0x1002,       // address of the referencing instruction
+///     "target": 0x2000,       // referenced address
+///     "function": <UUID>,     // function containing the referencing instruction
+///     "name": "func_1000"     // and its name
+/// },...]
+/// ```
+pub fn xrefs_to_address(arg0: &Variant) -> Variant {
+    let addr = if let &Variant::String(ref x) = arg0 {
+        let res = if x.starts_with("0x") {
+            u64::from_str_radix(&x[2..],16)
+        } else {
+            u64::from_str_radix(x,10)
+        };
+
+        match res {
+            Ok(a) => a,
+            Err(_) => return Variant::String(return_json::<()>(Err("1st argument is not an address".into()))),
+        }
+    } else {
+        return Variant::String(return_json::<()>(Err("1st argument is not a string".into())));
+    };
+
+    Variant::String(return_json(Controller::read_xrefs(|proj,xrefs| -> Result<Vec<XrefInfo>> {
+        let root = try!(proj.data.dependencies.vertex_label(proj.data.root).ok_or("Internal error"));
+
+        Ok(xref_info(proj,&xrefs.to(root.name(),addr).iter().collect::<Vec<_>>()))
+    }).and_then(|x| x)))
+}
+
+/// JSON list of all references to the entry point of the function with UUID `arg0`. See
+/// `xrefs_to_address` for the format.
+pub fn xrefs_to_function(arg0: &Variant) -> Variant {
+    let uu = if let &Variant::String(ref x) = arg0 {
+        match Uuid::parse_str(x) {
+            Ok(uu) => uu,
+            Err(_) => return Variant::String(return_json::<()>(Err("1st argument is not a valid UUID".into()))),
+        }
+    } else {
+        return Variant::String(return_json::<()>(Err("1st argument is not a string".into())));
+    };
+
+    Variant::String(return_json(Controller::read_xrefs(|proj,xrefs| -> Result<Vec<XrefInfo>> {
+        let func = try!(proj.find_function_by_uuid(&uu).ok_or("No function found for this UUID"));
+        let entry = func.entry_point.and_then(|vx| match func.cflow_graph.vertex_label(vx) {
+            Some(&ControlFlowTarget::Resolved(ref bb)) => Some(bb.area.start),
+            _ => None,
+        });
+
+        Ok(match entry {
+            Some(a) => xref_info(proj,&xrefs.to(&func.region,a).iter().collect::<Vec<_>>()),
+            None => vec![],
+        })
+    }).and_then(|x| x)))
+}
+
 pub fn find_data_file(arg0: &Variant) -> Variant {
     use paths;
     use std::path::Path;
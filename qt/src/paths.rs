@@ -38,7 +38,7 @@ pub fn session_directory() -> Result<PathBuf> {
                     .create(ret.clone()));
             Ok(ret)
         },
-        Err(e) => Err(result::Error(Cow::Owned(e.description().to_string()))),
+        Err(e) => Err(result::Error::Generic(Cow::Owned(e.description().to_string()))),
     }
 }
 
@@ -55,7 +55,7 @@ pub fn session_directory() -> Result<PathBuf> {
 				.create(ret.clone()));
 			Ok(ret)
         },
-        Err(e) => Err(result::Error(Cow::Owned(e.description().to_string()))),
+        Err(e) => Err(result::Error::Generic(Cow::Owned(e.description().to_string()))),
     }
 }
 
@@ -69,7 +69,7 @@ pub fn session_directory() -> Result<PathBuf> {
 				.create(ret.clone()));
 			Ok(ret)
         },
-        Err(e) => Err(result::Error(Cow::Owned(e.description().to_string()))),
+        Err(e) => Err(result::Error::Generic(Cow::Owned(e.description().to_string()))),
     }
 }
 
@@ -96,7 +96,7 @@ fn find_data_file_impl(p: &Path) -> Result<Option<PathBuf>> {
     use std::env;
     match BaseDirectories::with_prefix("panopticon") {
         Ok(dirs) => Ok(dirs.find_data_file(p)),
-        Err(e) => Err(result::Error(Cow::Owned(e.description().to_string()))),
+        Err(e) => Err(result::Error::Generic(Cow::Owned(e.description().to_string()))),
     }
 }
 
@@ -107,7 +107,7 @@ fn find_data_file_impl(p: &Path) -> Result<Option<PathBuf>> {
                            .and_then(|x| x.parent())
                            .map(|x| x.join("Resources").join(p))
                            .and_then(|x| if x.exists() { Some(x) } else { None })),
-        Err(e) => Err(result::Error(Cow::Owned(e.description().to_string()))),
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -117,6 +117,6 @@ fn find_data_file_impl(p: &Path) -> Result<Option<PathBuf>> {
         Ok(path) => Ok(path.parent()
                            .map(|x| x.join(p))
                            .and_then(|x| if x.exists() { Some(x) } else { None })),
-        Err(e) => Err(result::Error(Cow::Owned(e.description().to_string()))),
+        Err(e) => Err(e.into()),
     }
 }
@@ -20,33 +20,29 @@ use panopticon::{
     Project,
     Function,
     Program,CallTarget,
-    ControlFlowTarget,
     elf,
     pe,
     Rvalue,
     Result,
-    ssa_convertion,
-    Lvalue,
     Architecture,
-    OpaqueLayer,
-    Layer,
-    Region,
-    Bound,
-    World,
-    approximate,
+    Target,
+    loader,
+    firmware,
+    Change,
     Kset,
+    Approximation,
+    approximate_program,
 };
 use panopticon::amd64;
 use panopticon::mos;
 use panopticon::avr;
 
 use std::path::Path;
+use std::fs::File;
+use std::io::Read;
 use std::thread;
-use std::collections::{
-    HashMap,
-    HashSet,
-};
 use std::fmt::Debug;
+use std::sync::RwLock;
 
 use qmlrs::{Variant};
 use graph_algos::{
@@ -63,75 +59,80 @@ use controller::{
 };
 use uuid::Uuid;
 
+lazy_static! {
+    /// Result of the inter-procedural analysis done after the last disassembly pass.
+    pub static ref APPROXIMATION: RwLock<Option<Approximation<Kset>>> = RwLock::new(None);
+}
+
+/// Targets supported by `create_raw_project`.
+const RAW_TARGETS: [&'static str; 5] = ["mos6502","atmega103","atmega8","atmega88","atmega16"];
+
 /// Prepares to disassemble a memory image.
+///
+/// Intel HEX and S-record files are mapped at the addresses they specify, other files at
+/// `_base`. If `_entry` is negative the entry points are taken from the
+/// interrupt vector of the target.
 pub fn create_raw_project(_path: &Variant, _tgt: &Variant, _base: &Variant, _entry: &Variant) -> Variant {
     Variant::String(if let &Variant::String(ref s) = _path {
         let p = Path::new(s);
         if let &Variant::I64(base) = _base {
             if let &Variant::I64(entry) = _entry {
-                if let Some(nam) = p.file_name().and_then(|x| x.to_str()).or(p.to_str()) {
-                    if let Ok(b) = OpaqueLayer::open(p) {
-                        let mut reg = Region::undefined(nam.to_string(),b.iter().len() + base as u64);
-
-                        reg.cover(Bound::new(base as u64,base as u64 + b.iter().len()),Layer::Opaque(b));
+                if let &Variant::String(ref tgt_s) = _tgt {
+                    if !RAW_TARGETS.contains(&tgt_s.as_str()) {
+                        return Variant::String(return_json::<()>(Err(format!("No such target '{}'",tgt_s).into())));
+                    }
 
-                        if let &Variant::String(ref tgt_s) = _tgt {
-                            let iv: Result<Vec<(&'static str,u64,&'static str)>> = {
+                    let mut head = [0u8;512];
+                    let len = File::open(p).and_then(|mut fd| fd.read(&mut head)).unwrap_or(0);
+                    let maybe_proj = match loader::sniff(&head[0..len]).first() {
+                        Some(&loader::Format::IntelHex) => firmware::ihex(p),
+                        Some(&loader::Format::SRecord) => firmware::srec(p),
+                        _ => Project::raw(p,base as u64),
+                    };
+
+                    match maybe_proj {
+                        Ok(mut proj) => {
+                            let maybe_prep = if entry >= 0 {
+                                let reg_name = proj.data.dependencies.vertex_label(proj.data.root).unwrap().name().clone();
+                                let uu =  Uuid::new_v4();
+
+                                proj.code[0].call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(entry as u64),Some("Entry point".to_string()),uu));
+                                proj.comments.insert((reg_name,entry as u64),"User supplied entry point".to_string());
+                                Ok(1)
+                            } else {
                                 match tgt_s.as_str() {
-                                    //"mos6502" => Mos::prepare(&reg,&mos::Variant::mos6502()),
-                                    //"atmega103" => Avr::prepare(&reg,&Mcu::atmega103()),
-                                    //"atmega8" => Avr::prepare(&reg,&Mcu::atmega8()),
-                                    //"atmega88" => Avr::prepare(&reg,&Mcu::atmega88()),
-                                    //"atmega16" => Avr::prepare(&reg,&Mcu::atmega16()),
-                                    _ => Err(format!("No such target '{}'",tgt_s).into()),
+                                    "mos6502" => proj.prepare::<mos::Mos>(&mos::Variant::mos6502()),
+                                    "atmega103" => proj.prepare::<avr::Avr>(&avr::Mcu::atmega103()),
+                                    "atmega8" => proj.prepare::<avr::Avr>(&avr::Mcu::atmega8()),
+                                    "atmega88" => proj.prepare::<avr::Avr>(&avr::Mcu::atmega88()),
+                                    "atmega16" => proj.prepare::<avr::Avr>(&avr::Mcu::atmega16()),
+                                    _ => unreachable!()
                                 }
                             };
 
-                            if let Ok(ref iv) = iv {
-                                let mut proj = Project{
-                                    name: nam.to_string(),
-                                    code: Vec::new(),
-                                    data: World::new(reg),
-                                    comments: HashMap::new(),
-                                };
-                                let mut prog = Program::new("prog0");
-
-                                if entry >= 0 {
-                                    let uu =  Uuid::new_v4();
-                                    prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(entry as u64),Some("Entry point".to_string()),uu));
-                                    proj.comments.insert((nam.to_string(),entry as u64),"User supplied entry point".to_string());
-                                } else {
-                                    for &(name,off,cmnt) in iv.iter() {
-                                        let uu =  Uuid::new_v4();
-                                        prog.call_graph.add_vertex(CallTarget::Todo(Rvalue::new_u64(off),Some(name.to_string()),uu));
-                                        proj.comments.insert((nam.to_string(),off),cmnt.to_string());
+                            match maybe_prep {
+                                Ok(0) => return_json::<()>(Err("No entry point found".into())),
+                                Ok(_) => {
+                                    let ret = return_json(Controller::replace(proj,None));
+
+                                    match tgt_s.as_str() {
+                                        "mos6502" => spawn_disassembler::<mos::Mos>(mos::Variant::mos6502()),
+                                        "atmega103" => spawn_disassembler::<avr::Avr>(avr::Mcu::atmega103()),
+                                        "atmega8" => spawn_disassembler::<avr::Avr>(avr::Mcu::atmega8()),
+                                        "atmega88" => spawn_disassembler::<avr::Avr>(avr::Mcu::atmega88()),
+                                        "atmega16" => spawn_disassembler::<avr::Avr>(avr::Mcu::atmega16()),
+                                        _ => unreachable!()
                                     }
-                                }
 
-                                proj.code.push(prog);
-
-                                let ret = return_json(Controller::replace(proj,None));
-                                match tgt_s.as_str() {
-                                    //"mos6502" => spawn_disassembler::<Mos>(mos::Variant::mos6502()),
-                                    //"atmega103" => spawn_disassembler::<Avr>(Mcu::atmega103()),
-                                    //"atmega8" => spawn_disassembler::<Avr>(Mcu::atmega8()),
-                                    //"atmega88" => spawn_disassembler::<Avr>(Mcu::atmega88()),
-                                    //"atmega16" => spawn_disassembler::<Avr>(Mcu::atmega16()),
-                                    _ => unreachable!()
-                                }
-
-                                ret
-                            } else {
-                                return_json::<()>(Err(iv.err().unwrap()))
+                                    ret
+                                },
+                                Err(e) => return_json::<()>(Err(e)),
                             }
-                        } else {
-                            return_json::<()>(Err("2nd argument is not a string".into()))
-                        }
-                    } else {
-                        return_json::<()>(Err("Can't open file".into()))
+                        },
+                        Err(_) => return_json::<()>(Err("Can't open file".into())),
                     }
                 } else {
-                    return_json::<()>(Err("Can't get file name".into()))
+                    return_json::<()>(Err("2nd argument is not a string".into()))
                 }
             } else {
                 return_json::<()>(Err("4th argument is not an integer".into()))
@@ -154,6 +155,7 @@ pub fn create_elf_project(_path: &Variant) -> Variant {
                     elf::Machine::Ia32 => spawn_disassembler::<amd64::Amd64>(amd64::Mode::Protected),
                     elf::Machine::Amd64 => spawn_disassembler::<amd64::Amd64>(amd64::Mode::Long),
                     elf::Machine::Avr => spawn_disassembler::<avr::Avr>(avr::Mcu::atmega88()),
+                    m => warn!("No disassembler for {:?}, only mapping the file",m),
                 }
 
                 return_json(Controller::replace(proj,None))
@@ -168,17 +170,56 @@ pub fn create_elf_project(_path: &Variant) -> Variant {
 /// Prepares to disassemble an PE file.
 pub fn create_pe_project(_path: &Variant) -> Variant {
     Variant::String(if let &Variant::String(ref s) = _path {
-        match pe::pe(Path::new(s)) {
-            Some(_) => {
-                return Variant::String(return_json::<()>(Err("Unsupported format".into())));
+        match pe::load(Path::new(s)) {
+            Ok((proj,m)) => {
+                let ret = return_json(Controller::replace(proj,None));
+
+                match m {
+                    pe::Machine::Ia32 => spawn_disassembler::<amd64::Amd64>(amd64::Mode::Protected),
+                    pe::Machine::Amd64 => spawn_disassembler::<amd64::Amd64>(amd64::Mode::Long),
+                }
+
+                ret
             },
-            None => return_json::<()>(Err("Failed to read PE file".into())),
+            Err(_) => return_json::<()>(Err("Failed to read PE file".into())),
         }
     } else {
         return_json::<()>(Err("1st argument is not a string".into()))
     })
 }
 
+/// Guesses the format of a file and prepares to disassemble it. Saved Panopticon projects are
+/// opened like `open_project` does.
+pub fn create_project(_path: &Variant) -> Variant {
+    if let &Variant::String(ref s) = _path {
+        let p = Path::new(s);
+        let mut head = [0u8;512];
+        let len = File::open(p).and_then(|mut fd| fd.read(&mut head)).unwrap_or(0);
+
+        if loader::sniff(&head[0..len]).first() == Some(&loader::Format::Panopticon) {
+            return open_project(_path);
+        }
+
+        Variant::String(match Project::load(p) {
+            Ok((proj,tgt)) => {
+                let ret = return_json(Controller::replace(proj,None));
+
+                match tgt {
+                    Some(Target::Amd64(mode)) => spawn_disassembler::<amd64::Amd64>(mode),
+                    Some(Target::Avr(mcu)) => spawn_disassembler::<avr::Avr>(mcu),
+                    Some(Target::Mos(var)) => spawn_disassembler::<mos::Mos>(var),
+                    None => warn!("Unknown CPU, only mapping the file"),
+                }
+
+                ret
+            },
+            Err(e) => return_json::<()>(Err(e.into())),
+        })
+    } else {
+        Variant::String(return_json::<()>(Err("1st argument is not a string".into())))
+    }
+}
+
 /// Prepares to open a saved Panopticon project.
 pub fn open_project(_path: &Variant) -> Variant {
     Variant::String(if let &Variant::String(ref s) = _path {
@@ -219,14 +260,14 @@ pub fn set_request(_req: &Variant) -> Variant {
 
 /// Starts disassembly
 pub fn spawn_disassembler<A: 'static + Architecture + Debug>(_cfg: A::Configuration) where A::Configuration: Debug + Sync, A::Token: Sync + Send {
-    use std::sync::Mutex;
-
     thread::spawn(move || -> Result<()> {
         let maybe_prog_uuid = try!(Controller::read(|proj| {
             proj.code.first().map(|x| x.uuid)
         }));
 
         if let Some(prog_uuid) = maybe_prog_uuid {
+            *try!(APPROXIMATION.write()) = None;
+
             let todo_funcs = try!(Controller::read(|proj| {
                 let prog: &Program = proj.find_program_by_uuid(&prog_uuid).unwrap();
 
@@ -246,101 +287,28 @@ pub fn spawn_disassembler<A: 'static + Architecture + Debug>(_cfg: A::Configurat
             loop {
                 let maybe_tgt = try!(Controller::read(|proj| {
                     let prog: &Program = proj.find_program_by_uuid(&prog_uuid).unwrap();
-
-                    prog.call_graph.vertices().filter_map(|x| {
-                        if let Some(&CallTarget::Todo(ref tgt@Rvalue::Constant{ .. },ref name,uuid)) = prog.call_graph.vertex_label(x) {
-                            Some((tgt.clone(),name.clone(),uuid))
-                        } else {
-                            None
-                        }
-                    }).next()
+                    prog.next_todo()
                 }));
 
                 match maybe_tgt {
-                    Some((Rvalue::Constant{ value: tgt,.. },maybe_name,uuid)) => {
+                    Some((tgt,maybe_name,uuid)) => {
                         try!(Controller::emit(STARTED_FUNCTION,&uuid.to_string()));
 
                         let cfg = _cfg.clone();
                         let th = thread::spawn(move || -> Result<Vec<Uuid>> {
-                            let entry = tgt;
-                            let mut func = try!(Controller::read(|proj| {
-                                let name = maybe_name.unwrap_or(format!("func_{:x}",tgt));
-                                let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
-                                Function::with_uuid(name,uuid,root.name().clone())
-                            }));
-
-                            debug!("start new function {:?} at {:?}",uuid,entry);
+                            debug!("start new function {:?} at {:?}",uuid,tgt);
 
-                            func = try!(Controller::read(|proj| {
+                            let func = try!(try!(Controller::read(|proj| {
+                                let name = maybe_name.unwrap_or(format!("func_{:x}",tgt));
                                 let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
-                                let mut func = {
-                                    Function::disassemble::<A>(Some(func),cfg.clone(),&root,entry)
-                                };
-
-                                func.entry_point = func.find_basic_block_at_address(entry);
-
-                                func
-                            }));
-
-                            if func.cflow_graph.num_vertices() == 0 || func.entry_point.is_none() {
-                                debug!("failed to disassemble for {}",func.name);
-
-                                let uu = func.uuid.clone();
-                                try!(Controller::modify(|proj| {
-                                    let mut prog: &mut Program = proj.find_program_by_uuid_mut(&prog_uuid).unwrap();
-                                    prog.insert(CallTarget::Concrete(func));
-                                }));
-
-                                try!(Controller::emit(FINISHED_FUNCTION,&uu.to_string()));
-                                return Ok(vec![]);
-                            }
-
-                            debug!("primary pass done");
-
-                            let mut fixpoint = false;
+                                let func = Function::with_uuid(name,uuid,root.name().clone());
 
-                            while !fixpoint {
-                                fixpoint = true;
-                                ssa_convertion(&mut func);
+                                Program::disassemble_function::<A>(func,cfg,&root,tgt)
+                            })));
 
-                                let vals = try!(approximate::<Kset>(&func));
-                                let vxs = { func.cflow_graph.vertices().collect::<Vec<_>>() };
-                                let mut resolved_jumps = HashSet::<u64>::new();
-
-                                for &vx in vxs.iter() {
-                                    if let Some(&mut ControlFlowTarget::Unresolved(ref mut var@Rvalue::Variable{..})) = func.cflow_graph.vertex_label_mut(vx) {
-                                        if let Some(&Kset::Set(ref v)) = vals.get(&Lvalue::from_rvalue(var.clone()).unwrap()) {
-                                            if let Some(&(val,sz)) = v.first() {
-                                                *var = Rvalue::Constant{ value: val, size: sz };
-                                                fixpoint = true;
-                                                debug!("resolved {:?} to {:?}",var,val);
-                                                resolved_jumps.insert(val);
-                                            }
-                                        }
-                                    }
-                                }
-
-                                for addr in resolved_jumps {
-                                    debug!("continue at {:?}",addr);
-                                    func = try!(Controller::read(|proj| {
-                                        let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
-                                        let mut func = {
-                                            Function::disassemble::<A>(Some(func),cfg.clone(),&root,addr)
-                                        };
-
-                                        func.entry_point = func.find_basic_block_at_address(entry);
-
-                                        func
-                                    }));
-                                }
-
-                                debug!("secondary pass done");
-                            }
-
-                            let new_functions = try!(Controller::modify(|proj| {
-                                let mut prog: &mut Program = proj.find_program_by_uuid_mut(&prog_uuid).unwrap();
-
-                                prog.insert(CallTarget::Concrete(func))
+                            let new_functions = try!(Controller::record_deferred(Change::Function{
+                                program: prog_uuid,
+                                function: func,
                             }));
 
                             debug!("function finished");
@@ -358,22 +326,33 @@ pub fn spawn_disassembler<A: 'static + Architecture + Debug>(_cfg: A::Configurat
                             },
                             Err(e) => {
                                 error!("error while disassembling {:?}: {:?}",uuid,e);
-                                try!(Controller::emit(FINISHED_FUNCTION,&uuid.to_string()))
+                                try!(Controller::emit(FINISHED_FUNCTION,&uuid.to_string()));
+                                break;
                             },
                             Ok(Err(e)) => {
                                 error!("error while disassembling {:?}: {:?}",uuid,e);
-                                try!(Controller::emit(FINISHED_FUNCTION,&uuid.to_string()))
+                                try!(Controller::emit(FINISHED_FUNCTION,&uuid.to_string()));
+                                break;
                             },
                         }
                     }
-                    Some((rv,maybe_name,uuid)) => {
-                        debug!("skip call to {:?} ({:?},{:?})",rv,maybe_name,uuid);
-                    }
                     None => {
                         break;
                     }
                 }
             }
+
+            // functions are journaled without waiting for the disk, flush them once per pass
+            try!(Controller::sync_journal());
+
+            // adds the __call mnemonics to all functions, there's no journal entry for this
+            let stack_pointer = A::stack_pointer(&_cfg);
+            let approx = try!(try!(Controller::modify_and_snapshot(|proj| {
+                let prog = proj.find_program_by_uuid_mut(&prog_uuid).unwrap();
+                approximate_program::<Kset>(prog,stack_pointer.as_ref())
+            })));
+
+            *try!(APPROXIMATION.write()) = Some(approx);
         } else {
             unreachable!()
         }
@@ -0,0 +1,80 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+extern crate panopticon;
+extern crate graph_algos;
+extern crate tempdir;
+
+use panopticon::{Project,CallTarget,Annotations,Merge,amd64,dos};
+
+use std::path::Path;
+use tempdir::TempDir;
+use graph_algos::{GraphTrait,MutableGraphTrait,VertexListGraphTrait};
+
+fn analyze() -> Project {
+    let mut proj = dos::load_com(Path::new("tests/data/dos.com")).unwrap();
+
+    {
+        let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+        proj.code[0].disassemble_all::<amd64::Amd64,_>(root,amd64::Mode::Real,|_| {}).unwrap();
+    }
+
+    proj
+}
+
+fn function_name(proj: &Project, entry: u64) -> Option<String> {
+    let prog = &proj.code[0];
+
+    prog.find_function_by_entry(entry).and_then(|vx| match prog.call_graph.vertex_label(vx) {
+        Some(&CallTarget::Concrete(ref f)) => Some(f.name.clone()),
+        _ => None,
+    })
+}
+
+#[test]
+fn reapply_to_new_analysis() {
+    let tmp = TempDir::new("test-panop-annotations").unwrap();
+    let path = tmp.path().join("dos.annotations");
+    let mut old = analyze();
+
+    for vx in old.code[0].call_graph.vertices().collect::<Vec<_>>() {
+        if let Some(&mut CallTarget::Concrete(ref mut f)) = old.code[0].call_graph.vertex_label_mut(vx) {
+            if f.name != "dos.com" {
+                f.name = "exit_code".to_string();
+            }
+        }
+    }
+
+    old.comments.insert(("ram".to_string(),0x103),"count down".to_string());
+    Annotations::from_project(&old).write(&path).unwrap();
+
+    let ann = Annotations::read(&path).unwrap();
+    let mut new = analyze();
+
+    assert_eq!(ann.functions.len(),2);
+    assert_eq!(function_name(&new,0x10f),Some("func_10f".to_string()));
+
+    let report = ann.apply(&mut new,Merge::Keep).unwrap();
+
+    assert_eq!(report.renamed,1);
+    assert_eq!(report.comments,1);
+    assert!(report.name_conflicts.is_empty());
+    assert!(report.comment_conflicts.is_empty());
+    assert_eq!(function_name(&new,0x10f),Some("exit_code".to_string()));
+    assert_eq!(new.comments.get(&("ram".to_string(),0x103)),Some(&"count down".to_string()));
+}
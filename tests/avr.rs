@@ -24,6 +24,7 @@ use panopticon::avr::{Mcu,Avr};
 use panopticon::avr::syntax::disassembler;
 use panopticon::function::{ControlFlowTarget,Function};
 use panopticon::elf;
+use panopticon::{Project,CallTarget,firmware};
 
 use std::path::Path;
 
@@ -77,3 +78,54 @@ fn avr_elf() {
     let proj = elf::load(Path::new("tests/data/hello-world")).ok();
     assert!(proj.is_some());
 }
+
+#[test]
+fn avr_raw_project() {
+    let mut proj = Project::raw(Path::new("tests/data/sosse"),0).unwrap();
+
+    assert_eq!(proj.prepare::<Avr>(&Mcu::atmega16()).unwrap(),21);
+    assert_eq!(proj.comments.get(&("sosse".to_string(),0)).map(|x| x.as_str()),Some("MCU Reset Interrupt"));
+
+    {
+        let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+        proj.code[0].disassemble_all::<Avr,_>(root,Mcu::atmega16(),|_| {}).unwrap();
+    }
+
+    let prog = &proj.code[0];
+    let mut reset = None;
+
+    for vx in prog.call_graph.vertices() {
+        match prog.call_graph.vertex_label(vx) {
+            Some(&CallTarget::Concrete(ref f)) if f.name == "RESET" => reset = Some(f),
+            Some(&CallTarget::Todo(..)) => panic!("Todo left after disassembly"),
+            _ => {}
+        }
+    }
+
+    let reset = reset.unwrap();
+    assert!(reset.cflow_graph.vertices().any(|vx| match reset.cflow_graph.vertex_label(vx) {
+        Some(&ControlFlowTarget::Resolved(ref bb)) => bb.area.start == 0,
+        _ => false,
+    }));
+}
+
+#[test]
+fn avr_firmware_images() {
+    let raw = Project::raw(Path::new("tests/data/sosse"),0).unwrap();
+    let raw_bytes = raw.data.dependencies.vertex_label(raw.data.root).unwrap().iter().collect::<Vec<_>>();
+
+    for p in &["tests/data/sosse.hex","tests/data/sosse.srec"] {
+        let mut proj = if p.ends_with(".hex") {
+            firmware::ihex(Path::new(p)).unwrap()
+        } else {
+            firmware::srec(Path::new(p)).unwrap()
+        };
+
+        {
+            let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+            assert_eq!(root.iter().collect::<Vec<_>>(),raw_bytes);
+        }
+
+        assert_eq!(proj.prepare::<Avr>(&Mcu::atmega16()).unwrap(),21);
+    }
+}
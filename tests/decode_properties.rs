@@ -0,0 +1,116 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+extern crate panopticon;
+
+use panopticon::{
+    Region,
+    Architecture,
+    Mnemonic,
+    amd64,
+    avr,
+    mos,
+};
+use std::mem;
+
+/// Number of random instructions decoded per architecture.
+const ROUNDS: usize = 5000;
+
+/// Xorshift PRNG. Fixed seed so failures can be reproduced.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// Checks that `mnes` cover `addr` up to `addr + len` without gaps and all RREIL statements are
+/// well-formed.
+fn check_mnemonics(mnes: &[Mnemonic], addr: u64, len: u64, input: &[u8]) {
+    let mut next = addr;
+
+    for mne in mnes.iter() {
+        assert_eq!(mne.area.start,next,"{} at {:#x} doesn't follow the previous mnemonic. Input: {:?}",mne.opcode,mne.area.start,input);
+        assert!(mne.area.start <= mne.area.end,"{} has a negative length. Input: {:?}",mne.opcode,input);
+
+        for stmt in mne.instructions.iter() {
+            if let Err(e) = stmt.sanity_check() {
+                panic!("{}: '{}' is malformed: {}. Input: {:?}",mne.opcode,stmt,e,input);
+            }
+        }
+
+        next = mne.area.end;
+    }
+
+    assert_eq!(next,addr + len,"Mnemonics don't match the decoded bytes. Input: {:?}",input);
+}
+
+fn decode_random<A: Architecture>(seed: u64, cfg: A::Configuration) {
+    let mut rng = Rng(seed);
+
+    for _ in 0..ROUNDS {
+        let buf = rng.bytes(16);
+        let reg = Region::wrap("base".to_string(),buf.clone());
+
+        if let Ok(m) = A::decode(&reg,0,&cfg) {
+            let len = (m.tokens.len() * mem::size_of::<A::Token>()) as u64;
+
+            assert!(len > 0 && len <= buf.len() as u64);
+            check_mnemonics(&m.mnemonics,0,len,&buf);
+        }
+    }
+}
+
+#[test]
+fn amd64_properties() {
+    decode_random::<amd64::Amd64>(0x2545f4914f6cdd1d,amd64::Mode::Long);
+    decode_random::<amd64::Amd64>(0x9e3779b97f4a7c15,amd64::Mode::Protected);
+    decode_random::<amd64::Amd64>(0xbf58476d1ce4e5b9,amd64::Mode::Real);
+}
+
+#[test]
+fn amd64_read_properties() {
+    let mut rng = Rng(0x94d049bb133111eb);
+
+    for _ in 0..ROUNDS {
+        let buf = rng.bytes(15);
+
+        if let Ok((len,mne,_)) = amd64::read(amd64::Mode::Long,&buf,0x1000) {
+            assert!(len > 0 && len <= 15);
+            check_mnemonics(&[mne],0x1000,len,&buf);
+        }
+    }
+}
+
+#[test]
+fn avr_properties() {
+    decode_random::<avr::Avr>(0x2545f4914f6cdd1d,avr::Mcu::atmega88());
+}
+
+#[test]
+fn mos_properties() {
+    decode_random::<mos::Mos>(0x2545f4914f6cdd1d,mos::Variant::mos6502());
+}
@@ -17,10 +17,16 @@
  */
 
 extern crate panopticon;
+extern crate graph_algos;
 
-use panopticon::elf;
+use panopticon::{elf,CallTarget,Rvalue};
 use std::path::Path;
 
+use graph_algos::{
+    VertexListGraphTrait,
+    GraphTrait,
+};
+
 #[test]
 fn elf_load_static() {
     match elf::load(Path::new("tests/data/static")) {
@@ -28,3 +34,40 @@ fn elf_load_static() {
         Err(_) => panic!()
     }
 }
+
+#[test]
+fn elf_load_symbols() {
+    let (proj,_) = elf::load(Path::new("tests/data/static")).ok().unwrap();
+    let prog = &proj.code[0];
+    let main = prog.call_graph.vertices().find(|&vx| match prog.call_graph.vertex_label(vx) {
+        Some(&CallTarget::Todo(Rvalue::Constant{ value: 0x4009be,.. },Some(ref n),_)) => n == "main",
+        _ => false,
+    });
+
+    assert!(main.is_some());
+    assert!(prog.call_graph.num_vertices() > 800);
+}
+
+#[test]
+fn elf_machine() {
+    let (_,m) = elf::load(Path::new("tests/data/static")).ok().unwrap();
+    assert_eq!(m, elf::Machine::Amd64);
+
+    let (_,m) = elf::load(Path::new("tests/data/hello-world")).ok().unwrap();
+    assert_eq!(m, elf::Machine::Avr);
+
+    let ident = elf::Ident::open(Path::new("tests/data/static")).ok().unwrap();
+    assert_eq!(ident.endianess(), elf::Endianess::Little);
+    assert!(ident.is_64bit());
+}
+
+#[test]
+fn elf_load_plt() {
+    let (proj,_) = elf::load(Path::new("tests/data/dynamic")).ok().unwrap();
+    let prog = &proj.code[0];
+
+    assert_eq!(prog.imports.get(&0x401030).map(|x| x.as_str()),Some("puts"));
+    assert_eq!(prog.imports.get(&0x401040).map(|x| x.as_str()),Some("strlen"));
+    assert_eq!(prog.imports.get(&0x401060).map(|x| x.as_str()),Some("exit"));
+    assert!(prog.imports.get(&0x401020).is_none());
+}
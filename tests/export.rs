@@ -0,0 +1,55 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+extern crate panopticon;
+extern crate graph_algos;
+extern crate rustc_serialize;
+
+use panopticon::{amd64,dos,export};
+use panopticon::export::ProjectExport;
+
+use std::path::Path;
+use rustc_serialize::json;
+use graph_algos::GraphTrait;
+
+#[test]
+fn export_com() {
+    let mut proj = dos::load_com(Path::new("tests/data/dos.com")).unwrap();
+
+    {
+        let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+        proj.code[0].disassemble_all::<amd64::Amd64,_>(root,amd64::Mode::Real,|_| {}).unwrap();
+    }
+
+    let exp: ProjectExport = json::decode(&export::to_json(&proj).unwrap()).unwrap();
+    let funcs = &exp.programs[0].functions;
+
+    assert_eq!(exp.name,"dos.com");
+    assert_eq!(funcs.len(),2);
+    assert_eq!(funcs[0].entry,Some(0x100));
+    assert_eq!(funcs[0].basic_blocks.len(),3);
+    assert!(funcs[0].basic_blocks.iter().all(|bb| bb.mnemonics.iter().all(|m| !m.text.is_empty())));
+    assert_eq!(exp.programs[0].calls.len(),1);
+    assert_eq!(exp.comments[0].offset,0x100);
+
+    let lst = export::listing(&proj);
+
+    assert!(lst.starts_with("dos.com:"));
+    assert!(lst.contains("0000000000000100 <dos.com>:"));
+    assert!(lst.contains("     100:\tb9 "));
+}
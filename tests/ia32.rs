@@ -0,0 +1,228 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+extern crate panopticon;
+extern crate graph_algos;
+
+use panopticon::{
+    Project,
+    Program,
+    Function,
+    CallTarget,
+    ControlFlowTarget,
+    amd64,
+    elf,
+    pe,
+    dos,
+    Frame,
+    StackSlot,
+    SlotKind,
+    Architecture,
+    Lvalue,
+    Rvalue,
+    Kset,
+};
+use panopticon::abstractinterp::approximate_with;
+
+use std::path::Path;
+use std::borrow::Cow;
+use std::collections::{BTreeMap,HashMap};
+
+use graph_algos::{
+    VertexListGraphTrait,
+    GraphTrait,
+};
+
+/// Disassembles `proj` and returns the number of basic blocks of each function, keyed by entry
+/// point.
+fn disassemble(proj: &mut Project, mode: amd64::Mode) -> BTreeMap<u64,(String,usize)> {
+    let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+    let prog = &mut proj.code[0];
+    let mut ret = BTreeMap::new();
+
+    prog.disassemble_all::<amd64::Amd64,_>(root,mode,|_| {}).unwrap();
+
+    for vx in prog.call_graph.vertices() {
+        match prog.call_graph.vertex_label(vx) {
+            Some(&CallTarget::Concrete(ref f)) => {
+                let entry = match f.entry_point.and_then(|e| f.cflow_graph.vertex_label(e)) {
+                    Some(&ControlFlowTarget::Resolved(ref bb)) => bb.area.start,
+                    _ => panic!("function {} has no entry point",f.name),
+                };
+                let bbs = f.cflow_graph.vertices().filter(|&v| match f.cflow_graph.vertex_label(v) {
+                    Some(&ControlFlowTarget::Resolved(_)) => true,
+                    _ => false,
+                }).count();
+
+                ret.insert(entry,(f.name.clone(),bbs));
+            },
+            // indirect calls stay as Todo nodes
+            Some(&CallTarget::Todo(Rvalue::Constant{ .. },_,_)) => panic!("Todo left after disassembly"),
+            _ => {},
+        }
+    }
+
+    ret
+}
+
+#[test]
+fn ia32_elf() {
+    let (mut proj,machine) = elf::load(Path::new("tests/data/ia32-elf")).unwrap();
+
+    assert_eq!(machine,elf::Machine::Ia32);
+
+    let funcs = disassemble(&mut proj,amd64::Mode::Protected);
+
+    assert_eq!(funcs.len(),2);
+    assert_eq!(funcs.get(&0x8049000).map(|x| x.1),Some(1));
+    assert_eq!(funcs.get(&0x8049019),Some(&("count".to_string(),3)));
+}
+
+#[test]
+fn ia32_elf_frames() {
+    let (mut proj,_) = elf::load(Path::new("tests/data/ia32-elf")).unwrap();
+
+    disassemble(&mut proj,amd64::Mode::Protected);
+
+    let prog = &proj.code[0];
+    let frame = |entry: u64| -> Frame {
+        match prog.find_function_by_entry(entry).and_then(|vx| prog.call_graph.vertex_label(vx)) {
+            Some(&CallTarget::Concrete(ref f)) => f.frame.clone().unwrap(),
+            _ => panic!("no function at {:x}",entry),
+        }
+    };
+
+    // push $5; mov $3,%ecx; call count; add $4,%esp; ...; ret
+    let start = frame(0x8049000);
+
+    assert_eq!(start.deltas.iter().map(|(&a,&d)| (a,d)).collect::<Vec<_>>(),vec![
+        (0x8049000,0),(0x8049002,-4),(0x8049007,-4),(0x804900c,-4),
+        (0x804900f,0),(0x8049014,0),(0x8049016,0),(0x8049018,0),
+    ]);
+    assert!(start.is_balanced());
+    assert_eq!(start.slots,vec![
+        StackSlot{ offset: -4, size: 32, name: "var_4".to_string(), kind: SlotKind::Local },
+    ]);
+
+    // push %ebp; mov %esp,%ebp; sub $8,%esp; mov 8(%ebp),%eax; mov %ecx,-4(%ebp);
+    // mov -4(%ebp),%edx; 1: dec %ecx; jne 1b; leave; ret
+    let count = frame(0x8049019);
+
+    assert_eq!(count.deltas.iter().map(|(&a,&d)| (a,d)).collect::<Vec<_>>(),vec![
+        (0x8049019,0),(0x804901a,-4),(0x804901c,-4),(0x804901f,-12),(0x8049022,-12),
+        (0x8049025,-12),(0x8049028,-12),(0x8049029,-12),(0x804902b,-12),(0x804902c,0),
+    ]);
+    assert!(count.is_balanced());
+    assert_eq!(count.size,12);
+    assert_eq!(count.slots,vec![
+        StackSlot{ offset: -8, size: 32, name: "var_8".to_string(), kind: SlotKind::Local },
+        StackSlot{ offset: -4, size: 32, name: "var_4".to_string(), kind: SlotKind::Spill("EBP".to_string()) },
+        StackSlot{ offset: 4, size: 32, name: "arg_4".to_string(), kind: SlotKind::Argument },
+    ]);
+    assert_eq!(count.slot(0x8049019,"ESP").map(|s| s.offset),Some(-4));
+    assert_eq!(count.slot(0x804901f,"EBP+0x8").map(|s| s.name.clone()),Some("arg_4".to_string()));
+    assert_eq!(count.slot(0x8049022,"EBP-0x4").map(|s| s.name.clone()),Some("var_8".to_string()));
+    assert_eq!(count.slot(0x8049025,"EBP-0x4").map(|s| s.name.clone()),Some("var_8".to_string()));
+    assert_eq!(count.slot(0x804902b,"ESP").map(|s| s.offset),Some(-4));
+}
+
+#[test]
+fn ia32_disassemble_function_frame() {
+    let (proj,_) = elf::load(Path::new("tests/data/ia32-elf")).unwrap();
+    let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+    let func = Function::new("count".to_string(),root.name().clone());
+    let func = Program::disassemble_function::<amd64::Amd64>(func,amd64::Mode::Protected,root,0x8049019).unwrap();
+    let frame = func.frame.unwrap();
+
+    assert_eq!(frame.size,12);
+    assert_eq!(frame.slots.len(),3);
+    assert_eq!(frame.deltas.get(&0x804901f),Some(&-12));
+    assert_eq!(frame.deltas.get(&0x804902c),Some(&0));
+}
+
+#[test]
+fn ia32_stack_slot_round_trip() {
+    let (proj,_) = elf::load(Path::new("tests/data/ia32-elf")).unwrap();
+    let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+    let func = Function::new("count".to_string(),root.name().clone());
+    let func = Program::disassemble_function::<amd64::Amd64>(func,amd64::Mode::Protected,root,0x8049019).unwrap();
+    let sp = amd64::Amd64::stack_pointer(&amd64::Mode::Protected);
+    let mut entry = HashMap::new();
+
+    entry.insert(Cow::Borrowed("ECX"),Kset::Set(vec![(3,32)]));
+
+    // mov %ecx,-4(%ebp); mov -4(%ebp),%edx
+    let vals = approximate_with::<Kset>(&func,&entry,&HashMap::new(),sp.as_ref()).unwrap();
+    let edx = vals.iter().filter_map(|(lv,v)| match lv {
+        &Lvalue::Variable{ ref name,.. } if name == "EDX" => Some(v.clone()),
+        _ => None,
+    }).collect::<Vec<_>>();
+
+    assert_eq!(edx,vec![Kset::Set(vec![(3,32)])]);
+
+    // without the stack pointer the load is unknown
+    let vals = approximate_with::<Kset>(&func,&entry,&HashMap::new(),None).unwrap();
+    let edx = vals.iter().filter_map(|(lv,v)| match lv {
+        &Lvalue::Variable{ ref name,.. } if name == "EDX" => Some(v.clone()),
+        _ => None,
+    }).collect::<Vec<_>>();
+
+    assert_eq!(edx,vec![Kset::Join]);
+}
+
+#[test]
+fn ia32_elf_interproc() {
+    let (mut proj,_) = elf::load(Path::new("tests/data/ia32-elf")).unwrap();
+    let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+    let prog = &mut proj.code[0];
+    let approx = prog.disassemble_all::<amd64::Amd64,_>(root,amd64::Mode::Protected,|_| {}).unwrap();
+    let count = prog.call_graph.vertices().filter_map(|vx| match prog.call_graph.vertex_label(vx) {
+        Some(&CallTarget::Concrete(ref f)) if f.name == "count" => Some(f.uuid),
+        _ => None,
+    }).next().unwrap();
+    let summary = &approx.summaries[&count];
+
+    // mov $3,%ecx; call count
+    assert!(summary.inputs.contains_key("ECX"));
+    assert_eq!(approx.inputs[&count].get("ECX"),Some(&Kset::Set(vec![(3,32)])));
+    assert_eq!(summary.stack_delta,Some(0));
+}
+
+#[test]
+fn ia32_pe() {
+    let (mut proj,machine) = pe::load(Path::new("tests/data/test.exe")).unwrap();
+
+    assert_eq!(machine,pe::Machine::Ia32);
+
+    let funcs = disassemble(&mut proj,amd64::Mode::Protected);
+    let entry = funcs.get(&0x4112c6).unwrap();
+
+    // jmp 0x416a00; push ebp ... ret
+    assert_eq!(funcs.len(),44);
+    assert_eq!(entry.1,2);
+}
+
+#[test]
+fn real_mode_com() {
+    let mut proj = dos::load_com(Path::new("tests/data/dos.com")).unwrap();
+    let funcs = disassemble(&mut proj,amd64::Mode::Real);
+
+    assert_eq!(funcs.len(),2);
+    assert_eq!(funcs.get(&0x100).map(|x| x.1),Some(3));
+    assert_eq!(funcs.get(&0x10f).map(|x| x.1),Some(1));
+}
@@ -0,0 +1,109 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+extern crate panopticon;
+extern crate graph_algos;
+
+use panopticon::{macho,amd64,Program,CallTarget,Rvalue};
+use std::path::Path;
+
+use graph_algos::{
+    VertexListGraphTrait,
+    AdjacencyMatrixGraphTrait,
+    GraphTrait,
+};
+
+fn todo<'a>(prog: &'a Program, addr: u64) -> Option<&'a str> {
+    prog.call_graph.vertices().filter_map(|vx| match prog.call_graph.vertex_label(vx) {
+        Some(&CallTarget::Todo(Rvalue::Constant{ value,.. },Some(ref n),_)) if value == addr => Some(n.as_str()),
+        _ => None,
+    }).next()
+}
+
+fn symbolic(prog: &Program) -> Vec<String> {
+    let mut ret = prog.call_graph.vertices().filter_map(|vx| match prog.call_graph.vertex_label(vx) {
+        Some(&CallTarget::Symbolic(ref n,_)) => Some(n.clone()),
+        _ => None,
+    }).collect::<Vec<_>>();
+
+    ret.sort();
+    ret
+}
+
+#[test]
+fn macho_load() {
+    let (proj,machine) = macho::load(Path::new("tests/data/macho-x86_64"),None).unwrap();
+    let prog = &proj.code[0];
+
+    assert_eq!(machine,macho::Machine::Amd64);
+    assert_eq!(todo(prog,0x100000f00),Some("_main"));
+    assert_eq!(todo(prog,0x100000f10),Some("_helper"));
+    assert_eq!(symbolic(prog),vec!["_exit".to_string(),"_printf".to_string(),"dyld_stub_binder".to_string()]);
+    assert_eq!(prog.imports.get(&0x100001000).map(|x| x.as_str()),Some("dyld_stub_binder"));
+    assert_eq!(prog.imports.get(&0x100001010).map(|x| x.as_str()),Some("_printf"));
+    assert_eq!(prog.imports.get(&0x100001018).map(|x| x.as_str()),Some("_exit"));
+
+    let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+    assert_eq!(root.iter().seek(0x100000f00).next(),Some(Some(0xe8)));
+    assert_eq!(root.iter().seek(0xf00).next(),Some(None));
+}
+
+#[test]
+fn macho_stubs() {
+    let (mut proj,_) = macho::load(Path::new("tests/data/macho-x86_64"),None).unwrap();
+
+    assert_eq!(proj.code[0].imports.get(&0x100000f20).map(|x| x.as_str()),Some("_printf"));
+    assert_eq!(proj.code[0].imports.get(&0x100000f26).map(|x| x.as_str()),Some("_exit"));
+
+    {
+        let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+        proj.code[0].disassemble_all::<amd64::Amd64,_>(root,amd64::Mode::Long,|_| {}).unwrap();
+    }
+
+    // _main calls the _printf stub
+    let prog = &proj.code[0];
+    let main = prog.find_function_by_entry(0x100000f00).unwrap();
+    let printf = prog.call_graph.vertices().find(|&vx| match prog.call_graph.vertex_label(vx) {
+        Some(&CallTarget::Symbolic(ref n,_)) => n == "_printf",
+        _ => false,
+    }).unwrap();
+
+    assert!(prog.call_graph.edge(main,printf).is_some());
+}
+
+#[test]
+fn macho_fat() {
+    let p = Path::new("tests/data/macho-fat");
+
+    assert_eq!(macho::slices(p).unwrap(),vec![macho::Machine::Amd64,macho::Machine::Aarch64]);
+
+    let (proj,machine) = macho::load(p,None).unwrap();
+    assert_eq!(machine,macho::Machine::Amd64);
+    assert_eq!(todo(&proj.code[0],0x100000f00),Some("_main"));
+
+    let (proj,machine) = macho::load(p,Some(macho::Machine::Aarch64)).unwrap();
+    assert_eq!(machine,macho::Machine::Aarch64);
+    assert_eq!(todo(&proj.code[0],0x100000f00),Some("_main"));
+
+    assert!(macho::load(p,Some(macho::Machine::PowerPc)).is_err());
+}
+
+#[test]
+fn macho_not_macho() {
+    assert!(macho::load(Path::new("tests/data/static"),None).is_err());
+}
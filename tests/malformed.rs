@@ -0,0 +1,78 @@
+/*
+ * Panopticon - A libre disassembler
+ * Copyright (C) 2016  Panopticon authors
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+extern crate panopticon;
+
+use panopticon::{elf,pe,macho,loader};
+use std::path::{Path,PathBuf};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new("tests/data/malformed").join(name)
+}
+
+#[test]
+fn malformed_elf() {
+    for f in &[
+        "elf-truncated",
+        "elf-phoff-overflow",
+        "elf-phentsize",
+        "elf-huge-filesz",
+        "elf-vaddr-overflow",
+        "elf-overlapping-segments",
+    ] {
+        assert!(elf::load(&fixture(f)).is_err(),"{} was accepted",f);
+        assert!(loader::load(&fixture(f)).is_err(),"{} was accepted",f);
+    }
+}
+
+#[test]
+fn malformed_elf_sections() {
+    // broken section headers only cost us the symbol tables
+    let (proj,m) = elf::load(&fixture("elf-shoff-overflow")).unwrap();
+
+    assert_eq!(m,elf::Machine::Amd64);
+    assert_eq!(proj.code[0].imports.len(),0);
+}
+
+#[test]
+fn malformed_pe() {
+    for f in &[
+        "pe-truncated",
+        "pe-lfanew",
+        "pe-huge-section",
+        "pe-section-table",
+        "pe-overlapping-sections",
+        "pe-huge-exports",
+    ] {
+        assert!(pe::load(&fixture(f)).is_err(),"{} was accepted",f);
+        assert!(loader::load(&fixture(f)).is_err(),"{} was accepted",f);
+    }
+}
+
+#[test]
+fn malformed_macho() {
+    for f in &[
+        "macho-truncated",
+        "macho-segment-outside",
+        "macho-overlapping-segments",
+        "macho-huge-ncmds",
+    ] {
+        assert!(macho::load(&fixture(f),None).is_err(),"{} was accepted",f);
+        assert!(loader::load(&fixture(f)).is_err(),"{} was accepted",f);
+    }
+}
@@ -17,15 +17,86 @@
  */
 
 extern crate panopticon;
+extern crate graph_algos;
+extern crate tempdir;
 
 use std::path::Path;
+use std::fs::File;
+use std::io::Write;
+use tempdir::TempDir;
 use panopticon::project::Project;
+use panopticon::{CallTarget,Rvalue,Target,Format,LoadError,Metadata,amd64,session};
+
+use graph_algos::{
+    VertexListGraphTrait,
+    EdgeListGraphTrait,
+    GraphTrait,
+};
 
 #[test]
 fn project_pe() {
     let maybe_project = Project::pe(Path::new("tests/data/test.exe"));
 
-    assert!(maybe_project.is_some());
+    assert!(maybe_project.is_ok());
+}
+
+#[test]
+fn project_pe_imports() {
+    let proj = Project::pe(Path::new("tests/data/test.exe")).unwrap();
+    let prog = &proj.code[0];
+    let entry = prog.call_graph.vertices().any(|vx| match prog.call_graph.vertex_label(vx) {
+        Some(&CallTarget::Todo(Rvalue::Constant{ value: 0x4112c6,.. },_,_)) => true,
+        _ => false,
+    });
+    let exit = prog.call_graph.vertices().any(|vx| match prog.call_graph.vertex_label(vx) {
+        Some(&CallTarget::Symbolic(ref s,_)) => s == "MSVCR120D.dll!exit",
+        _ => false,
+    });
+
+    assert!(entry);
+    assert!(exit);
+    assert!(prog.imports.values().any(|s| s == "MSVCR120D.dll!exit"));
+}
+
+#[test]
+fn project_pe_import_calls() {
+    let mut proj = Project::pe(Path::new("tests/data/test.exe")).unwrap();
+
+    {
+        let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+        proj.code[0].disassemble_all::<amd64::Amd64,_>(root,amd64::Mode::Protected,|_| {}).unwrap();
+    }
+
+    // call DWORD PTR [slot]
+    let prog = &proj.code[0];
+    let linked = prog.call_graph.edges().any(|e| {
+        let from = prog.call_graph.vertex_label(prog.call_graph.source(e));
+        let to = prog.call_graph.vertex_label(prog.call_graph.target(e));
+
+        match (from,to) {
+            (Some(&CallTarget::Concrete(_)),Some(&CallTarget::Symbolic(..))) => true,
+            _ => false,
+        }
+    });
+
+    assert!(linked);
+}
+
+#[test]
+fn project_pe_raw_padding() {
+    // SizeOfRawData of .text is padded past the start of .data
+    let proj = Project::pe(Path::new("tests/data/pe-raw-padding")).unwrap();
+    let root = proj.data.dependencies.vertex_label(proj.data.root).unwrap();
+    let bytes = root.iter().seek(0x401000).take(0x101).map(|x| x.unwrap_or(0xff)).collect::<Vec<u8>>();
+
+    assert_eq!(bytes[0],0xc3);
+    assert_eq!(bytes[0xff],0);
+    assert_eq!(bytes[0x100],0x90);
+}
+
+#[test]
+fn project_pe_not_pe() {
+    assert!(Project::pe(Path::new("tests/data/static")).is_err());
 }
 
 #[test]
@@ -35,9 +106,84 @@ fn project_open() {
     assert!(maybe_project.ok().is_some());
 }
 
+#[test]
+fn project_migrate_legacy() {
+    let tmpdir = TempDir::new("test-panop-migrate").unwrap();
+    let p = tmpdir.path().join("save.panop");
+
+    assert_eq!(session::read_header(Path::new("tests/data/save.panop")).unwrap().version,0);
+
+    let old = Project::open(Path::new("tests/data/save.panop")).unwrap();
+    let meta = Project::metadata(Path::new("tests/data/save.panop")).unwrap();
+
+    assert_eq!(old.name,"sosse");
+    assert!(old.code.iter().all(|prog| prog.imports.is_empty()));
+    assert_eq!(meta,Metadata::new(&old));
+
+    old.snapshot(&p).unwrap();
+
+    assert_eq!(session::read_header(&p).unwrap().version,session::FORMAT_VERSION);
+    assert_eq!(Project::metadata(&p).unwrap(),meta);
+
+    let new = Project::open(&p).unwrap();
+
+    assert_eq!(new.name,old.name);
+    assert_eq!(new.code.len(),old.code.len());
+    assert_eq!(new.comments,old.comments);
+    assert_eq!(new.code[0].call_graph.num_vertices(),old.code[0].call_graph.num_vertices());
+}
+
 #[test]
 fn project_empty() {
     let maybe_project = Project::open(Path::new("tests/data/empty.panop"));
 
     assert!(maybe_project.ok().is_none());
 }
+
+#[test]
+fn project_load() {
+    match Project::load(Path::new("tests/data/test.exe")) {
+        Ok((proj,Some(Target::Amd64(amd64::Mode::Protected)))) => assert_eq!(proj.name,"test.exe"),
+        _ => unreachable!(),
+    }
+
+    match Project::load(Path::new("tests/data/hello-world")) {
+        Ok((_,Some(Target::Avr(_)))) => {},
+        _ => unreachable!(),
+    }
+
+    match Project::load(Path::new("tests/data/save.panop")) {
+        Ok((proj,None)) => assert_eq!(proj.name,"sosse"),
+        _ => unreachable!(),
+    }
+
+    match Project::load(Path::new("tests/data/sosse.hex")) {
+        Ok((_,None)) => {},
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn project_load_errors() {
+    match Project::load(Path::new("tests/data/empty.panop")) {
+        Err(LoadError::Unrecognized(_,ref tried)) => assert!(tried.is_empty()),
+        _ => unreachable!(),
+    }
+
+    match Project::load(Path::new("tests/data/does-not-exist")) {
+        Err(LoadError::Io(..)) => {},
+        _ => unreachable!(),
+    }
+
+    let tmpdir = TempDir::new("test-panop-load").unwrap();
+    let p = tmpdir.path().join("broken");
+
+    File::create(&p).unwrap().write_all(b"\x7fELF\x01\x01\x01\x00 not really").unwrap();
+
+    match Project::load(&p) {
+        Err(LoadError::Unrecognized(_,ref tried)) => {
+            assert_eq!(tried.iter().map(|x| x.0).collect::<Vec<_>>(),vec![Format::Elf]);
+        },
+        _ => unreachable!(),
+    }
+}